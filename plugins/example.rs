@@ -0,0 +1,55 @@
+// Plugins compiled into es-dumper with
+//
+//     ES_DUMPER_PLUGINS=/path/to/this/file.rs cargo build --release --features plugins
+//
+// This file becomes a module of the crate: it can use the crate's own items through `crate::`
+// and its dependencies (`serde_json`, `sha2`, `reqwest`, ...), and has to define `register`.
+// Select the plugins in config.toml:
+//
+//     [plugins]
+//     transforms = ["drop-fields"]
+//
+//     [plugins.options.drop-fields]
+//     fields = ["password", "ssn"]
+
+use crate::error::EsDumperError;
+use crate::plugins::{ DocumentTransform, PluginRegistry };
+use serde_json::Value;
+
+pub fn register(registry: &mut PluginRegistry) {
+    registry.register_transform("drop-fields", DropFields::from_options);
+}
+
+/// Removes top-level `_source` fields from every backed-up document.
+pub struct DropFields {
+    fields: Vec<String>,
+}
+
+impl DropFields {
+    fn from_options(options: &Value) -> Result<Box<dyn DocumentTransform>, EsDumperError> {
+        let fields = options["fields"]
+            .as_array()
+            .ok_or_else(|| {
+                EsDumperError::Config("drop-fields needs a list of `fields` to drop".to_string())
+            })?
+            .iter()
+            .filter_map(|field| field.as_str().map(str::to_string))
+            .collect();
+        Ok(Box::new(DropFields { fields }))
+    }
+}
+
+impl DocumentTransform for DropFields {
+    fn name(&self) -> &str {
+        "drop-fields"
+    }
+
+    fn transform(&self, _index: &str, document: &mut Value) -> Result<(), EsDumperError> {
+        if let Some(source) = document["_source"].as_object_mut() {
+            for field in &self.fields {
+                source.remove(field);
+            }
+        }
+        Ok(())
+    }
+}
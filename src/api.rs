@@ -0,0 +1,425 @@
+use crate::error::EsDumperError;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+/// Oldest Elasticsearch version backups and restores are known to work with.
+pub const MIN_ELASTICSEARCH_VERSION: EsVersion = EsVersion::new(5, 0, 0);
+pub const MIN_OPENSEARCH_VERSION: EsVersion = EsVersion::new(1, 0, 0);
+
+/// Version of a cluster, from the `version.number` of its root endpoint, ordered by semver
+/// precedence: a pre-release such as `8.0.0-rc1` comes before the release `8.0.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EsVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Pre-release label, e.g. `SNAPSHOT` or `alpha2`.
+    pub pre_release: Option<String>,
+}
+
+impl EsVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        EsVersion { major, minor, patch, pre_release: None }
+    }
+
+    /// Parses `major.minor[.patch][-pre_release][+build]`, with the build metadata ignored.
+    pub fn parse(version: &str) -> Result<Self, EsDumperError> {
+        let invalid = || {
+            EsDumperError::UnexpectedResponse(format!("Unrecognized version number: {}", version))
+        };
+        let version_core = version.trim().split('+').next().unwrap_or_default();
+        let (numbers, pre_release) = match version_core.split_once('-') {
+            Some((numbers, pre_release)) if !pre_release.is_empty() => {
+                (numbers, Some(pre_release.to_string()))
+            }
+            Some(_) => {
+                return Err(invalid());
+            }
+            None => (version_core, None),
+        };
+
+        let numbers = numbers
+            .split('.')
+            .map(|part| {
+                if part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                part.parse::<u64>().map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<u64>, EsDumperError>>()?;
+        let (major, minor, patch) = match numbers[..] {
+            [major, minor] => (major, minor, 0),
+            [major, minor, patch] => (major, minor, patch),
+            _ => {
+                return Err(invalid());
+            }
+        };
+        Ok(EsVersion { major, minor, patch, pre_release })
+    }
+
+    /// Whether this is `major.minor` or a later version, pre-releases of `major.minor.0`
+    /// included.
+    pub fn at_least(&self, major: u64, minor: u64) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+}
+
+impl Ord for EsVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| {
+                match (&self.pre_release, &other.pre_release) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some(_), None) => Ordering::Less,
+                    (Some(a), Some(b)) => compare_pre_releases(a, b),
+                }
+            })
+    }
+}
+
+impl PartialOrd for EsVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for EsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre_release) = &self.pre_release {
+            write!(f, "-{}", pre_release)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares dot-separated pre-release labels part by part: numeric parts by value and below
+/// alphanumeric ones, which compare as text; a label that runs out of parts first is lower.
+fn compare_pre_releases(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => {
+                return Ordering::Equal;
+            }
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(a), Some(b)) =>
+                match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => a.cmp(b),
+                }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Elasticsearch,
+    OpenSearch,
+}
+
+impl Distribution {
+    /// The distribution named by `version.distribution`, which only OpenSearch reports.
+    pub fn parse(distribution: Option<&str>) -> Self {
+        match distribution {
+            Some("opensearch") => Distribution::OpenSearch,
+            _ => Distribution::Elasticsearch,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Distribution::Elasticsearch => "Elasticsearch",
+            Distribution::OpenSearch => "OpenSearch",
+        }
+    }
+
+    /// Oldest version of the distribution that is supported.
+    pub fn minimum_version(&self) -> EsVersion {
+        match self {
+            Distribution::Elasticsearch => MIN_ELASTICSEARCH_VERSION,
+            Distribution::OpenSearch => MIN_OPENSEARCH_VERSION,
+        }
+    }
+}
+
+/// What differs between the cluster versions and distributions a backup or restore talks to,
+/// so callers ask the cluster's API instead of matching version strings.
+pub trait EsApi: fmt::Debug + Send + Sync {
+    fn distribution(&self) -> Distribution;
+
+    fn version(&self) -> &EsVersion;
+
+    /// Whether the version is at least the oldest supported one of its distribution.
+    fn supported(&self) -> bool {
+        *self.version() >= self.distribution().minimum_version()
+    }
+
+    /// The index entries of a `_cat/indices?format=json` response.
+    fn cat_indices<'a>(&self, response: &'a Value) -> Result<Vec<&'a Value>, EsDumperError> {
+        index_entries(self.distribution(), self.version(), response)
+    }
+
+    /// Documents requested per scroll page for a configured `scroll_size`.
+    fn scroll_size(&self, configured: u64) -> u64 {
+        configured
+    }
+
+    /// Whether mappings are nested under a document type, as before Elasticsearch 7.0.
+    fn typed_mappings(&self) -> bool {
+        false
+    }
+
+    /// Endpoint opening a point in time on `index`, on versions that have one. Exports use
+    /// scroll contexts where the cluster keeps them.
+    fn point_in_time_endpoint(&self, index: &str) -> Option<String>;
+
+    /// Endpoint and body closing the point in time `id`.
+    fn close_point_in_time(&self, id: &str) -> (&'static str, Value) {
+        ("_pit", serde_json::json!({ "id": id }))
+    }
+
+    /// Whether searches in a point in time can sort on `_shard_doc`, which orders every
+    /// document of the point in time.
+    fn supports_shard_doc(&self) -> bool {
+        false
+    }
+
+    /// Whether the cluster keeps scroll contexts; without them, exports page with
+    /// `search_after`.
+    fn supports_scroll(&self) -> bool {
+        true
+    }
+
+    /// Whether the `_cat` APIs are available for listing indices.
+    fn supports_cat(&self) -> bool {
+        true
+    }
+
+    /// Whether indices can be refreshed on request, e.g. before counting restored documents.
+    fn supports_refresh(&self) -> bool {
+        true
+    }
+
+    /// Whether mappings can define runtime fields, as from Elasticsearch 7.11.
+    fn supports_runtime_fields(&self) -> bool {
+        false
+    }
+
+    /// Features deprecated or removed in this version that backups and restores rely on.
+    fn deprecations(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+/// The API of a cluster of the given version and distribution.
+pub fn select_api(version: EsVersion, distribution: Distribution) -> Arc<dyn EsApi> {
+    match distribution {
+        Distribution::OpenSearch => Arc::new(OpenSearch { version }),
+        Distribution::Elasticsearch if !version.at_least(7, 0) => {
+            Arc::new(TypedElasticsearch { version })
+        }
+        Distribution::Elasticsearch => Arc::new(Elasticsearch { version }),
+    }
+}
+
+/// The API of a target configured with `compatibility = "serverless"`.
+pub fn serverless_api() -> Arc<dyn EsApi> {
+    Arc::new(Serverless { version: SERVERLESS_VERSION })
+}
+
+/// Serverless collections report no version; they offer the API of OpenSearch 2.x, less the
+/// parts that need state on the nodes.
+const SERVERLESS_VERSION: EsVersion = EsVersion::new(2, 0, 0);
+
+/// Elasticsearch 5.x and 6.x, with mapping types.
+#[derive(Debug)]
+struct TypedElasticsearch {
+    version: EsVersion,
+}
+
+impl EsApi for TypedElasticsearch {
+    fn distribution(&self) -> Distribution {
+        Distribution::Elasticsearch
+    }
+
+    fn version(&self) -> &EsVersion {
+        &self.version
+    }
+
+    fn typed_mappings(&self) -> bool {
+        true
+    }
+
+    fn point_in_time_endpoint(&self, _index: &str) -> Option<String> {
+        None
+    }
+
+    fn deprecations(&self) -> Vec<&'static str> {
+        vec![
+            "mapping types were removed in Elasticsearch 7.0; restore backups holding several types into newer clusters with --split-types"
+        ]
+    }
+}
+
+/// Elasticsearch 7.0 and later.
+#[derive(Debug)]
+struct Elasticsearch {
+    version: EsVersion,
+}
+
+impl Elasticsearch {
+    /// 8.3.x, which answers `_cat/indices` with a map on some builds and times out on large
+    /// scroll pages.
+    fn is_8_3(&self) -> bool {
+        self.version.major == 8 && self.version.minor == 3
+    }
+}
+
+impl EsApi for Elasticsearch {
+    fn distribution(&self) -> Distribution {
+        Distribution::Elasticsearch
+    }
+
+    fn version(&self) -> &EsVersion {
+        &self.version
+    }
+
+    fn cat_indices<'a>(&self, response: &'a Value) -> Result<Vec<&'a Value>, EsDumperError> {
+        let Some(map) = response.as_object().filter(|_| self.is_8_3()) else {
+            return index_entries(self.distribution(), &self.version, response);
+        };
+        if map.is_empty() {
+            return Ok(Vec::new());
+        }
+        map.get("indices")
+            .and_then(|indices| indices.as_array())
+            .map(|indices| indices.iter().collect())
+            .ok_or_else(|| {
+                EsDumperError::UnexpectedResponse(
+                    format!(
+                        "Expected 'indices' array in map response for ES 8.3.x, got: {}",
+                        response
+                    )
+                )
+            })
+    }
+
+    fn scroll_size(&self, configured: u64) -> u64 {
+        if self.is_8_3() {
+            (configured / 2).max(1000)
+        } else {
+            configured
+        }
+    }
+
+    fn point_in_time_endpoint(&self, index: &str) -> Option<String> {
+        self.version.at_least(7, 10).then(|| format!("{}/_pit", index))
+    }
+
+    fn supports_shard_doc(&self) -> bool {
+        self.version.at_least(7, 12)
+    }
+
+    fn supports_runtime_fields(&self) -> bool {
+        self.version.at_least(7, 11)
+    }
+
+    fn deprecations(&self) -> Vec<&'static str> {
+        let mut deprecations = Vec::new();
+        if self.version.major == 7 && self.version.minor >= 14 {
+            deprecations.push(
+                "frozen indices are deprecated and removed in 8.0; restores recreate them as regular indices"
+            );
+        }
+        deprecations
+    }
+}
+
+/// OpenSearch, which reports versions of its own starting again at 1.0.
+#[derive(Debug)]
+struct OpenSearch {
+    version: EsVersion,
+}
+
+impl EsApi for OpenSearch {
+    fn distribution(&self) -> Distribution {
+        Distribution::OpenSearch
+    }
+
+    fn version(&self) -> &EsVersion {
+        &self.version
+    }
+
+    fn point_in_time_endpoint(&self, index: &str) -> Option<String> {
+        self.version.at_least(2, 4).then(|| format!("{}/_search/point_in_time", index))
+    }
+
+    fn close_point_in_time(&self, id: &str) -> (&'static str, Value) {
+        ("_search/point_in_time", serde_json::json!({ "pit_id": [id] }))
+    }
+}
+
+/// OpenSearch Serverless, without scroll contexts, points in time, `_cat` or `_refresh`.
+#[derive(Debug)]
+struct Serverless {
+    version: EsVersion,
+}
+
+impl EsApi for Serverless {
+    fn distribution(&self) -> Distribution {
+        Distribution::OpenSearch
+    }
+
+    fn version(&self) -> &EsVersion {
+        &self.version
+    }
+
+    fn point_in_time_endpoint(&self, _index: &str) -> Option<String> {
+        None
+    }
+
+    fn supports_scroll(&self) -> bool {
+        false
+    }
+
+    fn supports_cat(&self) -> bool {
+        false
+    }
+
+    fn supports_refresh(&self) -> bool {
+        false
+    }
+}
+
+/// The entries of a `_cat/indices` response given as an array, the format of every version.
+fn index_entries<'a>(
+    distribution: Distribution,
+    version: &EsVersion,
+    response: &'a Value
+) -> Result<Vec<&'a Value>, EsDumperError> {
+    response
+        .as_array()
+        .map(|indices| indices.iter().collect())
+        .ok_or_else(|| {
+            EsDumperError::UnexpectedResponse(
+                format!(
+                    "Unexpected response format for {} version {}: {}",
+                    distribution.as_str(),
+                    version,
+                    response
+                )
+            )
+        })
+}
@@ -0,0 +1,295 @@
+use crate::error::EsDumperError;
+use crate::utils::timestamp;
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+use std::fs::{ File, OpenOptions };
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Hash of the record before the first one.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Append-only trail of the changes restores make to the target cluster, one JSON record per
+/// line. Every record carries the SHA-256 hash of the previous one and its own hash over its
+/// contents, so editing, removing or reordering records breaks the chain and shows up in
+/// [`verify_audit_log`]. Runs append to the same file and continue its chain.
+pub struct AuditLog {
+    chain: Option<Mutex<AuditChain>>,
+}
+
+struct AuditChain {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub timestamp: String,
+    /// The run that took the action, as in its `X-Opaque-Id`.
+    pub run_id: String,
+    /// What was done, e.g. `index_created` or `documents_written`.
+    pub action: String,
+    pub index: Option<String>,
+    pub count: Option<u64>,
+    pub detail: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditRecord {
+    /// SHA-256 over the record with an empty `hash`, as lowercase hex.
+    fn compute_hash(&self) -> Result<String, EsDumperError> {
+        let unhashed = AuditRecord { hash: String::new(), ..self.clone() };
+        let digest = Sha256::digest(serde_json::to_vec(&unhashed)?);
+        Ok(
+            digest
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        )
+    }
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, continuing the chain of its last record.
+    pub fn open(path: &str) -> Result<Self, EsDumperError> {
+        let (seq, last_hash) = match last_record(Path::new(path))? {
+            Some(record) => (record.seq, record.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(AuditLog {
+            chain: Some(Mutex::new(AuditChain { file, seq, last_hash })),
+        })
+    }
+
+    /// An audit log that records nothing.
+    pub fn disabled() -> Self {
+        AuditLog { chain: None }
+    }
+
+    /// Appends a record of `action` and syncs it to disk before returning, so no change to the
+    /// cluster goes unrecorded after a crash.
+    pub fn record(
+        &self,
+        run_id: &str,
+        action: &str,
+        index: Option<&str>,
+        count: Option<u64>,
+        detail: Option<String>
+    ) -> Result<(), EsDumperError> {
+        let Some(chain) = &self.chain else {
+            return Ok(());
+        };
+        let mut chain = chain.lock().unwrap();
+
+        let mut record = AuditRecord {
+            seq: chain.seq + 1,
+            timestamp: timestamp(),
+            run_id: run_id.to_string(),
+            action: action.to_string(),
+            index: index.map(str::to_string),
+            count,
+            detail,
+            prev_hash: chain.last_hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash()?;
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        chain.file.write_all(&line)?;
+        chain.file.sync_data()?;
+        chain.seq = record.seq;
+        chain.last_hash = record.hash;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").field("enabled", &self.chain.is_some()).finish()
+    }
+}
+
+fn last_record(path: &Path) -> Result<Option<AuditRecord>, EsDumperError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e.into());
+        }
+    };
+
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    match last {
+        Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+        None => Ok(None),
+    }
+}
+
+/// Checks every record of the audit log at `path` against its hash and its predecessor's,
+/// returning the number of records, or an error naming the first record that does not match.
+pub fn verify_audit_log(path: &str) -> Result<u64, EsDumperError> {
+    let file = File::open(path)?;
+    let mut expected_seq = 1;
+    let mut prev_hash = GENESIS_HASH.to_string();
+
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let broken = |reason: &str| {
+            EsDumperError::PartialFailure(
+                format!("Audit log {} is broken at line {}: {}", path, line_number + 1, reason)
+            )
+        };
+
+        let record: AuditRecord = serde_json
+            ::from_str(&line)
+            .map_err(|e| broken(&format!("unreadable record ({})", e)))?;
+        if record.seq != expected_seq {
+            return Err(
+                broken(&format!("expected record {}, found {}", expected_seq, record.seq))
+            );
+        }
+        if record.prev_hash != prev_hash {
+            return Err(broken("the previous record was changed or removed"));
+        }
+        if record.compute_hash()? != record.hash {
+            return Err(broken("the record was changed"));
+        }
+
+        expected_seq += 1;
+        prev_hash = record.hash;
+    }
+    Ok(expected_seq - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh audit log path in the temp directory, removed when dropped.
+    struct TempLog(String);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(
+                format!("es-dumper-audit-{}-{}.jsonl", name, std::process::id())
+            );
+            let _ = std::fs::remove_file(&path);
+            TempLog(path.to_string_lossy().into_owned())
+        }
+
+        fn records(&self) -> Vec<AuditRecord> {
+            std::fs::read_to_string(&self.0)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+
+        fn write(&self, records: &[AuditRecord]) {
+            let lines: Vec<String> = records
+                .iter()
+                .map(|record| serde_json::to_string(record).unwrap())
+                .collect();
+            std::fs::write(&self.0, lines.join("\n") + "\n").unwrap();
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn record_three(path: &str) {
+        let audit = AuditLog::open(path).unwrap();
+        audit.record("run-1", "index_created", Some("logs"), None, None).unwrap();
+        audit.record("run-1", "documents_written", Some("logs"), Some(42), None).unwrap();
+        let alias = Some("logs-current".to_string());
+        audit.record("run-1", "alias_added", Some("logs"), None, alias).unwrap();
+    }
+
+    #[test]
+    fn chains_records() {
+        let log = TempLog::new("chain");
+        record_three(&log.0);
+
+        let records = log.records();
+        assert_eq!(records[0].prev_hash, GENESIS_HASH);
+        assert_eq!(records[1].prev_hash, records[0].hash);
+        assert_eq!(records[2].prev_hash, records[1].hash);
+        assert_eq!(verify_audit_log(&log.0).unwrap(), 3);
+    }
+
+    #[test]
+    fn continues_the_chain_across_runs() {
+        let log = TempLog::new("append");
+        record_three(&log.0);
+        let audit = AuditLog::open(&log.0).unwrap();
+        audit.record("run-2", "index_deleted", Some("logs"), None, None).unwrap();
+
+        let records = log.records();
+        assert_eq!(records[3].seq, 4);
+        assert_eq!(records[3].prev_hash, records[2].hash);
+        assert_eq!(verify_audit_log(&log.0).unwrap(), 4);
+    }
+
+    #[test]
+    fn detects_changed_records() {
+        let log = TempLog::new("changed");
+        record_three(&log.0);
+        let mut records = log.records();
+        records[1].count = Some(41);
+        log.write(&records);
+
+        let error = verify_audit_log(&log.0).unwrap_err().to_string();
+        assert!(error.contains("line 2: the record was changed"), "{}", error);
+    }
+
+    #[test]
+    fn detects_removed_records() {
+        let log = TempLog::new("removed");
+        record_three(&log.0);
+        let mut records = log.records();
+        records.remove(1);
+        log.write(&records);
+
+        let error = verify_audit_log(&log.0).unwrap_err().to_string();
+        assert!(error.contains("line 2: expected record 2, found 3"), "{}", error);
+    }
+
+    #[test]
+    fn detects_rehashed_records() {
+        let log = TempLog::new("rehashed");
+        record_three(&log.0);
+        let mut records = log.records();
+        records[0].action = "nothing".to_string();
+        records[0].hash = records[0].compute_hash().unwrap();
+        log.write(&records);
+
+        let error = verify_audit_log(&log.0).unwrap_err().to_string();
+        assert!(error.contains("line 2: the previous record was changed or removed"), "{}", error);
+    }
+
+    #[test]
+    fn disabled_logs_record_nothing() {
+        AuditLog::disabled().record("run-1", "index_created", Some("logs"), None, None).unwrap();
+    }
+}
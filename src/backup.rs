@@ -1,352 +1,3156 @@
-use crate::config::{ BackupConfig, DEFAULT_LOG_FILE };
-use crate::http_client::build_http_client;
-use crate::utils::{ log, reduce_document_size, compress_file, get_elasticsearch_version };
-use indicatif::{ MultiProgress, ProgressBar, ProgressStyle };
-use rayon::prelude::*;
-use reqwest::blocking::Client;
+use crate::api::EsApi;
+use crate::bloom::BloomFilter;
+use crate::compressor::CompressionTask;
+use crate::config::{
+    BackupConfig,
+    ClosedIndexPolicy,
+    CompressionMode,
+    Durability,
+    EmptyIndexPolicy,
+    ExistingPolicy,
+    MAX_PARTITIONS,
+};
+use crate::error::EsDumperError;
+use crate::hooks::run_hook;
+use crate::http_client::{ build_http_client, opaque_id, HttpClient };
+use crate::job::Job;
+use crate::latency::LatencyKind;
+use crate::manifest::{ DocumentSource, IndexManifest, IndexMeta, Partition };
+use crate::observer::{ BatchDone, IndexDeferred, IndexError, IndexStart, RunStart };
+use crate::output::{
+    data_file_candidates,
+    part_file_candidates,
+    partial_file,
+    CompressionEstimate,
+    DataFormat,
+    DataWriter,
+};
+use crate::report::{ IndexReport, RunReport, SkippedIndex };
+use crate::restore;
+use crate::state::{
+    StateStore,
+    CHECKPOINT_DEFERRED,
+    CHECKPOINT_DONE,
+    CHECKPOINT_INTERRUPTED,
+    CHECKPOINT_PARTIAL,
+};
+use crate::transforms::backup_transforms;
+use crate::watches::backup_watches;
+use crate::utils::{
+    catch_panic,
+    dir_size,
+    free_space_mb,
+    log,
+    LogFile,
+    panic_message,
+    parse_duration,
+    reduce_document_size,
+    retry_transient,
+    run_queue,
+    set_path,
+    sync_file,
+    timestamp,
+    get_cluster_info,
+    cluster_dir_name,
+    link_dir,
+    matches_pattern,
+    ClusterInfo,
+};
+use reqwest::StatusCode;
 use serde_json::Value;
-use std::fs::{ self, File };
-use std::io::{ BufWriter, Write };
-use std::path::Path;
+use std::cmp::Reverse;
+use std::collections::{ HashMap, HashSet };
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, BufWriter };
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicU64, Ordering };
 use std::sync::{ Arc, Mutex };
-use std::time::Duration;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+const ID_FILTER_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Indices per batched `_mapping`, `_settings` and `_alias` request, and the longest index list
+/// put into one request URL.
+const METADATA_BATCH_SIZE: usize = 100;
+const METADATA_BATCH_MAX_URL_LEN: usize = 3000;
+
+/// The metadata endpoints fetched for every index, with the key naming their backup files.
+const METADATA_ENDPOINTS: [(&str, &str); 3] = [
+    ("_mapping", "mapping"),
+    ("_settings", "settings"),
+    ("_alias", "aliases"),
+];
+
+/// Mappings, settings and aliases fetched in batches before the indices are backed up, by
+/// `(key, index)`; indices missing from it are fetched on their own.
+type PrefetchedMetadata = HashMap<(&'static str, String), Value>;
+
+/// Mapping types whose values are kept neither in doc values nor, unless `store` is set, as
+/// stored fields.
+const NO_DOC_VALUES_TYPES: &[&str] = &[
+    "text",
+    "match_only_text",
+    "annotated_text",
+    "search_as_you_type",
+    "completion",
+    "percolator",
+];
+
+/// Mapping types a partition field may have besides dates.
+const NUMERIC_TYPES: &[&str] = &[
+    "long",
+    "integer",
+    "short",
+    "byte",
+    "unsigned_long",
+    "double",
+    "float",
+    "half_float",
+    "scaled_float",
+];
+
+/// Store sizes of an index in bytes, from `_stats/store`.
+#[derive(Debug, Clone, Copy)]
+struct StoreSize {
+    primaries: u64,
+    /// Including replicas.
+    total: u64,
+}
+
+struct BackupOutcome {
+    docs: u64,
+    bytes: u64,
+    warnings: Vec<String>,
+    /// Why the index was left out instead of backed up.
+    skipped: Option<String>,
+    /// `--max-index-duration` stopped the export before every document was written.
+    interrupted: bool,
+}
+
+/// A scroll context opened by a backup. It is recorded in the state store while open and
+/// cleared (best effort) when dropped, so no error path leaves it behind on the cluster and
+/// `cleanup-scrolls` can clear the contexts of a run that was killed.
+struct ScrollGuard<'a> {
+    config: &'a BackupConfig,
+    client: &'a HttpClient,
+    state: &'a StateStore,
+    cluster: &'a str,
+    index: &'a str,
+    id: String,
+}
+
+impl<'a> ScrollGuard<'a> {
+    fn open(
+        config: &'a BackupConfig,
+        client: &'a HttpClient,
+        state: &'a StateStore,
+        cluster: &'a str,
+        index: &'a str,
+        id: String
+    ) -> Self {
+        let _ = state.add_scroll(cluster, index, &id);
+        ScrollGuard { config, client, state, cluster, index, id }
+    }
+
+    /// Follows the scroll ID returned by the latest page, which may differ from the first one.
+    fn update(&mut self, id: &str) {
+        if id != self.id {
+            let _ = self.state.remove_scroll(&self.id);
+            let _ = self.state.add_scroll(self.cluster, self.index, id);
+            self.id = id.to_string();
+        }
+    }
+}
+
+impl Drop for ScrollGuard<'_> {
+    fn drop(&mut self) {
+        clear_scroll(self.config, self.client, &self.id);
+        let _ = self.state.remove_scroll(&self.id);
+    }
+}
+
+/// A point in time opened by an export, closed (best effort) when dropped.
+struct PointInTime<'a> {
+    config: &'a BackupConfig,
+    client: &'a HttpClient,
+    api: &'a dyn EsApi,
+    id: String,
+}
+
+impl<'a> PointInTime<'a> {
+    fn open(
+        config: &'a BackupConfig,
+        client: &'a HttpClient,
+        api: &'a dyn EsApi,
+        index: &str,
+        frozen: bool
+    ) -> Result<Self, EsDumperError> {
+        let endpoint = api
+            .point_in_time_endpoint(index)
+            .ok_or_else(|| {
+                EsDumperError::Config(
+                    format!("The cluster has no points in time to export index '{}' with", index)
+                )
+            })?;
+        let url = |host: &str| {
+            let url = format!("{}/{}?keep_alive={}", host, endpoint, config.scroll_time);
+            if frozen { format!("{}&ignore_throttled=false", url) } else { url }
+        };
+        let response = config.hosts.send(|host| {
+            client
+                .post(url(host))
+                .timeout(Duration::from_secs(config.scroll_timeout_secs))
+        })?;
+        if !response.status().is_success() {
+            return Err(
+                EsDumperError::from_response(
+                    format!("Failed to open a point in time on index {}", index),
+                    response
+                )
+            );
+        }
+
+        // Elasticsearch names the ID `id` and OpenSearch `pit_id`
+        let response_json: Value = response.json()?;
+        let id = response_json["id"]
+            .as_str()
+            .or(response_json["pit_id"].as_str())
+            .ok_or_else(|| {
+                EsDumperError::UnexpectedResponse("No point in time ID returned".to_string())
+            })?;
+        Ok(PointInTime { config, client, api, id: id.to_string() })
+    }
+}
+
+impl Drop for PointInTime<'_> {
+    fn drop(&mut self) {
+        let (endpoint, body) = self.api.close_point_in_time(&self.id);
+        let _ = self.config.hosts.send(|host| {
+            self.client
+                .delete(format!("{}/{}", host, endpoint))
+                .json(&body)
+                .timeout(Duration::from_secs(self.config.scroll_timeout_secs))
+        });
+    }
+}
+
+/// Order of the pages of a `search_after` export, whose last sort values continue it.
+#[derive(Clone, Copy)]
+enum PageSort<'a> {
+    /// A field unique per document, from `sort_fields`.
+    Field(&'a str),
+    /// The document order of a point in time.
+    ShardDoc,
+}
+
+/// How the slices of an index page through its documents.
+#[derive(Clone, Copy)]
+enum Paging<'a> {
+    Scroll,
+    /// `search_after`, in a point in time when `point_in_time` is set.
+    SearchAfter {
+        sort: PageSort<'a>,
+        point_in_time: bool,
+    },
+}
+
+/// Limits of the export of an index, shared by its slices and partitions: the documents it
+/// may still write under `--max-docs` and when `--max-index-duration` stops it.
+struct ExportLimits {
+    remaining: AtomicU64,
+    deadline: Option<Instant>,
+}
+
+impl ExportLimits {
+    fn new(max_docs: Option<u64>, max_duration: Option<Duration>) -> Self {
+        ExportLimits {
+            remaining: AtomicU64::new(max_docs.unwrap_or(u64::MAX)),
+            deadline: max_duration.map(|max_duration| Instant::now() + max_duration),
+        }
+    }
+
+    /// Takes up to `wanted` documents off the cap, returning how many may be written.
+    fn take(&self, wanted: usize) -> usize {
+        let wanted = wanted as u64;
+        let previous = self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                Some(remaining.saturating_sub(wanted))
+            })
+            .unwrap_or_default();
+        previous.min(wanted) as usize
+    }
+
+    fn exhausted(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Where the scroll takes document contents from.
+enum ScrollSource {
+    /// `_source`, leaving out the excluded fields.
+    Source {
+        excludes: Vec<String>,
+    },
+    /// Doc values and stored fields of an index with `_source` disabled, as `(field, path)`
+    /// pairs: the field requested and the path it is written to in the rebuilt document.
+    DocValues {
+        docvalue_fields: Vec<(String, String)>,
+        stored_fields: Vec<String>,
+    },
+}
 
 pub fn run_backup(
     config: &BackupConfig,
-    log_file: &Arc<Mutex<File>>,
-    specific_index: Option<&str>
-) -> Result<(), Box<dyn std::error::Error>> {
+    log_file: &LogFile,
+    specific_index: Option<&str>,
+    job: &Job
+) -> Result<RunReport, EsDumperError> {
     log(log_file, "Starting Elasticsearch backup process")?;
+    if let Some(command) = &config.pre_backup_cmd {
+        run_hook(
+            "pre_backup_cmd",
+            command,
+            &hook_env(job, Path::new(&config.backup_dir)),
+            log_file
+        )?;
+    }
+
+    let mut report = RunReport::new("backup", timestamp());
+    report.run_id = Some(job.run_id.clone());
+    report.run_uuid = Some(log_file.run_uuid().to_string());
+    let deadline = config.max_duration.map(|max_duration| Instant::now() + max_duration);
+
+    let run_opaque_id = opaque_id(config, job, None);
+    log(log_file, &format!("Requests of this run carry X-Opaque-Id: {}", run_opaque_id))?;
+    let client = build_http_client(config, &run_opaque_id)?;
+    config.hosts.breaker().monitor(&client, log_file);
+    let cluster = get_cluster_info(config, &client, log_file)?;
+    log(
+        log_file,
+        &format!(
+            "Detected {} version: {} (cluster: {})",
+            cluster.api.distribution().as_str(),
+            cluster.version,
+            cluster.name
+        )
+    )?;
+
+    let state = if config.no_local_files {
+        StateStore::open_in_memory()?
+    } else {
+        StateStore::open(&config.backup_dir)?
+    };
 
-    let client = build_http_client(config)?;
-    let es_version = get_elasticsearch_version(&client, &config.host, log_file)?;
-    log(log_file, &format!("Detected Elasticsearch version: {}", es_version))?;
+    // Index directories and cluster-wide definitions, in a directory per cluster when namespaced
+    let target_dir = if config.namespace_by_cluster {
+        let target_dir = Path::new(&config.backup_dir).join(cluster_dir_name(&cluster.name));
+        log(log_file, &format!("Backing up into {}", target_dir.display()))?;
+        target_dir
+    } else {
+        PathBuf::from(&config.backup_dir)
+    };
 
-    let indices = match specific_index {
-        Some(index) => {
-            let url = format!("{}/{}/_count", config.host, index);
-            let response = client.get(&url).send()?;
+    let store_sizes = fetch_store_sizes(config, &client, log_file)?;
+    let closed = if cluster.api.supports_cat() {
+        fetch_closed_indices(config, &client)?
+    } else {
+        HashSet::new()
+    };
+    let alias = match specific_index {
+        Some(name) => resolve_alias(config, &client, name)?.map(|indices| (name, indices)),
+        None => None,
+    };
+    let indices = match (specific_index, &alias) {
+        (Some(name), Some((_, indices))) => {
+            log(
+                log_file,
+                &format!("{} is an alias of {}, backing them up", name, indices.join(", "))
+            )?;
+            indices.clone()
+        }
+        (Some(index), None) if closed.contains(index) => vec![index.to_string()],
+        (Some(index), None) => {
+            let response = config.hosts.send(|host| {
+                client
+                    .get(format!("{}/{}/_count", host, index))
+                    .timeout(Duration::from_secs(config.metadata_timeout_secs))
+            })?;
             if !response.status().is_success() {
-                let pb = ProgressBar::new_spinner();
-                pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
-                pb.set_message(format!("Index '{}' does not exist", index));
-                pb.finish_and_clear();
-                return Err(format!("Index '{}' does not exist", index).into());
+                return Err(
+                    EsDumperError::from_response(
+                        format!("Index '{}' does not exist", index),
+                        response
+                    )
+                );
             }
             vec![index.to_string()]
         }
-        None => fetch_indices(config, log_file, &es_version)?,
-    };
+        (None, _) => {
+            let (indices, skipped) = fetch_indices(
+                config,
+                &client,
+                log_file,
+                cluster.api.as_ref(),
+                store_sizes.as_ref()
+            )?;
+            report.skipped = skipped;
+            indices
+        }
+    };
+    let alias = alias.map(|(name, _)| name);
+
+    let resuming = config.resume && specific_index.is_none();
+    let resumed = if resuming {
+        state.checkpointed(&cluster.uuid, "backup", CHECKPOINT_DONE)?
+    } else {
+        Vec::new()
+    };
+    // Interrupted exports are replaced like those of a failed attempt
+    let interrupted = if resuming {
+        state.checkpointed(&cluster.uuid, "backup", CHECKPOINT_INTERRUPTED)?
+    } else {
+        Vec::new()
+    };
+    let mut indices = if resuming {
+        let mut pending = state.checkpointed(&cluster.uuid, "backup", CHECKPOINT_DEFERRED)?;
+        pending.extend(state.checkpointed(&cluster.uuid, "backup", CHECKPOINT_PARTIAL)?);
+        pending.extend(interrupted.iter().cloned());
+        log(
+            log_file,
+            &format!("Resuming {} deferred or partially exported indices", pending.len())
+        )?;
+        indices
+            .into_iter()
+            .filter(|index| pending.contains(index))
+            .collect()
+    } else {
+        indices
+    };
+
+    if config.closed_indices == ClosedIndexPolicy::Skip {
+        let closed_skipped: Vec<String> = indices
+            .iter()
+            .filter(|index| closed.contains(*index))
+            .cloned()
+            .collect();
+        if !closed_skipped.is_empty() {
+            log(
+                log_file,
+                &format!(
+                    "Skipping {} closed indices (set closed_indices = \"open\" to back them up): {}",
+                    closed_skipped.len(),
+                    closed_skipped.join(", ")
+                )
+            )?;
+            indices.retain(|index| !closed.contains(index));
+            report.skipped.extend(
+                closed_skipped
+                    .iter()
+                    .map(|index| SkippedIndex::new(index, "closed, with closed_indices = \"skip\""))
+            );
+        }
+    }
+
+    if indices.is_empty() {
+        log(log_file, "No indices found to backup")?;
+        log_skipped(&report, log_file)?;
+        post_backup_hook(config, job, &target_dir, &report, false, log_file)?;
+        return Ok(report);
+    }
+
+    log(log_file, &format!("Found {} indices to backup", indices.len()))?;
+    if let Some(store_sizes) = &store_sizes {
+        // Largest first, so the giant indices don't start last and leave a long single-index tail
+        indices.sort_by_key(|index| {
+            Reverse(store_sizes.get(index).map_or(0, |size| size.primaries))
+        });
+        if !config.no_local_files && !config.metadata_only {
+            check_disk_space(config, &indices, store_sizes, log_file)?;
+        }
+    }
+    if !config.no_local_files {
+        fs::create_dir_all(&target_dir)?;
+    }
+    backup_templates(config, &client, &target_dir, log_file)?;
+    backup_transforms(config, &client, &target_dir, &indices, log_file)?;
+    if config.include_watches {
+        backup_watches(config, &client, &target_dir, log_file)?;
+    }
+    let frozen = fetch_frozen_indices(config, &client)?;
+    let prefetched = if indices.len() > 1 {
+        prefetch_metadata(config, &client, &indices, log_file)?
+    } else {
+        HashMap::new()
+    };
+    job.set_total_indices(indices.len());
+
+    // Documents already backed up by the interrupted run count towards a resumed run
+    let resumed_docs: u64 = resumed
+        .iter()
+        .filter_map(|index| state.get(&cluster.uuid, "backup", index).ok().flatten())
+        .map(|previous| previous.last_docs)
+        .sum();
+    let doc_counts = if config.metadata_only || !cluster.api.supports_cat() {
+        HashMap::new()
+    } else {
+        fetch_doc_counts(config, &client)?
+    };
+    let observer = job.observer();
+    observer.on_run_start(
+        &(RunStart {
+            operation: "backup",
+            indices: indices.len(),
+            expected_docs: Some(
+                indices
+                    .iter()
+                    .filter_map(|index| doc_counts.get(index))
+                    .sum()
+            ),
+            completed_docs: resumed_docs,
+            expected_bytes: store_sizes.as_ref().filter(|_| !config.metadata_only).map(|sizes| {
+                let store_bytes: u64 = indices
+                    .iter()
+                    .filter_map(|index| sizes.get(index))
+                    .map(|size| size.primaries)
+                    .sum();
+                ((store_bytes as f64) * config.size_factor) as u64
+            }),
+        })
+    );
+
+    let start_time = Instant::now();
+
+    let index_reports = Mutex::new(Vec::new());
+    let deferred = Mutex::new(Vec::new());
+    let stopped = Mutex::new(Vec::new());
+    let skipped = Mutex::new(Vec::new());
+    // Directories whose free space dropped below the floor; their indices are not started
+    let low_space: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    run_queue(&indices, config.max_parallel_indices, |index| {
+        if job.is_cancelled() {
+            return;
+        }
+        let log_file = &log_file.for_index(index);
+
+        let data_dir = data_root(config, index);
+        let low = low_space.lock().unwrap().contains(&data_dir);
+        if low || !has_free_space(config, data_dir) {
+            let mut low_space = low_space.lock().unwrap();
+            if !low_space.contains(&data_dir) {
+                low_space.push(data_dir);
+                let _ = log(
+                    log_file,
+                    &format!(
+                        "Free space in {} dropped below {} MB, not starting further indices there",
+                        data_dir,
+                        config.min_free_space_mb
+                    )
+                );
+            }
+            deferred.lock().unwrap().push(index.clone());
+            observer.on_index_deferred(
+                &(IndexDeferred { index, expected_docs: doc_counts.get(index).copied() })
+            );
+            return;
+        }
+
+        let previous = state.get(&cluster.uuid, "backup", index).ok().flatten();
+        if let Some(deadline) = deadline {
+            // Don't start an index that is expected to run past the deadline
+            let expected = Duration::from_secs_f64(
+                previous.as_ref().map_or(0.0, |previous| previous.last_duration_secs)
+            );
+            if Instant::now() + expected >= deadline {
+                let _ = log(
+                    log_file,
+                    &format!("Deadline reached, deferring backup of index: {}", index)
+                );
+                deferred.lock().unwrap().push(index.clone());
+                observer.on_index_deferred(
+                    &(IndexDeferred { index, expected_docs: doc_counts.get(index).copied() })
+                );
+                return;
+            }
+        }
+
+        let active = job.index_started();
+        if
+            let Err(e) = log(
+                log_file,
+                &format!("Starting backup for index: {} (active indices: {})", index, active)
+            )
+        {
+            observer.on_message(&format!("Failed to log for index {}: {}", index, e));
+        }
+
+        observer.on_index_start(
+            &(IndexStart { index, expected_docs: doc_counts.get(index).copied() })
+        );
+
+        if let Some(previous) = &previous {
+            let _ = log(
+                log_file,
+                &format!("Previous backup of index {} {}", index, previous.describe())
+            );
+        }
+
+        let index_start = Instant::now();
+        // Appended or streamed documents cannot be taken back, so those runs are not retried
+        let retries = if config.existing == ExistingPolicy::Append || config.no_local_files {
+            0
+        } else {
+            config.index_retries
+        };
+        let mut attempts = 1;
+        let mut backup = || {
+            let (result, made) = retry_transient(
+                &format!("backup of index {}", index),
+                retries,
+                config.index_retry_backoff,
+                log_file,
+                job,
+                |attempt| {
+                    catch_panic(&format!("Backup of index {}", index), || {
+                        backup_index(
+                            config,
+                            &client,
+                            &target_dir,
+                            index,
+                            log_file,
+                            &cluster,
+                            &state,
+                            &prefetched,
+                            alias,
+                            frozen.contains(index),
+                            attempt > 1 || interrupted.contains(index),
+                            job
+                        )
+                    })
+                }
+            );
+            attempts = made;
+            result
+        };
+        // Closed indices are opened for their backup and closed again whatever its outcome
+        let result = if closed.contains(index) {
+            let _ = log(log_file, &format!("Opening closed index {} for its backup", index));
+            set_index_open(config, &client, index, true).and_then(|()| {
+                let result = backup();
+                match set_index_open(config, &client, index, false) {
+                    Ok(()) => {
+                        let _ = log(log_file, &format!("Closed index {} again", index));
+                    }
+                    Err(e) => {
+                        let _ = log(
+                            log_file,
+                            &format!("Failed to close index {} again: {}", index, e)
+                        );
+                    }
+                }
+                result
+            })
+        } else {
+            backup()
+        };
+        let index_duration = index_start.elapsed().as_secs_f64();
+        let skip_reason = result.as_ref().ok().and_then(|outcome| outcome.skipped.clone());
+        let index_stopped = result.as_ref().is_ok_and(|outcome| outcome.interrupted);
+        let mut index_report = match result {
+            Ok(outcome) => {
+                let mut index_report = IndexReport::success(
+                    index,
+                    outcome.docs,
+                    outcome.bytes,
+                    index_duration
+                );
+                index_report.warnings = outcome.warnings;
+                index_report
+            }
+            Err(e) => {
+                let _ = log(log_file, &format!("Error backing up index {}: {}", index, e));
+                observer.on_index_error(&(IndexError { index, error: &e }));
+                IndexReport::failure(index, e.to_string(), index_duration)
+            }
+        };
+        index_report.record_attempts(attempts);
+        if
+            config.self_test &&
+            !config.no_local_files &&
+            index_report.success &&
+            skip_reason.is_none() &&
+            !index_stopped
+        {
+            // The data file must be complete before it is restored
+            let tested = match config.compressor.wait_for(std::slice::from_ref(index)).pop() {
+                Some((_, error)) => Err(EsDumperError::Io(io::Error::other(error))),
+                None =>
+                    client
+                        .with_opaque_id(&opaque_id(config, job, Some(index)))
+                        .and_then(|client| {
+                            restore::self_test(
+                                config,
+                                &client,
+                                &target_dir.join(index),
+                                index,
+                                // Appended documents join those of earlier runs in the data file
+                                Some(index_report.docs).filter(|_| {
+                                    config.existing != ExistingPolicy::Append
+                                }),
+                                cluster.api.as_ref(),
+                                log_file,
+                                job
+                            )
+                        }),
+            };
+            match tested {
+                Ok(warnings) => index_report.warnings.extend(warnings),
+                Err(e) => {
+                    let _ = log(log_file, &format!("Self-test of index {} failed: {}", index, e));
+                    observer.on_index_error(&(IndexError { index, error: &e }));
+                    index_report.success = false;
+                    index_report.error = Some(format!("Self-test failed: {}", e));
+                }
+            }
+        }
+        if let Some(storage) = job.storage() {
+            let complete = index_report.success && skip_reason.is_none() && !index_stopped;
+            if complete && !config.no_local_files {
+                let index_dir = target_dir.join(index);
+                // The data file must be complete before it is stored
+                let stored = match config.compressor.wait_for(std::slice::from_ref(index)).pop() {
+                    Some((_, error)) => Err(EsDumperError::Io(io::Error::other(error))),
+                    None => storage.store_index(index, &index_dir),
+                };
+                match stored {
+                    Ok(()) => {
+                        let _ = log(
+                            log_file,
+                            &format!("Stored index {} in {}", index, storage.name())
+                        );
+                    }
+                    Err(e) => {
+                        let error = format!("Failed to store index in {}: {}", storage.name(), e);
+                        let _ = log(log_file, &format!("Index {}: {}", index, error));
+                        observer.on_index_error(&(IndexError { index, error: &e }));
+                        index_report.success = false;
+                        index_report.error = Some(error);
+                    }
+                }
+            }
+        }
+        if let Some(command) = &config.post_index_cmd {
+            let status = if index_stopped {
+                "interrupted"
+            } else if skip_reason.is_some() {
+                "skipped"
+            } else if index_report.success {
+                "success"
+            } else {
+                "failed"
+            };
+            let mut env = hook_env(job, &target_dir);
+            env.extend([
+                ("INDEX", index.clone()),
+                ("INDEX_DIR", target_dir.join(index).display().to_string()),
+                ("STATUS", status.to_string()),
+                ("DOCS", index_report.docs.to_string()),
+                ("BYTES", index_report.bytes.to_string()),
+                ("ERROR", index_report.error.clone().unwrap_or_default()),
+            ]);
+            if let Err(e) = run_hook("post_index_cmd", command, &env, log_file) {
+                let _ = log(log_file, &format!("Index {}: {}", index, e));
+                index_report.warnings.push(e.to_string());
+            }
+        }
+        if index_stopped {
+            // Neither a success nor a failure, so the state store keeps the previous run
+            let _ = log(
+                log_file,
+                &format!("Deferring the rest of index {} to a --resume run", index)
+            );
+            let _ = state.set_checkpoint(
+                &cluster.uuid,
+                "backup",
+                index,
+                Some(CHECKPOINT_INTERRUPTED)
+            );
+            stopped.lock().unwrap().push(index.clone());
+            observer.on_index_deferred(
+                &(IndexDeferred { index, expected_docs: doc_counts.get(index).copied() })
+            );
+        } else {
+            if index_report.success {
+                observer.on_index_done(&index_report);
+            }
+            if let Err(e) = state.record_index(&cluster.uuid, "backup", &index_report) {
+                let _ = log(log_file, &format!("Failed to record state for {}: {}", index, e));
+            }
+            if index_report.success {
+                let _ = state.set_checkpoint(&cluster.uuid, "backup", index, None);
+            } else if
+                IndexManifest::load(&target_dir.join(index), index)
+                    .ok()
+                    .flatten()
+                    .is_some_and(|manifest| manifest.has_unfinished_partitions())
+            {
+                let _ = log(
+                    log_file,
+                    &format!(
+                        "Index {} was exported in part, run with --resume to export its remaining partitions",
+                        index
+                    )
+                );
+                let _ = state.set_checkpoint(
+                    &cluster.uuid,
+                    "backup",
+                    index,
+                    Some(CHECKPOINT_PARTIAL)
+                );
+            }
+            match skip_reason {
+                Some(reason) => skipped.lock().unwrap().push(SkippedIndex::new(index, reason)),
+                None => index_reports.lock().unwrap().push(index_report),
+            }
+        }
+
+        let active = job.index_done();
+        if
+            let Err(e) = log(
+                log_file,
+                &format!("Completed backup for index: {} (active indices: {})", index, active)
+            )
+        {
+            observer.on_message(&format!("Failed to log for index {}: {}", index, e));
+        }
+    });
+
+    // Data files still being compressed in the background are part of the run
+    let compression_failures = config.compressor.wait_for(&indices);
+
+    let duration = start_time.elapsed();
+    log(
+        log_file,
+        &format!("Backup completed successfully in {:.2} seconds", duration.as_secs_f64())
+    )?;
+
+    report.deferred = deferred.into_inner().unwrap();
+    for index in &report.deferred {
+        state.set_checkpoint(&cluster.uuid, "backup", index, Some(CHECKPOINT_DEFERRED))?;
+    }
+    report.deferred.extend(stopped.into_inner().unwrap());
+    report.deferred.sort();
+    let mut index_reports = index_reports.into_inner().unwrap();
+    for (index, error) in compression_failures {
+        if let Some(index_report) = index_reports.iter_mut().find(|r| r.index == index) {
+            index_report.success = false;
+            index_report.error = Some(error);
+        }
+    }
+    if !report.deferred.is_empty() {
+        for index_report in index_reports.iter().filter(|r| r.success) {
+            let index = &index_report.index;
+            state.set_checkpoint(&cluster.uuid, "backup", index, Some(CHECKPOINT_DONE))?;
+        }
+    } else {
+        for index in &resumed {
+            state.set_checkpoint(&cluster.uuid, "backup", index, None)?;
+        }
+    }
+
+    report.duration_secs = duration.as_secs_f64();
+    report.indices = index_reports;
+    report.indices.sort_by(|a, b| a.index.cmp(&b.index));
+    report.skipped.extend(skipped.into_inner().unwrap());
+    report.skipped.sort_by(|a, b| a.index.cmp(&b.index));
+    report.latency = Some(job.latencies().summary());
+    let http = client.stats();
+    log(log_file, &format!("HTTP: {}", http.line()))?;
+    report.http = Some(http);
+    log_skipped(&report, log_file)?;
+
+    let low_space = low_space.into_inner().unwrap();
+    post_backup_hook(config, job, &target_dir, &report, !low_space.is_empty(), log_file)?;
+    observer.on_run_complete(&report);
+
+    if !low_space.is_empty() {
+        return Err(
+            EsDumperError::storage_full(
+                format!(
+                    "Free space in {} dropped below {} MB; {} indices were not backed up",
+                    low_space.join(", "),
+                    config.min_free_space_mb,
+                    report.deferred.len()
+                )
+            )
+        );
+    }
+    if !report.deferred.is_empty() {
+        log(
+            log_file,
+            &format!(
+                "Deadline reached: {} indices deferred, run with --resume to continue",
+                report.deferred.len()
+            )
+        )?;
+    }
+    Ok(report)
+}
+
+/// Environment of the backup hook commands that every one of them gets.
+fn hook_env(job: &Job, backup_dir: &Path) -> Vec<(&'static str, String)> {
+    vec![
+        ("OPERATION", "backup".to_string()),
+        ("RUN_ID", job.run_id.clone()),
+        ("BACKUP_DIR", backup_dir.display().to_string())
+    ]
+}
+
+/// Runs `post_backup_cmd`, if set. The data is backed up whatever the hook does, so its failure
+/// is only reported.
+fn post_backup_hook(
+    config: &BackupConfig,
+    job: &Job,
+    target_dir: &Path,
+    report: &RunReport,
+    low_space: bool,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let Some(command) = &config.post_backup_cmd else {
+        return Ok(());
+    };
+    let complete = report.failed_count() == 0 && report.deferred.is_empty() && !low_space;
+    let mut env = hook_env(job, target_dir);
+    env.extend([
+        ("STATUS", (if complete { "success" } else { "partial" }).to_string()),
+        ("INDICES", report.indices.len().to_string()),
+        ("FAILED", report.failed_count().to_string()),
+        ("DEFERRED", report.deferred.len().to_string()),
+        ("DOCS", report.total_docs().to_string()),
+        ("BYTES", report.total_bytes().to_string()),
+        ("DURATION_SECS", format!("{:.2}", report.duration_secs)),
+    ]);
+    if let Err(e) = run_hook("post_backup_cmd", command, &env, log_file) {
+        log(log_file, &e.to_string())?;
+        job.observer().on_message(&format!("Warning: {}", e));
+    }
+    Ok(())
+}
+
+/// Logs the indices left out of a run, a line per reason, so none goes unnoticed.
+fn log_skipped(report: &RunReport, log_file: &LogFile) -> Result<(), EsDumperError> {
+    for (reason, indices) in report.skipped_by_reason() {
+        log(
+            log_file,
+            &format!("Skipped {} indices ({}): {}", indices.len(), reason, indices.join(", "))
+        )?;
+    }
+    Ok(())
+}
+
+/// Backs up an index. A `retry` after a transient error replaces the data files the failed
+/// attempt left behind, keeping the partitions it completed.
+#[allow(clippy::too_many_arguments)]
+fn backup_index(
+    config: &BackupConfig,
+    run_client: &HttpClient,
+    target_dir: &Path,
+    index: &str,
+    log_file: &LogFile,
+    cluster: &ClusterInfo,
+    state: &StateStore,
+    prefetched: &PrefetchedMetadata,
+    alias: Option<&str>,
+    frozen: bool,
+    retry: bool,
+    job: &Job
+) -> Result<BackupOutcome, EsDumperError> {
+    log(log_file, &format!("Processing index: {}", index))?;
+
+    let client = run_client.with_opaque_id(&opaque_id(config, job, Some(index)))?;
+    if
+        config.empty_indices == EmptyIndexPolicy::Skip &&
+        count_docs(config, &client, index, frozen)? == 0
+    {
+        log(log_file, &format!("Index {} is empty, skipping it", index))?;
+        return Ok(BackupOutcome {
+            docs: 0,
+            bytes: 0,
+            warnings: Vec::new(),
+            skipped: Some("empty, with empty_indices = \"skip\"".to_string()),
+            interrupted: false,
+        });
+    }
+
+    let index_dir = target_dir.join(index);
+    if !config.no_local_files {
+        create_index_dir(config, target_dir, index, log_file)?;
+    }
+
+    // The manifest of a partitioned export that `--resume` or a retry continues
+    let continues = config.resume || retry;
+    let unfinished = if continues && !config.no_local_files && !config.metadata_only {
+        IndexManifest::load(&index_dir, index)?.filter(IndexManifest::has_unfinished_partitions)
+    } else {
+        None
+    };
+
+    if !config.no_local_files && !config.metadata_only && unfinished.is_none() {
+        let existing = existing_data_files(&index_dir, index);
+        let policy = if retry { ExistingPolicy::Overwrite } else { config.existing };
+        if !existing.is_empty() {
+            match policy {
+                ExistingPolicy::Fail => {
+                    return Err(
+                        EsDumperError::Config(
+                            format!(
+                                "A backup of index '{}' already exists in {} (use --overwrite, --skip-existing or --append)",
+                                index,
+                                index_dir.display()
+                            )
+                        )
+                    );
+                }
+                ExistingPolicy::SkipExisting => {
+                    log(log_file, &format!("Backup of index {} already exists, skipping", index))?;
+                    return Ok(BackupOutcome {
+                        docs: 0,
+                        bytes: dir_size(&index_dir),
+                        warnings: Vec::new(),
+                        skipped: Some("already backed up, with --skip-existing".to_string()),
+                        interrupted: false,
+                    });
+                }
+                ExistingPolicy::Overwrite => {
+                    for file in &existing {
+                        fs::remove_file(file)?;
+                    }
+                }
+                ExistingPolicy::Append => {
+                    if !config.data_format.supports_append() {
+                        return Err(
+                            EsDumperError::Config(
+                                format!(
+                                    "Cannot append to the backup of index '{}': {:?} data files do not support appending",
+                                    index,
+                                    config.data_format
+                                )
+                            )
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let mapping = backup_mapping(config, &client, index, &index_dir, prefetched, log_file)?;
+    let settings = backup_settings(config, &client, index, &index_dir, prefetched, log_file)?;
+    if config.include_field_caps {
+        backup_field_caps(config, &client, index, &index_dir, &mapping, log_file)?;
+    }
+
+    let mut manifest = IndexManifest {
+        index: index.to_string(),
+        created_at: timestamp(),
+        run_id: Some(job.run_id.clone()),
+        run_uuid: Some(log_file.run_uuid().to_string()),
+        cluster_name: Some(cluster.name.clone()),
+        cluster_uuid: Some(cluster.uuid.clone()),
+        alias: alias.map(str::to_string),
+        ..Default::default()
+    };
+    if config.metadata_only {
+        manifest.source = DocumentSource::None;
+        manifest.data_skipped = Some("metadata-only backup".to_string());
+        save_manifest(config, &manifest, &index_dir)?;
+        log(log_file, &format!("Metadata backup completed for index: {}", index))?;
+        return Ok(BackupOutcome {
+            docs: 0,
+            bytes: dir_size(&index_dir),
+            warnings: Vec::new(),
+            skipped: None,
+            interrupted: false,
+        });
+    }
+
+    let mut warnings = Vec::new();
+    let source = if source_disabled(&mapping) {
+        let (docvalue_fields, stored_fields, lost_fields) = doc_value_fields(&mapping);
+        let warning = if config.doc_values_fallback {
+            manifest.source = DocumentSource::DocValues;
+            manifest.limitations.push(
+                "_source is disabled: documents were rebuilt from doc values and stored fields, so multi-valued fields may be sorted and deduplicated, values are in their indexed form (e.g. normalized keywords, formatted dates) and keyword sub-fields stand in for their text fields".to_string()
+            );
+            if !lost_fields.is_empty() {
+                manifest.limitations.push(
+                    format!(
+                        "Fields with neither doc values nor stored values were not exported: {}",
+                        lost_fields.join(", ")
+                    )
+                );
+            }
+            format!(
+                "Index {} has _source disabled, rebuilding documents from doc values and stored fields ({} fields lost)",
+                index,
+                lost_fields.len()
+            )
+        } else {
+            manifest.source = DocumentSource::None;
+            manifest.limitations.push(
+                "_source is disabled: only document IDs and metadata were backed up".to_string()
+            );
+            format!(
+                "Index {} has _source disabled, its backup holds no document contents (set doc_values_fallback = true to rebuild them from doc values)",
+                index
+            )
+        };
+        log(log_file, &format!("WARNING: {}", warning))?;
+        warnings.push(warning);
+
+        if config.doc_values_fallback {
+            ScrollSource::DocValues { docvalue_fields, stored_fields }
+        } else {
+            ScrollSource::Source { excludes: Vec::new() }
+        }
+    } else {
+        let excludes = excluded_fields(config, &mapping);
+        if !excludes.is_empty() {
+            log(
+                log_file,
+                &format!(
+                    "Excluding fields from _source for index {}: {}",
+                    index,
+                    excludes.join(", ")
+                )
+            )?;
+            manifest.limitations.push(
+                format!("Fields excluded from _source: {}", excludes.join(", "))
+            );
+        }
+        ScrollSource::Source { excludes }
+    };
+    if !job.transforms().is_empty() {
+        let names: Vec<&str> = job
+            .transforms()
+            .iter()
+            .map(|transform| transform.name())
+            .collect();
+        manifest.limitations.push(
+            format!("Documents were changed by document transforms: {}", names.join(", "))
+        );
+    }
+
+    let data_start = Instant::now();
+    let mut meta = fetch_index_meta(config, &client, index, frozen, &settings, log_file)?;
+    let exported = backup_data(
+        config,
+        &client,
+        index,
+        &index_dir,
+        log_file,
+        cluster,
+        state,
+        &source,
+        &mapping,
+        &mut manifest,
+        unfinished,
+        frozen,
+        job
+    )?;
+    let docs = match exported {
+        Some(docs) => docs,
+        None => {
+            manifest.data_skipped = Some("the index was empty".to_string());
+            0
+        }
+    };
+    if let Some(max_docs) = manifest.max_docs {
+        warnings.push(format!("Export stopped at the --max-docs cap of {} documents", max_docs));
+    }
+    meta.doc_count = docs;
+    meta.duration_secs = data_start.elapsed().as_secs_f64();
+    save_manifest(config, &manifest, &index_dir)?;
+    if manifest.interrupted.is_some() {
+        return Ok(BackupOutcome {
+            docs,
+            bytes: dir_size(&index_dir),
+            warnings,
+            skipped: None,
+            interrupted: true,
+        });
+    }
+    if config.no_local_files {
+        let record = serde_json::json!({ "_index": index, "_index_meta": meta });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        meta.save(&index_dir, config.durability == Durability::Fsync)?;
+    }
+    log(log_file, &format!("Backup completed for index: {}", index))?;
+    Ok(BackupOutcome {
+        docs,
+        bytes: dir_size(&index_dir),
+        warnings,
+        skipped: None,
+        interrupted: false,
+    })
+}
+
+/// Shard count, highest sequence number and timestamp range of an index, read before its
+/// documents are exported. Statistics the cluster cannot provide are left unset.
+fn fetch_index_meta(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    frozen: bool,
+    settings: &Value,
+    log_file: &LogFile
+) -> Result<IndexMeta, EsDumperError> {
+    let mut meta = IndexMeta {
+        index: index.to_string(),
+        shards: settings[index]["settings"]["index"]["number_of_shards"]
+            .as_str()
+            .and_then(|shards| shards.parse().ok()),
+        timestamp_field: config.timestamp_field.clone(),
+        ..Default::default()
+    };
+
+    let mut aggs = serde_json::json!({ "max_seq_no": { "max": { "field": "_seq_no" } } });
+    if let Some(field) = &config.timestamp_field {
+        aggs["min_timestamp"] = serde_json::json!({ "min": { "field": field } });
+        aggs["max_timestamp"] = serde_json::json!({ "max": { "field": field } });
+    }
+    let response = config.hosts.send(|host| {
+        client
+            .post(search_url(host, index, "_search", frozen))
+            .json(&serde_json::json!({ "size": 0, "track_total_hits": false, "aggs": aggs }))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(
+            log_file,
+            &format!(
+                "Could not read sequence numbers and timestamps of index {} ({}), leaving them out of its metadata",
+                index,
+                response.status()
+            )
+        )?;
+        return Ok(meta);
+    }
+
+    let json: Value = response.json()?;
+    let aggregations = &json["aggregations"];
+    meta.max_seq_no = aggregations["max_seq_no"]["value"].as_f64().map(|value| value as u64);
+    // Dates come back as epoch milliseconds with a formatted `value_as_string`
+    let bound = |name: &str| {
+        let aggregation = &aggregations[name];
+        aggregation
+            .get("value_as_string")
+            .or_else(|| aggregation.get("value"))
+            .filter(|value| !value.is_null())
+            .cloned()
+    };
+    meta.min_timestamp = bound("min_timestamp");
+    meta.max_timestamp = bound("max_timestamp");
+    Ok(meta)
+}
+
+fn save_manifest(
+    config: &BackupConfig,
+    manifest: &IndexManifest,
+    index_dir: &Path
+) -> Result<(), EsDumperError> {
+    if config.no_local_files {
+        let record = serde_json::json!({ "_index": manifest.index, "_manifest": manifest });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+        Ok(())
+    } else {
+        manifest.save(index_dir, config.durability == Durability::Fsync)
+    }
+}
+
+/// Exports the documents of an index, in one data file or, for indices with a partition field,
+/// in a data file per partition recorded in `manifest`. Partitions that `unfinished`, the
+/// manifest of an interrupted export, records as complete are kept.
+#[allow(clippy::too_many_arguments)]
+fn backup_data(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    index_dir: &Path,
+    log_file: &LogFile,
+    cluster: &ClusterInfo,
+    state: &StateStore,
+    source: &ScrollSource,
+    mapping: &Value,
+    manifest: &mut IndexManifest,
+    unfinished: Option<IndexManifest>,
+    frozen: bool,
+    job: &Job
+) -> Result<Option<u64>, EsDumperError> {
+    let doc_count = count_docs(config, client, index, frozen)?;
+    if doc_count == 0 && config.empty_indices != EmptyIndexPolicy::Full {
+        log(log_file, &format!("Index {} is empty, skipping data backup", index))?;
+        return Ok(None);
+    }
+
+    let effective_scroll_size = cluster.api.scroll_size(config.scroll_size);
+
+    // Without scroll contexts or points in time, pages are sorted on a field unique per
+    // document; sorting on `_id` needs fielddata, which recent clusters disable
+    let sort_field = config.sort_fields.get(index).map(String::as_str);
+    let paging = if !cluster.api.supports_scroll() {
+        let field = sort_field.ok_or_else(|| {
+            EsDumperError::Config(
+                format!(
+                    "Index '{}' needs a [backup.sort_fields] entry naming a field unique per document, as the cluster keeps no scroll contexts to export it with",
+                    index
+                )
+            )
+        })?;
+        Paging::SearchAfter { sort: PageSort::Field(field), point_in_time: false }
+    } else if config.max_scroll_restarts == 0 {
+        Paging::Scroll
+    } else {
+        // An expired search context is resumed after the last hit written, which takes a
+        // point in time sorted on a unique field or, failing that, on its own document order
+        let has_pit = cluster.api.point_in_time_endpoint(index).is_some();
+        match sort_field {
+            Some(field) if has_pit => {
+                Paging::SearchAfter { sort: PageSort::Field(field), point_in_time: true }
+            }
+            None if has_pit && cluster.api.supports_shard_doc() => {
+                Paging::SearchAfter { sort: PageSort::ShardDoc, point_in_time: true }
+            }
+            _ => {
+                log(
+                    log_file,
+                    &format!(
+                        "Exporting index {} with a scroll that fails the index if it expires: resuming needs a point in time (Elasticsearch 7.10 or OpenSearch 2.4 and later), sorted on a [backup.sort_fields] entry before Elasticsearch 7.12 and on OpenSearch",
+                        index
+                    )
+                )?;
+                Paging::Scroll
+            }
+        }
+    };
+
+    // Slices need a scroll context, so clusters without one export with a single worker
+    let requested_slices = if cluster.api.supports_scroll() {
+        resolve_slice_count(config, client, index, log_file)?
+    } else {
+        1
+    };
+    // Held until the export is done, so the slices go back to the run's budget afterwards
+    let slice_grant = config.slice_scheduler.acquire(requested_slices);
+    let slice_count = slice_grant.slices();
+    if slice_count < requested_slices {
+        log(
+            log_file,
+            &format!(
+                "Running {} of {} slices of index {} to share the slice budget with the other indices",
+                slice_count,
+                requested_slices,
+                index
+            )
+        )?;
+    }
+
+    log(
+        log_file,
+        &format!(
+            "Starting data export for index: {} ({} documents, scroll_size: {}, slices: {})",
+            index,
+            doc_count,
+            effective_scroll_size,
+            slice_count
+        )
+    )?;
+
+    let partition_field = config.partition_fields
+        .get(index)
+        .filter(|_| config.existing != ExistingPolicy::Append);
+    if let Some(field) = partition_field {
+        let is_date = partition_field_is_date(index, field, mapping)?;
+        match unfinished.filter(|previous| previous.partition_field.as_ref() == Some(field)) {
+            Some(previous) => {
+                let done = previous.partitions
+                    .iter()
+                    .filter(|partition| partition.docs.is_some())
+                    .count();
+                log(
+                    log_file,
+                    &format!(
+                        "Resuming partitioned export of index {}: {} of {} partitions already written",
+                        index,
+                        done,
+                        previous.partitions.len()
+                    )
+                )?;
+                manifest.partitions = previous.partitions;
+            }
+            None if doc_count > config.partition_docs => {
+                manifest.partitions = plan_partitions(
+                    config,
+                    client,
+                    index,
+                    frozen,
+                    field,
+                    is_date,
+                    doc_count,
+                    log_file
+                )?;
+            }
+            None => {}
+        }
+        if !manifest.partitions.is_empty() {
+            manifest.partition_field = Some(field.clone());
+            if !config.no_local_files {
+                save_manifest(config, manifest, index_dir)?;
+            }
+        }
+    }
+
+    // Each export writes the documents matching its query to its own data file
+    let exports: Vec<(Option<usize>, PathBuf, Value)> = match &manifest.partition_field {
+        Some(field) => {
+            let is_date = partition_field_is_date(index, field, mapping)?;
+            manifest.partitions
+                .iter()
+                .enumerate()
+                .filter(|(_, partition)| partition.docs.is_none())
+                .map(|(number, partition)| {
+                    (
+                        Some(number),
+                        index_dir.join(&partition.file),
+                        partition_query(field, is_date, partition),
+                    )
+                })
+                .collect()
+        }
+        None => {
+            let data_file = index_dir.join(config.data_format.data_file_name(index));
+            vec![(None, data_file, serde_json::json!({ "match_all": {} }))]
+        }
+    };
+
+    let start_time = Instant::now();
+    let mut total_docs: u64 = manifest.partitions
+        .iter()
+        .filter_map(|partition| partition.docs)
+        .sum();
+    // Partitions completed by an interrupted run count towards the cap
+    let limits = ExportLimits::new(
+        config.max_docs.map(|max_docs| max_docs.saturating_sub(total_docs)),
+        config.max_index_duration
+    );
+
+    let mut compression = CompressionEstimate::default();
+    // Bytes written to the data files of the index, for its progress bar
+    let written = Arc::new(AtomicU64::new(0));
+    let ids_file = index_dir.join(format!("{}_ids.bloom", index));
+    let keep_ids = config.existing == ExistingPolicy::Append || total_docs > 0;
+    let mut ids = if config.no_local_files {
+        None
+    } else {
+        match BloomFilter::load(&ids_file) {
+            Ok(ids) if keep_ids => Some(ids),
+            _ => Some(BloomFilter::new(doc_count, ID_FILTER_FALSE_POSITIVE_RATE)),
+        }
+    };
+
+    // With the `compression` feature, data files are gzipped as they are written or, in the
+    // background mode, handed to the compression workers once complete
+    let compress = cfg!(feature = "compression") && !config.no_local_files;
+    let inline = compress && config.compression_mode == CompressionMode::Inline;
+
+    for (partition, data_file, query) in exports {
+        if let Some(number) = partition {
+            log(
+                log_file,
+                &format!(
+                    "Exporting partition {} of {} of index {}",
+                    number + 1,
+                    manifest.partitions.len(),
+                    index
+                )
+            )?;
+        }
+
+        // Without local files, documents (tagged with their index) are streamed to stdout as NDJSON
+        let written_file = if inline {
+            PathBuf::from(format!("{}.gz", data_file.display()))
+        } else {
+            data_file.clone()
+        };
+        // A gzipped file is written under a temporary name and renamed once its stream is
+        // complete, so an export that is killed never leaves half a gzip stream behind
+        let open_file = if inline && config.existing != ExistingPolicy::Append {
+            partial_file(&written_file)
+        } else {
+            written_file.clone()
+        };
+        let mut writer = if config.no_local_files {
+            DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson)
+        } else {
+            // Appending to a gzipped file adds a gzip member, which decompresses as one file
+            let file = if config.existing == ExistingPolicy::Append {
+                OpenOptions::new().create(true).append(true).open(&written_file)?
+            } else {
+                // Restores prefer an uncompressed file, so one left over would shadow the new data
+                if inline && data_file.exists() {
+                    fs::remove_file(&data_file)?;
+                }
+                File::create(&open_file)?
+            };
+            let mut writer = DataWriter::new(
+                Box::new(BufWriter::with_capacity(config.buffer_size, file)),
+                config.data_format
+            )
+                .count_written(Arc::clone(&written))
+                .flush_pages(config.durability != Durability::None)
+                .estimate_compression(compression);
+            // An appended file also holds the documents of earlier runs, which are not hashed
+            if config.existing != ExistingPolicy::Append {
+                writer = writer.checksum();
+            }
+            if inline {
+                writer = writer.gzip();
+            }
+            match ids.take() {
+                Some(ids) => writer.track_ids(ids),
+                None => writer,
+            }
+        };
+        writer.begin()?;
+        let writer = Mutex::new(writer);
+
+        // Each slice runs its own scroll; pages are appended to the shared writer as they arrive
+        let results: Vec<Result<u64, EsDumperError>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..slice_count)
+                .map(|slice_id| {
+                    let client = &client;
+                    let writer = &writer;
+                    let query = &query;
+                    let limits = &limits;
+                    scope.spawn(move || {
+                        if let Paging::SearchAfter { sort, point_in_time } = paging {
+                            return search_after_export(
+                                config,
+                                client,
+                                cluster.api.as_ref(),
+                                index,
+                                frozen,
+                                query,
+                                sort,
+                                point_in_time,
+                                slice_id,
+                                slice_count,
+                                effective_scroll_size,
+                                writer,
+                                doc_count,
+                                source,
+                                limits,
+                                log_file,
+                                job
+                            );
+                        }
+                        scroll_slice(
+                            config,
+                            client,
+                            index,
+                            frozen,
+                            query,
+                            slice_id,
+                            slice_count,
+                            effective_scroll_size,
+                            writer,
+                            doc_count,
+                            source,
+                            limits,
+                            state,
+                            &cluster.uuid,
+                            log_file,
+                            job
+                        )
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|payload| {
+                            Err(
+                                EsDumperError::Panic(
+                                    format!("Slice worker panicked: {}", panic_message(payload.as_ref()))
+                                )
+                            )
+                        })
+                })
+                .collect()
+        });
+
+        let mut export_docs = 0;
+        for result in results {
+            export_docs += result?;
+        }
+        total_docs += export_docs;
+
+        let writer = writer.into_inner().unwrap();
+        if let Some(estimate) = writer.compression_estimate() {
+            compression = estimate;
+        }
+        let written = writer.finish()?;
+        ids = written.ids;
+        if let Some(ids) = &ids {
+            ids.save(&ids_file)?;
+            if config.durability == Durability::Fsync {
+                sync_file(&ids_file)?;
+            }
+        }
+
+        if !config.no_local_files {
+            if config.durability == Durability::Fsync {
+                sync_file(&open_file)?;
+            }
+            if open_file != written_file {
+                fs::rename(&open_file, &written_file)?;
+            }
+        }
+
+        // The data file written when the time ran out is exported again by `--resume`
+        if limits.expired() {
+            log(
+                log_file,
+                &format!(
+                    "Stopped the export of index {} after {} documents at the --max-index-duration limit",
+                    index,
+                    total_docs
+                )
+            )?;
+            manifest.interrupted = Some(
+                format!("--max-index-duration stopped the export after {} documents", total_docs)
+            );
+            break;
+        }
+        if compress && !inline {
+            config.compressor.submit(CompressionTask {
+                index: index.to_string(),
+                path: data_file.clone(),
+                sync: config.durability == Durability::Fsync,
+                log_file: log_file.clone(),
+            });
+        }
+
+        // A complete partition is recorded right away, so `--resume` can continue after it
+        if let Some(number) = partition {
+            manifest.partitions[number].docs = Some(export_docs);
+            manifest.partitions[number].sha256 = written.sha256;
+            manifest.partitions[number].compressed = inline;
+            if !config.no_local_files {
+                save_manifest(config, manifest, index_dir)?;
+            }
+        } else {
+            manifest.data_sha256 = written.sha256;
+        }
+    }
+
+    if let Some(max_docs) = config.max_docs.filter(|max_docs| total_docs >= *max_docs) {
+        if total_docs < doc_count {
+            log(
+                log_file,
+                &format!(
+                    "Stopped the export of index {} at the --max-docs cap of {} of its {} documents",
+                    index,
+                    max_docs,
+                    doc_count
+                )
+            )?;
+            manifest.max_docs = Some(max_docs);
+            manifest.limitations.push(
+                format!(
+                    "Truncated by --max-docs: {} of the {} documents of the index were exported",
+                    total_docs,
+                    doc_count
+                )
+            );
+        }
+    }
+
+    let duration = start_time.elapsed();
+    log(
+        log_file,
+        &format!(
+            "Completed data export for index: {}. Total documents: {}. Duration: {:.2} seconds",
+            index,
+            total_docs,
+            duration.as_secs_f64()
+        )
+    )?;
+
+    // Rounded, as the ratio is an estimate from a sample of the pages
+    manifest.compression_ratio = compression.ratio().map(|ratio| (ratio * 1000.0).round() / 1000.0);
+    manifest.estimated_compressed_bytes = compression.projected_bytes(total_docs);
+    let projected = manifest.estimated_compressed_bytes;
+    if let (Some(ratio), Some(projected)) = (compression.ratio(), projected) {
+        log(
+            log_file,
+            &format!(
+                "Estimated compressed size of index {}: {:.1} MB ({:.0}% of the exported data)",
+                index,
+                (projected as f64) / (1024.0 * 1024.0),
+                ratio * 100.0
+            )
+        )?;
+    }
+
+    Ok(Some(total_docs))
+}
+
+/// URL of a search endpoint of an index, e.g. `_count`. Searches skip frozen indices unless
+/// they are sent with `ignore_throttled=false`.
+fn search_url(host: &str, index: &str, endpoint: &str, frozen: bool) -> String {
+    let url = format!("{}/{}/{}", host, index, endpoint);
+    if !frozen {
+        return url;
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}ignore_throttled=false", url, separator)
+}
+
+/// Names of the closed indices, from `_cat/indices`; none when it is unavailable.
+fn fetch_closed_indices(
+    config: &BackupConfig,
+    client: &HttpClient
+) -> Result<HashSet<String>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_cat/indices?format=json&h=index,status&expand_wildcards=all", host))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Ok(HashSet::new());
+    }
+
+    let json: Value = response.json()?;
+    Ok(
+        json
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|index| index["status"] == "close")
+            .filter_map(|index| index["index"].as_str().map(str::to_string))
+            .collect()
+    )
+}
+
+/// Names of the frozen indices (Elasticsearch 7), from their `index.frozen` setting; none when
+/// the setting is unavailable.
+fn fetch_frozen_indices(
+    config: &BackupConfig,
+    client: &HttpClient
+) -> Result<HashSet<String>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_all/_settings/index.frozen?flat_settings=true", host))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Ok(HashSet::new());
+    }
+
+    let json: Value = response.json()?;
+    Ok(
+        json
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(_, index)| index["settings"]["index.frozen"] == "true")
+            .map(|(name, _)| name.clone())
+            .collect()
+    )
+}
+
+/// Opens or closes an index, waiting for its primaries when opening.
+fn set_index_open(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    open: bool
+) -> Result<(), EsDumperError> {
+    let endpoint = if open { "_open?wait_for_active_shards=1" } else { "_close" };
+    let response = config.hosts.send(|host| {
+        client
+            .post(format!("{}/{}/{}", host, index, endpoint))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Failed to {} index '{}'", if open { "open" } else { "close" }, index),
+                response
+            )
+        );
+    }
+    Ok(())
+}
+
+/// The concrete indices behind `name` when it is an alias, from `_alias`; `None` for indices
+/// and names that don't exist.
+fn resolve_alias(
+    config: &BackupConfig,
+    client: &HttpClient,
+    name: &str
+) -> Result<Option<Vec<String>>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_alias/{}", host, name))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+
+    let json: Value = response.json()?;
+    let mut indices: Vec<String> = json
+        .as_object()
+        .map(|indices| indices.keys().cloned().collect())
+        .unwrap_or_default();
+    if indices.is_empty() || indices.iter().any(|index| index == name) {
+        return Ok(None);
+    }
+    indices.sort();
+    Ok(Some(indices))
+}
+
+fn count_docs(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    frozen: bool
+) -> Result<u64, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(search_url(host, index, "_count", frozen))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    let json: Value = response.json()?;
+    Ok(json["count"].as_u64().unwrap_or(0))
+}
+
+fn resolve_slice_count(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    log_file: &LogFile
+) -> Result<usize, EsDumperError> {
+    if let Some(&slices) = config.slice_overrides.get(index) {
+        return Ok(slices.max(1));
+    }
+
+    if config.max_slices <= 1 {
+        return Ok(1);
+    }
+
+    // Slices aligned with primary shards perform best
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/{}/_settings/index.number_of_shards", host, index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(
+            log_file,
+            &format!(
+                "Could not read shard count for index {} ({}), using a single slice",
+                index,
+                response.status()
+            )
+        )?;
+        return Ok(1);
+    }
+
+    let json: Value = response.json()?;
+    let shards = json[index]["settings"]["index"]["number_of_shards"]
+        .as_str()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1);
+
+    Ok(shards.clamp(1, config.max_slices))
+}
+
+/// Whether the partition field of an index is a date field, failing for fields that are
+/// neither numeric nor dates.
+fn partition_field_is_date(
+    index: &str,
+    field: &str,
+    mapping: &Value
+) -> Result<bool, EsDumperError> {
+    let field_type = type_mappings(mapping)
+        .into_iter()
+        .find_map(|mappings| {
+            let mut properties = &mappings["properties"];
+            let mut field_type = None;
+            for name in field.split('.') {
+                field_type = properties[name]["type"].as_str();
+                properties = &properties[name]["properties"];
+            }
+            field_type
+        });
+
+    match field_type {
+        Some("date" | "date_nanos") => Ok(true),
+        Some(field_type) if NUMERIC_TYPES.contains(&field_type) => Ok(false),
+        _ =>
+            Err(
+                EsDumperError::Config(
+                    format!(
+                        "Partition field '{}' of index '{}' is not a numeric or date field",
+                        field,
+                        index
+                    )
+                )
+            ),
+    }
+}
+
+/// Splits the range of `field` into partitions of about `partition_docs` documents each, at
+/// boundaries from a `percentiles` aggregation. Returns no partitions when the field has too
+/// few distinct values to split on.
+#[allow(clippy::too_many_arguments)]
+fn plan_partitions(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    frozen: bool,
+    field: &str,
+    is_date: bool,
+    doc_count: u64,
+    log_file: &LogFile
+) -> Result<Vec<Partition>, EsDumperError> {
+    let count = doc_count.div_ceil(config.partition_docs.max(1)).clamp(1, MAX_PARTITIONS as u64);
+    let percents: Vec<f64> = (1..count).map(|i| ((i as f64) * 100.0) / (count as f64)).collect();
+    let response = config.hosts.send(|host| {
+        client
+            .post(search_url(host, index, "_search", frozen))
+            .json(
+                &serde_json::json!({
+                    "size": 0,
+                    "track_total_hits": false,
+                    "aggs": {
+                        "bounds": {
+                            "percentiles": { "field": field, "percents": percents, "keyed": false }
+                        }
+                    }
+                })
+            )
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Failed to compute the partitions of index {} on {}", index, field),
+                response
+            )
+        );
+    }
+
+    let json: Value = response.json()?;
+    let values: Vec<f64> = json["aggregations"]["bounds"]["values"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|percentile| percentile["value"].as_f64())
+        .collect();
+    // Dates and whole numbers are cut at whole values, which date range queries can parse
+    let mut bounds: Vec<Value> = Vec::new();
+    for value in values {
+        let bound = if is_date || value.fract() == 0.0 {
+            Value::from(value.floor() as i64)
+        } else {
+            Value::from(value)
+        };
+        if bounds.last() != Some(&bound) {
+            bounds.push(bound);
+        }
+    }
+    if bounds.is_empty() {
+        log(
+            log_file,
+            &format!(
+                "Field {} of index {} has too few distinct values to partition on, exporting it in one piece",
+                field,
+                index
+            )
+        )?;
+        return Ok(Vec::new());
+    }
+
+    let lower = std::iter::once(None).chain(bounds.iter().cloned().map(Some));
+    let upper = bounds.iter().cloned().map(Some).chain(std::iter::once(None));
+    let partitions: Vec<Partition> = lower
+        .zip(upper)
+        .enumerate()
+        .map(|(number, (gte, lt))| Partition {
+            gte,
+            lt,
+            file: config.data_format.part_file_name(index, number),
+            docs: None,
+            sha256: None,
+            compressed: false,
+        })
+        .collect();
+    log(
+        log_file,
+        &format!(
+            "Partitioning the export of index {} into {} ranges of {}",
+            index,
+            partitions.len(),
+            field
+        )
+    )?;
+    Ok(partitions)
+}
+
+/// Query matching the documents of a partition.
+fn partition_query(field: &str, is_date: bool, partition: &Partition) -> Value {
+    let mut range = serde_json::json!({});
+    if let Some(gte) = &partition.gte {
+        range["gte"] = gte.clone();
+    }
+    if let Some(lt) = &partition.lt {
+        range["lt"] = lt.clone();
+    }
+    if is_date {
+        range["format"] = Value::from("epoch_millis");
+    }
+    let in_range = serde_json::json!({ "range": { field: range } });
+
+    if partition.gte.is_some() {
+        return in_range;
+    }
+    // The first partition also holds the documents without a value for the field
+    serde_json::json!({
+        "bool": {
+            "should": [
+                in_range,
+                { "bool": { "must_not": { "exists": { "field": field } } } }
+            ]
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn scroll_slice(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    frozen: bool,
+    query: &Value,
+    slice_id: usize,
+    slice_count: usize,
+    scroll_size: u64,
+    writer: &Mutex<DataWriter>,
+    doc_count: u64,
+    source: &ScrollSource,
+    limits: &ExportLimits,
+    state: &StateStore,
+    cluster_uuid: &str,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<u64, EsDumperError> {
+    let mut scroll_body = search_body(config, query, scroll_size, source);
+    scroll_body["sort"] = serde_json::json!(["_doc"]);
+    if slice_count > 1 {
+        scroll_body["slice"] = serde_json::json!({ "id": slice_id, "max": slice_count });
+    }
+
+    let response = job.latencies().time_request(LatencyKind::Scroll, || {
+        config.hosts.send(|host| {
+            client
+                .post(
+                    search_url(
+                        host,
+                        index,
+                        &format!("_search?scroll={}", config.scroll_time),
+                        frozen
+                    )
+                )
+                .json(&scroll_body)
+                .timeout(Duration::from_secs(config.scroll_timeout_secs))
+        })
+    })?;
+
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Failed to initialize scroll for {} (slice {})", index, slice_id),
+                response
+            )
+        );
+    }
+
+    let response_json: Value = response.json()?;
+    let scroll_id = response_json["_scroll_id"]
+        .as_str()
+        .ok_or_else(|| EsDumperError::UnexpectedResponse("No scroll ID returned".to_string()))?
+        .to_string();
+    let mut scroll = ScrollGuard::open(config, client, state, cluster_uuid, index, scroll_id);
+
+    let mut hits = response_json["hits"]["hits"]
+        .as_array()
+        .ok_or_else(|| EsDumperError::UnexpectedResponse("Invalid hits format".to_string()))?
+        .clone();
+    let mut total_docs = 0;
+
+    // The keep-alive grows when draining a page takes close to the scroll timeout,
+    // so slow storage does not let the scroll context expire between pages
+    let mut scroll_time = config.scroll_time.clone();
+    let mut keep_alive = parse_duration(&config.scroll_time);
+    let max_keep_alive = parse_duration(&config.max_scroll_time);
+
+    while !hits.is_empty() {
+        if job.is_cancelled() {
+            return Err(EsDumperError::Cancelled);
+        }
+        if limits.expired() {
+            break;
+        }
+        ensure_free_space(config, index)?;
+
+        let allowed = limits.take(hits.len());
+        let capped = allowed < hits.len() || limits.exhausted();
+        hits.truncate(allowed);
+
+        let write_start = Instant::now();
+        if !hits.is_empty() {
+            let (bytes, compression, written_bytes) = write_hits(
+                config,
+                index,
+                writer,
+                source,
+                &hits,
+                job
+            )?;
+            total_docs += hits.len() as u64;
+            job.observer().on_batch_done(
+                &(BatchDone {
+                    index,
+                    docs: hits.len() as u64,
+                    bytes,
+                    expected_docs: Some(doc_count),
+                    projected_bytes: compression.and_then(|estimate| {
+                        estimate.projected_bytes(doc_count)
+                    }),
+                    written_bytes,
+                })
+            );
+        }
+        let write_elapsed = write_start.elapsed();
+        job.latencies().record(LatencyKind::LocalWrite, write_elapsed);
+        // The scroll is cleared when its guard is dropped
+        if capped {
+            break;
+        }
+
+        if let (Some(current), Some(max)) = (keep_alive, max_keep_alive) {
+            if write_elapsed * 2 > current && current < max {
+                let grown = (current * 2).max(write_elapsed * 3).min(max);
+                keep_alive = Some(grown);
+                scroll_time = format!("{}s", grown.as_secs().max(1));
+                log(
+                    log_file,
+                    &format!(
+                        "Writing a page for index {} took {:.2} seconds, extending scroll keep-alive to {}",
+                        index,
+                        write_elapsed.as_secs_f64(),
+                        scroll_time
+                    )
+                )?;
+            }
+        }
+
+        let continue_body =
+            serde_json::json!({
+            "scroll": scroll_time,
+            "scroll_id": scroll.id
+        });
+
+        let continue_response = job.latencies().time_request(LatencyKind::Scroll, || {
+            config.hosts.send(|host| {
+                client
+                    .post(format!("{}/_search/scroll", host))
+                    .json(&continue_body)
+                    .timeout(Duration::from_secs(config.scroll_timeout_secs))
+            })
+        })?;
+
+        if !continue_response.status().is_success() {
+            return Err(
+                EsDumperError::from_response("Failed to continue scroll", continue_response)
+            );
+        }
+
+        let continue_json: Value = continue_response.json()?;
+        scroll.update(
+            continue_json["_scroll_id"]
+                .as_str()
+                .ok_or_else(|| {
+                    EsDumperError::UnexpectedResponse("No scroll ID returned".to_string())
+                })?
+        );
+
+        hits = continue_json["hits"]["hits"]
+            .as_array()
+            .ok_or_else(|| EsDumperError::UnexpectedResponse("Invalid hits format".to_string()))?
+            .clone();
+    }
+
+    Ok(total_docs)
+}
+
+/// Search request for a page of documents matching `query`, with the fields `source` names
+/// and the metadata `preserve_metadata` keeps.
+fn search_body(config: &BackupConfig, query: &Value, size: u64, source: &ScrollSource) -> Value {
+    let mut body =
+        serde_json::json!({
+        "size": size,
+        "query": query,
+        "_source": true
+    });
+    // Hits only carry versions and sequence numbers when asked for them
+    let preserved = |field: &str| config.preserve_metadata.iter().any(|kept| kept == field);
+    if preserved("_version") {
+        body["version"] = Value::Bool(true);
+    }
+    if preserved("_seq_no") || preserved("_primary_term") {
+        body["seq_no_primary_term"] = Value::Bool(true);
+    }
+    match source {
+        ScrollSource::Source { excludes } if !excludes.is_empty() => {
+            body["_source"] = serde_json::json!({ "excludes": excludes });
+        }
+        ScrollSource::Source { .. } => {}
+        ScrollSource::DocValues { docvalue_fields, stored_fields } => {
+            let fields: Vec<&str> = docvalue_fields
+                .iter()
+                .map(|(field, _)| field.as_str())
+                .collect();
+            body["_source"] = Value::Bool(false);
+            body["docvalue_fields"] = serde_json::json!(fields);
+            body["stored_fields"] = serde_json::json!(stored_fields);
+        }
+    }
+    body
+}
+
+/// Exports the documents matching `query` in pages ordered by `sort`, each continuing with
+/// `search_after` from the sort values of the last hit written. With `point_in_time`, the
+/// pages come from a point in time, which can be sliced; when it expires, e.g. because writing
+/// a page stalled, a new one is opened and the export goes on after the last hit written, up to
+/// `max_scroll_restarts` times. Without one, for clusters that keep no search contexts,
+/// documents changed during the export may be missed or included.
+#[allow(clippy::too_many_arguments)]
+fn search_after_export(
+    config: &BackupConfig,
+    client: &HttpClient,
+    api: &dyn EsApi,
+    index: &str,
+    frozen: bool,
+    query: &Value,
+    sort: PageSort,
+    point_in_time: bool,
+    slice_id: usize,
+    slice_count: usize,
+    page_size: u64,
+    writer: &Mutex<DataWriter>,
+    doc_count: u64,
+    source: &ScrollSource,
+    limits: &ExportLimits,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<u64, EsDumperError> {
+    let mut body = search_body(config, query, page_size, source);
+    body["sort"] = match sort {
+        PageSort::Field(field) => serde_json::json!([{ field: "asc" }]),
+        PageSort::ShardDoc => serde_json::json!([{ "_shard_doc": "asc" }]),
+    };
+    if slice_count > 1 {
+        body["slice"] = serde_json::json!({ "id": slice_id, "max": slice_count });
+    }
+    let mut pit = if point_in_time {
+        Some(PointInTime::open(config, client, api, index, frozen)?)
+    } else {
+        None
+    };
+    let mut total_docs = 0;
+    let mut restarts = 0;
+
+    loop {
+        if job.is_cancelled() {
+            return Err(EsDumperError::Cancelled);
+        }
+        if limits.expired() {
+            break;
+        }
+        ensure_free_space(config, index)?;
+
+        // Searches in a point in time name no index, as the point in time holds it
+        if let Some(pit) = &pit {
+            body["pit"] = serde_json::json!({ "id": pit.id, "keep_alive": config.scroll_time });
+        }
+        let response = job.latencies().time_request(LatencyKind::Scroll, || {
+            config.hosts.send(|host| {
+                let url = match pit {
+                    Some(_) => format!("{}/_search", host),
+                    None => search_url(host, index, "_search", frozen),
+                };
+                client
+                    .post(url)
+                    .json(&body)
+                    .timeout(Duration::from_secs(config.scroll_timeout_secs))
+            })
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text()?;
+            let expired =
+                status == StatusCode::NOT_FOUND &&
+                text.contains("search_context_missing_exception");
+            if pit.is_some() && expired && restarts < config.max_scroll_restarts {
+                restarts += 1;
+                log(
+                    log_file,
+                    &format!(
+                        "Point in time of index {} (slice {}) expired after {} documents, resuming after the last one written in a new one ({} of {})",
+                        index,
+                        slice_id,
+                        total_docs,
+                        restarts,
+                        config.max_scroll_restarts
+                    )
+                )?;
+                pit = Some(PointInTime::open(config, client, api, index, frozen)?);
+                continue;
+            }
+            return Err(
+                EsDumperError::from_status(
+                    format!("Failed to search index {} after {} documents", index, total_docs),
+                    status,
+                    text
+                )
+            );
+        }
+
+        let mut response_json: Value = response.json()?;
+        // Each response may carry a new ID for the point in time
+        if let (Some(pit), Some(id)) = (&mut pit, response_json["pit_id"].as_str()) {
+            pit.id = id.to_string();
+        }
+        let mut hits = match response_json["hits"]["hits"].take() {
+            Value::Array(hits) => hits,
+            _ => {
+                return Err(EsDumperError::UnexpectedResponse("Invalid hits format".to_string()));
+            }
+        };
+        let Some(last) = hits.last() else {
+            break;
+        };
+        body["search_after"] = last["sort"].clone();
+        let page_full = (hits.len() as u64) >= page_size;
+        hits.truncate(limits.take(hits.len()));
 
-    if indices.is_empty() {
-        log(log_file, "No indices found to backup")?;
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
-        pb.set_message("No indices found to backup");
-        pb.finish_and_clear();
-        return Ok(());
+        let write_start = Instant::now();
+        let (bytes, compression, written_bytes) = write_hits(
+            config,
+            index,
+            writer,
+            source,
+            &hits,
+            job
+        )?;
+        job.latencies().record(LatencyKind::LocalWrite, write_start.elapsed());
+        total_docs += hits.len() as u64;
+        job.observer().on_batch_done(
+            &(BatchDone {
+                index,
+                docs: hits.len() as u64,
+                bytes,
+                expected_docs: Some(doc_count),
+                projected_bytes: compression.and_then(|estimate| {
+                    estimate.projected_bytes(doc_count)
+                }),
+                written_bytes,
+            })
+        );
+        if !page_full || limits.exhausted() {
+            break;
+        }
     }
 
-    log(log_file, &format!("Found {} indices to backup", indices.len()))?;
-
-    let multi = Arc::new(MultiProgress::new());
-    let pb_main = multi.add(ProgressBar::new(indices.len() as u64));
-    pb_main.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) Indices"
-            )
-            .unwrap()
-            .progress_chars("#>-")
-    );
+    Ok(total_docs)
+}
 
-    let start_time = std::time::Instant::now();
+fn clear_scroll(config: &BackupConfig, client: &HttpClient, scroll_id: &str) -> bool {
+    config.hosts
+        .send(|host| {
+            client
+                .delete(format!("{}/_search/scroll", host))
+                .json(&serde_json::json!({"scroll_id": [scroll_id]}))
+                .timeout(Duration::from_secs(config.scroll_timeout_secs))
+        })
+        .is_ok_and(|response| {
+            // A context that already expired is gone as well
+            response.status().is_success() || response.status() == StatusCode::NOT_FOUND
+        })
+}
 
-    let completed_indices = Arc::new(Mutex::new(0));
-    let active_indices = Arc::new(Mutex::new(0));
+/// Clears the scroll contexts that earlier backups of the cluster opened and never cleared,
+/// e.g. because the process was killed. Must not run while a backup of the cluster is in
+/// progress, whose contexts it would clear as well. Returns the number of contexts cleared.
+pub fn cleanup_scrolls(config: &BackupConfig, log_file: &LogFile) -> Result<usize, EsDumperError> {
+    let client = build_http_client(config, &format!("{}-cleanup", config.opaque_id_prefix))?;
+    let cluster = get_cluster_info(config, &client, log_file)?;
+    let state = StateStore::open(&config.backup_dir)?;
 
-    // Configure Rayon thread pool to limit concurrency
-    let pool = rayon::ThreadPoolBuilder::new().num_threads(config.max_parallel_indices).build()?;
+    let open_scrolls = state.open_scrolls(&cluster.uuid)?;
+    let mut cleared = 0;
+    for (index, scroll_id) in &open_scrolls {
+        if clear_scroll(config, &client, scroll_id) {
+            state.remove_scroll(scroll_id)?;
+            cleared += 1;
+        } else {
+            log(log_file, &format!("Failed to clear a scroll context of index {}", index))?;
+        }
+    }
 
-    pool.install(|| {
-        indices.par_chunks(config.max_parallel_indices).for_each(|chunk| {
-            for index in chunk {
-                let mut active = active_indices.lock().unwrap();
-                *active += 1;
-                if
-                    let Err(e) = log(
-                        log_file,
-                        &format!(
-                            "Starting backup for index: {} (active indices: {})",
-                            index,
-                            *active
-                        )
-                    )
-                {
-                    eprintln!("Failed to log for index {}: {}", index, e);
-                }
-                drop(active);
+    log(
+        log_file,
+        &format!(
+            "Cleared {} of {} leftover scroll contexts on cluster {}",
+            cleared,
+            open_scrolls.len(),
+            cluster.name
+        )
+    )?;
+    Ok(cleared)
+}
 
-                let pb_index = multi.add(ProgressBar::new(0));
-                pb_index.set_style(
-                    ProgressStyle::default_bar()
-                        .template(
-                            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}"
-                        )
-                        .unwrap()
-                        .progress_chars("#>-")
-                );
-                pb_index.set_message(index.to_string());
-
-                let result = backup_index(config, index, log_file, &pb_index, &es_version);
-                if let Err(e) = result {
-                    let _ = log(log_file, &format!("Error backing up index {}: {}", index, e));
-                    pb_index.abandon_with_message(format!("Error: {}", e));
-                } else {
-                    pb_index.finish_and_clear();
-                }
+/// Writes a page of hits, returning the bytes written and the writer's compression estimate.
+fn write_hits(
+    config: &BackupConfig,
+    index: &str,
+    writer: &Mutex<DataWriter>,
+    source: &ScrollSource,
+    hits: &[Value],
+    job: &Job
+) -> Result<(u64, Option<CompressionEstimate>, Option<u64>), EsDumperError> {
+    let mut docs = Vec::with_capacity(hits.len());
+    for hit in hits {
+        let mut doc = reduce_document_size(hit, &config.preserve_metadata)?;
+        if let ScrollSource::DocValues { docvalue_fields, .. } = source {
+            rebuild_source(&mut doc, docvalue_fields);
+        }
+        for transform in job.transforms() {
+            transform.transform(index, &mut doc)?;
+        }
+        if config.no_local_files || config.data_format == DataFormat::Bulk {
+            doc["_index"] = Value::from(index);
+        }
+        docs.push(doc);
+    }
 
-                let mut active = active_indices.lock().unwrap();
-                *active -= 1;
-                if
-                    let Err(e) = log(
-                        log_file,
-                        &format!(
-                            "Completed backup for index: {} (active indices: {})",
-                            index,
-                            *active
-                        )
-                    )
-                {
-                    eprintln!("Failed to log for index {}: {}", index, e);
-                }
+    let mut writer = writer.lock().unwrap();
+    let bytes = writer.write_docs(&docs)?;
+    Ok((bytes, writer.compression_estimate(), writer.written_bytes()))
+}
 
-                let mut completed = completed_indices.lock().unwrap();
-                *completed += 1;
-                pb_main.set_position(*completed);
+/// Fetches the mappings, settings and aliases of `indices` in batches, several batches at a
+/// time, so clusters with thousands of small indices don't spend a backup on per-index metadata
+/// requests. A failed batch is logged and its indices are fetched on their own later.
+fn prefetch_metadata(
+    config: &BackupConfig,
+    client: &HttpClient,
+    indices: &[String],
+    log_file: &LogFile
+) -> Result<PrefetchedMetadata, EsDumperError> {
+    let mut batches: Vec<Vec<&str>> = Vec::new();
+    let mut url_len = 0;
+    for index in indices {
+        match batches.last_mut() {
+            Some(batch) if
+                batch.len() < METADATA_BATCH_SIZE &&
+                url_len + index.len() < METADATA_BATCH_MAX_URL_LEN
+            => {
+                batch.push(index);
+                url_len += index.len() + 1;
             }
+            _ => {
+                batches.push(vec![index]);
+                url_len = index.len();
+            }
+        }
+    }
+    let requests: Vec<(&[&str], &str, &'static str)> = batches
+        .iter()
+        .flat_map(|batch| {
+            METADATA_ENDPOINTS.iter().map(move |&(endpoint, key)| (batch.as_slice(), endpoint, key))
+        })
+        .collect();
+
+    let prefetched = Mutex::new(HashMap::new());
+    run_queue(&requests, config.max_parallel_indices, |&(batch, endpoint, key)| {
+        let response = config.hosts.send(|host| {
+            client
+                .get(format!("{}/{}/{}?ignore_unavailable=true", host, batch.join(","), endpoint))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
         });
+        let json = match response {
+            Ok(response) if response.status().is_success() => response.json::<Value>().ok(),
+            _ => None,
+        };
+        let Some(Value::Object(json)) = json else {
+            let _ = log(
+                log_file,
+                &format!(
+                    "Batched {} request for {} indices failed, fetching them one by one",
+                    endpoint,
+                    batch.len()
+                )
+            );
+            return;
+        };
+
+        let mut prefetched = prefetched.lock().unwrap();
+        for (index, value) in json {
+            prefetched.insert((key, index), value);
+        }
     });
 
-    let duration = start_time.elapsed();
-    pb_main.finish_with_message(format!("Completed in {:.2} seconds", duration.as_secs_f64()));
+    let prefetched = prefetched.into_inner().unwrap();
     log(
         log_file,
-        &format!("Backup completed successfully in {:.2} seconds", duration.as_secs_f64())
+        &format!(
+            "Fetched mappings, settings and aliases of {} indices in {} batched requests",
+            indices.len(),
+            requests.len()
+        )
     )?;
+    Ok(prefetched)
+}
 
-    Ok(())
+/// The response a single-index metadata request would have given, from the prefetched batches.
+fn prefetched_response(
+    prefetched: &PrefetchedMetadata,
+    key: &'static str,
+    index: &str
+) -> Option<Value> {
+    let value = prefetched.get(&(key, index.to_string()))?;
+    Some(serde_json::json!({ index: value }))
 }
 
-fn backup_index(
+fn backup_mapping(
     config: &BackupConfig,
+    client: &HttpClient,
     index: &str,
-    log_file: &Arc<Mutex<File>>,
-    pb_index: &ProgressBar,
-    es_version: &str
-) -> Result<(), Box<dyn std::error::Error>> {
-    log(log_file, &format!("Processing index: {}", index))?;
+    index_dir: &Path,
+    prefetched: &PrefetchedMetadata,
+    log_file: &LogFile
+) -> Result<Value, EsDumperError> {
+    let mapping_json = match prefetched_response(prefetched, "mapping", index) {
+        Some(mapping_json) => mapping_json,
+        None => {
+            let mapping_response = config.hosts.send(|host| {
+                client
+                    .get(format!("{}/{}/_mapping", host, index))
+                    .timeout(Duration::from_secs(config.metadata_timeout_secs))
+            })?;
+            mapping_response.json()?
+        }
+    };
 
-    let index_dir = Path::new(&config.backup_dir).join(index);
-    fs::create_dir_all(&index_dir)?;
+    if config.no_local_files {
+        let record = serde_json::json!({ "_index": index, "_mapping": mapping_json });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        let mapping_file = index_dir.join(format!("{}_mapping.json", index));
+        let file = File::create(&mapping_file)?;
+        serde_json::to_writer_pretty(&file, &mapping_json)?;
+        if config.durability == Durability::Fsync {
+            file.sync_all()?;
+        }
+    }
 
-    backup_mapping(config, index, &index_dir, log_file)?;
-    backup_data(config, index, &index_dir, log_file, pb_index, es_version)?;
-    log(log_file, &format!("Backup completed for index: {}", index))?;
-    Ok(())
+    log(log_file, &format!("Mapping backed up for index: {}", index))?;
+    Ok(mapping_json)
 }
 
-fn backup_data(
+/// Backs up the settings and aliases of an index next to its mapping, returning the settings.
+fn backup_settings(
     config: &BackupConfig,
+    client: &HttpClient,
     index: &str,
     index_dir: &Path,
-    log_file: &Arc<Mutex<File>>,
-    pb_index: &ProgressBar,
-    es_version: &str
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = build_http_client(config)?;
-
-    let count_url = format!("{}/{}/_count", config.host, index);
-    let count_response = client.get(&count_url).send()?;
-    let count_json: Value = count_response.json()?;
-    let doc_count = count_json["count"].as_u64().unwrap_or(0);
-
-    if doc_count == 0 {
-        log(log_file, &format!("Index {} is empty, skipping data backup", index))?;
-        pb_index.set_message(format!("{} (empty)", index));
-        pb_index.finish_and_clear();
-        return Ok(());
+    prefetched: &PrefetchedMetadata,
+    log_file: &LogFile
+) -> Result<Value, EsDumperError> {
+    let mut settings = Value::Null;
+    for &(endpoint, key) in &METADATA_ENDPOINTS[1..] {
+        let json = match prefetched_response(prefetched, key, index) {
+            Some(json) => json,
+            None => {
+                let response = config.hosts.send(|host| {
+                    client
+                        .get(format!("{}/{}/{}", host, index, endpoint))
+                        .timeout(Duration::from_secs(config.metadata_timeout_secs))
+                })?;
+                if !response.status().is_success() {
+                    return Err(
+                        EsDumperError::from_response(
+                            format!("Failed to fetch {} for index '{}'", key, index),
+                            response
+                        )
+                    );
+                }
+                response.json()?
+            }
+        };
+
+        if config.no_local_files {
+            let record = serde_json::json!({ "_index": index, format!("_{}", key): json });
+            DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+        } else {
+            let file = File::create(index_dir.join(format!("{}_{}.json", index, key)))?;
+            serde_json::to_writer_pretty(&file, &json)?;
+            if config.durability == Durability::Fsync {
+                file.sync_all()?;
+            }
+        }
+        if key == "settings" {
+            settings = json;
+        }
     }
 
-    pb_index.set_length(doc_count);
+    log(log_file, &format!("Settings and aliases backed up for index: {}", index))?;
+    Ok(settings)
+}
 
-    // Adjust scroll_size for Elasticsearch 8.3.3
-    let effective_scroll_size = if es_version.starts_with("8.3") {
-        (config.scroll_size / 2).max(1000) // Reduce to 5000, minimum 1000
-    } else {
-        config.scroll_size
-    };
+/// Backs up the `_field_caps` of an index and the runtime fields of its mapping, so fields that
+/// only exist at search time are kept apart from the mapping they may be edited out of.
+fn backup_field_caps(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    index_dir: &Path,
+    mapping: &Value,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/{}/_field_caps?fields=*", host, index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Failed to fetch field capabilities for index '{}'", index),
+                response
+            )
+        );
+    }
+    let field_caps: Value = response.json()?;
 
-    let scroll_url = format!("{}/{}/_search?scroll={}", config.host, index, config.scroll_time);
+    let runtime = mapping
+        .as_object()
+        .and_then(|indices| indices.values().next())
+        .map(|index_mapping| &index_mapping["mappings"]["runtime"])
+        .filter(|runtime| runtime.as_object().is_some_and(|fields| !fields.is_empty()));
+    let runtime_json = runtime.map(|runtime| serde_json::json!({ index: { "runtime": runtime } }));
 
-    let scroll_body =
-        serde_json::json!({
-        "size": effective_scroll_size,
-        "query": { "match_all": {} },
-        "_source": true,
-        "sort": ["_doc"]
-    });
+    if config.no_local_files {
+        let mut record = serde_json::json!({ "_index": index, "_field_caps": field_caps });
+        if let Some(runtime_json) = &runtime_json {
+            record["_runtime"] = runtime_json.clone();
+        }
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        let runtime_file = index_dir.join(format!("{}_runtime.json", index));
+        let mut files = vec![(index_dir.join(format!("{}_field_caps.json", index)), &field_caps)];
+        match &runtime_json {
+            Some(runtime_json) => files.push((runtime_file, runtime_json)),
+            // Left by an earlier backup of the index, when it still had runtime fields
+            None if runtime_file.exists() => fs::remove_file(runtime_file)?,
+            None => {}
+        }
+        for (path, json) in files {
+            let file = File::create(path)?;
+            serde_json::to_writer_pretty(&file, json)?;
+            if config.durability == Durability::Fsync {
+                file.sync_all()?;
+            }
+        }
+    }
 
+    let runtime_fields = runtime.and_then(Value::as_object).map_or(0, |fields| fields.len());
     log(
         log_file,
         &format!(
-            "Starting data export for index: {} ({} documents, scroll_size: {})",
+            "Field capabilities backed up for index: {} ({} runtime fields)",
             index,
-            doc_count,
-            effective_scroll_size
+            runtime_fields
         )
     )?;
+    Ok(())
+}
 
-    let start_time = std::time::Instant::now();
-    let response = client.post(&scroll_url).json(&scroll_body).send()?;
+/// Backs up the cluster's index, component and legacy templates to `templates.json`. Template
+/// APIs the cluster does not support are recorded as `null`.
+fn backup_templates(
+    config: &BackupConfig,
+    client: &HttpClient,
+    target_dir: &Path,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let mut templates = serde_json::Map::new();
 
-    if !response.status().is_success() {
-        pb_index.abandon_with_message(format!("Scroll failed: {}", response.status()));
-        return Err(
-            format!("Failed to initialize scroll for {}: {}", index, response.status()).into()
-        );
+    for (endpoint, key) in [
+        ("_index_template", "index_templates"),
+        ("_component_template", "component_templates"),
+        ("_template", "legacy_templates"),
+    ] {
+        let response = config.hosts.send(|host| {
+            client
+                .get(format!("{}/{}", host, endpoint))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        let json = if response.status().is_success() { response.json()? } else { Value::Null };
+        templates.insert(key.to_string(), json);
     }
 
-    let response_json: Value = response.json()?;
-    let mut scroll_id = response_json["_scroll_id"]
-        .as_str()
-        .ok_or("No scroll ID returned")?
-        .to_string();
-
-    let data_file = index_dir.join(format!("{}_data.json", index));
-    let file = File::create(&data_file)?;
-    let mut writer = BufWriter::with_capacity(config.buffer_size, file);
+    if config.no_local_files {
+        let record = serde_json::json!({ "_templates": templates });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        let file = File::create(target_dir.join("templates.json"))?;
+        serde_json::to_writer_pretty(&file, &templates)?;
+        if config.durability == Durability::Fsync {
+            file.sync_all()?;
+        }
+    }
 
-    let hits = response_json["hits"]["hits"].as_array().ok_or("Invalid hits format")?;
+    log(log_file, "Templates backed up")?;
+    Ok(())
+}
 
-    writer.write_all(b"[")?;
+/// Moves the `fields` of a hit into a `_source` of the same shape as the original document.
+fn rebuild_source(doc: &mut Value, docvalue_fields: &[(String, String)]) {
+    let Some(fields) = doc.as_object_mut().and_then(|doc| doc.remove("fields")) else {
+        return;
+    };
 
-    let mut total_docs = 0;
-    let mut is_first = true;
+    let mut rebuilt = serde_json::json!({});
+    for (field, values) in fields.as_object().into_iter().flatten() {
+        let path = docvalue_fields
+            .iter()
+            .find(|(requested, _)| requested == field)
+            .map_or(field.as_str(), |(_, path)| path.as_str());
+        // Doc values come back as arrays, even for single values
+        let value = match values.as_array() {
+            Some(values) if values.len() == 1 => values[0].clone(),
+            _ => values.clone(),
+        };
+        set_path(&mut rebuilt, path, value);
+    }
+    doc["_source"] = rebuilt;
+}
 
-    for hit in hits {
-        if !is_first {
-            writer.write_all(b",")?;
+/// The mappings of each document type for both typeless (7.x+) and pre-7.x mappings.
+fn type_mappings(mapping: &Value) -> Vec<&Value> {
+    let mut type_mappings = Vec::new();
+    for index_mapping in mapping.as_object().into_iter().flat_map(|m| m.values()) {
+        let mappings = &index_mapping["mappings"];
+        if mappings.get("properties").is_some() || mappings.get("_source").is_some() {
+            type_mappings.push(mappings);
+        } else if let Some(types) = mappings.as_object() {
+            // Pre-7.x mappings are nested under their document type
+            type_mappings.extend(types.values());
         }
+    }
+    type_mappings
+}
 
-        let reduced_doc = reduce_document_size(hit)?;
-        serde_json::to_writer(&mut writer, &reduced_doc)?;
+fn source_disabled(mapping: &Value) -> bool {
+    type_mappings(mapping)
+        .iter()
+        .any(|mappings| mappings["_source"]["enabled"] == false)
+}
 
-        is_first = false;
-        total_docs += 1;
-        pb_index.inc(1);
+/// Fields a document of an index with `_source` disabled can be rebuilt from: doc value fields
+/// (with their target paths), stored fields, and the fields that cannot be exported at all.
+fn doc_value_fields(mapping: &Value) -> (Vec<(String, String)>, Vec<String>, Vec<String>) {
+    let mut docvalue_fields = Vec::new();
+    let mut stored_fields = Vec::new();
+    let mut lost_fields = Vec::new();
+    for mappings in type_mappings(mapping) {
+        collect_doc_value_fields(
+            &mappings["properties"],
+            "",
+            &mut docvalue_fields,
+            &mut stored_fields,
+            &mut lost_fields
+        );
     }
 
-    let mut batch_hits: Vec<Value>;
+    docvalue_fields.sort();
+    docvalue_fields.dedup();
+    stored_fields.sort();
+    stored_fields.dedup();
+    lost_fields.sort();
+    lost_fields.dedup();
+    (docvalue_fields, stored_fields, lost_fields)
+}
 
-    while !hits.is_empty() {
-        let scroll_continue_url = format!("{}/_search/scroll", config.host);
-        let continue_body =
-            serde_json::json!({
-            "scroll": config.scroll_time,
-            "scroll_id": scroll_id
-        });
+fn collect_doc_value_fields(
+    properties: &Value,
+    prefix: &str,
+    docvalue_fields: &mut Vec<(String, String)>,
+    stored_fields: &mut Vec<String>,
+    lost_fields: &mut Vec<String>
+) {
+    let Some(properties) = properties.as_object() else {
+        return;
+    };
 
-        let continue_response = client.post(&scroll_continue_url).json(&continue_body).send()?;
+    for (name, field) in properties {
+        let path = format!("{}{}", prefix, name);
+        let field_type = field["type"].as_str().unwrap_or("object");
+        match field_type {
+            "object" => {
+                collect_doc_value_fields(
+                    &field["properties"],
+                    &format!("{}.", path),
+                    docvalue_fields,
+                    stored_fields,
+                    lost_fields
+                );
+                continue;
+            }
+            // Aliases point at fields exported under their own name
+            "alias" => {
+                continue;
+            }
+            _ => {}
+        }
 
-        if !continue_response.status().is_success() {
-            let _ = client
-                .delete(&format!("{}/_search/scroll", config.host))
-                .json(&serde_json::json!({"scroll_id": [scroll_id]}))
-                .send();
+        let has_doc_values = match field["doc_values"].as_bool() {
+            Some(doc_values) => doc_values,
+            None => field_type != "binary" && !NO_DOC_VALUES_TYPES.contains(&field_type),
+        };
+        // A keyword sub-field (e.g. `message.keyword`) stands in for a text field
+        let keyword_sub_field = field["fields"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .find(|(_, sub_field)| {
+                sub_field["type"] == "keyword" && sub_field["doc_values"] != false
+            })
+            .map(|(sub_name, _)| format!("{}.{}", path, sub_name));
 
-            pb_index.abandon_with_message(format!("Scroll failed: {}", continue_response.status()));
-            return Err(format!("Failed to continue scroll: {}", continue_response.status()).into());
+        if field_type == "nested" {
+            lost_fields.push(path);
+        } else if field["store"] == true {
+            stored_fields.push(path);
+        } else if has_doc_values {
+            docvalue_fields.push((path.clone(), path));
+        } else if let Some(sub_field) = keyword_sub_field {
+            docvalue_fields.push((sub_field, path));
+        } else {
+            lost_fields.push(path);
         }
+    }
+}
 
-        let continue_json: Value = continue_response.json()?;
-        scroll_id = continue_json["_scroll_id"]
-            .as_str()
-            .ok_or("No scroll ID returned")?
-            .to_string();
+/// Paths of the mapped fields whose type is listed in `exclude_field_types`. `dense_vector`
+/// fields are only excluded from `dense_vector_min_dims` dimensions upwards.
+fn excluded_fields(config: &BackupConfig, mapping: &Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    if config.exclude_field_types.is_empty() {
+        return fields;
+    }
+
+    for mappings in type_mappings(mapping) {
+        collect_excluded_fields(config, &mappings["properties"], "", &mut fields);
+    }
 
-        batch_hits = continue_json["hits"]["hits"].as_array().ok_or("Invalid hits format")?.clone();
+    fields.sort();
+    fields.dedup();
+    fields
+}
 
-        if batch_hits.is_empty() {
-            break;
+fn collect_excluded_fields(
+    config: &BackupConfig,
+    properties: &Value,
+    prefix: &str,
+    fields: &mut Vec<String>
+) {
+    let Some(properties) = properties.as_object() else {
+        return;
+    };
+
+    for (name, field) in properties {
+        let path = format!("{}{}", prefix, name);
+        let field_type = field["type"].as_str().unwrap_or("object");
+        let excluded =
+            config.exclude_field_types.iter().any(|t| t == field_type) &&
+            (field_type != "dense_vector" ||
+                field["dims"].as_u64().unwrap_or(u64::MAX) >= config.dense_vector_min_dims);
+
+        if excluded {
+            fields.push(path);
+        } else if field.get("properties").is_some() {
+            collect_excluded_fields(config, &field["properties"], &format!("{}.", path), fields);
         }
+    }
+}
+
+/// Non-empty data files left in `index_dir` by an earlier backup of the index, including the
+/// partitions its manifest lists.
+fn existing_data_files(index_dir: &Path, index: &str) -> Vec<PathBuf> {
+    let partitions = IndexManifest::load(index_dir, index)
+        .ok()
+        .flatten()
+        .map(|manifest| manifest.partitions)
+        .unwrap_or_default();
+    data_file_candidates(index_dir, index)
+        .into_iter()
+        .chain(
+            partitions
+                .iter()
+                .flat_map(|partition| part_file_candidates(index_dir, &partition.file))
+        )
+        .filter(|path| path.metadata().is_ok_and(|metadata| metadata.len() > 0))
+        .collect()
+}
 
-        for hit in &batch_hits {
-            writer.write_all(b",")?;
-            let reduced_doc = reduce_document_size(hit)?;
-            serde_json::to_writer(&mut writer, &reduced_doc)?;
-            total_docs += 1;
-            pb_index.inc(1);
+/// The directory an index is backed up into: that of the most specific `[backup.paths]`
+/// pattern matching it, or `backup_dir`.
+fn data_root<'a>(config: &'a BackupConfig, index: &str) -> &'a str {
+    config.index_paths
+        .iter()
+        .filter(|(pattern, _)| matches_pattern(pattern, index))
+        .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+        .map_or(&config.backup_dir, |(_, dir)| dir)
+}
+
+/// Creates the directory of an index in `target_dir`. Indices that `[backup.paths]` maps to
+/// another directory are written there, with a link to it in `target_dir` so restores and
+/// `find` see them like any other backup. An index directory already in `target_dir` is kept
+/// where it is.
+fn create_index_dir(
+    config: &BackupConfig,
+    target_dir: &Path,
+    index: &str,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let index_dir = target_dir.join(index);
+    let root = data_root(config, index);
+    if root == config.backup_dir {
+        fs::create_dir_all(&index_dir)?;
+        return Ok(());
+    }
+    if let Ok(metadata) = index_dir.symlink_metadata() {
+        if !metadata.file_type().is_symlink() {
+            log(
+                log_file,
+                &format!(
+                    "An earlier backup of index {} is in {}, keeping it there instead of {}",
+                    index,
+                    index_dir.display(),
+                    root
+                )
+            )?;
         }
+        fs::create_dir_all(&index_dir)?;
+        return Ok(());
+    }
+
+    // Cluster directories are kept on the other storage as well
+    let namespace = target_dir.strip_prefix(&config.backup_dir).unwrap_or(Path::new(""));
+    let tier_dir = Path::new(root).join(namespace).join(index);
+    fs::create_dir_all(&tier_dir)?;
+    link_dir(&fs::canonicalize(&tier_dir)?, &index_dir)?;
+    log(log_file, &format!("Backing up index {} into {}", index, tier_dir.display()))?;
+    Ok(())
+}
 
-        writer.flush()?;
+/// Compares the estimated size of the backup with the free space in `backup_dir` and in the
+/// `[backup.paths]` directories, each holding the indices mapped to it.
+fn check_disk_space(
+    config: &BackupConfig,
+    indices: &[String],
+    store_sizes: &HashMap<String, StoreSize>,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let mut store_bytes: Vec<(&str, u64)> = Vec::new();
+    for index in indices {
+        let size = store_sizes.get(index).map_or(0, |size| size.primaries);
+        let dir = data_root(config, index);
+        match store_bytes.iter_mut().find(|(known, _)| *known == dir) {
+            Some((_, bytes)) => {
+                *bytes += size;
+            }
+            None => store_bytes.push((dir, size)),
+        }
     }
 
-    writer.write_all(b"]")?;
-    writer.flush()?;
+    for (dir, bytes) in store_bytes {
+        let estimated_mb = ((bytes as f64) * config.size_factor / (1024.0 * 1024.0)).ceil();
+        let estimated_mb = estimated_mb as u64;
+        // Directories of `[backup.paths]` are only created with their first index
+        let free_mb = free_space_mb(dir).or_else(|_| {
+            fs::create_dir_all(dir)?;
+            free_space_mb(dir)
+        })?;
+        log(
+            log_file,
+            &format!(
+                "Estimated backup size: {} MB, free space in {}: {} MB",
+                estimated_mb,
+                dir,
+                free_mb
+            )
+        )?;
 
-    let _ = client
-        .delete(&format!("{}/_search/scroll", config.host))
-        .json(&serde_json::json!({"scroll_id": [scroll_id]}))
-        .send();
+        if estimated_mb + config.min_free_space_mb > free_mb {
+            return Err(
+                EsDumperError::storage_full(
+                    format!(
+                        "Insufficient disk space in {}: estimated backup size {} MB plus the {} MB floor exceeds {} MB free",
+                        dir,
+                        estimated_mb,
+                        config.min_free_space_mb,
+                        free_mb
+                    )
+                )
+            );
+        }
+    }
+    Ok(())
+}
 
-    let duration = start_time.elapsed();
-    log(
-        log_file,
-        &format!(
-            "Completed data export for index: {}. Total documents: {}. Duration: {:.2} seconds",
-            index,
-            total_docs,
-            duration.as_secs_f64()
+/// Whether `dir` still has more than `min_free_space_mb` available. Always true when nothing
+/// is written locally or the free space cannot be determined.
+fn has_free_space(config: &BackupConfig, dir: &str) -> bool {
+    config.no_local_files ||
+        free_space_mb(dir).map_or(true, |free| free >= config.min_free_space_mb)
+}
+
+/// Fails once the free space in the directory of `index` drops below `min_free_space_mb`.
+fn ensure_free_space(config: &BackupConfig, index: &str) -> Result<(), EsDumperError> {
+    let dir = data_root(config, index);
+    if has_free_space(config, dir) {
+        return Ok(());
+    }
+    Err(
+        EsDumperError::storage_full(
+            format!("Free space in {} dropped below {} MB", dir, config.min_free_space_mb)
         )
-    )?;
+    )
+}
 
-    #[cfg(feature = "compression")]
-    {
-        log(log_file, &format!("Compressing data file for index: {}", index))?;
-        compress_file(&data_file)?;
+/// Store sizes of every index from a single `_stats/store` request, or None when it is
+/// unavailable.
+fn fetch_store_sizes(
+    config: &BackupConfig,
+    client: &HttpClient,
+    log_file: &LogFile
+) -> Result<Option<HashMap<String, StoreSize>>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_stats/store", host))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(
+            log_file,
+            &format!(
+                "Skipping size filtering, ordering and disk space preflight, _stats/store returned {}",
+                response.status()
+            )
+        )?;
+        return Ok(None);
     }
+    let stats: Value = response.json()?;
 
-    Ok(())
+    Ok(
+        Some(
+            stats["indices"]
+                .as_object()
+                .map(|indices| {
+                    indices
+                        .iter()
+                        .filter_map(|(index, stats)| {
+                            let size = StoreSize {
+                                primaries: stats["primaries"]["store"]["size_in_bytes"].as_u64()?,
+                                total: stats["total"]["store"]["size_in_bytes"].as_u64()?,
+                            };
+                            Some((index.clone(), size))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        )
+    )
 }
 
-fn backup_mapping(
+/// Document count of every index, used to size the overall progress bar.
+fn fetch_doc_counts(
     config: &BackupConfig,
-    index: &str,
-    index_dir: &Path,
-    log_file: &Arc<Mutex<File>>
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = build_http_client(config)?;
-    let mapping_url = format!("{}/{}/_mapping", config.host, index);
-    let mapping_response = client.get(&mapping_url).send()?;
-    let mapping_json: Value = mapping_response.json()?;
+    client: &HttpClient
+) -> Result<HashMap<String, u64>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_cat/indices?format=json&h=index,docs.count", host))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    let json: Value = response.json()?;
 
-    let mapping_file = index_dir.join(format!("{}_mapping.json", index));
-    let file = File::create(&mapping_file)?;
-    serde_json::to_writer_pretty(file, &mapping_json)?;
-
-    log(log_file, &format!("Mapping backed up for index: {}", index))?;
-    Ok(())
+    Ok(
+        json
+            .as_array()
+            .map(|indices| {
+                indices
+                    .iter()
+                    .filter_map(|index| {
+                        let name = index["index"].as_str()?;
+                        let count = index["docs.count"].as_str()?.parse().ok()?;
+                        Some((name.to_string(), count))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    )
 }
 
-fn fetch_indices(
+/// Names of every index in `_cat/indices`.
+fn cat_index_names(
     config: &BackupConfig,
-    log_file: &Arc<Mutex<File>>,
-    es_version: &str
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let client = build_http_client(config)?;
-    let cat_indices_url = format!("{}/_cat/indices?format=json&v=true", config.host);
-    let response = client.get(&cat_indices_url).send()?;
+    client: &HttpClient,
+    log_file: &LogFile,
+    api: &dyn EsApi
+) -> Result<Vec<String>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/_cat/indices?format=json&v=true", host))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
 
     let status = response.status();
     let response_text = response.text()?;
@@ -355,7 +3159,7 @@ fn fetch_indices(
         &format!(
             "Response from _cat/indices (status: {}, version: {}): {}",
             status,
-            es_version,
+            api.version(),
             response_text
         )
     )?;
@@ -363,73 +3167,118 @@ fn fetch_indices(
     let json: Value = serde_json
         ::from_str(&response_text)
         .map_err(|e| {
-            format!("Failed to parse _cat/indices response: {}. Raw response: {}", e, response_text)
+            EsDumperError::UnexpectedResponse(
+                format!(
+                    "Failed to parse _cat/indices response: {}. Raw response: {}",
+                    e,
+                    response_text
+                )
+            )
         })?;
 
     if let Some(error) = json.get("error") {
         let reason = error["reason"].as_str().unwrap_or("Unknown error");
         let error_type = error["type"].as_str().unwrap_or("Unknown type");
-        return Err(format!("Elasticsearch error (type: {}): {}", error_type, reason).into());
+        return Err(
+            EsDumperError::from_status(
+                "Failed to list indices",
+                status,
+                format!("{}: {}", error_type, reason)
+            )
+        );
     }
 
-    let indices_array = if json.is_array() {
-        json.as_array().ok_or_else(|| format!("Expected array of indices, got: {}", json))?
-    } else if json.is_object() && es_version.starts_with("8.3") {
-        log(
-            log_file,
-            "Received map response from _cat/indices, attempting to handle for ES 8.3.x"
-        )?;
-        if
-            json
-                .as_object()
-                .map(|o| o.is_empty())
-                .unwrap_or(false)
-        {
-            log(log_file, "No indices found in map response")?;
-            return Ok(vec![]);
-        }
-        json
-            .get("indices")
-            .and_then(|v| v.as_array())
-            .ok_or_else(|| {
-                format!("Expected 'indices' array in map response for ES 8.3.x, got: {}", json)
-            })?
+    if json.is_object() {
+        log(log_file, "Received map response from _cat/indices")?;
+    }
+    let indices_array = api.cat_indices(&json)?;
+
+    Ok(
+        indices_array
+            .iter()
+            .filter_map(|index| index["index"].as_str().map(str::to_string))
+            .collect()
+    )
+}
+
+/// Names of every index, for clusters without `_cat`: the keys of `_aliases` or, where that
+/// is unavailable as well, of `_settings`.
+fn listed_index_names(
+    config: &BackupConfig,
+    client: &HttpClient
+) -> Result<Vec<String>, EsDumperError> {
+    let list = |endpoint: &str| {
+        config.hosts.send(|host| {
+            client
+                .get(format!("{}/{}", host, endpoint))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })
+    };
+    let mut response = list("_aliases")?;
+    if !response.status().is_success() {
+        response = list("_settings")?;
+    }
+    if !response.status().is_success() {
+        return Err(EsDumperError::from_response("Failed to list indices", response));
+    }
+
+    let json: Value = response.json()?;
+    let mut names: Vec<String> = json
+        .as_object()
+        .into_iter()
+        .flat_map(|indices| indices.keys().cloned())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+fn fetch_indices(
+    config: &BackupConfig,
+    client: &HttpClient,
+    log_file: &LogFile,
+    api: &dyn EsApi,
+    store_sizes: Option<&HashMap<String, StoreSize>>
+) -> Result<(Vec<String>, Vec<SkippedIndex>), EsDumperError> {
+    let index_names = if api.supports_cat() {
+        cat_index_names(config, client, log_file, api)?
     } else {
-        return Err(
-            format!("Unexpected response format for ES version {}: {}", es_version, json).into()
-        );
+        listed_index_names(config, client)?
     };
 
-    let mut result = indices_array
-        .iter()
-        .filter_map(|index| {
-            let index_name = index["index"].as_str()?;
-            if index_name.starts_with('.') || config.skip_indices.contains(&index_name.to_string()) {
-                None
-            } else {
-                Some(index_name.to_string())
-            }
-        })
-        .collect::<Vec<String>>();
+    let mut result = Vec::new();
+    let mut skipped = Vec::new();
+    for index_name in index_names.iter().map(String::as_str) {
+        if index_name.starts_with('.') {
+            skipped.push(
+                SkippedIndex::new(index_name, "system or hidden, the name starts with '.'")
+            );
+        } else if config.skip_indices.iter().any(|skip| skip == index_name) {
+            skipped.push(SkippedIndex::new(index_name, "listed in skip_indices"));
+        } else {
+            result.push(index_name.to_string());
+        }
+    }
 
-    if let Some(max_size_mb) = config.max_index_size_mb {
+    // Indices of unknown size are kept
+    if let (Some(max_size_mb), Some(store_sizes)) = (config.max_index_size_mb, store_sizes) {
         result.retain(|index| {
-            let size_url = format!("{}/{}/_stats/store", config.host, index);
-            if let Ok(response) = client.get(&size_url).send() {
-                if let Ok(json) = response.json::<Value>() {
-                    if
-                        let Some(size_bytes) =
-                            json["indices"][index]["total"]["store"]["size_in_bytes"].as_u64()
-                    {
-                        let size_mb = size_bytes / (1024 * 1024);
-                        return size_mb <= max_size_mb;
-                    }
-                }
+            let Some(size) = store_sizes.get(index) else {
+                return true;
+            };
+            let size_mb = size.total / (1024 * 1024);
+            if size_mb <= max_size_mb {
+                return true;
             }
-            true
+            skipped.push(
+                SkippedIndex::new(
+                    index,
+                    format!("larger than max_index_size_mb = {}", max_size_mb)
+                )
+            );
+            false
         });
     }
 
     result.sort();
-    Ok(result)
+    Ok((result, skipped))
 }
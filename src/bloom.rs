@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{ self, BufReader, BufWriter, Read, Write };
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"ESBLOOM1";
+
+/// Bloom filter over document IDs, saved next to each data file so `find` can skip backups
+/// that cannot contain a document without reading them.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    /// A filter sized for `expected_items` entries at roughly `false_positive_rate`.
+    pub fn new(expected_items: u64, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = ((-items * false_positive_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hash_count = (((bit_count as f64) / items) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+        }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        let positions: Vec<u64> = self.bit_positions(item).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.bit_positions(item).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.hash_count.to_le_bytes())?;
+        writer.write_all(&self.bit_count.to_le_bytes())?;
+        for word in &self.bits {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a bloom filter file"));
+        }
+
+        let mut hash_count = [0u8; 4];
+        reader.read_exact(&mut hash_count)?;
+        let mut bit_count = [0u8; 8];
+        reader.read_exact(&mut bit_count)?;
+        let bit_count = u64::from_le_bytes(bit_count);
+
+        let mut bits = vec![0u64; bit_count.div_ceil(64) as usize];
+        let mut word = [0u8; 8];
+        for slot in bits.iter_mut() {
+            reader.read_exact(&mut word)?;
+            *slot = u64::from_le_bytes(word);
+        }
+
+        Ok(BloomFilter {
+            bits,
+            bit_count,
+            hash_count: u32::from_le_bytes(hash_count),
+        })
+    }
+
+    // Double hashing over two FNV-1a variants keeps the file format independent of std's hasher
+    fn bit_positions(&self, item: &str) -> impl Iterator<Item = u64> + '_ {
+        let h1 = fnv1a(item.as_bytes(), 0xcbf29ce484222325);
+        let h2 = fnv1a(item.as_bytes(), 0x84222325cbf29ce4) | 1;
+        (0..self.hash_count as u64).map(move |i| {
+            h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count
+        })
+    }
+}
+
+fn fnv1a(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(seed, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_of(ids: &[String]) -> BloomFilter {
+        let mut filter = BloomFilter::new(ids.len() as u64, 0.01);
+        for id in ids {
+            filter.insert(id);
+        }
+        filter
+    }
+
+    #[test]
+    fn contains_every_inserted_item() {
+        let ids: Vec<String> = (0..1000).map(|n| format!("doc-{}", n)).collect();
+        let filter = filter_of(&ids);
+        assert!(ids.iter().all(|id| filter.contains(id)));
+    }
+
+    #[test]
+    fn keeps_false_positives_near_the_requested_rate() {
+        let ids: Vec<String> = (0..1000).map(|n| format!("doc-{}", n)).collect();
+        let filter = filter_of(&ids);
+        let false_positives = (0..10_000)
+            .filter(|n| filter.contains(&format!("other-{}", n)))
+            .count();
+        assert!(false_positives < 300, "{} false positives in 10000", false_positives);
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let ids: Vec<String> = (0..100).map(|n| format!("doc-{}", n)).collect();
+        let filter = filter_of(&ids);
+        let path = std::env::temp_dir().join(format!("es-dumper-bloom-{}", std::process::id()));
+        filter.save(&path).unwrap();
+        let loaded = BloomFilter::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.bit_count, filter.bit_count);
+        assert_eq!(loaded.hash_count, filter.hash_count);
+        assert_eq!(loaded.bits, filter.bits);
+        assert!(ids.iter().all(|id| loaded.contains(id)));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("es-dumper-bloom-bad-{}", std::process::id()));
+        std::fs::write(&path, b"not a bloom filter").unwrap();
+        let loaded = BloomFilter::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::utils::{ log, LogFile };
+use crate::http_client::HttpClient;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+/// Pauses every new request for a cooldown while the cluster is struggling, instead of adding
+/// load to it. Trips on `error_threshold` failed requests (connection errors, timeouts, 429 and
+/// 5xx responses) within `window`, and optionally on a red cluster or a backlog of pending
+/// tasks seen by a periodic `_cluster/health` check.
+pub struct CircuitBreaker {
+    settings: BreakerSettings,
+    state: Mutex<BreakerState>,
+    monitor: Mutex<Option<(HttpClient, LogFile)>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakerSettings {
+    /// Failed requests within `window` that trip the breaker; 0 disables the error count.
+    pub error_threshold: usize,
+    pub window: Duration,
+    pub cooldown: Duration,
+    /// Interval of `_cluster/health` checks; none when unset.
+    pub health_check: Option<Duration>,
+    /// Pending cluster tasks above which a health check trips the breaker.
+    pub max_pending_tasks: Option<u64>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: VecDeque<Instant>,
+    open_until: Option<Instant>,
+    next_health_check: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: BreakerSettings) -> Self {
+        CircuitBreaker {
+            settings,
+            state: Mutex::new(BreakerState::default()),
+            monitor: Mutex::new(None),
+        }
+    }
+
+    /// A breaker that never trips.
+    pub fn disabled() -> Self {
+        Self::new(BreakerSettings {
+            error_threshold: 0,
+            window: Duration::ZERO,
+            cooldown: Duration::ZERO,
+            health_check: None,
+            max_pending_tasks: None,
+        })
+    }
+
+    /// HttpClient for the health checks and log for reporting pauses, set by every run.
+    pub fn monitor(&self, client: &HttpClient, log_file: &LogFile) {
+        *self.monitor.lock().unwrap() = Some((client.clone(), Arc::clone(log_file)));
+    }
+
+    /// Blocks while the breaker is open, first running the health check against `host` when one
+    /// is due.
+    pub fn wait(&self, host: &str) {
+        self.check_health(host);
+        loop {
+            let open_until = self.state.lock().unwrap().open_until;
+            match open_until {
+                Some(until) if until > Instant::now() => {
+                    thread::sleep(until.saturating_duration_since(Instant::now()));
+                }
+                _ => {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of a request, tripping the breaker on too many recent failures.
+    pub fn record(&self, failed: bool) {
+        if !failed || self.settings.error_threshold == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.failures.push_back(now);
+        while state.failures.front().is_some_and(|&at| now - at > self.settings.window) {
+            state.failures.pop_front();
+        }
+        if state.failures.len() >= self.settings.error_threshold {
+            state.failures.clear();
+            drop(state);
+            self.trip(
+                &format!(
+                    "{} failed requests within {} seconds",
+                    self.settings.error_threshold,
+                    self.settings.window.as_secs()
+                )
+            );
+        }
+    }
+
+    fn trip(&self, reason: &str) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.is_some_and(|until| until > now) {
+            return;
+        }
+        state.open_until = Some(now + self.settings.cooldown);
+        drop(state);
+
+        if let Some((_, log_file)) = &*self.monitor.lock().unwrap() {
+            let _ = log(
+                log_file,
+                &format!(
+                    "Cluster is struggling ({}), pausing requests for {} seconds",
+                    reason,
+                    self.settings.cooldown.as_secs()
+                )
+            );
+        }
+    }
+
+    fn check_health(&self, host: &str) {
+        let Some(interval) = self.settings.health_check else {
+            return;
+        };
+        {
+            // Only one request per interval runs the check
+            let now = Instant::now();
+            let mut state = self.state.lock().unwrap();
+            if state.next_health_check.is_some_and(|next| next > now) {
+                return;
+            }
+            state.next_health_check = Some(now + interval);
+        }
+        let Some(client) = self.monitor
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(client, _)| client.clone()) else {
+            return;
+        };
+
+        let health: Value = match
+            client
+                .get(format!("{}/_cluster/health", host))
+                .send()
+                .and_then(|response| response.json())
+        {
+            Ok(health) => health,
+            Err(_) => {
+                self.record(true);
+                return;
+            }
+        };
+
+        let pending_tasks = health["number_of_pending_tasks"].as_u64().unwrap_or(0);
+        if health["status"] == "red" {
+            self.trip("cluster health is red");
+        } else if self.settings.max_pending_tasks.is_some_and(|max| pending_tasks > max) {
+            self.trip(&format!("{} pending cluster tasks", pending_tasks));
+        }
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker").field("settings", &self.settings).finish()
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::{ HashMap, HashSet };
+
+/// Boolean `--flag` options accepted after the operation and index arguments.
+const FLAGS: &[&str] = &[
+    "no-local-files",
+    "strict",
+    "resume",
+    "metadata-only",
+    "mapping-only",
+    "data-only",
+    "overwrite",
+    "skip-existing",
+    "append",
+    "restore-transforms",
+    "start-transforms",
+    "restore-watches",
+    "prefer-backup",
+    "prefer-template",
+    "split-types",
+    "update-existing",
+    "force",
+    "self-test",
+];
+
+/// Options that take a value, given as `--name value` or `--name=value`. Options given more
+/// than once keep every value, in order.
+const VALUE_FLAGS: &[&str] = &[
+    "max-duration",
+    "max-index-duration",
+    "max-docs",
+    "id",
+    "index",
+    "format",
+    "bulk-file",
+    "record",
+    "replay",
+    "index-settings",
+    "include",
+    "skip",
+    "from",
+    "source-cluster",
+];
+
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub positional: Vec<String>,
+    flags: HashSet<String>,
+    values: HashMap<String, Vec<String>>,
+}
+
+impl CliArgs {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut cli = CliArgs::default();
+
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if FLAGS.contains(&name) => {
+                    cli.flags.insert(name.to_string());
+                }
+                Some(name) if VALUE_FLAGS.contains(&name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Option '--{}' requires a value", name))?;
+                    cli.values.entry(name.to_string()).or_default().push(value);
+                }
+                Some(option) if option.contains('=') => {
+                    let (name, value) = option.split_once('=').unwrap();
+                    if !VALUE_FLAGS.contains(&name) {
+                        return Err(format!("Unknown option '--{}'", name));
+                    }
+                    cli.values.entry(name.to_string()).or_default().push(value.to_string());
+                }
+                Some(name) => {
+                    return Err(format!("Unknown option '--{}'", name));
+                }
+                None => cli.positional.push(arg),
+            }
+        }
+
+        Ok(cli)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// The value of an option, the last one when it was given more than once.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).and_then(|values| values.last()).map(String::as_str)
+    }
+
+    /// Every value of a repeatable option, e.g. `--index-settings a=1 --index-settings b=2`.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.values
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The comma-separated entries of an option, e.g. `--skip "logs-*,tmp"`.
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.value(name)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn arg(&self, position: usize) -> Option<String> {
+        self.positional.get(position).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn separates_positional_arguments_flags_and_values() {
+        let cli = parse(&["restore", "logs", "--overwrite", "--max-docs", "10", "--format=ndjson"])
+            .unwrap();
+        assert_eq!(cli.positional, ["restore", "logs"]);
+        assert_eq!(cli.arg(1).as_deref(), Some("logs"));
+        assert_eq!(cli.arg(2), None);
+        assert!(cli.has("overwrite"));
+        assert!(!cli.has("strict"));
+        assert_eq!(cli.value("max-docs"), Some("10"));
+        assert_eq!(cli.value("format"), Some("ndjson"));
+        assert_eq!(cli.value("id"), None);
+    }
+
+    #[test]
+    fn keeps_every_value_of_repeated_options() {
+        let cli = parse(&[
+            "restore",
+            "--index-settings",
+            "number_of_replicas=0",
+            "--index-settings=refresh_interval=1s,5s",
+        ]).unwrap();
+        assert_eq!(
+            cli.values("index-settings"),
+            ["number_of_replicas=0", "refresh_interval=1s,5s"]
+        );
+        assert_eq!(cli.value("index-settings"), Some("refresh_interval=1s,5s"));
+        assert!(cli.values("skip").is_empty());
+    }
+
+    #[test]
+    fn splits_lists_on_commas() {
+        let cli = parse(&["backup", "--skip", " logs-*, ,tmp,"]).unwrap();
+        assert_eq!(cli.list("skip"), ["logs-*", "tmp"]);
+        assert!(cli.list("include").is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_options_and_missing_values() {
+        assert_eq!(parse(&["backup", "--nope"]).unwrap_err(), "Unknown option '--nope'");
+        assert_eq!(parse(&["backup", "--strict=yes"]).unwrap_err(), "Unknown option '--strict'");
+        assert_eq!(
+            parse(&["backup", "--max-docs"]).unwrap_err(),
+            "Option '--max-docs' requires a value"
+        );
+    }
+}
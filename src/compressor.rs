@@ -0,0 +1,122 @@
+use crate::utils::{ compress_file, log, sync_file, LogFile };
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+
+/// Gzips finished data files on worker threads of its own, so compressing the data of one index
+/// overlaps with exporting the next instead of holding up its backup. Workers are started as
+/// files are queued, up to `workers` at a time, and exit once the queue is empty.
+pub struct CompressionPool {
+    workers: usize,
+    shared: Arc<Shared>,
+}
+
+/// A data file waiting to be compressed.
+pub struct CompressionTask {
+    pub index: String,
+    pub path: PathBuf,
+    /// Sync the compressed file to disk once written.
+    pub sync: bool,
+    pub log_file: LogFile,
+}
+
+struct Shared {
+    state: Mutex<PoolState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    queue: VecDeque<CompressionTask>,
+    /// Indices of the files being compressed.
+    running: Vec<String>,
+    workers: usize,
+    /// Indices whose files failed to compress, with the error.
+    failures: Vec<(String, String)>,
+}
+
+impl CompressionPool {
+    pub fn new(workers: usize) -> Self {
+        CompressionPool {
+            workers: workers.max(1),
+            shared: Arc::new(Shared {
+                state: Mutex::new(PoolState::default()),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Queues a file, starting a worker for it when fewer than `workers` are running.
+    pub fn submit(&self, task: CompressionTask) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.push_back(task);
+        if state.workers < self.workers {
+            state.workers += 1;
+            let shared = Arc::clone(&self.shared);
+            thread::spawn(move || work(&shared));
+        }
+    }
+
+    /// Blocks until no file of `indices` is queued or being compressed, then returns the
+    /// indices among them whose files failed to compress, with the error.
+    pub fn wait_for(&self, indices: &[String]) -> Vec<(String, String)> {
+        let pending = |state: &PoolState| {
+            state.queue.iter().any(|task| indices.contains(&task.index)) ||
+                state.running.iter().any(|index| indices.contains(index))
+        };
+        let mut state = self.shared.state.lock().unwrap();
+        while pending(&state) {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        let (failures, others) = std::mem::take(&mut state.failures)
+            .into_iter()
+            .partition(|(index, _)| indices.contains(index));
+        state.failures = others;
+        failures
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let task = {
+            let mut state = shared.state.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(task) => {
+                    state.running.push(task.index.clone());
+                    task
+                }
+                None => {
+                    state.workers -= 1;
+                    return;
+                }
+            }
+        };
+
+        let _ = log(&task.log_file, &format!("Compressing data file for index: {}", task.index));
+        let result = compress_file(&task.path).and_then(|()| {
+            if task.sync {
+                sync_file(&PathBuf::from(format!("{}.gz", task.path.display())))?;
+            }
+            Ok(())
+        });
+
+        let mut state = shared.state.lock().unwrap();
+        if let Some(position) = state.running.iter().position(|index| *index == task.index) {
+            state.running.swap_remove(position);
+        }
+        if let Err(e) = result {
+            let message = format!("Failed to compress {}: {}", task.path.display(), e);
+            let _ = log(&task.log_file, &message);
+            state.failures.push((task.index, message));
+        }
+        shared.changed.notify_all();
+    }
+}
+
+impl fmt::Debug for CompressionPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionPool").field("workers", &self.workers).finish()
+    }
+}
@@ -1,6 +1,17 @@
+use crate::audit::AuditLog;
+use crate::compressor::CompressionPool;
+use crate::http_client::HostPool;
+use crate::output::DataFormat;
+use crate::pacer::{ RestoreThrottle, ShardWatch, WritePacer };
+use crate::plugins::PluginSelection;
+use crate::scheduler::SliceScheduler;
 use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{ Read, Write };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Operation {
@@ -10,23 +21,347 @@ pub enum Operation {
     Restore {
         index: Option<String>,
     },
+    History {
+        index: Option<String>,
+    },
+    Serve {
+        listen: String,
+    },
+    Find {
+        id: String,
+        index: Option<String>,
+    },
+    VerifyAudit {
+        path: Option<String>,
+    },
+    CleanupScrolls,
+}
+
+/// How hard backup writes are pushed to disk before an index is reported as done.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Leave buffering to the writer and the OS; only flush once the file is complete.
+    None,
+    /// Flush buffered data after every page of documents.
+    #[default]
+    Flush,
+    /// Flush every page and fsync each file once it is complete.
+    Fsync,
+}
+
+/// `refresh` parameter of restore `_bulk` requests.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BulkRefresh {
+    /// Leave refreshing to the index's `refresh_interval`.
+    #[default]
+    #[serde(rename = "false")]
+    False,
+    /// Return once the batch is searchable, without forcing a refresh.
+    #[serde(rename = "wait_for")]
+    WaitFor,
+    /// Refresh the affected shards after every batch.
+    #[serde(rename = "true")]
+    True,
+}
+
+impl BulkRefresh {
+    /// The query parameter, or None when `_bulk` is sent without one.
+    pub fn as_param(self) -> Option<&'static str> {
+        match self {
+            BulkRefresh::False => None,
+            BulkRefresh::WaitFor => Some("wait_for"),
+            BulkRefresh::True => Some("true"),
+        }
+    }
+}
+
+/// What a restore does when a data file no longer matches the checksum recorded at backup.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumMismatch {
+    /// Fail the index.
+    #[default]
+    Abort,
+    /// Restore it anyway, with a warning in the run report.
+    Warn,
+}
+
+/// Whether restores gzip `_bulk` bodies. Starts out as configured and turns itself off for the
+/// rest of the run when the cluster rejects the first compressed request.
+#[derive(Debug, Default)]
+pub struct BulkCompression {
+    enabled: AtomicBool,
+    /// A compressed request was accepted, so later failures are not blamed on compression.
+    confirmed: AtomicBool,
+}
+
+impl BulkCompression {
+    pub fn new(enabled: bool) -> Self {
+        BulkCompression {
+            enabled: AtomicBool::new(enabled),
+            confirmed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn accepted(&self) {
+        self.confirmed.store(true, Ordering::Relaxed);
+    }
+
+    /// Turns compression off after a rejected request, unless an earlier compressed request
+    /// went through. Returns whether it was turned off by this call.
+    pub fn rejected(&self) -> bool {
+        !self.confirmed.load(Ordering::Relaxed) && self.enabled.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Cluster health a restore waits for before loading data.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Yellow,
+    Green,
+}
+
+impl HealthStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthStatus::Yellow => "yellow",
+            HealthStatus::Green => "green",
+        }
+    }
+}
+
+/// Shard copies that must be active before `_bulk` writes and index creation go ahead: a number
+/// of copies, or `"all"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ActiveShards {
+    Count(u32),
+    Named(String),
+}
+
+impl ActiveShards {
+    pub fn as_param(&self) -> String {
+        match self {
+            ActiveShards::Count(count) => count.to_string(),
+            ActiveShards::Named(name) => name.clone(),
+        }
+    }
+}
+
+/// Which API the cluster offers, for targets lacking parts of the standard one.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compatibility {
+    /// Read the version from the root endpoint and use what that version supports.
+    #[default]
+    Standard,
+    /// OpenSearch Serverless and similar targets without a root endpoint, scroll, point in
+    /// time or `_cat` APIs.
+    Serverless,
+}
+
+/// What a backup does with indices that hold no documents.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyIndexPolicy {
+    /// Leave them out of the backup entirely.
+    Skip,
+    /// Back up their mapping, settings and aliases without a data file.
+    #[default]
+    MappingOnly,
+    /// Back them up like any other index, with an empty data file.
+    Full,
+}
+
+/// When data files are gzipped, in builds with the `compression` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionMode {
+    /// Compress the documents as they are written, leaving only the `.gz` file.
+    #[default]
+    Inline,
+    /// Write the data file uncompressed and gzip it on a compression worker while the next
+    /// indices are exported, keeping both files.
+    Background,
+}
+
+/// What a backup does with closed indices, which can be neither counted nor searched.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedIndexPolicy {
+    /// Leave them out of the backup, listing them in the run report.
+    #[default]
+    Skip,
+    /// Open each one for its backup and close it again afterwards.
+    Open,
+}
+
+/// What a backup does when the target directory already holds data for an index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExistingPolicy {
+    /// Refuse to touch the existing backup.
+    Fail,
+    /// Replace the existing data files.
+    Overwrite,
+    /// Leave the index alone and move on.
+    SkipExisting,
+    /// Add the new documents to the existing data file (appendable formats only).
+    Append,
+}
+
+/// What a restore does when an index template of the target cluster that matches an index
+/// defines parts of its mapping differently from the backup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplateConflicts {
+    /// Refuse to create the index, listing the conflicts.
+    Fail,
+    /// Create the index with the backed-up definitions, which take precedence over the
+    /// template's.
+    PreferBackup,
+    /// Leave the conflicting parts out of the backed-up mapping, so the template's apply.
+    PreferTemplate,
 }
 
 #[derive(Debug)]
 pub struct BackupConfig {
-    pub host: String,
+    pub hosts: HostPool,
+    pub compatibility: Compatibility,
+    pub index_retries: u32,
+    pub index_retry_backoff: Duration,
     pub backup_dir: String,
     pub auth: Option<(String, String)>,
     pub skip_indices: Vec<String>,
     pub max_index_size_mb: Option<u64>,
     pub operation: Operation,
     pub connect_timeout_secs: u64,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout_secs: u64,
+    pub tcp_keepalive_secs: Option<u64>,
+    pub http2: bool,
+    pub user_agent: String,
+    pub opaque_id_prefix: String,
     pub request_timeout_secs: u64,
+    pub metadata_timeout_secs: u64,
+    pub scroll_timeout_secs: u64,
+    pub bulk_timeout_secs: u64,
     pub scroll_size: u64,
     pub scroll_time: String,
+    pub max_scroll_time: String,
+    pub max_scroll_restarts: usize,
+    pub max_slices: usize,
+    /// Budget of scroll slices shared by the indices backed up at a time.
+    pub slice_scheduler: SliceScheduler,
+    pub size_factor: f64,
+    pub min_free_space_mb: u64,
+    pub exclude_field_types: Vec<String>,
+    /// Metadata fields of each hit kept in the data files besides `_id` and `_source`.
+    pub preserve_metadata: Vec<String>,
+    pub dense_vector_min_dims: u64,
+    pub include_watches: bool,
+    pub include_field_caps: bool,
+    /// Shell commands run before and after a backup, and after each of its indices.
+    pub pre_backup_cmd: Option<String>,
+    pub post_backup_cmd: Option<String>,
+    pub post_index_cmd: Option<String>,
+    /// Shell commands run before and after a restore, given the run as JSON on stdin.
+    pub pre_restore_cmd: Option<String>,
+    pub post_restore_cmd: Option<String>,
+    pub doc_values_fallback: bool,
+    pub timestamp_field: Option<String>,
+    pub namespace_by_cluster: bool,
+    pub slice_overrides: HashMap<String, usize>,
+    /// Numeric or date field per index that exports of more than `partition_docs` documents
+    /// are range-partitioned on.
+    pub partition_fields: HashMap<String, String>,
+    pub partition_docs: u64,
+    /// Directory per index pattern that matching indices are backed up into instead of
+    /// `backup_dir`, e.g. `"logs-*" = "/mnt/cold"`.
+    pub index_paths: HashMap<String, String>,
+    /// Field per index, unique per document, that exports paging with `search_after` sort on.
+    pub sort_fields: HashMap<String, String>,
+    pub compression_mode: CompressionMode,
+    /// Workers gzipping data files in the background, shared by the indices of a run.
+    pub compressor: CompressionPool,
+    /// Indices backed up at a time.
     pub max_parallel_indices: usize,
+    /// Indices restored at a time.
+    pub restore_parallel_indices: usize,
+    /// Create the indices of all backups before loading any documents.
+    pub create_indices_first: bool,
+    /// Indices created at a time with `create_indices_first`.
+    pub create_concurrency: usize,
     pub buffer_size: usize,
+    pub durability: Durability,
+    pub empty_indices: EmptyIndexPolicy,
+    pub closed_indices: ClosedIndexPolicy,
     pub bulk_batch_size: usize,
+    pub bulk_max_bytes: usize,
+    pub compress_bulk: BulkCompression,
+    pub bulk_file: Option<String>,
+    pub verify_sample_size: usize,
+    pub bulk_refresh: BulkRefresh,
+    pub final_refresh: bool,
+    pub checksum_mismatch: ChecksumMismatch,
+    /// `_source` field restored documents keep their backed-up `_id` in, letting the cluster
+    /// assign new IDs.
+    pub copy_id_to: Option<String>,
+    pub index_settings: HashMap<String, Value>,
+    pub forcemerge_max_segments: Option<u32>,
+    pub wait_for_status: Option<HealthStatus>,
+    pub wait_for_status_timeout_secs: u64,
+    pub wait_between_indices: bool,
+    pub wait_for_active_shards: Option<ActiveShards>,
+    /// Directory or archive to restore from instead of `backup_dir`.
+    pub restore_from: Option<String>,
+    /// Name or UUID of the cluster whose backups a restore may use.
+    pub source_cluster: Option<String>,
+    /// Patterns (`*` wildcards) of backed-up indices to restore; all of them when empty.
+    pub restore_include: Vec<String>,
+    /// Patterns of backed-up indices left out of a restore.
+    pub restore_skip: Vec<String>,
+    /// Patterns of indices restored before all others, in this order.
+    pub restore_priority: Vec<String>,
+    pub template_conflicts: TemplateConflicts,
+    /// Restore each document type of a multi-type (pre-6.x) backup into an index of its own.
+    pub split_types: bool,
+    /// Update indices that exist already on the target with the fields and dynamic settings
+    /// they lack, instead of failing to create them.
+    pub update_existing: bool,
+    /// Run against cluster versions older than the supported ones.
+    pub force: bool,
+    pub write_pacer: WritePacer,
+    pub shard_watch: ShardWatch,
+    /// Aggregate documents and bytes per second of all indices restored in parallel.
+    pub restore_throttle: RestoreThrottle,
+    /// Plugins attached to the jobs of the command line and the control API.
+    pub plugins: PluginSelection,
+    pub audit: AuditLog,
+    pub report_index: Option<String>,
+    pub report_host: Option<String>,
+    pub strict: bool,
+    pub max_duration: Option<Duration>,
+    /// Time the export of a single index may take before it is stopped and left to `--resume`.
+    pub max_index_duration: Option<Duration>,
+    /// Documents exported per index at most, for bounded test datasets.
+    pub max_docs: Option<u64>,
+    /// Restore each backed-up index into a temporary index and verify it before moving on.
+    pub self_test: bool,
+    pub resume: bool,
+    pub metadata_only: bool,
+    pub existing: ExistingPolicy,
+    pub data_format: DataFormat,
+    pub mapping_only: bool,
+    pub data_only: bool,
+    pub restore_transforms: bool,
+    pub start_transforms: bool,
+    pub restore_watches: bool,
+    pub no_local_files: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,15 +369,48 @@ pub struct ConfigFile {
     pub elastic: ElasticConfig,
     pub backup: BackupConfigFile,
     pub restore: RestoreConfigFile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugins: Option<PluginsConfigFile>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ElasticConfig {
     pub host: Option<String>,
+    pub hosts: Option<Vec<String>>,
+    pub host_retry_secs: Option<u64>,
+    pub compatibility: Option<Compatibility>,
+    /// Times an index is backed up or restored again after failing with a transient error.
+    pub index_retries: Option<u32>,
+    /// Wait before the first retry of an index, doubled before each further one.
+    pub index_retry_backoff_secs: Option<u64>,
     pub username: Option<String>,
     pub password: Option<String>,
     pub timeout_secs: Option<u64>,
+    pub metadata_timeout_secs: Option<u64>,
+    pub scroll_timeout_secs: Option<u64>,
+    pub bulk_timeout_secs: Option<u64>,
     pub connect_timeout_secs: Option<u64>,
+    /// Idle connections kept open per host; unlimited when unset.
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Interval of TCP keep-alive probes on open connections; none when unset.
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Speak HTTP/2 without negotiation (h2c), e.g. to a proxy in front of the cluster.
+    pub http2: Option<bool>,
+    pub user_agent: Option<String>,
+    /// Prefix of the `X-Opaque-Id` sent with every request, followed by the run ID.
+    pub opaque_id_prefix: Option<String>,
+    /// Failed requests within `breaker_window_secs` that pause the run; 0 disables the count.
+    pub breaker_error_threshold: Option<usize>,
+    pub breaker_window_secs: Option<u64>,
+    pub breaker_cooldown_secs: Option<u64>,
+    /// Interval of `_cluster/health` checks that pause the run on a red cluster; none when unset.
+    pub health_check_secs: Option<u64>,
+    pub max_pending_tasks: Option<u64>,
+    /// Index the report of every run is written to; none when unset.
+    pub report_index: Option<String>,
+    /// Monitoring cluster holding `report_index`, instead of the backed-up one.
+    pub report_host: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,28 +418,193 @@ pub struct BackupConfigFile {
     pub backup_dir: Option<String>,
     pub scroll_size: Option<u64>,
     pub scroll_time: Option<String>,
+    pub max_scroll_time: Option<String>,
+    /// Times the export of a slice resumes after its search context expired, paging through a
+    /// point in time with `search_after`; 0 (the default) scrolls and fails the index instead.
+    pub max_scroll_restarts: Option<usize>,
+    /// Indices backed up at a time (and restored, unless `restore.max_parallel_indices` is
+    /// set); derived from the CPUs and memory of the host when unset.
     pub max_parallel_indices: Option<usize>,
+    /// Also measure the round trip to the cluster when deriving `max_parallel_indices`.
+    pub measure_latency: Option<bool>,
     pub skip_indices: Option<Vec<String>>,
     pub max_index_size_mb: Option<u64>,
+    pub max_slices: Option<usize>,
+    /// Scroll slices running at a time across all indices; each index runs its own slices
+    /// without a shared budget when unset.
+    pub max_total_slices: Option<usize>,
+    /// Fraction of `max_total_slices` a single index may run.
+    pub max_slice_share: Option<f64>,
+    pub size_factor: Option<f64>,
+    pub min_free_space_mb: Option<u64>,
+    pub durability: Option<Durability>,
+    pub empty_indices: Option<EmptyIndexPolicy>,
+    pub closed_indices: Option<ClosedIndexPolicy>,
+    pub exclude_field_types: Option<Vec<String>>,
+    /// Hit metadata kept in the data files, from `PRESERVABLE_METADATA`.
+    pub preserve_metadata: Option<Vec<String>>,
+    pub dense_vector_min_dims: Option<u64>,
+    pub include_watches: Option<bool>,
+    /// Also back up each index's `_field_caps` and runtime fields.
+    pub include_field_caps: Option<bool>,
+    /// Shell command run before a backup contacts the cluster; a failure aborts the backup.
+    pub pre_backup_cmd: Option<String>,
+    /// Shell command run once a backup has processed its indices.
+    pub post_backup_cmd: Option<String>,
+    /// Shell command run after each index of a backup, whatever its outcome.
+    pub post_index_cmd: Option<String>,
+    /// Rebuild documents of indices with `_source` disabled from doc values and stored fields.
+    pub doc_values_fallback: Option<bool>,
+    /// Date field whose range is recorded in `<index>_meta.json`, e.g. `@timestamp`.
+    pub timestamp_field: Option<String>,
+    /// Keep the backups of each cluster in a `backup_dir/<cluster name>` directory.
+    pub namespace_by_cluster: Option<bool>,
+    /// Documents per partition of the indices in `partitions`; smaller indices are exported in
+    /// one piece.
+    pub partition_docs: Option<u64>,
+    pub compression_mode: Option<CompressionMode>,
+    /// Data files gzipped at a time with `compression_mode = "background"`.
+    pub compression_workers: Option<usize>,
+    pub slices: Option<HashMap<String, usize>>,
+    /// Field per index to range-partition large exports on, e.g. `"logs" = "@timestamp"`.
+    pub partitions: Option<HashMap<String, String>>,
+    /// Directory per index pattern to back matching indices up into, e.g.
+    /// `"logs-*" = "/mnt/cold"`; the most specific matching pattern wins.
+    pub paths: Option<HashMap<String, String>>,
+    /// Field per index holding a value unique per document, e.g. `"orders" = "order_id"`,
+    /// that exports paging with `search_after` sort on.
+    pub sort_fields: Option<HashMap<String, String>>,
+}
+
+/// Plugins compiled in with the `plugins` feature, by the names they were registered under.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PluginsConfigFile {
+    /// Transforms applied to every backed-up document, in this order.
+    pub transforms: Option<Vec<String>>,
+    /// Storage backend backups are stored in and restores fetch them from.
+    pub storage: Option<String>,
+    /// Options of each plugin, e.g. `[plugins.options.drop-fields]`.
+    pub options: Option<HashMap<String, Value>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RestoreConfigFile {
+    /// Indices restored at a time; `backup.max_parallel_indices` when unset.
+    pub max_parallel_indices: Option<usize>,
+    /// `_bulk` requests in flight across all indices; `max_parallel_indices` when unset.
+    pub max_bulk_concurrency: Option<usize>,
+    /// Create every index with its mapping and settings before any documents are loaded.
+    pub create_indices_first: Option<bool>,
+    /// Index creation requests in flight with `create_indices_first`.
+    pub create_concurrency: Option<usize>,
     pub bulk_batch_size: Option<usize>,
+    pub bulk_max_bytes: Option<usize>,
+    /// Gzip `_bulk` request bodies; the cluster must have `http.compression` enabled.
+    pub compress_bulk: Option<bool>,
+    pub verify_sample_size: Option<usize>,
+    pub bulk_refresh: Option<BulkRefresh>,
+    /// Refresh every restored index before its document count is verified.
+    pub final_refresh: Option<bool>,
+    /// What to do with data files that changed since they were backed up.
+    pub checksum_mismatch: Option<ChecksumMismatch>,
+    /// Copy each document's `_id` into this `_source` field and restore it under a new ID.
+    pub copy_id_to: Option<String>,
+    /// Force-merge every restored index down to this many segments; no merge when unset.
+    pub forcemerge_max_segments: Option<u32>,
+    /// Settings that replace the backed-up ones when an index is created, e.g.
+    /// `number_of_replicas = 0`.
+    pub index_settings: Option<HashMap<String, Value>>,
+    /// Health the cluster must reach before indices are created; no wait when unset.
+    pub wait_for_status: Option<HealthStatus>,
+    pub wait_for_status_timeout_secs: Option<u64>,
+    /// Wait for `wait_for_status` again before every index, not only once per run.
+    pub wait_between_indices: Option<bool>,
+    /// `wait_for_active_shards` sent with every `_bulk` request and index creation; the
+    /// cluster's default (one copy) when unset.
+    pub wait_for_active_shards: Option<ActiveShards>,
+    /// Pause `_bulk` requests and index creation while the cluster has more unassigned shards
+    /// than this; no pause when unset.
+    pub max_unassigned_shards: Option<u64>,
+    /// Fraction of the write thread pool queues above which restores send fewer concurrent
+    /// `_bulk` requests; no pacing when unset.
+    pub max_write_queue_utilization: Option<f64>,
+    pub write_queue_check_secs: Option<u64>,
+    /// Documents per second sent by all `_bulk` requests of a restore together; unlimited when
+    /// unset.
+    pub max_docs_per_sec: Option<u64>,
+    /// Bytes of `_bulk` request bodies per second of a restore; unlimited when unset.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Hash-chained log of the changes restores make to the target cluster; none when unset.
+    pub audit_log: Option<String>,
+    /// Patterns (`*` wildcards) of indices to restore first, in order of priority.
+    pub restore_priority: Option<Vec<String>>,
+    /// Shell command run before a restore creates any index; a failure aborts the restore.
+    pub pre_restore_cmd: Option<String>,
+    /// Shell command run once a restore has processed its indices.
+    pub post_restore_cmd: Option<String>,
 }
 
 pub const DEFAULT_BACKUP_DIR: &str = "./backups";
-pub const DEFAULT_LOG_FILE: &str = "backup.log";
+/// Directory of `backup_dir` holding a log file per run, named by the run ID.
+pub const LOG_DIR: &str = "logs";
+/// Link in `backup_dir` to the log file of the latest run.
+pub const LATEST_LOG: &str = "latest.log";
+pub const DEFAULT_HISTORY_DB: &str = "history.db";
+pub const DEFAULT_STATE_DB: &str = "state.db";
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+pub const DEFAULT_SERVER_LISTEN: &str = "127.0.0.1:8080";
 pub const DEFAULT_ELASTIC_HOST: &str = "http://es.example.com:9200";
+pub const DEFAULT_HOST_RETRY_SECS: u64 = 30;
+pub const DEFAULT_INDEX_RETRIES: u32 = 2;
+pub const DEFAULT_INDEX_RETRY_BACKOFF_SECS: u64 = 10;
 pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 60;
 pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 180;
+pub const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+pub const DEFAULT_USER_AGENT: &str = concat!("es-dumper/", env!("CARGO_PKG_VERSION"));
+pub const DEFAULT_OPAQUE_ID_PREFIX: &str = "es-dumper";
+pub const DEFAULT_BREAKER_ERROR_THRESHOLD: usize = 10;
+pub const DEFAULT_BREAKER_WINDOW_SECS: u64 = 60;
+pub const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
 pub const DEFAULT_SCROLL_SIZE: u64 = 10000;
 pub const DEFAULT_SCROLL_TIME: &str = "10m";
+pub const DEFAULT_MAX_SCROLL_TIME: &str = "1h";
+/// Restarts are opt-in, as resuming needs pages sorted by a point in time rather than the
+/// cheaper unsorted scroll.
+pub const DEFAULT_MAX_SCROLL_RESTARTS: usize = 0;
 pub const DEFAULT_MAX_PARALLEL_INDICES: usize = 4;
+pub const MAX_AUTO_PARALLEL_INDICES: usize = 16;
+pub const AUTO_PARALLEL_MEMORY_PER_INDEX_MB: u64 = 512;
+/// Cluster round trip above which automatic parallelism runs two indices per CPU.
+pub const AUTO_PARALLEL_HIGH_LATENCY_MS: u64 = 50;
+pub const DEFAULT_MAX_SLICES: usize = 4;
+pub const DEFAULT_MAX_SLICE_SHARE: f64 = 0.5;
+pub const DEFAULT_PARTITION_DOCS: u64 = 10_000_000;
+pub const DEFAULT_COMPRESSION_WORKERS: usize = 2;
+/// Most partitions an index is split into, whatever its size.
+pub const MAX_PARTITIONS: usize = 100;
+pub const DEFAULT_SIZE_FACTOR: f64 = 1.0;
+pub const DEFAULT_MIN_FREE_SPACE_MB: u64 = 1024;
+pub const DEFAULT_DENSE_VECTOR_MIN_DIMS: u64 = 0;
+/// Metadata fields of search hits that `preserve_metadata` may keep in the data files.
+pub const PRESERVABLE_METADATA: &[&str] = &["_routing", "_version", "_seq_no", "_primary_term"];
+pub const DEFAULT_PRESERVE_METADATA: &[&str] = &["_routing"];
 pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_CREATE_CONCURRENCY: usize = 8;
 pub const DEFAULT_BULK_BATCH_SIZE: usize = 5000;
+pub const DEFAULT_BULK_MAX_BYTES: usize = 10 * 1024 * 1024;
+pub const DEFAULT_VERIFY_SAMPLE_SIZE: usize = 0;
+pub const DEFAULT_WAIT_FOR_STATUS_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_WRITE_QUEUE_CHECK_SECS: u64 = 5;
+
+/// Skipped indices named per reason in the summary printed at the end of a run.
+pub const SKIPPED_INDICES_SHOWN: usize = 10;
+
+/// Process exit status when `--max-duration` stopped the run before every index was processed.
+pub const EXIT_DEADLINE_REACHED: i32 = 3;
 
-pub fn load_config() -> Result<ConfigFile, Box<dyn std::error::Error>> {
+/// Loads `config.toml`, creating it with default values when it is missing and `create_default`
+/// is set.
+pub fn load_config(create_default: bool) -> Result<ConfigFile, Box<dyn std::error::Error>> {
     let config_path = "config.toml";
     let mut config_content = String::new();
 
@@ -83,27 +616,110 @@ pub fn load_config() -> Result<ConfigFile, Box<dyn std::error::Error>> {
             let default_config = ConfigFile {
                 elastic: ElasticConfig {
                     host: Some(DEFAULT_ELASTIC_HOST.to_string()),
+                    hosts: None,
+                    host_retry_secs: Some(DEFAULT_HOST_RETRY_SECS),
+                    compatibility: None,
+                    index_retries: Some(DEFAULT_INDEX_RETRIES),
+                    index_retry_backoff_secs: Some(DEFAULT_INDEX_RETRY_BACKOFF_SECS),
                     username: Some("es_user".to_string()),
                     password: Some("securepass123".to_string()),
                     timeout_secs: Some(DEFAULT_REQUEST_TIMEOUT_SECS),
+                    metadata_timeout_secs: None,
+                    scroll_timeout_secs: None,
+                    bulk_timeout_secs: None,
                     connect_timeout_secs: Some(DEFAULT_CONNECT_TIMEOUT_SECS),
+                    pool_max_idle_per_host: None,
+                    pool_idle_timeout_secs: None,
+                    tcp_keepalive_secs: None,
+                    http2: None,
+                    user_agent: None,
+                    opaque_id_prefix: None,
+                    breaker_error_threshold: Some(DEFAULT_BREAKER_ERROR_THRESHOLD),
+                    breaker_window_secs: Some(DEFAULT_BREAKER_WINDOW_SECS),
+                    breaker_cooldown_secs: Some(DEFAULT_BREAKER_COOLDOWN_SECS),
+                    health_check_secs: None,
+                    max_pending_tasks: None,
+                    report_index: None,
+                    report_host: None,
                 },
                 backup: BackupConfigFile {
                     backup_dir: Some(DEFAULT_BACKUP_DIR.to_string()),
                     scroll_size: Some(DEFAULT_SCROLL_SIZE),
                     scroll_time: Some(DEFAULT_SCROLL_TIME.to_string()),
-                    max_parallel_indices: Some(DEFAULT_MAX_PARALLEL_INDICES),
+                    max_scroll_time: Some(DEFAULT_MAX_SCROLL_TIME.to_string()),
+                    max_scroll_restarts: Some(DEFAULT_MAX_SCROLL_RESTARTS),
+                    max_parallel_indices: None,
+                    measure_latency: None,
                     skip_indices: Some(vec![]),
                     max_index_size_mb: None,
+                    max_slices: Some(DEFAULT_MAX_SLICES),
+                    max_total_slices: None,
+                    max_slice_share: Some(DEFAULT_MAX_SLICE_SHARE),
+                    size_factor: Some(DEFAULT_SIZE_FACTOR),
+                    min_free_space_mb: Some(DEFAULT_MIN_FREE_SPACE_MB),
+                    durability: Some(Durability::default()),
+                    empty_indices: Some(EmptyIndexPolicy::default()),
+                    closed_indices: Some(ClosedIndexPolicy::default()),
+                    exclude_field_types: Some(vec![]),
+                    preserve_metadata: Some(
+                        DEFAULT_PRESERVE_METADATA.iter()
+                            .map(|field| field.to_string())
+                            .collect()
+                    ),
+                    dense_vector_min_dims: Some(DEFAULT_DENSE_VECTOR_MIN_DIMS),
+                    include_watches: Some(false),
+                    include_field_caps: Some(false),
+                    pre_backup_cmd: None,
+                    post_backup_cmd: None,
+                    post_index_cmd: None,
+                    doc_values_fallback: Some(false),
+                    timestamp_field: None,
+                    namespace_by_cluster: Some(false),
+                    partition_docs: Some(DEFAULT_PARTITION_DOCS),
+                    compression_mode: None,
+                    compression_workers: None,
+                    slices: None,
+                    partitions: None,
+                    paths: None,
+                    sort_fields: None,
                 },
                 restore: RestoreConfigFile {
+                    max_parallel_indices: None,
+                    max_bulk_concurrency: None,
+                    create_indices_first: Some(false),
+                    create_concurrency: None,
                     bulk_batch_size: Some(DEFAULT_BULK_BATCH_SIZE),
+                    bulk_max_bytes: Some(DEFAULT_BULK_MAX_BYTES),
+                    compress_bulk: Some(false),
+                    verify_sample_size: Some(DEFAULT_VERIFY_SAMPLE_SIZE),
+                    bulk_refresh: Some(BulkRefresh::default()),
+                    final_refresh: Some(true),
+                    checksum_mismatch: Some(ChecksumMismatch::default()),
+                    copy_id_to: None,
+                    forcemerge_max_segments: None,
+                    index_settings: None,
+                    wait_for_status: None,
+                    wait_for_status_timeout_secs: None,
+                    wait_between_indices: None,
+                    wait_for_active_shards: None,
+                    max_unassigned_shards: None,
+                    max_write_queue_utilization: None,
+                    write_queue_check_secs: None,
+                    max_docs_per_sec: None,
+                    max_bytes_per_sec: None,
+                    audit_log: None,
+                    restore_priority: None,
+                    pre_restore_cmd: None,
+                    post_restore_cmd: None,
                 },
+                plugins: None,
             };
 
-            let toml_content = toml::to_string(&default_config)?;
-            let mut file = File::create(config_path)?;
-            file.write_all(toml_content.as_bytes())?;
+            if create_default {
+                let toml_content = toml::to_string(&default_config)?;
+                let mut file = File::create(config_path)?;
+                file.write_all(toml_content.as_bytes())?;
+            }
             return Ok(default_config);
         }
     }
@@ -0,0 +1,271 @@
+use crate::error::EsDumperError;
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    FixedOffset,
+    Months,
+    NaiveDate,
+    TimeZone,
+    Timelike,
+    Utc,
+};
+
+/// Format of date math expressions without one, as in Elasticsearch.
+const DEFAULT_FORMAT: &str = "yyyy.MM.dd";
+
+/// Resolves the date math expressions in an index name the way Elasticsearch resolves date
+/// math index names, e.g. `logs-{now-1d}` to `logs-2025.01.01` and
+/// `metrics-{now/M{yyyy.MM}}` to `metrics-2025.01`. An expression is `now`, followed by any
+/// number of `+<n><unit>`, `-<n><unit>` and `/<unit>` (rounding down) operations with units
+/// `y`, `M`, `w`, `d`, `h`, `m` and `s`, and optionally a `{format}` or `{format|+01:00}`
+/// block with a Java-style format (`yyyy`, `yy`, `MM`, `dd`, `HH`, `mm`, `ss`) and a UTC
+/// offset. Names may be wrapped in `<...>` like in Elasticsearch; names without expressions
+/// are returned unchanged.
+pub fn resolve_index_name(name: &str, now: DateTime<Utc>) -> Result<String, EsDumperError> {
+    let invalid = |reason: &str| {
+        EsDumperError::Config(format!("Invalid date math in index name '{}': {}", name, reason))
+    };
+    let unwrapped = name
+        .strip_prefix('<')
+        .and_then(|name| name.strip_suffix('>'))
+        .unwrap_or(name);
+
+    let mut resolved = String::new();
+    let mut rest = unwrapped;
+    while let Some(start) = rest.find("{now") {
+        resolved.push_str(&rest[..start]);
+        let expression = &rest[start + 1..];
+
+        // The expression ends at the brace matching its opening one
+        let mut depth = 1;
+        let end = expression
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '{' => {
+                        depth += 1;
+                    }
+                    '}' => {
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(position, _)| position)
+            .ok_or_else(|| invalid("unclosed '{'"))?;
+        resolved.push_str(&evaluate(&expression[..end], now).map_err(|reason| invalid(&reason))?);
+        rest = &expression[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// Evaluates one expression, without its outer braces.
+fn evaluate(expression: &str, now: DateTime<Utc>) -> Result<String, String> {
+    let (math, format) = match expression.find('{') {
+        Some(start) =>
+            (
+                &expression[..start],
+                expression[start + 1..]
+                    .strip_suffix('}')
+                    .ok_or_else(|| "unclosed format".to_string())?,
+            ),
+        None => (expression, DEFAULT_FORMAT),
+    };
+    let (format, offset) = match format.split_once('|') {
+        Some((format, zone)) => (format, parse_offset(zone)?),
+        None => (format, FixedOffset::east_opt(0).unwrap()),
+    };
+
+    let mut time = now.with_timezone(&offset);
+    let mut operations = math.strip_prefix("now").ok_or_else(|| "expected 'now'".to_string())?;
+    while let Some(operator) = operations.chars().next() {
+        operations = &operations[1..];
+        let digits = operations
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(operations.len());
+        let amount = &operations[..digits];
+        let unit = operations[digits..]
+            .chars()
+            .next()
+            .ok_or_else(|| format!("missing unit after '{}{}'", operator, amount))?;
+        operations = &operations[digits + unit.len_utf8()..];
+
+        time = match operator {
+            '/' if amount.is_empty() => round_down(time, unit)?,
+            '+' | '-' => {
+                let amount: i64 = amount
+                    .parse()
+                    .map_err(|_| format!("missing amount after '{}'", operator))?;
+                add(time, if operator == '-' { -amount } else { amount }, unit)?
+            }
+            _ => {
+                return Err(format!("unexpected '{}'", operator));
+            }
+        };
+    }
+    format_date(time, format)
+}
+
+fn parse_offset(zone: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("unsupported time zone '{}' (expected a UTC offset like +01:00)", zone);
+    if zone == "UTC" || zone == "Z" {
+        return Ok(FixedOffset::east_opt(0).unwrap());
+    }
+    let sign = match zone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => {
+            return Err(invalid());
+        }
+    };
+    let (hours, minutes) = zone[1..].split_once(':').unwrap_or((&zone[1..], "0"));
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+fn add(
+    time: DateTime<FixedOffset>,
+    amount: i64,
+    unit: char
+) -> Result<DateTime<FixedOffset>, String> {
+    let months = |months: i64| {
+        let shift = Months::new(months.unsigned_abs() as u32);
+        if months < 0 { time.checked_sub_months(shift) } else { time.checked_add_months(shift) }
+    };
+    let result = match unit {
+        'y' => months(amount * 12),
+        'M' => months(amount),
+        'w' => time.checked_add_signed(Duration::weeks(amount)),
+        'd' => time.checked_add_signed(Duration::days(amount)),
+        'h' | 'H' => time.checked_add_signed(Duration::hours(amount)),
+        'm' => time.checked_add_signed(Duration::minutes(amount)),
+        's' => time.checked_add_signed(Duration::seconds(amount)),
+        _ => {
+            return Err(format!("unknown unit '{}'", unit));
+        }
+    };
+    result.ok_or_else(|| "date out of range".to_string())
+}
+
+fn round_down(time: DateTime<FixedOffset>, unit: char) -> Result<DateTime<FixedOffset>, String> {
+    let date = time.date_naive();
+    let start = match unit {
+        'y' => NaiveDate::from_ymd_opt(date.year(), 1, 1).and_then(|day| day.and_hms_opt(0, 0, 0)),
+        'M' => date.with_day(1).and_then(|day| day.and_hms_opt(0, 0, 0)),
+        'w' => {
+            let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+            monday.and_hms_opt(0, 0, 0)
+        }
+        'd' => date.and_hms_opt(0, 0, 0),
+        'h' | 'H' => date.and_hms_opt(time.hour(), 0, 0),
+        'm' => date.and_hms_opt(time.hour(), time.minute(), 0),
+        's' => date.and_hms_opt(time.hour(), time.minute(), time.second()),
+        _ => {
+            return Err(format!("unknown unit '{}'", unit));
+        }
+    };
+    start
+        .and_then(|start| time.timezone().from_local_datetime(&start).single())
+        .ok_or_else(|| "date out of range".to_string())
+}
+
+/// Formats a date with a Java-style pattern of the letters Elasticsearch index names usually
+/// use.
+fn format_date(time: DateTime<FixedOffset>, format: &str) -> Result<String, String> {
+    let mut chrono_format = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_alphabetic() {
+            if c == '%' {
+                chrono_format.push('%');
+            }
+            chrono_format.push(c);
+            continue;
+        }
+        let mut run = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            run += 1;
+        }
+        let specifier = match (c, run) {
+            ('y' | 'u', 4) => "%Y",
+            ('y' | 'u', 2) => "%y",
+            ('M', 2) => "%m",
+            ('d', 2) => "%d",
+            ('H', 2) => "%H",
+            ('m', 2) => "%M",
+            ('s', 2) => "%S",
+            _ => {
+                return Err(format!("unsupported format '{}'", format));
+            }
+        };
+        chrono_format.push_str(specifier);
+    }
+    Ok(time.format(&chrono_format).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 5, 14, 30, 15).unwrap()
+    }
+
+    fn resolve(name: &str) -> String {
+        resolve_index_name(name, now()).unwrap()
+    }
+
+    #[test]
+    fn names_without_expressions_are_unchanged() {
+        assert_eq!(resolve("logs-2025"), "logs-2025");
+        assert_eq!(resolve("<logs>"), "logs");
+    }
+
+    #[test]
+    fn resolves_now_with_the_default_format() {
+        assert_eq!(resolve("logs-{now}"), "logs-2025.03.05");
+        assert_eq!(resolve("<logs-{now}>"), "logs-2025.03.05");
+    }
+
+    #[test]
+    fn applies_operations_in_order() {
+        assert_eq!(resolve("logs-{now-1d}"), "logs-2025.03.04");
+        assert_eq!(resolve("logs-{now-5d/M}"), "logs-2025.02.01");
+        assert_eq!(resolve("logs-{now/M-1d}"), "logs-2025.02.28");
+        assert_eq!(resolve("logs-{now+1y-2M}"), "logs-2026.01.05");
+        assert_eq!(resolve("logs-{now/w}"), "logs-2025.03.03");
+    }
+
+    #[test]
+    fn applies_formats_and_offsets() {
+        assert_eq!(resolve("metrics-{now/M{yyyy.MM}}"), "metrics-2025.03");
+        assert_eq!(resolve("logs-{now{yy-MM-dd HH:mm:ss}}"), "logs-25-03-05 14:30:15");
+        assert_eq!(resolve("logs-{now{HH|+10:00}}"), "logs-00");
+        assert_eq!(resolve("logs-{now/d{yyyy.MM.dd|-15:00}}"), "logs-2025.03.04");
+    }
+
+    #[test]
+    fn resolves_several_expressions() {
+        assert_eq!(resolve("{now/y{yyyy}}-{now{MM}}"), "2025-03");
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for name in [
+            "logs-{now",
+            "logs-{now-d}",
+            "logs-{now-1}",
+            "logs-{now-1q}",
+            "logs-{now*2d}",
+            "logs-{now{yyyy.MMM}}",
+            "logs-{now{yyyy|Europe/Paris}}",
+        ] {
+            assert!(resolve_index_name(name, now()).is_err(), "{} should be rejected", name);
+        }
+    }
+}
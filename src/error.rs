@@ -0,0 +1,95 @@
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
+use std::io;
+use thiserror::Error;
+
+/// Errors of backup and restore runs, typed so callers can tell an authentication failure from
+/// an expired scroll or a full disk.
+#[derive(Debug, Error)]
+pub enum EsDumperError {
+    /// Invalid configuration, command-line arguments or existing backup layout.
+    #[error("{0}")]
+    Config(String),
+    /// The cluster could not be reached, or a request timed out.
+    #[error("Connection error: {0}")]
+    Connection(#[from] reqwest::Error),
+    /// The cluster rejected the configured credentials.
+    #[error("Authentication failed ({status}): {body}")]
+    Auth {
+        status: u16,
+        body: String,
+    },
+    /// The cluster answered a request with an error status.
+    #[error("{context} ({status}): {body}")]
+    EsApi {
+        context: String,
+        status: u16,
+        body: String,
+    },
+    /// The cluster answered with a body this tool does not understand.
+    #[error("{0}")]
+    UnexpectedResponse(String),
+    /// Local file errors, including running out of disk space (`ErrorKind::StorageFull`).
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    /// The state store in `backup_dir` could not be read or updated.
+    #[error("State store error: {0}")]
+    State(#[from] rusqlite::Error),
+    /// Some of the work was done but not all of it, e.g. documents rejected by `_bulk` or a
+    /// failed verification under `--strict`.
+    #[error("{0}")]
+    PartialFailure(String),
+    /// A configured hook command could not be started or exited unsuccessfully.
+    #[error("{0}")]
+    Hook(String),
+    /// The work of an index panicked, a bug caught so the other indices carry on.
+    #[error("{0}")]
+    Panic(String),
+    #[error("Job cancelled")]
+    Cancelled,
+}
+
+impl EsDumperError {
+    /// Builds the error for an unsuccessful response, consuming its body.
+    pub fn from_response(context: impl Into<String>, response: Response) -> Self {
+        let status = response.status();
+        Self::from_status(context, status, response.text().unwrap_or_default())
+    }
+
+    /// Builds the error for an unsuccessful response whose body has already been read.
+    pub fn from_status(context: impl Into<String>, status: StatusCode, body: String) -> Self {
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return EsDumperError::Auth { status: status.as_u16(), body };
+        }
+        EsDumperError::EsApi { context: context.into(), status: status.as_u16(), body }
+    }
+
+    /// Whether the error is likely to pass on its own, such as a dropped connection, a timeout
+    /// or an overloaded cluster, so the work that failed is worth retrying.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            EsDumperError::Connection(e) => !e.is_decode() && !e.is_builder(),
+            EsDumperError::EsApi { status, .. } => *status == 429 || *status >= 500,
+            EsDumperError::Io(e) =>
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionReset |
+                        io::ErrorKind::ConnectionAborted |
+                        io::ErrorKind::TimedOut
+                ),
+            _ => false,
+        }
+    }
+
+    /// An out-of-space condition in `backup_dir`, reported as an I/O error.
+    pub fn storage_full(message: String) -> Self {
+        EsDumperError::Io(io::Error::new(io::ErrorKind::StorageFull, message))
+    }
+}
+
+/// An I/O error for a malformed data file.
+pub fn invalid_data(message: String) -> EsDumperError {
+    EsDumperError::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
@@ -0,0 +1,111 @@
+use crate::bloom::BloomFilter;
+use crate::config::BackupConfig;
+use crate::manifest::IndexManifest;
+use crate::output::{ data_file_candidates, partition_files, read_documents };
+use crate::utils::{ index_backup_dirs, log, matches_pattern, LogFile };
+use chrono::{ DateTime, Local };
+use serde_json::Value;
+use std::fs::{ self, File };
+use std::io::BufReader;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+
+/// Lists the index backups in `backup_dir` that contain the document `id`. Backups whose ID
+/// filter rules the document out are skipped without reading their data file.
+pub fn find_document(
+    config: &BackupConfig,
+    id: &str,
+    index_pattern: Option<&str>,
+    log_file: &LogFile
+) -> Result<(), Box<dyn std::error::Error>> {
+    let indices: Vec<String> = index_backup_dirs(Path::new(&config.backup_dir))?
+        .into_iter()
+        .filter(|index| index_pattern.is_none_or(|pattern| matches_pattern(pattern, index)))
+        .collect();
+
+    let mut found = 0;
+    let mut scanned = 0;
+    for index in &indices {
+        let index_dir = Path::new(&config.backup_dir).join(index);
+        let data_paths = data_files(&index_dir, index);
+        if data_paths.is_empty() {
+            continue;
+        }
+
+        if let Ok(ids) = BloomFilter::load(&index_dir.join(format!("{}_ids.bloom", index))) {
+            if !ids.contains(id) {
+                continue;
+            }
+        }
+
+        for data_path in &data_paths {
+            scanned += 1;
+            let documents = read_data_file(data_path)?;
+            let Some(doc) = documents.iter().find(|doc| doc["_id"].as_str() == Some(id)) else {
+                continue;
+            };
+            found += 1;
+            let backed_up_at = fs
+                ::metadata(data_path)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| {
+                    DateTime::<Local>::from(modified).format("%Y-%m-%d %H:%M:%S").to_string()
+                })
+                .unwrap_or_else(|_| "unknown time".to_string());
+            println!("{} (backed up {}): {}", index, backed_up_at, data_path.display());
+            println!("  {}", doc["_source"]);
+            break;
+        }
+    }
+
+    if found == 0 {
+        println!("Document '{}' not found in {} backups", id, indices.len());
+    }
+    log(
+        log_file,
+        &format!(
+            "Searched {} backups for document {} ({} data files scanned, {} matches)",
+            indices.len(),
+            id,
+            scanned,
+            found
+        )
+    )?;
+    Ok(())
+}
+
+/// Existing data files of a backup, one per partition for partitioned backups.
+fn data_files(index_dir: &Path, index: &str) -> Vec<PathBuf> {
+    let partitions = IndexManifest::load(index_dir, index)
+        .ok()
+        .flatten()
+        .map(|manifest| manifest.partitions)
+        .unwrap_or_default();
+    if partitions.is_empty() {
+        return data_file_candidates(index_dir, index)
+            .into_iter()
+            .find(|path| path.exists())
+            .into_iter()
+            .collect();
+    }
+    partitions
+        .iter()
+        .filter_map(|partition| {
+            partition_files(index_dir, partition)
+                .into_iter()
+                .find(|path| path.exists())
+        })
+        .collect()
+}
+
+fn read_data_file(path: &Path) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        let output = Command::new("gunzip").arg("-c").arg(path).output()?;
+        if !output.status.success() {
+            return Err(format!("Failed to uncompress {}", path.display()).into());
+        }
+        return Ok(read_documents(&output.stdout[..], &path.with_extension(""))?);
+    }
+
+    Ok(read_documents(BufReader::new(File::open(path)?), path)?)
+}
@@ -0,0 +1,172 @@
+use crate::error::EsDumperError;
+use reqwest::blocking::{ Request, RequestBuilder, Response };
+use serde::{ Deserialize, Serialize };
+use std::collections::{ HashMap, VecDeque };
+use std::fs::{ self, File };
+use std::io::BufReader;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Mutex;
+
+/// One request and the cluster's answer, stored as a numbered JSON file in the fixtures directory.
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    /// Path and query string, without the host, so fixtures replay against any configured host.
+    path: String,
+    body: Option<String>,
+    status: u16,
+    response: String,
+}
+
+/// HTTP traffic captured to disk with `--record` or served back from it with `--replay`, which
+/// reproduces a run without a cluster.
+#[derive(Debug)]
+pub struct Fixtures {
+    mode: Mode,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record {
+        dir: PathBuf,
+        next: AtomicUsize,
+    },
+    /// Recorded responses by request, in recorded order, so repeated identical requests (such as
+    /// polling a task) get their responses in sequence.
+    Replay {
+        exchanges: Mutex<HashMap<String, VecDeque<Exchange>>>,
+    },
+}
+
+impl Fixtures {
+    /// Records into `dir`, numbering new exchanges after any already recorded there.
+    pub fn record(dir: &str) -> Result<Self, EsDumperError> {
+        fs::create_dir_all(dir)?;
+        let next = AtomicUsize::new(fixture_paths(dir)?.len());
+        Ok(Fixtures { mode: Mode::Record { dir: PathBuf::from(dir), next } })
+    }
+
+    pub fn replay(dir: &str) -> Result<Self, EsDumperError> {
+        let paths = fixture_paths(dir)?;
+
+        let mut exchanges: HashMap<String, VecDeque<Exchange>> = HashMap::new();
+        for path in paths {
+            let exchange: Exchange = serde_json::from_reader(BufReader::new(File::open(&path)?))?;
+            exchanges
+                .entry(key(&exchange.method, &exchange.path, exchange.body.as_deref()))
+                .or_default()
+                .push_back(exchange);
+        }
+
+        Ok(Fixtures { mode: Mode::Replay { exchanges: Mutex::new(exchanges) } })
+    }
+
+    /// Whether requests are answered from disk, so that no host needs to be reachable.
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay { .. })
+    }
+
+    /// Sends the request and records the exchange when recording, or answers it from the recorded
+    /// responses without contacting any host when replaying.
+    pub fn send(&self, builder: RequestBuilder) -> Result<Response, EsDumperError> {
+        match &self.mode {
+            Mode::Record { dir, next } => record(dir, next, builder),
+            Mode::Replay { exchanges } => replay(exchanges, builder),
+        }
+    }
+}
+
+fn record(
+    dir: &Path,
+    next: &AtomicUsize,
+    builder: RequestBuilder
+) -> Result<Response, EsDumperError> {
+    let request = builder.try_clone().map(RequestBuilder::build).transpose()?;
+
+    let response = builder.send()?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = response.bytes()?;
+
+    // Requests with streamed bodies cannot be cloned and go unrecorded
+    if let Some(request) = request {
+        let (method, path, body) = describe(&request);
+        let exchange = Exchange {
+            method,
+            path,
+            body,
+            status: status.as_u16(),
+            response: String::from_utf8_lossy(&bytes).into_owned(),
+        };
+        write_exchange(dir, next.fetch_add(1, Ordering::Relaxed), &exchange)?;
+    }
+
+    let mut rebuilt = http::Response::builder().status(status);
+    if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+        *rebuilt_headers = headers;
+    }
+    Ok(Response::from(rebuilt.body(bytes.to_vec()).expect("recorded status is valid")))
+}
+
+fn replay(
+    exchanges: &Mutex<HashMap<String, VecDeque<Exchange>>>,
+    builder: RequestBuilder
+) -> Result<Response, EsDumperError> {
+    let (method, path, body) = describe(&builder.build()?);
+
+    let exchange = exchanges
+        .lock()
+        .unwrap()
+        .get_mut(&key(&method, &path, body.as_deref()))
+        .and_then(VecDeque::pop_front)
+        .ok_or_else(|| {
+            EsDumperError::UnexpectedResponse(
+                format!("No recorded response for {} {}", method, path)
+            )
+        })?;
+
+    let response = http::Response
+        ::builder()
+        .status(exchange.status)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(exchange.response)
+        .map_err(|e| {
+            EsDumperError::UnexpectedResponse(format!("Invalid recorded response: {}", e))
+        })?;
+    Ok(Response::from(response))
+}
+
+fn fixture_paths(dir: &str) -> Result<Vec<PathBuf>, EsDumperError> {
+    let mut paths: Vec<PathBuf> = fs
+        ::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn describe(request: &Request) -> (String, String, Option<String>) {
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+
+    (request.method().to_string(), path, body)
+}
+
+fn key(method: &str, path: &str, body: Option<&str>) -> String {
+    format!("{} {}\n{}", method, path, body.unwrap_or_default())
+}
+
+fn write_exchange(dir: &Path, number: usize, exchange: &Exchange) -> Result<(), EsDumperError> {
+    let file = File::create(dir.join(format!("{:06}.json", number)))?;
+    serde_json::to_writer_pretty(file, exchange)?;
+    Ok(())
+}
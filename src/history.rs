@@ -0,0 +1,233 @@
+use crate::config::{ BackupConfig, DEFAULT_HISTORY_DB };
+use crate::error::EsDumperError;
+use crate::http_client::build_http_client;
+use crate::report::RunReport;
+use crate::utils::{ log, LogFile };
+use chrono::Utc;
+use rusqlite::{ params, Connection };
+use std::path::Path;
+use std::time::Duration;
+
+fn open(backup_dir: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(Path::new(backup_dir).join(DEFAULT_HISTORY_DB))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            operation TEXT NOT NULL,
+            started_at TEXT NOT NULL,
+            duration_secs REAL NOT NULL,
+            error TEXT,
+            indices INTEGER NOT NULL,
+            failed INTEGER NOT NULL,
+            docs INTEGER NOT NULL,
+            bytes INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS run_indices (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            index_name TEXT NOT NULL,
+            success INTEGER NOT NULL,
+            error TEXT,
+            docs INTEGER NOT NULL,
+            bytes INTEGER NOT NULL,
+            duration_secs REAL NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS run_indices_by_name ON run_indices(index_name);"
+    )?;
+    Ok(conn)
+}
+
+pub fn record_run(backup_dir: &str, report: &RunReport) -> Result<i64, Box<dyn std::error::Error>> {
+    let mut conn = open(backup_dir)?;
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        "INSERT INTO runs (operation, started_at, duration_secs, error, indices, failed, docs, bytes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            report.operation,
+            report.started_at,
+            report.duration_secs,
+            report.error,
+            report.indices.len() as i64,
+            report.failed_count() as i64,
+            report.total_docs() as i64,
+            report.total_bytes() as i64
+        ]
+    )?;
+    let run_id = tx.last_insert_rowid();
+
+    for index in &report.indices {
+        tx.execute(
+            "INSERT INTO run_indices (run_id, index_name, success, error, docs, bytes, duration_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                run_id,
+                index.index,
+                index.success,
+                index.error,
+                index.docs as i64,
+                index.bytes as i64,
+                index.duration_secs
+            ]
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(run_id)
+}
+
+/// Records the outcome of a run, including runs that failed before producing a report.
+pub fn record_result(
+    backup_dir: &str,
+    operation: &str,
+    started_at: String,
+    result: &Result<RunReport, EsDumperError>,
+    log_file: &LogFile
+) {
+    let mut report = RunReport::from_result(operation, started_at, result);
+    // Runs that failed before producing a report are still told apart by their log
+    report.run_id.get_or_insert_with(|| log_file.run_id().to_string());
+    report.run_uuid.get_or_insert_with(|| log_file.run_uuid().to_string());
+    if let Err(e) = record_run(backup_dir, &report) {
+        let _ = log(log_file, &format!("Failed to record run history: {}", e));
+    }
+}
+
+/// Indexes the outcome of a run as a document into `report_index`, on `report_host` when set
+/// and otherwise on the cluster of the run, so backup health can be dashboarded in Kibana.
+/// Failures are logged without failing the run.
+pub fn publish_result(
+    config: &BackupConfig,
+    operation: &str,
+    started_at: String,
+    result: &Result<RunReport, EsDumperError>,
+    log_file: &LogFile
+) {
+    let Some(report_index) = &config.report_index else {
+        return;
+    };
+    let mut report = RunReport::from_result(operation, started_at, result);
+    // Runs that failed before producing a report are still told apart by their log
+    report.run_id.get_or_insert_with(|| log_file.run_id().to_string());
+    report.run_uuid.get_or_insert_with(|| log_file.run_uuid().to_string());
+    if let Err(e) = publish_run(config, report_index, &report) {
+        let _ = log(log_file, &format!("Failed to index run report into {}: {}", report_index, e));
+    } else {
+        let _ = log(log_file, &format!("Run report indexed into {}", report_index));
+    }
+}
+
+fn publish_run(
+    config: &BackupConfig,
+    report_index: &str,
+    report: &RunReport
+) -> Result<(), EsDumperError> {
+    let mut doc = serde_json::to_value(report)?;
+    doc["@timestamp"] = Utc::now().to_rfc3339().into();
+    doc["success"] = (report.error.is_none() && report.failed_count() == 0).into();
+    doc["index_count"] = report.indices.len().into();
+    doc["failed_count"] = report.failed_count().into();
+    doc["total_docs"] = report.total_docs().into();
+    doc["total_bytes"] = report.total_bytes().into();
+
+    let client = build_http_client(config, &format!("{}-report", config.opaque_id_prefix))?;
+    let request = |host: &str| {
+        client
+            .post(format!("{}/{}/_doc", host, report_index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+            .json(&doc)
+    };
+    let response = match &config.report_host {
+        Some(host) => request(host.trim_end_matches('/')).send()?,
+        None => config.hosts.send(request)?,
+    };
+    if !response.status().is_success() {
+        return Err(EsDumperError::from_response("Indexing the run report failed", response));
+    }
+    Ok(())
+}
+
+/// Prints the most recent runs, or the recent outcomes of a single index when one is given.
+pub fn print_history(
+    backup_dir: &str,
+    index: Option<&str>,
+    limit: usize
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = open(backup_dir)?;
+
+    match index {
+        Some(index) => {
+            let mut stmt = conn.prepare(
+                "SELECT r.id, r.operation, r.started_at, i.success, i.docs, i.bytes, i.duration_secs, i.error
+                 FROM run_indices i JOIN runs r ON r.id = i.run_id
+                 WHERE i.index_name = ?1 ORDER BY r.id DESC LIMIT ?2"
+            )?;
+            let rows = stmt.query_map(params![index, limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, bool>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, f64>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                ))
+            })?;
+
+            println!("History for index: {}", index);
+            for row in rows {
+                let (id, operation, started_at, success, docs, bytes, duration, error) = row?;
+                println!(
+                    "#{:<5} {} {:<8} {:<7} docs: {:<10} bytes: {:<12} {:.2}s {}",
+                    id,
+                    started_at,
+                    operation,
+                    if success { "ok" } else { "failed" },
+                    docs,
+                    bytes,
+                    duration,
+                    error.unwrap_or_default()
+                );
+            }
+        }
+        None => {
+            let mut stmt = conn.prepare(
+                "SELECT id, operation, started_at, duration_secs, indices, failed, docs, bytes, error
+                 FROM runs ORDER BY id DESC LIMIT ?1"
+            )?;
+            let rows = stmt.query_map(params![limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, i64>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ))
+            })?;
+
+            for row in rows {
+                let (id, operation, started_at, duration, indices, failed, docs, bytes, error) =
+                    row?;
+                println!(
+                    "#{:<5} {} {:<8} indices: {:<5} failed: {:<4} docs: {:<10} bytes: {:<12} {:.2}s {}",
+                    id,
+                    started_at,
+                    operation,
+                    indices,
+                    failed,
+                    docs,
+                    bytes,
+                    duration,
+                    error.unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use crate::error::EsDumperError;
+use crate::utils::{ log, LogFile };
+use serde_json::Value;
+use std::io::{ self, Write };
+use std::process::{ Command, Output, Stdio };
+use std::thread;
+
+/// Prefix of the environment variables describing the run to hook commands.
+const ENV_PREFIX: &str = "ES_DUMPER_";
+
+/// Runs a configured hook command through the shell (`sh -c`, or `cmd /C` on Windows), with
+/// `env` passed as `ES_DUMPER_<NAME>` environment variables. Its output is written to the log
+/// rather than to the terminal, which may be carrying progress bars or streamed documents.
+/// Fails when the command cannot be started or exits unsuccessfully.
+pub fn run_hook(
+    name: &str,
+    command: &str,
+    env: &[(&str, String)],
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    run(name, command, env, None, log_file)
+}
+
+/// Runs a hook command like [`run_hook`], writing `payload` to its stdin as a single line of
+/// JSON.
+pub fn run_hook_with_payload(
+    name: &str,
+    command: &str,
+    env: &[(&str, String)],
+    payload: &Value,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    run(name, command, env, Some(payload.to_string()), log_file)
+}
+
+fn run(
+    name: &str,
+    command: &str,
+    env: &[(&str, String)],
+    input: Option<String>,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    log(log_file, &format!("Running {}: {}", name, command))?;
+    let mut shell = shell(command);
+    for (key, value) in env {
+        shell.env(format!("{}{}", ENV_PREFIX, key), value);
+    }
+    let started = |e: io::Error| EsDumperError::Hook(format!("Failed to start {}: {}", name, e));
+    let output = match input {
+        None => shell.output().map_err(started)?,
+        Some(input) => {
+            let mut child = shell
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(started)?;
+            // Written from a thread of its own, so a command that prints a lot before reading
+            // all of its input cannot block on a full stdout pipe
+            let writer = child.stdin.take().map(|mut stdin| {
+                thread::spawn(move || {
+                    stdin.write_all(input.as_bytes()).and_then(|()| stdin.write_all(b"\n"))
+                })
+            });
+            let output = child.wait_with_output()?;
+            // A command that exits without reading its input closes the pipe, which is fine
+            if let Some(Ok(Err(e))) = writer.map(|writer| writer.join()) {
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    return Err(e.into());
+                }
+            }
+            output
+        }
+    };
+    check_output(name, &output, log_file)
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+fn check_output(name: &str, output: &Output, log_file: &LogFile) -> Result<(), EsDumperError> {
+    for (stream, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        for line in String::from_utf8_lossy(bytes).lines().filter(|line| !line.trim().is_empty()) {
+            log(log_file, &format!("{} {}: {}", name, stream, line))?;
+        }
+    }
+    if !output.status.success() {
+        return Err(EsDumperError::Hook(format!("{} failed ({})", name, output.status)));
+    }
+    Ok(())
+}
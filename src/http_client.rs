@@ -1,25 +1,275 @@
+use crate::breaker::CircuitBreaker;
 use crate::config::BackupConfig;
-use reqwest::blocking::Client;
+use crate::error::EsDumperError;
+use crate::fixtures::Fixtures;
+use crate::job::Job;
+use reqwest::blocking::{ Client, RequestBuilder, Response };
 use reqwest::header::{ self, HeaderMap, HeaderValue };
-use std::time::Duration;
-use base64::encode;
+use reqwest::{ Method, StatusCode, Url };
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 
-pub fn build_http_client(config: &BackupConfig) -> Result<Client, Box<dyn std::error::Error>> {
+/// Client for a run and every index it processes, sharing one connection pool so connections
+/// and TLS sessions to the cluster are reused throughout the run. Every request carries the
+/// client's opaque ID as `X-Opaque-Id`, which the cluster echoes in its slow logs, audit logs
+/// and task list; the clients of single indices are derived with [`HttpClient::with_opaque_id`].
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    opaque_id: HeaderValue,
+    stats: Arc<Mutex<HttpStats>>,
+}
+
+/// Use of a run's connection pool, as included in its report.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HttpStats {
+    /// Requests sent, counting every attempt of a retried request.
+    pub requests: u64,
+    /// Requests sent to each node.
+    pub requests_per_host: BTreeMap<String, u64>,
+    /// Clients derived for single indices, all of them sharing the pool of the run.
+    pub index_clients: u64,
+}
+
+impl HttpStats {
+    /// A summary for the end of a run.
+    pub fn line(&self) -> String {
+        let hosts: Vec<String> = self.requests_per_host
+            .iter()
+            .map(|(host, requests)| format!("{} {}", host, requests))
+            .collect();
+        format!(
+            "{} requests over one connection pool shared by {} index clients ({})",
+            self.requests,
+            self.index_clients,
+            hosts.join(", ")
+        )
+    }
+}
+
+impl HttpClient {
+    /// A client with the same connection pool and statistics whose requests carry another
+    /// opaque ID, e.g. that of a single index.
+    pub fn with_opaque_id(&self, opaque_id: &str) -> Result<Self, EsDumperError> {
+        self.stats.lock().unwrap().index_clients += 1;
+        Ok(HttpClient {
+            client: self.client.clone(),
+            opaque_id: opaque_header(opaque_id)?,
+            stats: Arc::clone(&self.stats),
+        })
+    }
+
+    pub fn get(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn delete(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+
+    pub fn head(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request(Method::HEAD, url)
+    }
+
+    pub fn request(&self, method: Method, url: impl AsRef<str>) -> RequestBuilder {
+        let url = url.as_ref();
+        // Host pools build a request for every attempt, so each one built is sent
+        let host = Url::parse(url)
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_default();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.requests += 1;
+            *stats.requests_per_host.entry(host).or_insert(0) += 1;
+        }
+        self.client.request(method, url).header("x-opaque-id", self.opaque_id.clone())
+    }
+
+    /// Use of the connection pool so far, by this client and every client derived from it.
+    pub fn stats(&self) -> HttpStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Builds the client of a run, with a connection pool of its own.
+pub fn build_http_client(
+    config: &BackupConfig,
+    opaque_id: &str
+) -> Result<HttpClient, EsDumperError> {
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
     if let Some((username, password)) = &config.auth {
-        let auth = encode(format!("{}:{}", username, password));
-        let auth_header = HeaderValue::from_str(&format!("Basic {}", auth))?;
+        let auth = STANDARD.encode(format!("{}:{}", username, password));
+        let auth_header = HeaderValue::from_str(&format!("Basic {}", auth)).map_err(|e| {
+            EsDumperError::Config(format!("Invalid credentials: {}", e))
+        })?;
         headers.insert(header::AUTHORIZATION, auth_header);
     }
 
-    let client = Client::builder()
+    let mut builder = Client::builder()
         .default_headers(headers)
+        .user_agent(config.user_agent.as_str())
         .timeout(Duration::from_secs(config.request_timeout_secs))
         .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
-        .danger_accept_invalid_certs(true)
-        .build()?;
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+        .tcp_keepalive(config.tcp_keepalive_secs.map(Duration::from_secs))
+        // Scroll pages compress well; responses are decompressed transparently
+        .gzip(true)
+        .danger_accept_invalid_certs(true);
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if config.http2 {
+        builder = builder.http2_prior_knowledge();
+    }
+    let client = builder.build()?;
+
+    Ok(HttpClient {
+        client,
+        opaque_id: opaque_header(opaque_id)?,
+        stats: Arc::new(Mutex::new(HttpStats::default())),
+    })
+}
 
-    Ok(client)
+fn opaque_header(opaque_id: &str) -> Result<HeaderValue, EsDumperError> {
+    HeaderValue::from_str(opaque_id).map_err(|e| {
+        EsDumperError::Config(format!("Invalid opaque ID '{}': {}", opaque_id, e))
+    })
+}
+
+/// `X-Opaque-Id` of the requests of `job`, narrowed down to `index` for per-index requests:
+/// `<opaque_id_prefix>-<run ID>` or `<opaque_id_prefix>-<run ID>/<index>`.
+pub fn opaque_id(config: &BackupConfig, job: &Job, index: Option<&str>) -> String {
+    match index {
+        Some(index) => format!("{}-{}/{}", config.opaque_id_prefix, job.run_id, index),
+        None => format!("{}-{}", config.opaque_id_prefix, job.run_id),
+    }
+}
+
+/// Elasticsearch nodes used in round-robin order. A node that refuses connections is
+/// skipped until `retry_after` has elapsed, after which it is tried again.
+#[derive(Debug)]
+pub struct HostPool {
+    hosts: Vec<String>,
+    next: AtomicUsize,
+    down_until: Mutex<Vec<Option<Instant>>>,
+    retry_after: Duration,
+    fixtures: Option<Fixtures>,
+    breaker: CircuitBreaker,
+}
+
+impl HostPool {
+    pub fn new(hosts: Vec<String>, retry_after: Duration) -> Self {
+        let hosts: Vec<String> = hosts
+            .into_iter()
+            .map(|host| host.trim_end_matches('/').to_string())
+            .collect();
+        let down_until = Mutex::new(vec![None; hosts.len()]);
+
+        HostPool {
+            hosts,
+            next: AtomicUsize::new(0),
+            down_until,
+            retry_after,
+            fixtures: None,
+            breaker: CircuitBreaker::disabled(),
+        }
+    }
+
+    /// The node URLs, without trailing slashes.
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Records every exchange to, or replays every response from, `fixtures`.
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(fixtures);
+        self
+    }
+
+    /// Pauses requests through `breaker` while the cluster is struggling.
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Sends the request built by `build` to the next node in rotation, failing over to the
+    /// remaining nodes on connection errors. Only connection failures are retried elsewhere,
+    /// since a timed-out scroll or bulk request may already have been applied. Waits first while
+    /// the circuit breaker is open.
+    pub fn send<F>(&self, build: F) -> Result<Response, EsDumperError>
+        where F: Fn(&str) -> RequestBuilder
+    {
+        if let Some(fixtures) = self.fixtures.as_ref().filter(|fixtures| fixtures.is_replay()) {
+            return fixtures.send(build(&self.hosts[0]));
+        }
+
+        let candidates = self.candidates();
+        self.breaker.wait(&self.hosts[candidates[0]]);
+
+        let mut last_error = None;
+        for i in candidates {
+            let result = match &self.fixtures {
+                Some(fixtures) => fixtures.send(build(&self.hosts[i])),
+                None => build(&self.hosts[i]).send().map_err(EsDumperError::from),
+            };
+            match result {
+                Ok(response) => {
+                    self.mark(i, None);
+                    let status = response.status();
+                    self.breaker.record(
+                        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+                    );
+                    return Ok(response);
+                }
+                Err(EsDumperError::Connection(e)) if e.is_connect() => {
+                    self.mark(i, Some(Instant::now() + self.retry_after));
+                    self.breaker.record(true);
+                    last_error = Some(e);
+                }
+                Err(e) => {
+                    self.breaker.record(matches!(e, EsDumperError::Connection(_)));
+                    return Err(e);
+                }
+            }
+        }
+
+        Err(EsDumperError::Connection(last_error.expect("host pool is never empty")))
+    }
+
+    // Healthy nodes in round-robin order first, then nodes marked down as a last resort
+    fn candidates(&self) -> Vec<usize> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let down_until = self.down_until.lock().unwrap();
+
+        let (mut healthy, down): (Vec<usize>, Vec<usize>) = (0..self.hosts.len())
+            .map(|offset| (start + offset) % self.hosts.len())
+            .partition(|&i| down_until[i].is_none_or(|until| until <= now));
+
+        healthy.extend(down);
+        healthy
+    }
+
+    fn mark(&self, i: usize, until: Option<Instant>) {
+        self.down_until.lock().unwrap()[i] = until;
+    }
 }
@@ -0,0 +1,313 @@
+use crate::templates::typeless;
+use serde_json::{ Map, Value };
+
+/// Index settings (below `index.`) that an open index accepts changes to; an entry ending in
+/// `.` covers every setting under it. Static settings such as `number_of_shards` or the
+/// analysis chain can only be given when an index is created.
+const DYNAMIC_SETTINGS: &[&str] = &[
+    "number_of_replicas",
+    "auto_expand_replicas",
+    "refresh_interval",
+    "search.idle.after",
+    "max_result_window",
+    "max_inner_result_window",
+    "max_rescore_window",
+    "max_docvalue_fields_search",
+    "max_script_fields",
+    "max_ngram_diff",
+    "max_shingle_diff",
+    "max_refresh_listeners",
+    "max_terms_count",
+    "max_regex_length",
+    "analyze.max_token_count",
+    "highlight.max_analyzed_offset",
+    "default_pipeline",
+    "final_pipeline",
+    "gc_deletes",
+    "priority",
+    "hidden",
+    "query.default_field",
+    "lifecycle.",
+    "routing.allocation.",
+    "routing.rebalance.enable",
+    "unassigned.node_left.delayed_timeout",
+    "mapping.total_fields.limit",
+    "mapping.depth.limit",
+    "mapping.nested_fields.limit",
+    "mapping.nested_objects.limit",
+    "mapping.field_name_length.limit",
+    "translog.durability",
+    "translog.sync_interval",
+    "translog.flush_threshold_size",
+];
+
+/// What a backed-up mapping adds to the mapping of a live index.
+#[derive(Debug, Default)]
+pub struct MappingAdditions {
+    /// `properties` and `runtime` fields missing from the live mapping, as a typeless mapping
+    /// to put; empty when there are none.
+    pub mapping: Map<String, Value>,
+    /// Fields the backup adds, e.g. `user.email`.
+    pub added: Vec<String>,
+    /// Fields both define, differently; the live definition is kept.
+    pub conflicts: Vec<String>,
+}
+
+/// What backed-up settings change on a live index.
+#[derive(Debug, Default)]
+pub struct SettingsChanges {
+    /// Dynamic settings whose backed-up value differs, by their `index.` key.
+    pub settings: Map<String, Value>,
+    /// Static settings whose backed-up value differs, which the live index keeps.
+    pub static_changes: Vec<String>,
+}
+
+/// Compares the backed-up mapping `backup` with the mapping of the live index `live`: fields
+/// only the backup defines are added, object fields are compared field by field, and fields
+/// defined differently are left as they are, since a live field cannot change its type.
+pub fn mapping_additions(live: &Value, backup: &Value) -> MappingAdditions {
+    let (live, backup) = (typeless(live), typeless(backup));
+    let mut additions = MappingAdditions::default();
+
+    let mut properties = Map::new();
+    collect_properties(
+        &live["properties"],
+        &backup["properties"],
+        "",
+        &mut properties,
+        &mut additions
+    );
+    if !properties.is_empty() {
+        additions.mapping.insert("properties".to_string(), Value::Object(properties));
+    }
+
+    // Runtime fields are replaced as a whole, so only new ones are added
+    let mut runtime = Map::new();
+    for (name, field) in backup["runtime"].as_object().into_iter().flatten() {
+        match live["runtime"].get(name) {
+            None => {
+                runtime.insert(name.clone(), field.clone());
+                additions.added.push(name.clone());
+            }
+            Some(live_field) if live_field != field => {
+                additions.conflicts.push(name.clone());
+            }
+            Some(_) => {}
+        }
+    }
+    if !runtime.is_empty() {
+        additions.mapping.insert("runtime".to_string(), Value::Object(runtime));
+    }
+    additions
+}
+
+fn collect_properties(
+    live: &Value,
+    backup: &Value,
+    path: &str,
+    properties: &mut Map<String, Value>,
+    additions: &mut MappingAdditions
+) {
+    for (name, backup_field) in backup.as_object().into_iter().flatten() {
+        let field_path = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+        let Some(live_field) = live.get(name) else {
+            properties.insert(name.clone(), backup_field.clone());
+            additions.added.push(field_path);
+            continue;
+        };
+
+        if live_field.get("properties").is_some() && backup_field.get("properties").is_some() {
+            let mut inner = Map::new();
+            collect_properties(
+                &live_field["properties"],
+                &backup_field["properties"],
+                &field_path,
+                &mut inner,
+                additions
+            );
+            if !inner.is_empty() {
+                // Putting an object field needs its type, e.g. `nested`, to match the live one
+                let mut object = Map::new();
+                if let Some(field_type) = live_field.get("type") {
+                    object.insert("type".to_string(), field_type.clone());
+                }
+                object.insert("properties".to_string(), Value::Object(inner));
+                properties.insert(name.clone(), Value::Object(object));
+            }
+        } else if !same_definition(live_field, backup_field) {
+            additions.conflicts.push(field_path);
+        }
+    }
+}
+
+/// Whether the live definition of a field holds every parameter of the backed-up one. The
+/// cluster fills in defaults the backup may not name, so extra live parameters don't count.
+fn same_definition(live: &Value, backup: &Value) -> bool {
+    match (live, backup) {
+        (Value::Object(live), Value::Object(backup)) => {
+            backup.iter().all(|(key, backup)| {
+                live.get(key).is_some_and(|live| same_definition(live, backup))
+            })
+        }
+        _ => setting_text(live) == setting_text(backup),
+    }
+}
+
+/// Compares backed-up `settings` (nested under `index`, as `_settings` returns them) with the
+/// flat settings of the live index (`?flat_settings=true`), keeping the dynamic settings that
+/// differ. Settings the live index doesn't report are compared against nothing, so backed-up
+/// values of settings at their default are applied again, which changes nothing.
+pub fn settings_changes(live_flat: &Value, settings: &Value) -> SettingsChanges {
+    let mut backed_up = Vec::new();
+    flatten("index", &settings["index"], &mut backed_up);
+
+    let mut changes = SettingsChanges::default();
+    for (key, value) in backed_up {
+        if live_flat.get(&key).is_some_and(|live| setting_text(live) == setting_text(&value)) {
+            continue;
+        }
+        let name = key.strip_prefix("index.").unwrap_or(&key);
+        let dynamic = DYNAMIC_SETTINGS.iter().any(|setting| {
+            if setting.ends_with('.') { name.starts_with(setting) } else { name == *setting }
+        });
+        if dynamic {
+            changes.settings.insert(key, value);
+        } else if live_flat.get(&key).is_some() {
+            changes.static_changes.push(key);
+        }
+    }
+    changes
+}
+
+/// Appends the leaves of nested settings under `prefix` as dotted keys.
+fn flatten(prefix: &str, value: &Value, flat: &mut Vec<(String, Value)>) {
+    match value.as_object() {
+        Some(object) => {
+            for (key, value) in object {
+                flatten(&format!("{}.{}", prefix, key), value, flat);
+            }
+        }
+        None if !value.is_null() => flat.push((prefix.to_string(), value.clone())),
+        None => {}
+    }
+}
+
+/// Settings and mapping parameters as text, since `_settings` reports every value as a string.
+fn setting_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn adds_fields_only_the_backup_defines() {
+        let live = json!({
+            "properties": {
+                "title": { "type": "text", "analyzer": "standard" },
+                "user": {
+                    "type": "nested",
+                    "properties": { "name": { "type": "keyword" } }
+                }
+            },
+            "runtime": { "day": { "type": "keyword" } }
+        });
+        let backup = json!({
+            "properties": {
+                "title": { "type": "text" },
+                "count": { "type": "long" },
+                "user": {
+                    "type": "nested",
+                    "properties": {
+                        "name": { "type": "keyword" },
+                        "email": { "type": "keyword" }
+                    }
+                }
+            },
+            "runtime": { "day": { "type": "keyword" }, "hour": { "type": "long" } }
+        });
+
+        let additions = mapping_additions(&live, &backup);
+        assert_eq!(
+            Value::Object(additions.mapping),
+            json!({
+                "properties": {
+                    "count": { "type": "long" },
+                    "user": {
+                        "type": "nested",
+                        "properties": { "email": { "type": "keyword" } }
+                    }
+                },
+                "runtime": { "hour": { "type": "long" } }
+            })
+        );
+        assert_eq!(additions.added, ["count", "user.email", "hour"]);
+        assert!(additions.conflicts.is_empty());
+    }
+
+    #[test]
+    fn keeps_live_definitions_that_differ() {
+        let live = json!({
+            "properties": { "count": { "type": "integer" }, "tag": { "type": "keyword" } },
+            "runtime": { "day": { "type": "keyword" } }
+        });
+        let backup = json!({
+            "properties": { "count": { "type": "long" }, "tag": { "type": "keyword" } },
+            "runtime": { "day": { "type": "long" } }
+        });
+
+        let additions = mapping_additions(&live, &backup);
+        assert!(additions.mapping.is_empty());
+        assert!(additions.added.is_empty());
+        assert_eq!(additions.conflicts, ["count", "day"]);
+    }
+
+    #[test]
+    fn compares_typed_mappings_and_string_parameters() {
+        let live = json!({ "properties": { "size": { "type": "long", "coerce": "true" } } });
+        let backup = json!({
+            "_doc": { "properties": { "size": { "type": "long", "coerce": true } } }
+        });
+
+        let additions = mapping_additions(&live, &backup);
+        assert!(additions.mapping.is_empty());
+        assert!(additions.conflicts.is_empty());
+    }
+
+    #[test]
+    fn keeps_dynamic_settings_that_differ() {
+        let live = json!({
+            "index.number_of_shards": "1",
+            "index.number_of_replicas": "1",
+            "index.refresh_interval": "1s",
+            "index.routing.allocation.include._tier_preference": "data_content",
+        });
+        let settings = json!({
+            "index": {
+                "number_of_shards": "3",
+                "number_of_replicas": 0,
+                "refresh_interval": "1s",
+                "routing": { "allocation": { "include": { "_tier_preference": "data_hot" } } },
+                "lifecycle": { "name": "logs" },
+                "codec": "best_compression"
+            }
+        });
+
+        let changes = settings_changes(&live, &settings);
+        assert_eq!(
+            Value::Object(changes.settings),
+            json!({
+                "index.number_of_replicas": 0,
+                "index.routing.allocation.include._tier_preference": "data_hot",
+                "index.lifecycle.name": "logs",
+            })
+        );
+        // Static settings are only reported when the live index has a different value
+        assert_eq!(changes.static_changes, ["index.number_of_shards"]);
+    }
+}
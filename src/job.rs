@@ -0,0 +1,216 @@
+use crate::error::EsDumperError;
+use crate::latency::LatencyRecorder;
+use crate::observer::{ NoopObserver, RunObserver };
+use crate::plugins::{ DocumentTransform, StorageBackend };
+use crate::report::RunReport;
+use crate::utils::timestamp;
+use chrono::Local;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Indices of a run by state. Updated by the run's workers without taking a lock, and read by
+/// whoever reports on the run, such as the server's job status.
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    total: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl IndexStats {
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    /// Indices being processed right now.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug)]
+struct JobState {
+    status: JobStatus,
+    finished_at: Option<String>,
+    error: Option<String>,
+    report: Option<RunReport>,
+}
+
+/// ID of a run started now as job `id`: start time and job ID, e.g. `20250101T020000-1`.
+pub fn new_run_id(id: u64) -> String {
+    format!("{}-{}", Local::now().format("%Y%m%dT%H%M%S"), id)
+}
+
+/// A single backup or restore run. Progress and cancellation are shared with whoever started it,
+/// which is how the server mode reports and stops jobs it is running in the background.
+pub struct Job {
+    pub id: u64,
+    /// Identifies the run in the cluster's logs: start time and job ID, e.g. `20250101T020000-1`.
+    pub run_id: String,
+    pub operation: String,
+    pub index: Option<String>,
+    pub started_at: String,
+    cancelled: AtomicBool,
+    index_stats: IndexStats,
+    state: Mutex<JobState>,
+    observer: Box<dyn RunObserver>,
+    latencies: LatencyRecorder,
+    transforms: Vec<Box<dyn DocumentTransform>>,
+    storage: Option<Box<dyn StorageBackend>>,
+}
+
+impl Job {
+    pub fn new(id: u64, operation: &str, index: Option<String>) -> Self {
+        Job {
+            id,
+            run_id: new_run_id(id),
+            operation: operation.to_string(),
+            index,
+            started_at: timestamp(),
+            cancelled: AtomicBool::new(false),
+            index_stats: IndexStats::default(),
+            state: Mutex::new(JobState {
+                status: JobStatus::Running,
+                finished_at: None,
+                error: None,
+                report: None,
+            }),
+            observer: Box::new(NoopObserver),
+            latencies: LatencyRecorder::default(),
+            transforms: Vec::new(),
+            storage: None,
+        }
+    }
+
+    /// Identifies the run by `run_id` instead, e.g. one its log file was opened for.
+    pub fn with_run_id(mut self, run_id: String) -> Self {
+        self.run_id = run_id;
+        self
+    }
+
+    /// Reports the progress of the run to `observer`.
+    pub fn with_observer(mut self, observer: Box<dyn RunObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    pub fn observer(&self) -> &dyn RunObserver {
+        self.observer.as_ref()
+    }
+
+    /// Applies `transform` to every document the run backs up, after the transforms attached
+    /// before it.
+    pub fn with_transform(mut self, transform: Box<dyn DocumentTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    pub fn transforms(&self) -> &[Box<dyn DocumentTransform>] {
+        &self.transforms
+    }
+
+    /// Stores the backed-up indices in `storage`, and fetches indices from it before restoring
+    /// them.
+    pub fn with_storage(mut self, storage: Box<dyn StorageBackend>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn storage(&self) -> Option<&dyn StorageBackend> {
+        self.storage.as_deref()
+    }
+
+    /// Latencies of the run's scroll and bulk requests and local writes.
+    pub fn latencies(&self) -> &LatencyRecorder {
+        &self.latencies
+    }
+
+    /// Asks the run to stop at its next cancellation point (between indices, scroll pages and
+    /// bulk batches). Safe to call from any thread.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_total_indices(&self, total: usize) {
+        self.index_stats.total.store(total, Ordering::SeqCst);
+    }
+
+    /// Counts an index as being processed, returning the number of indices now active.
+    pub fn index_started(&self) -> usize {
+        self.index_stats.active.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Counts an index started with `index_started` as completed, returning the number of
+    /// indices still active.
+    pub fn index_done(&self) -> usize {
+        self.index_stats.completed.fetch_add(1, Ordering::SeqCst);
+        self.index_stats.active.fetch_sub(1, Ordering::SeqCst) - 1
+    }
+
+    pub fn index_stats(&self) -> &IndexStats {
+        &self.index_stats
+    }
+
+    pub fn finish(&self, result: &Result<RunReport, EsDumperError>) {
+        let mut state = self.state.lock().unwrap();
+        state.finished_at = Some(timestamp());
+        match result {
+            Ok(report) => {
+                state.status = if self.is_cancelled() {
+                    JobStatus::Cancelled
+                } else {
+                    JobStatus::Completed
+                };
+                state.report = Some(report.clone());
+            }
+            Err(e) => {
+                state.status = if self.is_cancelled() {
+                    JobStatus::Cancelled
+                } else {
+                    JobStatus::Failed
+                };
+                state.error = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().status
+    }
+
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        serde_json::json!({
+            "id": self.id,
+            "operation": self.operation,
+            "index": self.index,
+            "status": state.status,
+            "started_at": self.started_at,
+            "finished_at": state.finished_at,
+            "total_indices": self.index_stats.total(),
+            "active_indices": self.index_stats.active(),
+            "completed_indices": self.index_stats.completed(),
+            "error": state.error,
+            "report": state.report,
+        })
+    }
+}
@@ -0,0 +1,205 @@
+use crate::error::EsDumperError;
+use reqwest::blocking::Response;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+/// Buckets per doubling of the latency, which keeps percentiles within about 5% of the
+/// recorded values.
+const BUCKETS_PER_DOUBLING: f64 = 8.0;
+
+/// What a recorded latency was spent on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyKind {
+    /// Scroll and `search_after` page requests.
+    Scroll,
+    /// `_bulk` requests of restores.
+    Bulk,
+    /// Writing an exported page to the local data file.
+    LocalWrite,
+}
+
+/// Latencies of the requests and local writes of a run and counts of the failed requests by
+/// class, to tell slowness of the cluster from slowness of local storage.
+#[derive(Debug, Default)]
+pub struct LatencyRecorder {
+    recorded: Mutex<Recorded>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    scroll: Histogram,
+    bulk: Histogram,
+    local_write: Histogram,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+/// Log-scale histogram of latencies in microseconds.
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max_micros: u64,
+}
+
+/// Percentiles of one kind of latency, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyStats {
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Latencies and failed requests of a run, as included in its report.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencySummary {
+    pub scroll: Option<LatencyStats>,
+    pub bulk: Option<LatencyStats>,
+    pub local_write: Option<LatencyStats>,
+    /// Failed requests by class: `timeout`, `connection`, `rate_limited` (429), `server_error`
+    /// (5xx) and `client_error` (other 4xx).
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl LatencyRecorder {
+    pub fn record(&self, kind: LatencyKind, duration: Duration) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let histogram = match kind {
+            LatencyKind::Scroll => &mut recorded.scroll,
+            LatencyKind::Bulk => &mut recorded.bulk,
+            LatencyKind::LocalWrite => &mut recorded.local_write,
+        };
+        histogram.record(duration);
+    }
+
+    /// Sends a request with `send`, recording how long the response took and, for failures,
+    /// their class.
+    pub fn time_request(
+        &self,
+        kind: LatencyKind,
+        send: impl FnOnce() -> Result<Response, EsDumperError>
+    ) -> Result<Response, EsDumperError> {
+        let started = Instant::now();
+        let result = send();
+        self.record(kind, started.elapsed());
+        if let Some(class) = error_class(&result) {
+            *self.recorded.lock().unwrap().errors.entry(class).or_insert(0) += 1;
+        }
+        result
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let recorded = self.recorded.lock().unwrap();
+        LatencySummary {
+            scroll: recorded.scroll.stats(),
+            bulk: recorded.bulk.stats(),
+            local_write: recorded.local_write.stats(),
+            errors: recorded.errors
+                .iter()
+                .map(|(class, count)| (class.to_string(), *count))
+                .collect(),
+        }
+    }
+}
+
+impl LatencySummary {
+    /// One line per kind of latency recorded, e.g. `scroll: 120 requests, p50 35.2 ms, ...`,
+    /// and one for the failed requests.
+    pub fn lines(&self) -> Vec<String> {
+        let kinds = [
+            ("scroll", "requests", &self.scroll),
+            ("bulk", "requests", &self.bulk),
+            ("local write", "pages", &self.local_write),
+        ];
+        let mut lines: Vec<String> = kinds
+            .iter()
+            .filter_map(|(name, unit, stats)| {
+                let stats = stats.as_ref()?;
+                Some(
+                    format!(
+                        "{}: {} {}, p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+                        name,
+                        stats.count,
+                        unit,
+                        stats.p50_ms,
+                        stats.p95_ms,
+                        stats.p99_ms,
+                        stats.max_ms
+                    )
+                )
+            })
+            .collect();
+        if !self.errors.is_empty() {
+            let errors: Vec<String> = self.errors
+                .iter()
+                .map(|(class, count)| format!("{} {}", count, class))
+                .collect();
+            lines.push(format!("failed requests: {}", errors.join(", ")));
+        }
+        lines
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, duration: Duration) {
+        let micros = (duration.as_micros() as u64).max(1);
+        let bucket = ((micros as f64).log2() * BUCKETS_PER_DOUBLING) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    fn stats(&self) -> Option<LatencyStats> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(LatencyStats {
+            count: self.count,
+            p50_ms: self.percentile(0.5),
+            p95_ms: self.percentile(0.95),
+            p99_ms: self.percentile(0.99),
+            max_ms: (self.max_micros as f64) / 1000.0,
+        })
+    }
+
+    /// The latency below which `fraction` of the recorded ones fall, as the middle of its
+    /// bucket and never above the maximum.
+    fn percentile(&self, fraction: f64) -> f64 {
+        let rank = ((self.count as f64) * fraction).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let micros = 2f64.powf(((bucket as f64) + 0.5) / BUCKETS_PER_DOUBLING);
+                return micros.min(self.max_micros as f64) / 1000.0;
+            }
+        }
+        (self.max_micros as f64) / 1000.0
+    }
+}
+
+fn error_class(result: &Result<Response, EsDumperError>) -> Option<&'static str> {
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                Some("rate_limited")
+            } else if status.is_server_error() {
+                Some("server_error")
+            } else if status.is_client_error() {
+                Some("client_error")
+            } else {
+                None
+            }
+        }
+        Err(EsDumperError::Connection(e)) if e.is_timeout() => Some("timeout"),
+        Err(_) => Some("connection"),
+    }
+}
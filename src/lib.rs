@@ -0,0 +1,48 @@
+//! Backup and restore of Elasticsearch indices, usable from other applications as well as
+//! through the `dump-dump` binary.
+//!
+//! [`run_backup`] and [`run_restore`] take a [`Job`] that doubles as the cancellation handle:
+//! calling [`Job::cancel`] from another thread stops the run between scroll pages and bulk
+//! batches, after which it returns [`EsDumperError::Cancelled`] (or a report of the indices
+//! completed so far).
+
+pub mod api;
+pub mod audit;
+pub mod backup;
+pub mod bloom;
+pub mod breaker;
+pub mod compressor;
+pub mod config;
+pub mod date_math;
+pub mod error;
+pub mod find;
+pub mod fixtures;
+pub mod history;
+pub mod hooks;
+pub mod http_client;
+pub mod index_diff;
+pub mod job;
+pub mod latency;
+pub mod manifest;
+pub mod mapping_compat;
+pub mod observer;
+pub mod output;
+pub mod pacer;
+pub mod plugins;
+pub mod progress;
+pub mod report;
+pub mod restore;
+pub mod scheduler;
+pub mod server;
+pub mod state;
+pub mod templates;
+pub mod transforms;
+pub mod utils;
+pub mod watches;
+
+pub use backup::run_backup;
+pub use config::BackupConfig;
+pub use error::EsDumperError;
+pub use job::Job;
+pub use report::RunReport;
+pub use restore::run_restore;
@@ -1,77 +1,585 @@
-mod backup;
-mod config;
-mod http_client;
-mod restore;
-mod utils;
+mod cli;
 
-use config::{ BackupConfig, Operation };
+use cli::CliArgs;
+use dump_dump::audit::{ self, AuditLog };
+use dump_dump::breaker::{ BreakerSettings, CircuitBreaker };
+use dump_dump::compressor::CompressionPool;
+use dump_dump::pacer::{
+    PacerSettings,
+    RestoreThrottle,
+    ShardWatch,
+    ShardWatchSettings,
+    WritePacer,
+};
+use dump_dump::scheduler::{ SchedulerSettings, SliceScheduler };
+use dump_dump::config::{
+    self,
+    ActiveShards,
+    BackupConfig,
+    BulkCompression,
+    ExistingPolicy,
+    Operation,
+    TemplateConflicts,
+};
+use dump_dump::fixtures::Fixtures;
+use dump_dump::http_client::HostPool;
+use dump_dump::job::{ new_run_id, Job };
+use dump_dump::output::DataFormat;
+use dump_dump::plugins::{ compiled_plugins, PluginSelection };
+use dump_dump::progress::{ ProgressObserver, RateLimits };
+use dump_dump::utils::{ self, open_log, setup_backup_dir, timestamp };
+use dump_dump::{ backup, find, history, restore, server };
 use std::env;
-use std::fs::File;
-use std::path::Path;
-use std::sync::{ Arc, Mutex };
-use utils::{ log, setup_backup_dir };
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    let cli = CliArgs::parse(env::args().skip(1))?;
 
-    let operation = match args.get(1).map(|s| s.as_str()) {
+    let operation = match cli.arg(0).as_deref() {
         Some("restore") =>
             Operation::Restore {
-                index: args.get(2).cloned(),
+                index: cli.arg(1),
             },
         Some("backup") =>
             Operation::Backup {
-                index: args.get(2).cloned(),
+                index: cli.arg(1),
             },
+        Some("history") =>
+            Operation::History {
+                index: cli.arg(1),
+            },
+        Some("serve") =>
+            Operation::Serve {
+                listen: cli.arg(1).unwrap_or_else(|| config::DEFAULT_SERVER_LISTEN.to_string()),
+            },
+        Some("find") =>
+            Operation::Find {
+                id: cli.value("id").ok_or("find requires --id <doc_id>")?.to_string(),
+                index: cli.value("index").map(str::to_string),
+            },
+        Some("verify-audit") =>
+            Operation::VerifyAudit {
+                path: cli.arg(1),
+            },
+        Some("cleanup-scrolls") => Operation::CleanupScrolls,
         _ => Operation::Backup { index: None },
     };
 
-    let config_file = config::load_config()?;
+    if cli.has("mapping-only") && cli.has("data-only") {
+        return Err("--mapping-only and --data-only cannot be combined".into());
+    }
+
+    let existing = match
+        (cli.has("overwrite"), cli.has("skip-existing"), cli.has("append"))
+    {
+        (false, false, false) => ExistingPolicy::Fail,
+        (true, false, false) => ExistingPolicy::Overwrite,
+        (false, true, false) => ExistingPolicy::SkipExisting,
+        (false, false, true) => ExistingPolicy::Append,
+        _ => {
+            return Err("Only one of --overwrite, --skip-existing and --append can be given".into());
+        }
+    };
+
+    let template_conflicts = match (cli.has("prefer-backup"), cli.has("prefer-template")) {
+        (false, false) => TemplateConflicts::Fail,
+        (true, false) => TemplateConflicts::PreferBackup,
+        (false, true) => TemplateConflicts::PreferTemplate,
+        (true, true) => {
+            return Err("--prefer-backup and --prefer-template cannot be combined".into());
+        }
+    };
+
+    let data_format = match cli.value("format") {
+        Some(name) =>
+            DataFormat::parse(name).ok_or_else(||
+                format!("Unknown --format '{}' (expected json or bulk)", name)
+            )?,
+        None => DataFormat::Json,
+    };
+
+    let config_file = config::load_config(!cli.has("no-local-files"))?;
 
     let backup_dir = config_file.backup.backup_dir.unwrap_or(
         config::DEFAULT_BACKUP_DIR.to_string()
     );
-    let host = config_file.elastic.host.unwrap_or(config::DEFAULT_ELASTIC_HOST.to_string());
+    let hosts = match config_file.elastic.hosts {
+        Some(hosts) if !hosts.is_empty() => hosts,
+        _ => vec![config_file.elastic.host.unwrap_or(config::DEFAULT_ELASTIC_HOST.to_string())],
+    };
+    let host_retry_secs = config_file.elastic.host_retry_secs.unwrap_or(
+        config::DEFAULT_HOST_RETRY_SECS
+    );
+    let request_timeout_secs = config_file.elastic.timeout_secs.unwrap_or(
+        config::DEFAULT_REQUEST_TIMEOUT_SECS
+    );
+
+    let breaker = CircuitBreaker::new(BreakerSettings {
+        error_threshold: config_file.elastic.breaker_error_threshold.unwrap_or(
+            config::DEFAULT_BREAKER_ERROR_THRESHOLD
+        ),
+        window: Duration::from_secs(
+            config_file.elastic.breaker_window_secs.unwrap_or(config::DEFAULT_BREAKER_WINDOW_SECS)
+        ),
+        cooldown: Duration::from_secs(
+            config_file.elastic.breaker_cooldown_secs.unwrap_or(
+                config::DEFAULT_BREAKER_COOLDOWN_SECS
+            )
+        ),
+        health_check: config_file.elastic.health_check_secs.map(Duration::from_secs),
+        max_pending_tasks: config_file.elastic.max_pending_tasks,
+    });
+    let auth = match (config_file.elastic.username, config_file.elastic.password) {
+        (Some(username), Some(password)) => Some((username, password)),
+        _ => None,
+    };
+
+    // Derived from the host's resources unless configured; logged once the log file is open
+    let (max_parallel_indices, parallelism_note) = match config_file.backup.max_parallel_indices {
+        Some(max_parallel_indices) => (max_parallel_indices, None),
+        None => {
+            let latency = if config_file.backup.measure_latency.unwrap_or(false) {
+                utils::measure_latency(&hosts[0], auth.as_ref())
+            } else {
+                None
+            };
+            let (max_parallel_indices, note) = utils::auto_parallelism(latency);
+            (max_parallel_indices, Some(note))
+        }
+    };
+
+    let mut hosts = HostPool::new(hosts, Duration::from_secs(host_retry_secs)).with_breaker(
+        breaker
+    );
+    match (cli.value("record"), cli.value("replay")) {
+        (Some(_), Some(_)) => {
+            return Err("--record and --replay cannot be combined".into());
+        }
+        (Some(dir), None) => {
+            hosts = hosts.with_fixtures(Fixtures::record(dir)?);
+        }
+        (None, Some(dir)) => {
+            hosts = hosts.with_fixtures(Fixtures::replay(dir)?);
+        }
+        (None, None) => {}
+    }
+
+    // --index-settings entries take precedence over [restore.index_settings]. Each holds one
+    // setting, split at its first `=`, so values may contain commas and further `=`
+    let mut index_settings = config_file.restore.index_settings.unwrap_or_default();
+    for entry in cli.values("index-settings") {
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid --index-settings entry '{}' (expected key=value)", entry))?;
+        let value = serde_json
+            ::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        index_settings.insert(key.trim().to_string(), value);
+    }
+
+    let restore_parallel_indices = config_file.restore.max_parallel_indices.unwrap_or(
+        max_parallel_indices
+    );
+    let parallelism_note = parallelism_note.filter(|_| {
+        !matches!(operation, Operation::Restore { .. }) ||
+            config_file.restore.max_parallel_indices.is_none()
+    });
+    let max_bulk_concurrency = config_file.restore.max_bulk_concurrency.unwrap_or(
+        restore_parallel_indices
+    );
+    let write_pacer = match config_file.restore.max_write_queue_utilization {
+        Some(max_utilization) =>
+            WritePacer::new(PacerSettings {
+                max_utilization,
+                check_interval: Duration::from_secs(
+                    config_file.restore.write_queue_check_secs.unwrap_or(
+                        config::DEFAULT_WRITE_QUEUE_CHECK_SECS
+                    )
+                ),
+                max_concurrency: max_bulk_concurrency,
+            }),
+        None if config_file.restore.max_bulk_concurrency.is_some() =>
+            WritePacer::fixed(max_bulk_concurrency),
+        None => WritePacer::disabled(),
+    };
+
+    let wait_for_status_timeout_secs = config_file.restore.wait_for_status_timeout_secs.unwrap_or(
+        config::DEFAULT_WAIT_FOR_STATUS_TIMEOUT_SECS
+    );
+    let shard_watch = ShardWatch::new(
+        config_file.restore.max_unassigned_shards.map(|max_unassigned| ShardWatchSettings {
+            max_unassigned,
+            check_interval: Duration::from_secs(
+                config_file.restore.write_queue_check_secs.unwrap_or(
+                    config::DEFAULT_WRITE_QUEUE_CHECK_SECS
+                )
+            ),
+            timeout: Duration::from_secs(wait_for_status_timeout_secs),
+        })
+    );
+    for (key, limit) in [
+        ("max_docs_per_sec", config_file.restore.max_docs_per_sec),
+        ("max_bytes_per_sec", config_file.restore.max_bytes_per_sec),
+    ] {
+        if limit == Some(0) {
+            return Err(format!("Invalid {} 0 (leave it unset for no limit)", key).into());
+        }
+    }
+    let plugins_file = config_file.plugins.unwrap_or_default();
+    let plugins = PluginSelection {
+        transforms: plugins_file.transforms.unwrap_or_default(),
+        storage: plugins_file.storage,
+        options: plugins_file.options.unwrap_or_default(),
+    };
+    // Unknown plugins and rejected options fail the run before it touches the cluster
+    plugins.attach(&compiled_plugins(), Job::new(0, "plugins", None))?;
+    let restore_throttle = RestoreThrottle::new(RateLimits {
+        docs_per_sec: config_file.restore.max_docs_per_sec.map(|limit| limit as f64),
+        bytes_per_sec: config_file.restore.max_bytes_per_sec.map(|limit| limit as f64),
+    });
+    if let Some(ActiveShards::Named(name)) = &config_file.restore.wait_for_active_shards {
+        if name != "all" {
+            return Err(
+                format!(
+                    "Invalid wait_for_active_shards '{}' (expected a number or \"all\")",
+                    name
+                ).into()
+            );
+        }
+    }
+
+    let slice_scheduler = match config_file.backup.max_total_slices {
+        Some(max_total_slices) =>
+            SliceScheduler::new(SchedulerSettings {
+                max_total_slices,
+                max_share: config_file.backup.max_slice_share.unwrap_or(
+                    config::DEFAULT_MAX_SLICE_SHARE
+                ),
+            }),
+        None => SliceScheduler::disabled(),
+    };
+
+    // Only restores change the cluster, so other operations leave the audit log alone
+    let audit_log = config_file.restore.audit_log;
+    let audit = match (&operation, &audit_log) {
+        (Operation::Restore { .. } | Operation::Serve { .. }, Some(path)) => AuditLog::open(path)?,
+        _ => AuditLog::disabled(),
+    };
+
+    let max_duration = match cli.value("max-duration") {
+        Some(value) =>
+            Some(
+                utils
+                    ::parse_duration(value)
+                    .ok_or_else(|| format!("Invalid --max-duration '{}'", value))?
+            ),
+        None => None,
+    };
+
+    let max_index_duration = match cli.value("max-index-duration") {
+        Some(value) =>
+            Some(
+                utils
+                    ::parse_duration(value)
+                    .ok_or_else(|| format!("Invalid --max-index-duration '{}'", value))?
+            ),
+        None => None,
+    };
+    let max_docs = match cli.value("max-docs") {
+        Some(value) =>
+            Some(value.parse::<u64>().map_err(|_| format!("Invalid --max-docs '{}'", value))?),
+        None => None,
+    };
+
+    let preserve_metadata = match config_file.backup.preserve_metadata {
+        Some(fields) => fields,
+        None => config::DEFAULT_PRESERVE_METADATA.iter().map(|field| field.to_string()).collect(),
+    };
+    if let Some(field) = preserve_metadata
+        .iter()
+        .find(|field| !config::PRESERVABLE_METADATA.contains(&field.as_str()))
+    {
+        return Err(
+            format!(
+                "Unknown preserve_metadata field '{}' (expected one of {})",
+                field,
+                config::PRESERVABLE_METADATA.join(", ")
+            ).into()
+        );
+    }
 
     let config = BackupConfig {
-        host,
+        hosts,
+        compatibility: config_file.elastic.compatibility.unwrap_or_default(),
+        index_retries: config_file.elastic.index_retries.unwrap_or(config::DEFAULT_INDEX_RETRIES),
+        index_retry_backoff: Duration::from_secs(
+            config_file.elastic.index_retry_backoff_secs.unwrap_or(
+                config::DEFAULT_INDEX_RETRY_BACKOFF_SECS
+            )
+        ),
         backup_dir,
-        auth: match (config_file.elastic.username, config_file.elastic.password) {
-            (Some(username), Some(password)) => Some((username, password)),
-            _ => None,
-        },
+        auth,
         skip_indices: config_file.backup.skip_indices.unwrap_or_default(),
         max_index_size_mb: config_file.backup.max_index_size_mb,
         operation,
         connect_timeout_secs: config_file.elastic.connect_timeout_secs.unwrap_or(
             config::DEFAULT_CONNECT_TIMEOUT_SECS
         ),
-        request_timeout_secs: config_file.elastic.timeout_secs.unwrap_or(
-            config::DEFAULT_REQUEST_TIMEOUT_SECS
+        pool_max_idle_per_host: config_file.elastic.pool_max_idle_per_host,
+        pool_idle_timeout_secs: config_file.elastic.pool_idle_timeout_secs.unwrap_or(
+            config::DEFAULT_POOL_IDLE_TIMEOUT_SECS
         ),
+        tcp_keepalive_secs: config_file.elastic.tcp_keepalive_secs,
+        http2: config_file.elastic.http2.unwrap_or(false),
+        user_agent: config_file.elastic.user_agent.unwrap_or(config::DEFAULT_USER_AGENT.to_string()),
+        opaque_id_prefix: config_file.elastic.opaque_id_prefix.unwrap_or(
+            config::DEFAULT_OPAQUE_ID_PREFIX.to_string()
+        ),
+        request_timeout_secs,
+        metadata_timeout_secs: config_file.elastic.metadata_timeout_secs.unwrap_or(
+            request_timeout_secs
+        ),
+        scroll_timeout_secs: config_file.elastic.scroll_timeout_secs.unwrap_or(
+            request_timeout_secs
+        ),
+        bulk_timeout_secs: config_file.elastic.bulk_timeout_secs.unwrap_or(request_timeout_secs),
         scroll_size: config_file.backup.scroll_size.unwrap_or(config::DEFAULT_SCROLL_SIZE),
         scroll_time: config_file.backup.scroll_time.unwrap_or_else(||
             config::DEFAULT_SCROLL_TIME.to_string()
         ),
-        max_parallel_indices: config_file.backup.max_parallel_indices.unwrap_or(
-            config::DEFAULT_MAX_PARALLEL_INDICES
+        max_scroll_time: config_file.backup.max_scroll_time.unwrap_or_else(||
+            config::DEFAULT_MAX_SCROLL_TIME.to_string()
+        ),
+        max_scroll_restarts: config_file.backup.max_scroll_restarts.unwrap_or(
+            config::DEFAULT_MAX_SCROLL_RESTARTS
+        ),
+        max_slices: config_file.backup.max_slices.unwrap_or(config::DEFAULT_MAX_SLICES),
+        slice_scheduler,
+        size_factor: config_file.backup.size_factor.unwrap_or(config::DEFAULT_SIZE_FACTOR),
+        min_free_space_mb: config_file.backup.min_free_space_mb.unwrap_or(
+            config::DEFAULT_MIN_FREE_SPACE_MB
+        ),
+        exclude_field_types: config_file.backup.exclude_field_types.unwrap_or_default(),
+        preserve_metadata,
+        dense_vector_min_dims: config_file.backup.dense_vector_min_dims.unwrap_or(
+            config::DEFAULT_DENSE_VECTOR_MIN_DIMS
+        ),
+        include_watches: config_file.backup.include_watches.unwrap_or(false),
+        include_field_caps: config_file.backup.include_field_caps.unwrap_or(false),
+        pre_backup_cmd: config_file.backup.pre_backup_cmd,
+        post_backup_cmd: config_file.backup.post_backup_cmd,
+        post_index_cmd: config_file.backup.post_index_cmd,
+        pre_restore_cmd: config_file.restore.pre_restore_cmd,
+        post_restore_cmd: config_file.restore.post_restore_cmd,
+        doc_values_fallback: config_file.backup.doc_values_fallback.unwrap_or(false),
+        timestamp_field: config_file.backup.timestamp_field,
+        namespace_by_cluster: config_file.backup.namespace_by_cluster.unwrap_or(false),
+        slice_overrides: config_file.backup.slices.unwrap_or_default(),
+        partition_fields: config_file.backup.partitions.unwrap_or_default(),
+        partition_docs: config_file.backup.partition_docs.unwrap_or(config::DEFAULT_PARTITION_DOCS),
+        index_paths: config_file.backup.paths.unwrap_or_default(),
+        sort_fields: config_file.backup.sort_fields.unwrap_or_default(),
+        compression_mode: config_file.backup.compression_mode.unwrap_or_default(),
+        compressor: CompressionPool::new(
+            config_file.backup.compression_workers.unwrap_or(config::DEFAULT_COMPRESSION_WORKERS)
+        ),
+        max_parallel_indices,
+        restore_parallel_indices,
+        create_indices_first: config_file.restore.create_indices_first.unwrap_or(false),
+        create_concurrency: config_file.restore.create_concurrency.unwrap_or(
+            config::DEFAULT_CREATE_CONCURRENCY
         ),
         buffer_size: config::DEFAULT_BUFFER_SIZE,
+        durability: config_file.backup.durability.unwrap_or_default(),
+        empty_indices: config_file.backup.empty_indices.unwrap_or_default(),
+        closed_indices: config_file.backup.closed_indices.unwrap_or_default(),
         bulk_batch_size: config_file.restore.bulk_batch_size.unwrap_or(
             config::DEFAULT_BULK_BATCH_SIZE
         ),
+        bulk_max_bytes: config_file.restore.bulk_max_bytes.unwrap_or(
+            config::DEFAULT_BULK_MAX_BYTES
+        ),
+        compress_bulk: BulkCompression::new(config_file.restore.compress_bulk.unwrap_or(false)),
+        bulk_file: cli.value("bulk-file").map(str::to_string),
+        verify_sample_size: config_file.restore.verify_sample_size.unwrap_or(
+            config::DEFAULT_VERIFY_SAMPLE_SIZE
+        ),
+        bulk_refresh: config_file.restore.bulk_refresh.unwrap_or_default(),
+        final_refresh: config_file.restore.final_refresh.unwrap_or(true),
+        checksum_mismatch: config_file.restore.checksum_mismatch.unwrap_or_default(),
+        copy_id_to: config_file.restore.copy_id_to,
+        forcemerge_max_segments: config_file.restore.forcemerge_max_segments,
+        index_settings,
+        wait_for_status: config_file.restore.wait_for_status,
+        wait_for_status_timeout_secs,
+        wait_between_indices: config_file.restore.wait_between_indices.unwrap_or(false),
+        wait_for_active_shards: config_file.restore.wait_for_active_shards,
+        restore_from: cli.value("from").map(str::to_string),
+        source_cluster: cli.value("source-cluster").map(str::to_string),
+        restore_include: cli.list("include"),
+        restore_skip: cli.list("skip"),
+        restore_priority: config_file.restore.restore_priority.unwrap_or_default(),
+        template_conflicts,
+        split_types: cli.has("split-types"),
+        update_existing: cli.has("update-existing"),
+        force: cli.has("force"),
+        write_pacer,
+        shard_watch,
+        restore_throttle,
+        plugins,
+        audit,
+        report_index: config_file.elastic.report_index,
+        report_host: config_file.elastic.report_host,
+        strict: cli.has("strict"),
+        max_duration,
+        max_index_duration,
+        max_docs,
+        self_test: cli.has("self-test"),
+        resume: cli.has("resume"),
+        metadata_only: cli.has("metadata-only"),
+        existing,
+        data_format,
+        mapping_only: cli.has("mapping-only"),
+        data_only: cli.has("data-only"),
+        restore_transforms: cli.has("restore-transforms") || cli.has("start-transforms"),
+        start_transforms: cli.has("start-transforms"),
+        restore_watches: cli.has("restore-watches"),
+        no_local_files: cli.has("no-local-files"),
     };
 
-    setup_backup_dir(&config.backup_dir)?;
+    if !config.no_local_files {
+        setup_backup_dir(&config.backup_dir)?;
+    }
 
-    let log_path = Path::new(&config.backup_dir).join(config::DEFAULT_LOG_FILE);
-    let log_file = File::options().append(true).create(true).open(&log_path)?;
-    let log_file = Arc::new(Mutex::new(log_file));
+    // Each invocation logs to a file of its own; the server's records are those of job 0
+    let run_id = new_run_id(0);
+    let log_file = open_log(&config.backup_dir, &run_id, config.no_local_files)?;
+    if let Some(note) = parallelism_note {
+        utils::log(
+            &log_file,
+            &format!("Using {}; set max_parallel_indices to override", note)
+        )?;
+    }
 
-    match &config.operation {
-        Operation::Backup { index } => backup::run_backup(&config, &log_file, index.as_deref())?,
-        Operation::Restore { index } => restore::run_restore(&config, &log_file, index.as_deref())?,
+    if let Operation::Serve { listen } = &config.operation {
+        let listen = listen.clone();
+        return server::serve(Arc::new(config), log_file, &listen);
     }
 
+    let started_at = timestamp();
+    // Without local files log records go to stderr, so the bars stay hidden
+    let progress = || Box::new(ProgressObserver::new(config.no_local_files));
+    let (operation, result) = match &config.operation {
+        Operation::Backup { index } => {
+            let job = Job::new(0, "backup", index.clone())
+                .with_run_id(run_id)
+                .with_observer(progress());
+            let job = config.plugins.attach(&compiled_plugins(), job)?;
+            ("backup", backup::run_backup(&config, &log_file, index.as_deref(), &job))
+        }
+        Operation::Restore { index } => {
+            let job = Job::new(0, "restore", index.clone())
+                .with_run_id(run_id)
+                .with_observer(
+                    Box::new(
+                        ProgressObserver::new(config.no_local_files).with_rate_limits(
+                            config.restore_throttle.limits()
+                        )
+                    )
+                );
+            let job = config.plugins.attach(&compiled_plugins(), job)?;
+            ("restore", restore::run_restore(&config, &log_file, index.as_deref(), &job))
+        }
+        Operation::History { index } => {
+            return history::print_history(
+                &config.backup_dir,
+                index.as_deref(),
+                config::DEFAULT_HISTORY_LIMIT
+            );
+        }
+        Operation::Find { id, index } => {
+            return find::find_document(&config, id, index.as_deref(), &log_file);
+        }
+        Operation::CleanupScrolls => {
+            if config.no_local_files {
+                return Err("cleanup-scrolls reads the state store and needs local files".into());
+            }
+            let cleared = backup::cleanup_scrolls(&config, &log_file)?;
+            println!("Cleared {} leftover scroll contexts", cleared);
+            return Ok(());
+        }
+        Operation::VerifyAudit { path } => {
+            let path = path
+                .as_deref()
+                .or(audit_log.as_deref())
+                .ok_or("verify-audit requires a path or [restore] audit_log")?;
+            match audit::verify_audit_log(path) {
+                Ok(records) => {
+                    println!("Audit log {} is intact: {} records", path, records);
+                    return Ok(());
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Operation::Serve { .. } => unreachable!(),
+    };
+
+    if !config.no_local_files {
+        history::record_result(
+            &config.backup_dir,
+            operation,
+            started_at.clone(),
+            &result,
+            &log_file
+        );
+    }
+    history::publish_result(&config, operation, started_at, &result, &log_file);
+
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !report.skipped.is_empty() {
+        eprintln!("Skipped {} indices:", report.skipped.len());
+        for (reason, indices) in report.skipped_by_reason() {
+            let shown: Vec<&str> = indices
+                .iter()
+                .take(config::SKIPPED_INDICES_SHOWN)
+                .copied()
+                .collect();
+            let more = match indices.len().saturating_sub(config::SKIPPED_INDICES_SHOWN) {
+                0 => String::new(),
+                more => format!(" and {} more", more),
+            };
+            eprintln!("  {}: {}{}", reason, shown.join(", "), more);
+        }
+    }
+    if let Some(latency) = &report.latency {
+        let lines = latency.lines();
+        if !lines.is_empty() {
+            eprintln!("Latencies:");
+            for line in lines {
+                eprintln!("  {}", line);
+            }
+        }
+    }
+    if let Some(http) = &report.http {
+        eprintln!("HTTP: {}", http.line());
+    }
+    if !report.deferred.is_empty() {
+        eprintln!(
+            "Deadline reached, partial {}: {} indices deferred (run again with --resume)",
+            operation,
+            report.deferred.len()
+        );
+        std::process::exit(config::EXIT_DEADLINE_REACHED);
+    }
     Ok(())
 }
@@ -0,0 +1,162 @@
+use crate::error::EsDumperError;
+use serde::de::DeserializeOwned;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+use std::fs::File;
+use std::io::{ self, BufReader };
+use std::path::{ Path, PathBuf };
+
+/// Where the documents of a backup came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentSource {
+    /// The stored `_source` of each document.
+    #[default]
+    Source,
+    /// Reconstructed from doc values and stored fields, for indices with `_source` disabled.
+    DocValues,
+    /// Only document IDs and metadata: `_source` is disabled and no reconstruction was attempted.
+    None,
+}
+
+/// How an index was backed up, saved as `<index>_manifest.json` next to its data so restores
+/// and people browsing the backup know what the files can and cannot bring back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexManifest {
+    pub index: String,
+    pub created_at: String,
+    /// ID of the run that backed the index up, as in its log records and report.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub run_uuid: Option<String>,
+    /// The cluster the index was backed up from.
+    #[serde(default)]
+    pub cluster_name: Option<String>,
+    #[serde(default)]
+    pub cluster_uuid: Option<String>,
+    pub source: DocumentSource,
+    /// Alias named on the command line that the index was backed up through; a restore given
+    /// the alias restores every index recorded with it.
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// Known gaps of the backup, such as fields whose values were not exported.
+    #[serde(default)]
+    pub limitations: Vec<String>,
+    /// Why the backup has no data file, e.g. because the index was empty.
+    #[serde(default)]
+    pub data_skipped: Option<String>,
+    /// Gzipped size of the data projected while exporting, from compressing a sample of it.
+    #[serde(default)]
+    pub estimated_compressed_bytes: Option<u64>,
+    /// Compressed size of the sampled data as a fraction of its uncompressed size.
+    #[serde(default)]
+    pub compression_ratio: Option<f64>,
+    /// Field the documents were range-partitioned on, with a data file per partition.
+    #[serde(default)]
+    pub partition_field: Option<String>,
+    #[serde(default)]
+    pub partitions: Vec<Partition>,
+    /// SHA-256 of the uncompressed data file, checked while restoring it; partitioned backups
+    /// record one per partition instead.
+    #[serde(default)]
+    pub data_sha256: Option<String>,
+    /// The `--max-docs` cap that stopped the export before every document of the index was
+    /// written; none for complete backups.
+    #[serde(default)]
+    pub max_docs: Option<u64>,
+    /// Why the export stopped before every document was written, e.g. `--max-index-duration`.
+    /// Restores refuse the backup until `--resume` completes it.
+    #[serde(default)]
+    pub interrupted: Option<String>,
+}
+
+/// A range of the partition field exported into a data file of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Partition {
+    /// Inclusive lower bound; none for the first partition, which also holds the documents
+    /// without a value for the field.
+    pub gte: Option<Value>,
+    /// Exclusive upper bound; none for the last partition.
+    pub lt: Option<Value>,
+    /// Data file name in the index directory, before compression.
+    pub file: String,
+    /// Documents written, set once the partition is complete.
+    pub docs: Option<u64>,
+    /// SHA-256 of the uncompressed data file, set with `docs`.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The data file was gzipped as it was written and `<file>.gz` is complete, set with `docs`.
+    /// Restores then read only the compressed file.
+    #[serde(default)]
+    pub compressed: bool,
+}
+
+impl IndexManifest {
+    pub fn path(index_dir: &Path, index: &str) -> PathBuf {
+        index_dir.join(format!("{}_manifest.json", index))
+    }
+
+    pub fn save(&self, index_dir: &Path, sync: bool) -> Result<(), EsDumperError> {
+        save_json(&Self::path(index_dir, &self.index), self, sync)
+    }
+
+    /// The manifest of a backup, or `None` for backups made before manifests were written.
+    pub fn load(index_dir: &Path, index: &str) -> Result<Option<Self>, EsDumperError> {
+        load_json(&Self::path(index_dir, index))
+    }
+
+    /// Whether a partitioned export stopped before all of its partitions were written.
+    pub fn has_unfinished_partitions(&self) -> bool {
+        self.partitions.iter().any(|partition| partition.docs.is_none())
+    }
+}
+
+/// Statistics of a backed-up index, saved as `<index>_meta.json` for comparing backups with
+/// each other or with the cluster.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexMeta {
+    pub index: String,
+    /// Documents written to the backup.
+    pub doc_count: u64,
+    pub shards: Option<u64>,
+    /// Highest `_seq_no` when the export started; documents indexed later are not in the backup.
+    pub max_seq_no: Option<u64>,
+    pub timestamp_field: Option<String>,
+    pub min_timestamp: Option<Value>,
+    pub max_timestamp: Option<Value>,
+    /// Time spent exporting the documents.
+    pub duration_secs: f64,
+}
+
+impl IndexMeta {
+    pub fn path(index_dir: &Path, index: &str) -> PathBuf {
+        index_dir.join(format!("{}_meta.json", index))
+    }
+
+    pub fn save(&self, index_dir: &Path, sync: bool) -> Result<(), EsDumperError> {
+        save_json(&Self::path(index_dir, &self.index), self, sync)
+    }
+
+    /// The metadata of a backup, or `None` for metadata-only and older backups.
+    pub fn load(index_dir: &Path, index: &str) -> Result<Option<Self>, EsDumperError> {
+        load_json(&Self::path(index_dir, index))
+    }
+}
+
+fn save_json(path: &Path, value: &impl Serialize, sync: bool) -> Result<(), EsDumperError> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(&file, value)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn load_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, EsDumperError> {
+    match File::open(path) {
+        Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
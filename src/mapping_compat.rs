@@ -0,0 +1,461 @@
+use crate::api::{ Distribution, EsApi, EsVersion };
+use serde_json::{ Map, Value };
+
+/// Elasticsearch version whose mapping parameters OpenSearch accepts, as its fork.
+const OPENSEARCH_BASE: EsVersion = EsVersion::new(7, 10, 0);
+
+/// Where in a mapping a parameter lives.
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    /// The root of the mapping (or of a document type), e.g. `_all`.
+    Root,
+    /// The definition of a single field, e.g. `include_in_all`.
+    Field,
+}
+
+/// What a restore does with a parameter the target no longer accepts.
+#[derive(Clone, Copy)]
+enum Fix {
+    Remove,
+    /// Rewrites the object holding the parameter into its replacement.
+    Rewrite(fn(&mut Map<String, Value>)),
+}
+
+/// A mapping parameter that Elasticsearch removed or replaced, rejecting index creation with
+/// an opaque 400 from `removed_in` on.
+struct Rule {
+    parameter: &'static str,
+    scope: Scope,
+    removed_in: EsVersion,
+    fix: Fix,
+    /// What the restore did about it, for the warning.
+    change: &'static str,
+}
+
+/// Mapping parameters removed or renamed across Elasticsearch versions, applied in order. The
+/// `string` rule runs before the `index` rule, as it is the one turning `not_analyzed` string
+/// fields into keywords.
+const RULES: &[Rule] = &[
+    Rule {
+        parameter: "_timestamp",
+        scope: Scope::Root,
+        removed_in: EsVersion::new(5, 0, 0),
+        fix: Fix::Remove,
+        change: "removed the _timestamp field (removed in 5.0; use an ingest pipeline)",
+    },
+    Rule {
+        parameter: "_ttl",
+        scope: Scope::Root,
+        removed_in: EsVersion::new(5, 0, 0),
+        fix: Fix::Remove,
+        change: "removed the _ttl field (removed in 5.0; use index lifecycle management)",
+    },
+    Rule {
+        parameter: "_all",
+        scope: Scope::Root,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed the _all field (removed in 6.0; use copy_to)",
+    },
+    Rule {
+        parameter: "_field_names",
+        scope: Scope::Root,
+        removed_in: EsVersion::new(8, 0, 0),
+        fix: Fix::Remove,
+        change: "removed _field_names settings (no longer configurable in 8.0)",
+    },
+    Rule {
+        parameter: "type",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Rewrite(rewrite_string),
+        change: "replaced string fields with text, or keyword where not analyzed (string was removed in 6.0)",
+    },
+    Rule {
+        parameter: "index",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Rewrite(rewrite_index),
+        change: "replaced index: analyzed/not_analyzed/no with true or false (removed in 6.0)",
+    },
+    Rule {
+        parameter: "include_in_all",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed include_in_all (removed with _all in 6.0)",
+    },
+    Rule {
+        parameter: "norms",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Rewrite(rewrite_norms),
+        change: "replaced norms: {enabled: ...} with a boolean (removed in 6.0)",
+    },
+    Rule {
+        parameter: "lat_lon",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed lat_lon from geo_point fields (removed in 6.0)",
+    },
+    Rule {
+        parameter: "geohash",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed geohash from geo_point fields (removed in 6.0)",
+    },
+    Rule {
+        parameter: "geohash_prefix",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed geohash_prefix from geo_point fields (removed in 6.0)",
+    },
+    Rule {
+        parameter: "geohash_precision",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(6, 0, 0),
+        fix: Fix::Remove,
+        change: "removed geohash_precision from geo_point fields (removed in 6.0)",
+    },
+    Rule {
+        parameter: "similarity",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(7, 0, 0),
+        fix: Fix::Rewrite(rewrite_similarity),
+        change: "replaced the classic similarity with BM25 (classic was removed in 7.0)",
+    },
+    Rule {
+        parameter: "boost",
+        scope: Scope::Field,
+        removed_in: EsVersion::new(8, 0, 0),
+        fix: Fix::Remove,
+        change: "removed index-time boost (removed in 8.0; boost at query time)",
+    },
+];
+
+/// Parameters of a field definition that keyword fields reject.
+const TEXT_ONLY_PARAMETERS: &[&str] = &[
+    "analyzer",
+    "search_analyzer",
+    "search_quote_analyzer",
+    "position_increment_gap",
+    "term_vector",
+    "fielddata",
+];
+
+/// Strips or rewrites the parameters of `mappings` that the target cluster no longer accepts,
+/// so old backups can be restored into newer clusters. Returns a warning naming what was
+/// changed on which fields, or `None` when the mapping needed no change.
+pub fn adapt_mappings(mappings: &mut Value, api: &dyn EsApi) -> Option<String> {
+    let target = match api.distribution() {
+        Distribution::Elasticsearch => api.version().clone(),
+        Distribution::OpenSearch => OPENSEARCH_BASE,
+    };
+    let rules: Vec<&Rule> = RULES.iter()
+        .filter(|rule| target >= rule.removed_in)
+        .collect();
+    if rules.is_empty() {
+        return None;
+    }
+
+    // Fields changed by each rule, in the order of the rules
+    let mut changed: Vec<Vec<String>> = vec![Vec::new(); rules.len()];
+    for root in mapping_roots(mappings) {
+        adapt_root(root, &rules, &mut changed);
+    }
+
+    let changes: Vec<String> = rules
+        .iter()
+        .zip(&changed)
+        .filter(|(_, fields)| !fields.is_empty())
+        .map(|(rule, fields)| {
+            if rule.scope == Scope::Root {
+                rule.change.to_string()
+            } else {
+                format!("{} on {}", rule.change, fields.join(", "))
+            }
+        })
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+    Some(
+        format!(
+            "Adapted the mapping to {} {}: {}",
+            api.distribution().as_str(),
+            api.version(),
+            changes.join("; ")
+        )
+    )
+}
+
+/// The mappings of each document type of a typed mapping, or the mapping itself.
+fn mapping_roots(mappings: &mut Value) -> Vec<&mut Value> {
+    let Some(object) = mappings.as_object() else {
+        return Vec::new();
+    };
+    let typed =
+        !object.is_empty() &&
+        object
+            .iter()
+            .all(|(key, value)| {
+                value.is_object() &&
+                    !["properties", "dynamic_templates"].contains(&key.as_str()) &&
+                    (!key.starts_with('_') || key == "_default_")
+            });
+    if typed {
+        mappings
+            .as_object_mut()
+            .unwrap()
+            .values_mut()
+            .collect()
+    } else {
+        vec![mappings]
+    }
+}
+
+fn adapt_root(root: &mut Value, rules: &[&Rule], changed: &mut [Vec<String>]) {
+    let Some(object) = root.as_object_mut() else {
+        return;
+    };
+    for (number, rule) in rules.iter().enumerate() {
+        if rule.scope == Scope::Root && object.remove(rule.parameter).is_some() {
+            changed[number].push(rule.parameter.to_string());
+        }
+    }
+    if let Some(properties) = object.get_mut("properties") {
+        adapt_properties(properties, "", rules, changed);
+    }
+    // The mapping a dynamic template gives to new fields is a field definition as well
+    if let Some(templates) = object.get_mut("dynamic_templates").and_then(Value::as_array_mut) {
+        for template in templates.iter_mut().filter_map(Value::as_object_mut) {
+            for (name, template) in template.iter_mut() {
+                if let Some(mapping) = template.get_mut("mapping") {
+                    let path = format!("dynamic template {}", name);
+                    adapt_field(mapping, &path, rules, changed);
+                }
+            }
+        }
+    }
+}
+
+fn adapt_properties(properties: &mut Value, prefix: &str, rules: &[&Rule], changed: &mut [Vec<String>]) {
+    let Some(properties) = properties.as_object_mut() else {
+        return;
+    };
+    for (name, field) in properties.iter_mut() {
+        let path = format!("{}{}", prefix, name);
+        adapt_field(field, &path, rules, changed);
+    }
+}
+
+fn adapt_field(field: &mut Value, path: &str, rules: &[&Rule], changed: &mut [Vec<String>]) {
+    let Some(object) = field.as_object_mut() else {
+        return;
+    };
+    for (number, rule) in rules.iter().enumerate() {
+        if rule.scope != Scope::Field || !object.contains_key(rule.parameter) {
+            continue;
+        }
+        let before = Value::Object(object.clone());
+        match rule.fix {
+            Fix::Remove => {
+                object.remove(rule.parameter);
+            }
+            Fix::Rewrite(rewrite) => rewrite(object),
+        }
+        if before != Value::Object(object.clone()) {
+            changed[number].push(path.to_string());
+        }
+    }
+
+    // Object and nested fields hold fields of their own, and multi-fields sub-fields
+    if let Some(properties) = object.get_mut("properties") {
+        adapt_properties(properties, &format!("{}.", path), rules, changed);
+    }
+    if let Some(fields) = object.get_mut("fields") {
+        adapt_properties(fields, &format!("{}.", path), rules, changed);
+    }
+}
+
+/// `string` fields become `keyword` when they were not analyzed and `text` otherwise.
+fn rewrite_string(field: &mut Map<String, Value>) {
+    if field.get("type").and_then(Value::as_str) != Some("string") {
+        return;
+    }
+    let index = field.get("index").and_then(Value::as_str).map(str::to_string);
+    match index.as_deref() {
+        Some("not_analyzed") | Some("no") => {
+            field.insert("type".to_string(), "keyword".into());
+            for parameter in TEXT_ONLY_PARAMETERS {
+                field.remove(*parameter);
+            }
+            if index.as_deref() == Some("not_analyzed") {
+                field.remove("index");
+            }
+        }
+        _ => {
+            field.insert("type".to_string(), "text".into());
+            // Limits the length of keywords, which text fields don't have
+            field.remove("ignore_above");
+            if field.get("fielddata").is_some_and(|fielddata| !fielddata.is_boolean()) {
+                field.remove("fielddata");
+            }
+        }
+    }
+}
+
+fn rewrite_index(field: &mut Map<String, Value>) {
+    let indexed = match field.get("index").and_then(Value::as_str) {
+        Some("analyzed" | "not_analyzed") => true,
+        Some("no") => false,
+        _ => {
+            return;
+        }
+    };
+    field.insert("index".to_string(), indexed.into());
+}
+
+fn rewrite_norms(field: &mut Map<String, Value>) {
+    let enabled = match field.get("norms") {
+        Some(Value::Object(norms)) => norms.get("enabled").and_then(Value::as_bool).unwrap_or(true),
+        _ => {
+            return;
+        }
+    };
+    field.insert("norms".to_string(), enabled.into());
+}
+
+fn rewrite_similarity(field: &mut Map<String, Value>) {
+    if field.get("similarity").and_then(Value::as_str) == Some("classic") {
+        field.insert("similarity".to_string(), "BM25".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::select_api;
+    use serde_json::json;
+
+    fn adapt(mut mappings: Value, version: EsVersion) -> (Value, Option<String>) {
+        let api = select_api(version, Distribution::Elasticsearch);
+        let warning = adapt_mappings(&mut mappings, api.as_ref());
+        (mappings, warning)
+    }
+
+    #[test]
+    fn leaves_mappings_the_target_accepts() {
+        let mappings = json!({
+            "_all": { "enabled": false },
+            "properties": { "title": { "type": "string", "index": "not_analyzed" } }
+        });
+        let (adapted, warning) = adapt(mappings.clone(), EsVersion::new(5, 6, 0));
+        assert_eq!(adapted, mappings);
+        assert_eq!(warning, None);
+
+        let (adapted, warning) = adapt(json!({ "properties": {} }), EsVersion::new(8, 15, 0));
+        assert_eq!(adapted, json!({ "properties": {} }));
+        assert_eq!(warning, None);
+    }
+
+    #[test]
+    fn rewrites_string_fields() {
+        let mappings = json!({
+            "properties": {
+                "tag": { "type": "string", "index": "not_analyzed", "analyzer": "standard" },
+                "secret": { "type": "string", "index": "no" },
+                "body": { "type": "string", "ignore_above": 256, "include_in_all": false },
+            }
+        });
+        let (adapted, warning) = adapt(mappings, EsVersion::new(7, 17, 0));
+        assert_eq!(
+            adapted,
+            json!({
+                "properties": {
+                    "tag": { "type": "keyword" },
+                    "secret": { "type": "keyword", "index": false },
+                    "body": { "type": "text" },
+                }
+            })
+        );
+        let warning = warning.unwrap();
+        assert!(
+            warning.starts_with("Adapted the mapping to Elasticsearch 7.17.0: "),
+            "{}",
+            warning
+        );
+        assert!(warning.contains("string was removed in 6.0) on body, secret, tag"), "{}", warning);
+        assert!(warning.contains("(removed with _all in 6.0) on body"), "{}", warning);
+    }
+
+    #[test]
+    fn adapts_typed_mappings_objects_multi_fields_and_dynamic_templates() {
+        let mappings = json!({
+            "doc": {
+                "_all": { "enabled": true },
+                "_ttl": { "enabled": true },
+                "dynamic_templates": [
+                    {
+                        "strings": {
+                            "mapping": { "type": "string", "norms": { "enabled": false } }
+                        }
+                    }
+                ],
+                "properties": {
+                    "user": {
+                        "properties": {
+                            "name": {
+                                "type": "text",
+                                "similarity": "classic",
+                                "fields": { "raw": { "type": "keyword", "boost": 2 } }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        let (adapted, warning) = adapt(mappings, EsVersion::new(8, 0, 0));
+        assert_eq!(
+            adapted,
+            json!({
+                "doc": {
+                    "dynamic_templates": [
+                        { "strings": { "mapping": { "type": "text", "norms": false } } }
+                    ],
+                    "properties": {
+                        "user": {
+                            "properties": {
+                                "name": {
+                                    "type": "text",
+                                    "similarity": "BM25",
+                                    "fields": { "raw": { "type": "keyword" } }
+                                }
+                            }
+                        }
+                    }
+                }
+            })
+        );
+        let warning = warning.unwrap();
+        assert!(warning.contains("removed the _all field"), "{}", warning);
+        assert!(warning.contains("removed the _ttl field"), "{}", warning);
+        assert!(warning.contains("on dynamic template strings"), "{}", warning);
+        assert!(warning.contains("(classic was removed in 7.0) on user.name;"), "{}", warning);
+        assert!(warning.contains("boost at query time) on user.name.raw"), "{}", warning);
+    }
+
+    #[test]
+    fn adapts_to_opensearch_as_elasticsearch_7_10() {
+        let api = select_api(EsVersion::new(2, 11, 0), Distribution::OpenSearch);
+        let mut mappings = json!({
+            "properties": { "title": { "type": "text", "boost": 2, "include_in_all": true } }
+        });
+        let warning = adapt_mappings(&mut mappings, api.as_ref()).unwrap();
+        assert_eq!(mappings, json!({ "properties": { "title": { "type": "text", "boost": 2 } } }));
+        assert!(warning.starts_with("Adapted the mapping to OpenSearch 2.11.0: "), "{}", warning);
+    }
+}
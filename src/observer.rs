@@ -0,0 +1,71 @@
+use crate::error::EsDumperError;
+use crate::report::{ IndexReport, RunReport };
+
+/// A backup or restore run is about to process its indices.
+pub struct RunStart<'a> {
+    pub operation: &'a str,
+    pub indices: usize,
+    /// Documents expected across all indices, when known up front (backups only).
+    pub expected_docs: Option<u64>,
+    /// Documents already processed by the earlier run that this one resumes.
+    pub completed_docs: u64,
+    /// Estimated bytes to write, from the store size of the indices (backups only).
+    pub expected_bytes: Option<u64>,
+}
+
+pub struct IndexStart<'a> {
+    pub index: &'a str,
+    pub expected_docs: Option<u64>,
+}
+
+pub struct BatchDone<'a> {
+    pub index: &'a str,
+    /// Documents in this batch: a scroll page when backing up, a `_bulk` request when restoring.
+    pub docs: u64,
+    /// Bytes of the batch as written to the backup, or as sent to `_bulk` when restoring.
+    pub bytes: u64,
+    /// Total documents of the index, once known.
+    pub expected_docs: Option<u64>,
+    /// Gzipped size of the index's data projected from the pages written so far (backups only).
+    pub projected_bytes: Option<u64>,
+    /// Bytes of the index written to its data files so far, compressed or not (backups only).
+    pub written_bytes: Option<u64>,
+}
+
+pub struct IndexError<'a> {
+    pub index: &'a str,
+    pub error: &'a EsDumperError,
+}
+
+/// An index left for a later `--resume` run without being started.
+pub struct IndexDeferred<'a> {
+    pub index: &'a str,
+    pub expected_docs: Option<u64>,
+}
+
+/// Receives the progress of a run. Events arrive concurrently from the run's worker threads;
+/// every method does nothing by default, so implementations only handle what they need.
+pub trait RunObserver: Send + Sync {
+    fn on_run_start(&self, _event: &RunStart) {}
+
+    fn on_index_start(&self, _event: &IndexStart) {}
+
+    fn on_batch_done(&self, _event: &BatchDone) {}
+
+    /// An index completed successfully; its report carries any warnings.
+    fn on_index_done(&self, _report: &IndexReport) {}
+
+    fn on_index_error(&self, _event: &IndexError) {}
+
+    fn on_index_deferred(&self, _event: &IndexDeferred) {}
+
+    /// A message meant for whoever watches the run, such as a failure to write the log.
+    fn on_message(&self, _message: &str) {}
+
+    fn on_run_complete(&self, _report: &RunReport) {}
+}
+
+/// Observer of jobs nobody is watching.
+pub struct NoopObserver;
+
+impl RunObserver for NoopObserver {}
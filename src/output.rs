@@ -0,0 +1,423 @@
+use crate::bloom::BloomFilter;
+use crate::error::{ invalid_data, EsDumperError };
+use crate::manifest::Partition;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+use sha2::{ Digest, Sha256 };
+use std::io::{ self, BufRead, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataFormat {
+    /// A single JSON array of documents, the layout of `<index>_data.json`.
+    Json,
+    /// One document per line.
+    Ndjson,
+    /// Alternating action and source lines, ready to be sent to `_bulk` as is.
+    Bulk,
+}
+
+impl DataFormat {
+    /// Formats selectable for data files with `--format`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(DataFormat::Json),
+            "bulk" => Some(DataFormat::Bulk),
+            _ => None,
+        }
+    }
+
+    /// Whether new documents can be added to the end of an existing file in this format.
+    pub fn supports_append(self) -> bool {
+        self != DataFormat::Json
+    }
+
+    pub fn data_file_name(self, index: &str) -> String {
+        match self {
+            DataFormat::Json => format!("{}_data.json", index),
+            DataFormat::Ndjson | DataFormat::Bulk => format!("{}_data.ndjson", index),
+        }
+    }
+
+    /// Data file of partition `number` of a range-partitioned backup.
+    pub fn part_file_name(self, index: &str, number: usize) -> String {
+        self.data_file_name(&format!("{}_part{:04}", index, number))
+    }
+}
+
+/// Data files a backup of `index` may have left in `index_dir`, uncompressed before compressed.
+pub fn data_file_candidates(index_dir: &Path, index: &str) -> Vec<PathBuf> {
+    let json = DataFormat::Json.data_file_name(index);
+    let bulk = DataFormat::Bulk.data_file_name(index);
+    [json.clone(), format!("{}.gz", json), bulk.clone(), format!("{}.gz", bulk)]
+        .iter()
+        .map(|name| index_dir.join(name))
+        .collect()
+}
+
+/// Files a partition data file named `file` may be stored as, uncompressed before compressed.
+pub fn part_file_candidates(index_dir: &Path, file: &str) -> Vec<PathBuf> {
+    vec![index_dir.join(file), index_dir.join(format!("{}.gz", file))]
+}
+
+/// Files the data of a complete `partition` may be read from: only the gzipped file when it was
+/// compressed as it was written, as an uncompressed file next to it would be a leftover.
+pub fn partition_files(index_dir: &Path, partition: &Partition) -> Vec<PathBuf> {
+    if partition.compressed {
+        return vec![index_dir.join(format!("{}.gz", partition.file))];
+    }
+    part_file_candidates(index_dir, &partition.file)
+}
+
+/// Where a data file is written until it is complete, so that a crash or a kill never leaves a
+/// truncated file, such as half of a gzip stream, under the name restores and `--resume` read.
+pub fn partial_file(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.partial", path.display()))
+}
+
+/// Reads the documents of an uncompressed data file, in either on-disk format, as
+/// `{ "_id", "_source" }` objects (plus `_routing` where present).
+pub fn read_documents<R: BufRead>(
+    reader: R,
+    path: &Path
+) -> Result<Vec<Value>, EsDumperError> {
+    if path.extension().is_none_or(|extension| extension != "ndjson") {
+        return Ok(serde_json::from_reader(reader)?);
+    }
+
+    let mut documents = Vec::new();
+    let mut lines = reader
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()));
+    while let Some(action) = lines.next() {
+        let action: Value = serde_json::from_str(&action?)?;
+        let meta = action
+            .as_object()
+            .and_then(|action| action.values().next())
+            .ok_or_else(|| {
+                invalid_data(format!("Invalid bulk action line in {}", path.display()))
+            })?;
+        let source = lines
+            .next()
+            .ok_or_else(|| {
+                invalid_data(format!("Bulk action without source line in {}", path.display()))
+            })??;
+
+        let source: Value = serde_json::from_str(&source)?;
+        let mut doc = serde_json::json!({ "_id": meta["_id"], "_source": source });
+        let routing = if meta["routing"].is_null() { &meta["_routing"] } else { &meta["routing"] };
+        if !routing.is_null() {
+            doc["_routing"] = routing.clone();
+        }
+        documents.push(doc);
+    }
+    Ok(documents)
+}
+
+/// Pages written between two pages compressed for the estimate.
+const COMPRESSION_SAMPLE_INTERVAL: u64 = 8;
+
+/// Compressed bytes above which the samples are halved, so the ratio follows the documents
+/// currently being written rather than the whole export.
+const COMPRESSION_WINDOW_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Rolling estimate of how small the written data would be once gzipped, from compressing every
+/// few pages in memory.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionEstimate {
+    pages: u64,
+    docs: u64,
+    bytes: u64,
+    sampled_bytes: u64,
+    sampled_compressed_bytes: u64,
+}
+
+impl CompressionEstimate {
+    fn record(&mut self, page: &[u8], docs: u64) {
+        if self.pages % COMPRESSION_SAMPLE_INTERVAL == 0 && !page.is_empty() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            if encoder.write_all(page).is_ok() {
+                if let Ok(compressed) = encoder.finish() {
+                    self.sampled_bytes += page.len() as u64;
+                    self.sampled_compressed_bytes += compressed.len() as u64;
+                }
+            }
+            if self.sampled_compressed_bytes > COMPRESSION_WINDOW_BYTES {
+                self.sampled_bytes /= 2;
+                self.sampled_compressed_bytes /= 2;
+            }
+        }
+        self.pages += 1;
+        self.docs += docs;
+        self.bytes += page.len() as u64;
+    }
+
+    /// Compressed size as a fraction of the uncompressed size of the pages sampled so far.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.sampled_bytes == 0 {
+            return None;
+        }
+        Some((self.sampled_compressed_bytes as f64) / (self.sampled_bytes as f64))
+    }
+
+    /// Compressed size of `doc_count` documents like the ones written so far.
+    pub fn projected_bytes(&self, doc_count: u64) -> Option<u64> {
+        let ratio = self.ratio().filter(|_| self.docs > 0)?;
+        Some(((self.bytes as f64) / (self.docs as f64) * (doc_count as f64) * ratio) as u64)
+    }
+}
+
+/// Where a [`DataWriter`] writes to, gzipping the data on the way when compressed.
+enum Sink {
+    Plain(Box<dyn Write + Send>),
+    Gzip(GzEncoder<Box<dyn Write + Send>>),
+}
+
+impl Sink {
+    /// Flushes the data and, when compressed, writes the gzip trailer.
+    fn close(self) -> io::Result<()> {
+        match self {
+            Sink::Plain(mut inner) => inner.flush(),
+            Sink::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(inner) => inner.write(buf),
+            Sink::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(inner) => inner.flush(),
+            Sink::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Serializes exported documents in the chosen format. Each page of documents is written with
+/// a single `write_all`, so writers shared between indices (stdout) never interleave records.
+pub struct DataWriter {
+    inner: Sink,
+    format: DataFormat,
+    is_first: bool,
+    flush_pages: bool,
+    ids: Option<BloomFilter>,
+    compression: Option<CompressionEstimate>,
+    checksum: Option<Sha256>,
+    written: Option<Arc<AtomicU64>>,
+}
+
+/// Adds the bytes written through it to a counter, which the writers of several files can
+/// share, e.g. those of the partitions of an index.
+pub struct CountingWriter<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W, written: Arc<AtomicU64>) -> Self {
+        CountingWriter { inner, written }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What a [`DataWriter`] collected about the data it wrote.
+pub struct WrittenData {
+    pub ids: Option<BloomFilter>,
+    /// SHA-256 of everything written, as lowercase hex.
+    pub sha256: Option<String>,
+}
+
+impl DataWriter {
+    pub fn new(inner: Box<dyn Write + Send>, format: DataFormat) -> Self {
+        DataWriter {
+            inner: Sink::Plain(inner),
+            format,
+            is_first: true,
+            flush_pages: true,
+            ids: None,
+            compression: None,
+            checksum: None,
+            written: None,
+        }
+    }
+
+    /// Adds the bytes handed to the underlying writer to `written`. Called before `gzip`, it
+    /// counts the compressed bytes.
+    pub fn count_written(mut self, written: Arc<AtomicU64>) -> Self {
+        if let Sink::Plain(inner) = self.inner {
+            self.inner = Sink::Plain(Box::new(CountingWriter::new(inner, Arc::clone(&written))));
+            self.written = Some(written);
+        }
+        self
+    }
+
+    /// Bytes counted by the counter given to `count_written`, by this writer and the others
+    /// sharing it.
+    pub fn written_bytes(&self) -> Option<u64> {
+        self.written.as_ref().map(|written| written.load(Ordering::Relaxed))
+    }
+
+    /// Gzips the data as it is written; with a checksum, the checksum is of the uncompressed
+    /// data.
+    pub fn gzip(mut self) -> Self {
+        self.inner = match self.inner {
+            Sink::Plain(inner) => Sink::Gzip(GzEncoder::new(inner, Compression::default())),
+            gzip => gzip,
+        };
+        self
+    }
+
+    /// Computes a SHA-256 checksum of the written bytes, returned by `finish`.
+    pub fn checksum(mut self) -> Self {
+        self.checksum = Some(Sha256::new());
+        self
+    }
+
+    /// Records the `_id` of every written document in `filter`, returned by `finish`.
+    pub fn track_ids(mut self, filter: BloomFilter) -> Self {
+        self.ids = Some(filter);
+        self
+    }
+
+    /// Keeps `estimate` up to date with the compression of the written pages, continuing the
+    /// estimate of an earlier writer.
+    pub fn estimate_compression(mut self, estimate: CompressionEstimate) -> Self {
+        self.compression = Some(estimate);
+        self
+    }
+
+    pub fn compression_estimate(&self) -> Option<CompressionEstimate> {
+        self.compression
+    }
+
+    /// Whether every page is flushed to the underlying writer as soon as it is written.
+    pub fn flush_pages(mut self, enabled: bool) -> Self {
+        self.flush_pages = enabled;
+        self
+    }
+
+    pub fn begin(&mut self) -> std::io::Result<()> {
+        match self.format {
+            DataFormat::Json => self.write_bytes(b"["),
+            DataFormat::Ndjson | DataFormat::Bulk => Ok(()),
+        }
+    }
+
+    /// Writes a page of documents, returning the number of bytes written.
+    pub fn write_docs(&mut self, docs: &[Value]) -> Result<u64, EsDumperError> {
+        let mut page = Vec::new();
+
+        for doc in docs {
+            if let (Some(ids), Some(id)) = (&mut self.ids, doc["_id"].as_str()) {
+                ids.insert(id);
+            }
+            match self.format {
+                DataFormat::Json => {
+                    if !self.is_first {
+                        page.push(b',');
+                    }
+                    serde_json::to_writer(&mut page, doc)?;
+                }
+                DataFormat::Ndjson => {
+                    serde_json::to_writer(&mut page, doc)?;
+                    page.push(b'\n');
+                }
+                DataFormat::Bulk => {
+                    let mut action =
+                        serde_json::json!({ "_index": doc["_index"], "_id": doc["_id"] });
+                    if !doc["_routing"].is_null() {
+                        action["routing"] = doc["_routing"].clone();
+                    }
+                    serde_json::to_writer(&mut page, &serde_json::json!({ "index": action }))?;
+                    page.push(b'\n');
+                    serde_json::to_writer(&mut page, &doc["_source"])?;
+                    page.push(b'\n');
+                }
+            }
+            self.is_first = false;
+        }
+
+        self.write_bytes(&page)?;
+        if let Some(compression) = &mut self.compression {
+            compression.record(&page, docs.len() as u64);
+        }
+        if self.flush_pages {
+            self.inner.flush()?;
+        }
+        Ok(page.len() as u64)
+    }
+
+    pub fn finish(mut self) -> std::io::Result<WrittenData> {
+        if self.format == DataFormat::Json {
+            self.write_bytes(b"]")?;
+        }
+        self.inner.close()?;
+        Ok(WrittenData {
+            ids: self.ids,
+            sha256: self.checksum.map(|checksum| hex(&checksum.finalize())),
+        })
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.inner.write_all(bytes)?;
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(bytes);
+        }
+        Ok(())
+    }
+}
+
+/// Computes a SHA-256 checksum of the bytes read through it, so a data file is verified in the
+/// same pass that restores it.
+pub struct ChecksumReader<R> {
+    inner: R,
+    checksum: Sha256,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R) -> Self {
+        ChecksumReader { inner, checksum: Sha256::new() }
+    }
+}
+
+impl<R> ChecksumReader<R> {
+    /// SHA-256 of the bytes read so far, as lowercase hex.
+    pub fn sha256(&self) -> String {
+        hex(&self.checksum.clone().finalize())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.checksum.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,384 @@
+use crate::error::EsDumperError;
+use crate::http_client::{ HostPool, HttpClient };
+use crate::progress::RateLimits;
+use crate::utils::{ log, LogFile };
+use serde_json::Value;
+use std::fmt;
+use std::sync::{ Condvar, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+/// Limits the `_bulk` requests a restore has in flight, to a fixed number or by the pressure on
+/// the target cluster's write thread pools. Every `check_interval` the `_cat/thread_pool/write`
+/// queues are polled: new rejections or a queue above `max_utilization` of its capacity halve
+/// the limit, a queue below half of it raises the limit again by one, up to `max_concurrency`.
+pub struct WritePacer {
+    settings: Option<PacerSettings>,
+    state: Mutex<PacerState>,
+    released: Condvar,
+}
+
+#[derive(Debug, Clone)]
+pub struct PacerSettings {
+    /// Fraction of the write queue capacity to stay below, e.g. 0.5.
+    pub max_utilization: f64,
+    pub check_interval: Duration,
+    pub max_concurrency: usize,
+}
+
+struct PacerState {
+    limit: usize,
+    in_flight: usize,
+    next_check: Option<Instant>,
+    /// Rejections counted by the write pools at the previous check.
+    rejected: Option<u64>,
+}
+
+/// A `_bulk` request allowed to run; dropping it makes room for the next one.
+pub struct PacerPermit<'a> {
+    pacer: &'a WritePacer,
+}
+
+impl WritePacer {
+    pub fn new(settings: PacerSettings) -> Self {
+        WritePacer {
+            state: Mutex::new(PacerState {
+                limit: settings.max_concurrency.max(1),
+                in_flight: 0,
+                next_check: None,
+                rejected: None,
+            }),
+            settings: Some(settings),
+            released: Condvar::new(),
+        }
+    }
+
+    /// A pacer that never holds back a request.
+    pub fn disabled() -> Self {
+        Self::fixed(usize::MAX)
+    }
+
+    /// A pacer that allows `limit` requests at a time, whatever the write queues look like.
+    pub fn fixed(limit: usize) -> Self {
+        WritePacer {
+            settings: None,
+            state: Mutex::new(PacerState {
+                limit: limit.max(1),
+                in_flight: 0,
+                next_check: None,
+                rejected: None,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until another `_bulk` request may be sent, first checking the write queues when a
+    /// check is due.
+    pub fn acquire(
+        &self,
+        hosts: &HostPool,
+        client: &HttpClient,
+        log_file: &LogFile
+    ) -> PacerPermit<'_> {
+        if let Some(settings) = &self.settings {
+            self.check_queues(settings, hosts, client, log_file);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        while state.in_flight >= state.limit {
+            state = self.released.wait(state).unwrap();
+        }
+        state.in_flight += 1;
+        PacerPermit { pacer: self }
+    }
+
+    fn check_queues(
+        &self,
+        settings: &PacerSettings,
+        hosts: &HostPool,
+        client: &HttpClient,
+        log_file: &LogFile
+    ) {
+        {
+            // Only one request per interval runs the check
+            let now = Instant::now();
+            let mut state = self.state.lock().unwrap();
+            if state.next_check.is_some_and(|next| next > now) {
+                return;
+            }
+            state.next_check = Some(now + settings.check_interval);
+        }
+
+        let pools: Vec<Value> = match
+            hosts
+                .send(|host| {
+                    client.get(
+                        format!(
+                            "{}/_cat/thread_pool/write?format=json&h=node_name,queue,queue_size,rejected",
+                            host
+                        )
+                    )
+                })
+                .and_then(|response| Ok(response.json()?))
+        {
+            Ok(pools) => pools,
+            Err(_) => {
+                return;
+            }
+        };
+
+        let number = |pool: &Value, key: &str| {
+            pool[key].as_str().and_then(|value| value.parse::<i64>().ok())
+        };
+        let rejected: u64 = pools
+            .iter()
+            .filter_map(|pool| number(pool, "rejected"))
+            .map(|rejected| rejected.max(0) as u64)
+            .sum();
+        // Unbounded queues report a size of -1 and are left out
+        let utilization = pools
+            .iter()
+            .filter_map(|pool| {
+                let queue_size = number(pool, "queue_size").filter(|&size| size > 0)?;
+                Some((number(pool, "queue")?.max(0) as f64) / (queue_size as f64))
+            })
+            .fold(0.0, f64::max);
+
+        let mut state = self.state.lock().unwrap();
+        let new_rejections = state.rejected.map_or(0, |previous| rejected.saturating_sub(previous));
+        state.rejected = Some(rejected);
+
+        let previous_limit = state.limit;
+        if new_rejections > 0 || utilization > settings.max_utilization {
+            state.limit = (state.limit / 2).max(1);
+        } else if utilization < settings.max_utilization / 2.0 {
+            state.limit = (state.limit + 1).min(settings.max_concurrency.max(1));
+        }
+        let limit = state.limit;
+        drop(state);
+
+        if limit != previous_limit {
+            self.released.notify_all();
+            let _ = log(
+                log_file,
+                &format!(
+                    "Write queue at {:.0}% with {} new rejections, {} bulk concurrency to {}",
+                    utilization * 100.0,
+                    new_rejections,
+                    if limit < previous_limit { "lowering" } else { "raising" },
+                    limit
+                )
+            );
+        }
+    }
+}
+
+impl Drop for PacerPermit<'_> {
+    fn drop(&mut self) {
+        self.pacer.state.lock().unwrap().in_flight -= 1;
+        self.pacer.released.notify_one();
+    }
+}
+
+impl fmt::Debug for WritePacer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WritePacer").field("settings", &self.settings).finish()
+    }
+}
+
+/// Holds back `_bulk` requests and index creation while the target cluster has more than
+/// `max_unassigned` unassigned shards, e.g. while nodes of a capacity-constrained cluster are
+/// still allocating the replicas of indices created earlier in the restore. Health is polled at
+/// most once per `check_interval`; while the count stays above the limit every writer waits,
+/// and the restore fails once it has waited `timeout` in a row.
+pub struct ShardWatch {
+    settings: Option<ShardWatchSettings>,
+    next_check: Mutex<Option<Instant>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShardWatchSettings {
+    pub max_unassigned: u64,
+    pub check_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl ShardWatch {
+    pub fn new(settings: Option<ShardWatchSettings>) -> Self {
+        ShardWatch { settings, next_check: Mutex::new(None) }
+    }
+
+    /// Returns once the cluster has few enough unassigned shards, or right away when no check is
+    /// due.
+    pub fn wait(
+        &self,
+        hosts: &HostPool,
+        client: &HttpClient,
+        log_file: &LogFile
+    ) -> Result<(), EsDumperError> {
+        let Some(settings) = &self.settings else {
+            return Ok(());
+        };
+
+        // Held while paused, so every other writer waits behind this one
+        let mut next_check = self.next_check.lock().unwrap();
+        if next_check.is_some_and(|next| next > Instant::now()) {
+            return Ok(());
+        }
+
+        let mut paused_since = None;
+        // A failed health check does not stop the restore, the writes report their own errors
+        while let Some(unassigned) = unassigned_shards(hosts, client) {
+            if unassigned <= settings.max_unassigned {
+                if paused_since.is_some() {
+                    log(log_file, &format!("{} unassigned shards, resuming writes", unassigned))?;
+                }
+                break;
+            }
+
+            let since = match paused_since {
+                Some(since) => since,
+                None => {
+                    log(
+                        log_file,
+                        &format!(
+                            "{} unassigned shards (more than {}), pausing writes",
+                            unassigned,
+                            settings.max_unassigned
+                        )
+                    )?;
+                    *paused_since.insert(Instant::now())
+                }
+            };
+            if since.elapsed() >= settings.timeout {
+                return Err(
+                    EsDumperError::UnexpectedResponse(
+                        format!(
+                            "Cluster still has {} unassigned shards after waiting {} seconds",
+                            unassigned,
+                            settings.timeout.as_secs()
+                        )
+                    )
+                );
+            }
+            thread::sleep(settings.check_interval);
+        }
+
+        *next_check = Some(Instant::now() + settings.check_interval);
+        Ok(())
+    }
+}
+
+fn unassigned_shards(hosts: &HostPool, client: &HttpClient) -> Option<u64> {
+    let health: Value = hosts
+        .send(|host| client.get(format!("{}/_cluster/health", host)))
+        .ok()?
+        .json()
+        .ok()?;
+    health["unassigned_shards"].as_u64()
+}
+
+impl fmt::Debug for ShardWatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardWatch").field("settings", &self.settings).finish()
+    }
+}
+
+/// Holds the `_bulk` requests of all indices restored in parallel to one aggregate rate of
+/// documents and bytes per second. Each request takes its actions and body size from token
+/// buckets shared by every restore worker, each holding at most one second of tokens; a request
+/// larger than that leaves the bucket in debt, and the requests after it wait until it is paid
+/// back, so bursts are smoothed out instead of the limit being applied per index.
+pub struct RestoreThrottle {
+    buckets: Option<Mutex<ThrottleBuckets>>,
+    limits: RateLimits,
+}
+
+struct ThrottleBuckets {
+    docs: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+struct TokenBucket {
+    rate: f64,
+    /// Negative while requests larger than the bucket are being paid back.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        TokenBucket { rate, tokens: rate, refilled: now }
+    }
+
+    /// Takes `amount` tokens, returning how long the caller has to wait before using them.
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+impl RestoreThrottle {
+    /// A throttle to `limits`; requests are never held back when neither limit is set.
+    pub fn new(limits: RateLimits) -> Self {
+        let now = Instant::now();
+        let bucket = |rate: Option<f64>| {
+            rate.filter(|rate| *rate > 0.0).map(|rate| TokenBucket::new(rate, now))
+        };
+        let buckets = ThrottleBuckets {
+            docs: bucket(limits.docs_per_sec),
+            bytes: bucket(limits.bytes_per_sec),
+        };
+        RestoreThrottle {
+            buckets: (buckets.docs.is_some() || buckets.bytes.is_some()).then(||
+                Mutex::new(buckets)
+            ),
+            limits,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(RateLimits::default())
+    }
+
+    /// Blocks until a `_bulk` request of `docs` actions and `bytes` bytes fits the aggregate
+    /// rate.
+    pub fn wait(&self, docs: u64, bytes: u64) {
+        let Some(buckets) = &self.buckets else {
+            return;
+        };
+
+        let wait = {
+            let now = Instant::now();
+            let mut buckets = buckets.lock().unwrap();
+            let docs_wait = buckets.docs
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(docs as f64, now));
+            let bytes_wait = buckets.bytes
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(bytes as f64, now));
+            docs_wait.max(bytes_wait)
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// The limits this throttle holds restores to, for the ETAs of the progress bars.
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+}
+
+impl fmt::Debug for RestoreThrottle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RestoreThrottle").field("limits", &self.limits).finish()
+    }
+}
@@ -0,0 +1,135 @@
+use crate::error::EsDumperError;
+use crate::job::Job;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Changes documents as they are backed up, e.g. to mask personal data before it reaches the
+/// backup. Attached to a run with `Job::with_transform`; several transforms are applied in the
+/// order they were attached.
+pub trait DocumentTransform: Send + Sync {
+    /// Name of the transform, recorded in the manifests of the indices it changed.
+    fn name(&self) -> &str;
+
+    /// Changes a document of `index` in place before it is written. The document holds `_id`
+    /// and `_source`, plus any metadata kept by `preserve_metadata`. An error fails the index.
+    fn transform(&self, index: &str, document: &mut Value) -> Result<(), EsDumperError>;
+}
+
+/// A place backups are kept besides `backup_dir`, such as an object store. Attached to a run
+/// with `Job::with_storage`. Backups are still written to `backup_dir` first; the backend
+/// stores each finished index from there, and restores let it bring the indices back there
+/// before reading them.
+pub trait StorageBackend: Send + Sync {
+    /// Name of the backend, used in log records.
+    fn name(&self) -> &str;
+
+    /// Stores the complete backup of `index`, whose files are in `index_dir`. An error fails
+    /// the index.
+    fn store_index(&self, index: &str, index_dir: &Path) -> Result<(), EsDumperError>;
+
+    /// Makes the backup of `index` available in `index_dir` before it is restored, e.g. by
+    /// downloading it. The default does nothing, for backends that only keep copies.
+    fn fetch_index(&self, _index: &str, _index_dir: &Path) -> Result<(), EsDumperError> {
+        Ok(())
+    }
+}
+
+/// Builds a transform from the options given to it under `[plugins.options.<name>]`, or
+/// `null` when there are none.
+pub type TransformFactory = fn(&Value) -> Result<Box<dyn DocumentTransform>, EsDumperError>;
+pub type StorageFactory = fn(&Value) -> Result<Box<dyn StorageBackend>, EsDumperError>;
+
+/// Transforms and storage backends by the names the command line selects them with in the
+/// `[plugins]` section of its configuration.
+#[derive(Default)]
+pub struct PluginRegistry {
+    transforms: HashMap<String, TransformFactory>,
+    storages: HashMap<String, StorageFactory>,
+}
+
+impl PluginRegistry {
+    pub fn register_transform(&mut self, name: &str, factory: TransformFactory) {
+        self.transforms.insert(name.to_string(), factory);
+    }
+
+    pub fn register_storage(&mut self, name: &str, factory: StorageFactory) {
+        self.storages.insert(name.to_string(), factory);
+    }
+
+    fn lookup<'a, F>(
+        factories: &'a HashMap<String, F>,
+        kind: &str,
+        name: &str
+    ) -> Result<&'a F, EsDumperError> {
+        factories.get(name).ok_or_else(|| {
+            let mut known: Vec<&str> = factories.keys().map(String::as_str).collect();
+            known.sort();
+            let known = if known.is_empty() {
+                "none".to_string()
+            } else {
+                known.join(", ")
+            };
+            EsDumperError::Config(
+                if cfg!(feature = "plugins") {
+                    format!("Unknown {} plugin '{}' (compiled in: {})", kind, name, known)
+                } else {
+                    format!(
+                        "Unknown {} plugin '{}': this build has no plugins (build with --features plugins)",
+                        kind,
+                        name
+                    )
+                }
+            )
+        })
+    }
+}
+
+/// The plugins compiled into this build. With the `plugins` feature, the file named by the
+/// `ES_DUMPER_PLUGINS` environment variable at build time is compiled into the crate and its
+/// `register(&mut PluginRegistry)` function adds them, so plugins are kept and built outside
+/// this repository; see `plugins/example.rs`.
+pub fn compiled_plugins() -> PluginRegistry {
+    #[allow(unused_mut)]
+    let mut registry = PluginRegistry::default();
+    #[cfg(feature = "plugins")]
+    external::register(&mut registry);
+    registry
+}
+
+#[cfg(feature = "plugins")]
+mod external {
+    include!(
+        env!(
+            "ES_DUMPER_PLUGINS",
+            "the plugins feature needs ES_DUMPER_PLUGINS set to the path of the file registering the plugins"
+        )
+    );
+}
+
+/// The plugins a run attaches to its jobs, from the `[plugins]` section of the configuration.
+#[derive(Debug, Clone, Default)]
+pub struct PluginSelection {
+    /// Transform names, applied in this order.
+    pub transforms: Vec<String>,
+    pub storage: Option<String>,
+    /// Options by plugin name, handed to its factory.
+    pub options: HashMap<String, Value>,
+}
+
+impl PluginSelection {
+    /// Attaches new instances of the selected plugins from `registry` to `job`. Fails on names
+    /// the registry does not know and on plugins refusing their options.
+    pub fn attach(&self, registry: &PluginRegistry, mut job: Job) -> Result<Job, EsDumperError> {
+        let options = |name: &str| self.options.get(name).cloned().unwrap_or(Value::Null);
+        for name in &self.transforms {
+            let factory = PluginRegistry::lookup(&registry.transforms, "transform", name)?;
+            job = job.with_transform(factory(&options(name))?);
+        }
+        if let Some(name) = &self.storage {
+            let factory = PluginRegistry::lookup(&registry.storages, "storage", name)?;
+            job = job.with_storage(factory(&options(name))?);
+        }
+        Ok(job)
+    }
+}
@@ -0,0 +1,283 @@
+use crate::observer::{ BatchDone, IndexDeferred, IndexError, IndexStart, RunObserver, RunStart };
+use crate::report::{ IndexReport, RunReport };
+use indicatif::{
+    HumanBytes,
+    HumanDuration,
+    MultiProgress,
+    ProgressBar,
+    ProgressDrawTarget,
+    ProgressState,
+    ProgressStyle,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+/// Console progress bars of the command line: overall indices, documents and bytes with the
+/// aggregate throughput, plus one bar per index in flight. Cyan/blue while backing up,
+/// magenta/purple while restoring.
+pub struct ProgressObserver {
+    multi: MultiProgress,
+    bars: Mutex<Bars>,
+    limits: RateLimits,
+    /// Documents processed by this run, for the aggregate docs/s
+    run_docs: AtomicU64,
+}
+
+/// Throughput ceilings a run is held to. The ETAs of the overall bars never assume a faster
+/// rate than these, and are otherwise based on the average rate since the run started, which
+/// includes the pauses of the write pacer, circuit breaker and health checks, rather than on
+/// the latest burst.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    pub docs_per_sec: Option<f64>,
+    pub bytes_per_sec: Option<f64>,
+}
+
+#[derive(Default)]
+struct Bars {
+    colors: &'static str,
+    indices: Option<ProgressBar>,
+    docs: Option<ProgressBar>,
+    bytes: Option<ProgressBar>,
+    per_index: HashMap<String, ProgressBar>,
+    started: Option<Instant>,
+}
+
+impl ProgressObserver {
+    /// Bars drawn on stderr, or never drawn when `hidden` (log records then go to stderr).
+    pub fn new(hidden: bool) -> Self {
+        let multi = MultiProgress::new();
+        if hidden {
+            multi.set_draw_target(ProgressDrawTarget::hidden());
+        }
+
+        ProgressObserver {
+            multi,
+            bars: Mutex::new(Bars::default()),
+            limits: RateLimits::default(),
+            run_docs: AtomicU64::new(0),
+        }
+    }
+
+    /// Bases the overall ETAs on `limits` as well as on the rate achieved so far.
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    fn bar_style(colors: &str, label: &str) -> ProgressStyle {
+        ProgressStyle::default_bar()
+            .template(
+                &format!(
+                    "{{spinner:.green}} [{{elapsed_precise}}] [{{bar:40.{}}}] {{pos}}/{{len}} ({{eta}}) {}",
+                    colors,
+                    label
+                )
+            )
+            .unwrap()
+            .progress_chars("#>-")
+    }
+
+    fn bytes_style(colors: &str, known_total: bool) -> ProgressStyle {
+        let template = if known_total {
+            format!(
+                "{{spinner:.green}} [{{elapsed_precise}}] [{{bar:40.{}}}] {{bytes}}/{{total_bytes}} ({{eta}}) {{binary_bytes_per_sec}}, {{msg}}",
+                colors
+            )
+        } else {
+            "{spinner:.green} [{elapsed_precise}] {bytes} {binary_bytes_per_sec}, {msg}".to_string()
+        };
+        ProgressStyle::default_bar().template(&template).unwrap().progress_chars("#>-")
+    }
+
+    /// `{eta}` from the average rate since the bar was started at `start` (positions reached by
+    /// earlier runs, e.g. with `--resume`, don't count), never faster than `limit` per second.
+    fn paced_eta(
+        start: u64,
+        limit: Option<f64>
+    ) -> impl Fn(&ProgressState, &mut dyn fmt::Write) + Send + Sync + Clone {
+        move |state: &ProgressState, w: &mut dyn fmt::Write| {
+            let done = state.pos().saturating_sub(start) as f64;
+            let elapsed = state.elapsed().as_secs_f64();
+            let average = if done > 0.0 && elapsed > 0.0 { Some(done / elapsed) } else { None };
+            let rate = match (average, limit) {
+                (Some(average), Some(limit)) => Some(average.min(limit)),
+                (average, limit) => average.or(limit),
+            };
+            let remaining = state.len().unwrap_or(0).saturating_sub(state.pos()) as f64;
+            let _ = match rate.filter(|rate| *rate > 0.0) {
+                Some(rate) => {
+                    let eta = Duration::from_secs_f64(remaining / rate);
+                    write!(w, "{:#}", HumanDuration(eta))
+                }
+                None => write!(w, "-"),
+            };
+        }
+    }
+
+    fn spinner_style() -> ProgressStyle {
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {pos} documents {msg}")
+            .unwrap()
+    }
+
+    // Prints above the bars instead of through them; a no-op while the bars are hidden
+    fn print_above(&self, message: &str) {
+        let _ = self.multi.println(message);
+    }
+}
+
+impl RunObserver for ProgressObserver {
+    fn on_run_start(&self, event: &RunStart) {
+        let mut bars = self.bars.lock().unwrap();
+        bars.colors = if event.operation == "restore" { "magenta/purple" } else { "cyan/blue" };
+
+        let indices = self.multi.add(ProgressBar::new(event.indices as u64));
+        indices.set_style(Self::bar_style(bars.colors, "Indices"));
+        bars.indices = Some(indices);
+
+        if let Some(expected_docs) = event.expected_docs {
+            let docs = self.multi.add(ProgressBar::new(event.completed_docs + expected_docs));
+            docs.set_style(
+                Self::bar_style(bars.colors, "Documents").with_key(
+                    "eta",
+                    Self::paced_eta(event.completed_docs, self.limits.docs_per_sec)
+                )
+            );
+            docs.set_position(event.completed_docs);
+            bars.docs = Some(docs);
+        }
+
+        let bytes = self.multi.add(ProgressBar::new(event.expected_bytes.unwrap_or(0)));
+        bytes.set_style(
+            Self::bytes_style(bars.colors, event.expected_bytes.is_some()).with_key(
+                "eta",
+                Self::paced_eta(0, self.limits.bytes_per_sec)
+            )
+        );
+        bytes.set_message("0 docs/s");
+        bars.bytes = Some(bytes);
+        bars.started = Some(Instant::now());
+    }
+
+    fn on_index_start(&self, event: &IndexStart) {
+        let mut bars = self.bars.lock().unwrap();
+        let bar = match event.expected_docs {
+            Some(expected_docs) => {
+                let bar = self.multi.add(ProgressBar::new(expected_docs));
+                bar.set_style(Self::bar_style(bars.colors, "{msg}"));
+                bar
+            }
+            None => {
+                let bar = self.multi.add(ProgressBar::new_spinner());
+                bar.set_style(Self::spinner_style());
+                bar
+            }
+        };
+        bar.set_message(event.index.to_string());
+        bars.per_index.insert(event.index.to_string(), bar);
+    }
+
+    fn on_batch_done(&self, event: &BatchDone) {
+        // Bars are shared handles, updated without holding the lock every worker's batches need
+        let (bar, docs, bytes, started, colors) = {
+            let bars = self.bars.lock().unwrap();
+            (
+                bars.per_index.get(event.index).cloned(),
+                bars.docs.clone(),
+                bars.bytes.clone(),
+                bars.started,
+                bars.colors,
+            )
+        };
+        if let Some(bar) = bar {
+            if let (Some(expected_docs), None) = (event.expected_docs, bar.length()) {
+                bar.set_style(Self::bar_style(colors, "{msg}"));
+                bar.set_length(expected_docs);
+            }
+            bar.inc(event.docs);
+            let mut message = event.index.to_string();
+            if let Some(projected_bytes) = event.projected_bytes {
+                message.push_str(&format!(" (~{} gzipped)", HumanBytes(projected_bytes)));
+            }
+            // Sizes say more than document counts about indices with very different documents
+            if let Some(written_bytes) = event.written_bytes {
+                let written_mb = (written_bytes as f64) / BYTES_PER_MB;
+                let elapsed = bar.elapsed().as_secs_f64().max(0.001);
+                message.push_str(
+                    &format!(", {:.1} MB written, {:.1} MB/s", written_mb, written_mb / elapsed)
+                );
+            }
+            if message.len() > event.index.len() {
+                bar.set_message(message);
+            }
+        }
+        if let Some(docs) = docs {
+            docs.inc(event.docs);
+        }
+
+        let run_docs = self.run_docs.fetch_add(event.docs, Ordering::Relaxed) + event.docs;
+        if let (Some(bytes), Some(started)) = (bytes, started) {
+            bytes.inc(event.bytes);
+            let elapsed = started.elapsed().as_secs_f64().max(0.001);
+            bytes.set_message(format!("{:.0} docs/s", (run_docs as f64) / elapsed));
+        }
+    }
+
+    fn on_index_done(&self, report: &IndexReport) {
+        let mut bars = self.bars.lock().unwrap();
+        if let Some(bar) = bars.per_index.remove(&report.index) {
+            bar.finish_and_clear();
+        }
+        if let Some(indices) = &bars.indices {
+            indices.inc(1);
+        }
+        drop(bars);
+
+        for warning in &report.warnings {
+            self.print_above(&format!("Warning for index {}: {}", report.index, warning));
+        }
+    }
+
+    fn on_index_error(&self, event: &IndexError) {
+        let mut bars = self.bars.lock().unwrap();
+        if let Some(bar) = bars.per_index.remove(event.index) {
+            bar.abandon_with_message(format!("Error: {}", event.error));
+        }
+        if let Some(indices) = &bars.indices {
+            indices.inc(1);
+        }
+    }
+
+    fn on_index_deferred(&self, event: &IndexDeferred) {
+        let bars = self.bars.lock().unwrap();
+        if let (Some(docs), Some(expected_docs)) = (&bars.docs, event.expected_docs) {
+            docs.dec_length(expected_docs);
+        }
+    }
+
+    fn on_message(&self, message: &str) {
+        self.print_above(message);
+    }
+
+    fn on_run_complete(&self, report: &RunReport) {
+        let bars = self.bars.lock().unwrap();
+        if let Some(docs) = &bars.docs {
+            docs.finish_and_clear();
+        }
+        // Left on screen with the final totals and throughput
+        if let Some(bytes) = &bars.bytes {
+            bytes.abandon();
+        }
+        if let Some(indices) = &bars.indices {
+            indices.finish_with_message(
+                format!("Completed in {:.2} seconds", report.duration_secs)
+            );
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::error::EsDumperError;
+use crate::http_client::HttpStats;
+use crate::latency::LatencySummary;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexReport {
+    pub index: String,
+    pub success: bool,
+    pub error: Option<String>,
+    pub docs: u64,
+    pub bytes: u64,
+    pub duration_secs: f64,
+    pub warnings: Vec<String>,
+    /// Times the index was backed up or restored, more than once after transient errors.
+    pub attempts: u32,
+}
+
+impl IndexReport {
+    pub fn success(index: &str, docs: u64, bytes: u64, duration_secs: f64) -> Self {
+        IndexReport {
+            index: index.to_string(),
+            success: true,
+            error: None,
+            docs,
+            bytes,
+            duration_secs,
+            warnings: Vec::new(),
+            attempts: 1,
+        }
+    }
+
+    pub fn failure(index: &str, error: String, duration_secs: f64) -> Self {
+        IndexReport {
+            index: index.to_string(),
+            success: false,
+            error: Some(error),
+            docs: 0,
+            bytes: 0,
+            duration_secs,
+            warnings: Vec::new(),
+            attempts: 1,
+        }
+    }
+
+    /// Records how many attempts the index took; a success after retries is reported with a
+    /// warning, as it points at an unstable network or cluster.
+    pub fn record_attempts(&mut self, attempts: u32) {
+        self.attempts = attempts;
+        if self.success && attempts > 1 {
+            self.warnings.push(
+                format!("Succeeded on attempt {} after transient errors", attempts)
+            );
+        }
+    }
+}
+
+/// An index left out of a run, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedIndex {
+    pub index: String,
+    pub reason: String,
+}
+
+impl SkippedIndex {
+    pub fn new(index: &str, reason: impl Into<String>) -> Self {
+        SkippedIndex {
+            index: index.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Outcome of a single backup or restore run, recorded in the run history.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub operation: String,
+    /// ID of the run, which its log records, manifests and audit records carry as well.
+    pub run_id: Option<String>,
+    /// UUID of the run, as in its log records.
+    pub run_uuid: Option<String>,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+    pub indices: Vec<IndexReport>,
+    /// Indices left for a later `--resume` run because the deadline was reached.
+    pub deferred: Vec<String>,
+    /// Indices left out of the run, such as system, closed or empty indices.
+    pub skipped: Vec<SkippedIndex>,
+    /// Latency percentiles of the run's requests and local writes, and its failed requests.
+    pub latency: Option<LatencySummary>,
+    /// Requests the run sent through its connection pool.
+    pub http: Option<HttpStats>,
+}
+
+impl RunReport {
+    pub fn new(operation: &str, started_at: String) -> Self {
+        RunReport {
+            operation: operation.to_string(),
+            run_id: None,
+            run_uuid: None,
+            started_at,
+            duration_secs: 0.0,
+            error: None,
+            indices: Vec::new(),
+            deferred: Vec::new(),
+            skipped: Vec::new(),
+            latency: None,
+            http: None,
+        }
+    }
+
+    /// The report of a run, or of a run that failed before producing one.
+    pub fn from_result(
+        operation: &str,
+        started_at: String,
+        result: &Result<RunReport, EsDumperError>
+    ) -> Self {
+        match result {
+            Ok(report) => report.clone(),
+            Err(e) => {
+                let mut report = RunReport::new(operation, started_at);
+                report.error = Some(e.to_string());
+                report
+            }
+        }
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.indices
+            .iter()
+            .filter(|index| !index.success)
+            .count()
+    }
+
+    pub fn total_docs(&self) -> u64 {
+        self.indices
+            .iter()
+            .map(|index| index.docs)
+            .sum()
+    }
+
+    /// Names of the skipped indices grouped by the reason they were skipped, in order of the
+    /// reasons.
+    pub fn skipped_by_reason(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+        for skipped in &self.skipped {
+            match groups.iter_mut().find(|(reason, _)| *reason == skipped.reason) {
+                Some((_, indices)) => indices.push(&skipped.index),
+                None => groups.push((&skipped.reason, vec![&skipped.index])),
+            }
+        }
+        groups.sort_by_key(|(reason, _)| *reason);
+        groups
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.indices
+            .iter()
+            .map(|index| index.bytes)
+            .sum()
+    }
+}
@@ -1,295 +1,2452 @@
-use crate::config::{ BackupConfig, DEFAULT_LOG_FILE };
-use crate::http_client::build_http_client;
-use crate::utils::log;
-use indicatif::{ MultiProgress, ProgressBar, ProgressStyle };
-use rayon::prelude::*;
-use reqwest::header;
+use crate::api::EsApi;
+use crate::config::{ BackupConfig, ChecksumMismatch, TemplateConflicts };
+use crate::error::{ invalid_data, EsDumperError };
+use crate::hooks::run_hook_with_payload;
+use crate::http_client::{ build_http_client, opaque_id, HttpClient };
+use crate::job::Job;
+use crate::latency::LatencyKind;
+use crate::manifest::IndexManifest;
+use crate::index_diff::{ mapping_additions, settings_changes };
+use crate::mapping_compat::adapt_mappings;
+use crate::observer::{ BatchDone, IndexError, IndexStart, RunStart };
+use crate::output::{
+    data_file_candidates,
+    partition_files,
+    read_documents,
+    ChecksumReader,
+};
+use crate::report::{ IndexReport, RunReport };
+use crate::state::StateStore;
+use crate::templates::{
+    is_typed,
+    mapping_conflicts,
+    remove_conflicts,
+    typeless,
+    TargetTemplates,
+};
+use crate::transforms::restore_transforms;
+use crate::watches::restore_watches;
+use crate::utils::{
+    catch_panic,
+    cluster_dir_name,
+    get_cluster_info,
+    index_backup_dirs,
+    log,
+    matches_pattern,
+    retry_transient,
+    run_queue,
+    set_path,
+    timestamp,
+    ClusterInfo,
+    LogFile,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::Rng;
+use reqwest::{ header, StatusCode };
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs::{ self, File };
-use std::io::{ BufReader, Read };
-use std::path::Path;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
 use std::process::Command;
-use std::sync::{ Arc, Mutex };
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+/// Index settings of frozen indices and searchable snapshots that would leave a restored copy
+/// read-only, searched only on request or tied to the snapshot it was mounted from.
+const READ_ONLY_INDEX_SETTINGS: &[&str] = &[
+    "frozen",
+    "search.throttled",
+    "blocks.write",
+    "store.type",
+    "store.snapshot",
+    "recovery.type",
+    "routing.allocation.include._tier_preference",
+];
+
+/// Mapping differences named in the warning of a restored index.
+const MAPPING_DIFFERENCES_SHOWN: usize = 10;
+
+/// Prefix of the temporary indices `--self-test` restores backups into.
+const SELF_TEST_PREFIX: &str = "es-dumper-selftest-";
+
+/// Field recording the document type of each document restored from a split multi-type backup.
+const TYPE_NAME_FIELD: &str = "_type_name";
 
 pub fn run_restore(
     config: &BackupConfig,
-    log_file: &Arc<Mutex<File>>,
-    specific_index: Option<&str>
-) -> Result<(), Box<dyn std::error::Error>> {
+    log_file: &LogFile,
+    specific_index: Option<&str>,
+    job: &Job
+) -> Result<RunReport, EsDumperError> {
     log(log_file, "Starting Elasticsearch restore process")?;
 
-    let backup_dir_path = Path::new(&config.backup_dir);
+    let mut report = RunReport::new("restore", timestamp());
+    report.run_id = Some(job.run_id.clone());
+    report.run_uuid = Some(log_file.run_uuid().to_string());
+
+    let run_opaque_id = opaque_id(config, job, None);
+    log(log_file, &format!("Requests of this run carry X-Opaque-Id: {}", run_opaque_id))?;
+    let client = build_http_client(config, &run_opaque_id)?;
+    config.hosts.breaker().monitor(&client, log_file);
+    let cluster = get_cluster_info(config, &client, log_file)?;
+    let state = if config.no_local_files {
+        StateStore::open_in_memory()?
+    } else {
+        StateStore::open(&config.backup_dir)?
+    };
+
+    let source = RestoreSource::open(config, job, log_file)?;
+    let backup_dir_path = source.dir.as_path();
 
     let indices = match specific_index {
-        Some(index) => {
-            let index_path = backup_dir_path.join(index);
-            if !index_path.exists() || !index_path.is_dir() {
-                return Err(format!("Backup for index '{}' not found", index).into());
-            }
-            vec![index.to_string()]
+        Some(index) if config.bulk_file.is_some() => vec![index.to_string()],
+        None if config.bulk_file.is_some() => {
+            return Err(
+                EsDumperError::Config(
+                    "--bulk-file requires the name of the target index".to_string()
+                )
+            );
         }
-        None =>
-            fs
-                ::read_dir(backup_dir_path)?
-                .filter_map(|entry| {
-                    let entry = entry.ok()?;
-                    let path = entry.path();
-                    if path.is_dir() && path.file_name()?.to_str()?.starts_with('.') == false {
-                        Some(path.file_name()?.to_str()?.to_string())
-                    } else {
-                        None
-                    }
+        Some(index) if backup_dir_path.join(index).is_dir() => vec![index.to_string()],
+        // The storage backend brings the index into the source directory below
+        Some(index) if job.storage().is_some() => vec![index.to_string()],
+        Some(name) => {
+            // Indices backed up through an alias are restored under their own names
+            let aliased: Vec<String> = index_backup_dirs(backup_dir_path)?
+                .into_iter()
+                .filter(|index| {
+                    IndexManifest::load(&backup_dir_path.join(index), index)
+                        .ok()
+                        .flatten()
+                        .is_some_and(|manifest| manifest.alias.as_deref() == Some(name))
                 })
-                .collect(),
+                .collect();
+            if aliased.is_empty() {
+                return Err(
+                    EsDumperError::Config(format!("Backup for index '{}' not found", name))
+                );
+            }
+            log(
+                log_file,
+                &format!(
+                    "{} was backed up as an alias of {}, restoring them",
+                    name,
+                    aliased.join(", ")
+                )
+            )?;
+            aliased
+        }
+        None => index_backup_dirs(backup_dir_path)?,
     };
 
+    let found = indices.len();
+    let mut indices: Vec<String> = indices
+        .into_iter()
+        .filter(|index| {
+            (config.restore_include.is_empty() ||
+                config.restore_include.iter().any(|pattern| matches_pattern(pattern, index))) &&
+                !config.restore_skip.iter().any(|pattern| matches_pattern(pattern, index))
+        })
+        .collect();
+    if indices.len() < found {
+        log(
+            log_file,
+            &format!(
+                "Leaving out {} of {} backed-up indices (--include/--skip)",
+                found - indices.len(),
+                found
+            )
+        )?;
+    }
+
     if indices.is_empty() {
         log(log_file, "No backups found to restore")?;
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}").unwrap());
-        pb.set_message("No backups found to restore");
-        pb.finish_and_clear();
-        return Ok(());
+        return Ok(report);
+    }
+    if !config.restore_priority.is_empty() {
+        // Workers take the indices in order, so the first matching pattern decides the turn
+        indices.sort_by_key(|index| {
+            config.restore_priority
+                .iter()
+                .position(|pattern| matches_pattern(pattern, index))
+                .unwrap_or(config.restore_priority.len())
+        });
+        let prioritized = indices
+            .iter()
+            .take_while(|index| {
+                config.restore_priority.iter().any(|pattern| matches_pattern(pattern, index))
+            })
+            .count();
+        log(
+            log_file,
+            &format!(
+                "Restoring {} indices matching restore_priority before the other {}",
+                prioritized,
+                indices.len() - prioritized
+            )
+        )?;
+    }
+    if let Some(storage) = job.storage().filter(|_| config.bulk_file.is_none()) {
+        for index in &indices {
+            log(log_file, &format!("Fetching index {} from {}", index, storage.name()))?;
+            storage.fetch_index(index, &backup_dir_path.join(index))?;
+        }
+    }
+    if config.bulk_file.is_none() {
+        verify_source_cluster(config, backup_dir_path, &indices)?;
     }
 
     log(log_file, &format!("Found {} indices to restore", indices.len()))?;
+    if let Some(command) = &config.pre_restore_cmd {
+        let mut payload = hook_payload(job, &cluster, backup_dir_path);
+        payload["indices"] = serde_json::json!(indices);
+        run_hook_with_payload(
+            "pre_restore_cmd",
+            command,
+            &hook_env(job),
+            &payload,
+            log_file
+        )?;
+    }
+    wait_for_health(config, &client, log_file)?;
+    let templates = if config.data_only || config.bulk_file.is_some() {
+        TargetTemplates::default()
+    } else {
+        TargetTemplates::fetch(config, &client)?
+    };
+    let precreated = if
+        config.create_indices_first &&
+        !config.data_only &&
+        config.bulk_file.is_none()
+    {
+        create_indices_first(
+            config,
+            &client,
+            backup_dir_path,
+            &indices,
+            cluster.api.as_ref(),
+            &templates,
+            log_file,
+            job
+        )?
+    } else {
+        HashMap::new()
+    };
+    let precreated = Mutex::new(precreated);
+    job.set_total_indices(indices.len());
 
-    let multi = Arc::new(MultiProgress::new());
-    let pb_main = multi.add(ProgressBar::new(indices.len() as u64));
-    pb_main.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.magenta/purple}] {pos}/{len} ({eta}) Indices"
-            )
-            .unwrap()
-            .progress_chars("#>-")
+    let observer = job.observer();
+    observer.on_run_start(
+        &(RunStart {
+            operation: "restore",
+            indices: indices.len(),
+            expected_docs: None,
+            completed_docs: 0,
+            expected_bytes: None,
+        })
     );
 
-    let start_time = std::time::Instant::now();
+    let start_time = Instant::now();
 
-    let completed_indices = Arc::new(Mutex::new(0));
+    let index_reports = Mutex::new(Vec::new());
 
-    indices.par_chunks(config.max_parallel_indices).for_each(|chunk| {
-        for index in chunk {
-            let pb_index = multi.add(ProgressBar::new(0));
-            pb_index.set_style(
-                ProgressStyle::default_bar()
-                    .template(
-                        "{spinner:.green} [{elapsed_precise}] [{bar:40.magenta/purple}] {pos}/{len} ({eta}) {msg}"
-                    )
-                    .unwrap()
-                    .progress_chars("#>-")
+    run_queue(&indices, config.restore_parallel_indices, |index| {
+        if job.is_cancelled() {
+            return;
+        }
+        let log_file = &log_file.for_index(index);
+
+        job.index_started();
+        observer.on_index_start(&(IndexStart { index, expected_docs: None }));
+
+        if let Ok(Some(previous)) = state.get(&cluster.uuid, "restore", index) {
+            let _ = log(
+                log_file,
+                &format!("Previous restore of index {} {}", index, previous.describe())
             );
-            pb_index.set_message(index.to_string());
+        }
 
-            let result = restore_index(config, index, log_file, &pb_index);
-            if let Err(e) = result {
+        let index_start = Instant::now();
+        // Imported bulk files may hold create actions and documents without IDs, which another
+        // attempt would reject or duplicate
+        let retries = if config.bulk_file.is_some() { 0 } else { config.index_retries };
+        let mut created = Vec::new();
+        let (result, attempts) = retry_transient(
+            &format!("restore of index {}", index),
+            retries,
+            config.index_retry_backoff,
+            log_file,
+            job,
+            |attempt| {
+                if attempt > 1 {
+                    delete_indices(config, &client, &created, "the failed attempt", log_file)?;
+                    created.clear();
+                }
+                // Only the first attempt finds the indices created upfront
+                let precreated = precreated.lock().unwrap().remove(index);
+                catch_panic(&format!("Restore of index {}", index), || {
+                    restore_index(
+                        config,
+                        &client,
+                        backup_dir_path,
+                        index,
+                        cluster.api.as_ref(),
+                        &templates,
+                        precreated,
+                        &mut created,
+                        log_file,
+                        job
+                    )
+                })
+            }
+        );
+        let index_duration = index_start.elapsed().as_secs_f64();
+        let mut index_report = match result {
+            Ok(outcome) => {
+                let mut index_report = IndexReport::success(
+                    index,
+                    outcome.docs,
+                    outcome.bytes,
+                    index_duration
+                );
+                index_report.warnings = outcome.warnings;
+                index_report
+            }
+            Err(e) => {
                 let _ = log(log_file, &format!("Error restoring index {}: {}", index, e));
-                pb_index.abandon_with_message(format!("Error: {}", e));
-            } else {
-                pb_index.finish_and_clear();
+                observer.on_index_error(&(IndexError { index, error: &e }));
+                IndexReport::failure(index, e.to_string(), index_duration)
             }
-
-            let mut completed = completed_indices.lock().unwrap();
-            *completed += 1;
-            pb_main.set_position(*completed);
+        };
+        index_report.record_attempts(attempts);
+        if index_report.success {
+            observer.on_index_done(&index_report);
+        }
+        if let Err(e) = state.record_index(&cluster.uuid, "restore", &index_report) {
+            let _ = log(log_file, &format!("Failed to record state for {}: {}", index, e));
         }
+        index_reports.lock().unwrap().push(index_report);
+
+        job.index_done();
     });
 
+    let index_reports = index_reports.into_inner().unwrap();
+    if config.restore_transforms {
+        let restored: Vec<String> = index_reports
+            .iter()
+            .filter(|index| index.success)
+            .map(|index| index.index.clone())
+            .collect();
+        restore_transforms(config, &client, backup_dir_path, &restored, log_file, job)?;
+    }
+    if config.restore_watches {
+        restore_watches(config, &client, backup_dir_path, log_file, job)?;
+    }
+
     let duration = start_time.elapsed();
-    pb_main.finish_with_message(format!("Completed in {:.2} seconds", duration.as_secs_f64()));
     log(
         log_file,
         &format!("Restore completed successfully in {:.2} seconds", duration.as_secs_f64())
     )?;
 
-    Ok(())
+    report.duration_secs = duration.as_secs_f64();
+    report.indices = index_reports;
+    report.indices.sort_by(|a, b| a.index.cmp(&b.index));
+    report.latency = Some(job.latencies().summary());
+    let http = client.stats();
+    log(log_file, &format!("HTTP: {}", http.line()))?;
+    report.http = Some(http);
+    // The indices are restored whatever the hook does, so its failure is only reported
+    if let Some(command) = &config.post_restore_cmd {
+        let status = if report.failed_count() == 0 { "success" } else { "partial" };
+        let mut payload = hook_payload(job, &cluster, backup_dir_path);
+        payload["status"] = status.into();
+        payload["report"] = serde_json::to_value(&report)?;
+        let mut env = hook_env(job);
+        env.push(("STATUS", status.to_string()));
+        if
+            let Err(e) = run_hook_with_payload(
+                "post_restore_cmd",
+                command,
+                &env,
+                &payload,
+                log_file
+            )
+        {
+            log(log_file, &e.to_string())?;
+            observer.on_message(&format!("Warning: {}", e));
+        }
+    }
+    observer.on_run_complete(&report);
+    Ok(report)
 }
 
-fn restore_index(
-    config: &BackupConfig,
-    index: &str,
-    log_file: &Arc<Mutex<File>>,
-    pb_index: &ProgressBar
-) -> Result<(), Box<dyn std::error::Error>> {
-    log(log_file, &format!("Starting restore for index: {}", index))?;
+/// Environment of the restore hook commands.
+fn hook_env(job: &Job) -> Vec<(&'static str, String)> {
+    vec![("OPERATION", "restore".to_string()), ("RUN_ID", job.run_id.clone())]
+}
 
-    let index_dir = Path::new(&config.backup_dir).join(index);
-    if !index_dir.exists() || !index_dir.is_dir() {
-        return Err(format!("Backup directory for index '{}' not found", index).into());
+/// The part of the JSON given to restore hook commands on stdin that describes the run.
+fn hook_payload(job: &Job, cluster: &ClusterInfo, source_dir: &Path) -> Value {
+    serde_json::json!({
+        "operation": "restore",
+        "run_id": job.run_id,
+        "cluster": {
+            "name": cluster.name,
+            "uuid": cluster.uuid,
+            "version": cluster.version,
+        },
+        "source_dir": source_dir.display().to_string(),
+    })
+}
+
+/// Directory a restore reads the backups from: `backup_dir`, the directory given with `--from`,
+/// or a temporary directory that a `--from` archive is unpacked into and that is removed again
+/// once the restore is done.
+struct RestoreSource {
+    dir: PathBuf,
+    /// The temporary directory of an unpacked archive.
+    unpacked: Option<PathBuf>,
+}
+
+impl RestoreSource {
+    fn open(config: &BackupConfig, job: &Job, log_file: &LogFile) -> Result<Self, EsDumperError> {
+        let mut source = Self::locate(config, job, log_file)?;
+        if config.bulk_file.is_none() {
+            source.dir = select_cluster_dir(config, &source.dir, log_file)?;
+        }
+        Ok(source)
     }
 
-    restore_mapping(config, index, &index_dir, log_file)?;
-    restore_data(config, index, &index_dir, log_file, pb_index)?;
+    fn locate(config: &BackupConfig, job: &Job, log_file: &LogFile) -> Result<Self, EsDumperError> {
+        let Some(from) = &config.restore_from else {
+            return Ok(RestoreSource { dir: PathBuf::from(&config.backup_dir), unpacked: None });
+        };
+        if from.contains("://") {
+            return Err(
+                EsDumperError::Config(
+                    format!(
+                        "Cannot restore from '{}': only local directories and .tar, .tar.gz or .tgz archives are supported",
+                        from
+                    )
+                )
+            );
+        }
+
+        let path = Path::new(from);
+        if path.is_dir() {
+            log(log_file, &format!("Restoring from {}", path.display()))?;
+            return Ok(RestoreSource { dir: path.to_path_buf(), unpacked: None });
+        }
+        let is_archive = [".tar", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|extension| from.ends_with(extension));
+        if !path.is_file() || !is_archive {
+            return Err(
+                EsDumperError::Config(
+                    format!("'{}' is neither a backup directory nor a .tar/.tgz archive", from)
+                )
+            );
+        }
+
+        let dir = std::env::temp_dir().join(format!("es-dumper-{}", job.run_id));
+        fs::create_dir_all(&dir)?;
+        // Removes the directory again should unpacking fail
+        let mut source = RestoreSource { dir: dir.clone(), unpacked: Some(dir) };
+        log(log_file, &format!("Unpacking {} into {}", from, source.dir.display()))?;
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(path)
+            .arg("-C")
+            .arg(&source.dir)
+            .status()?;
+        if !status.success() {
+            return Err(
+                EsDumperError::Io(io::Error::other(format!("Failed to unpack archive '{}'", from)))
+            );
+        }
 
-    log(log_file, &format!("Restore completed for index: {}", index))?;
-    Ok(())
+        // Archives of a whole backup directory hold it as their only top-level entry
+        let entries: Vec<PathBuf> = fs
+            ::read_dir(&source.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        if let [only] = entries.as_slice() {
+            let holds_index_dirs =
+                only.is_dir() &&
+                fs
+                    ::read_dir(only)?
+                    .filter_map(Result::ok)
+                    .any(|entry| entry.path().is_dir());
+            if holds_index_dirs {
+                source.dir = only.clone();
+            }
+        }
+        Ok(source)
+    }
 }
 
-fn restore_data(
+/// The directory of the source cluster's backups in `dir`: the one of `--source-cluster` (by
+/// directory name or by the cluster recorded in its manifests), or with `namespace_by_cluster`
+/// the only cluster directory there is. Flat layouts are used as they are.
+fn select_cluster_dir(
     config: &BackupConfig,
-    index: &str,
-    index_dir: &Path,
-    log_file: &Arc<Mutex<File>>,
-    pb_index: &ProgressBar
-) -> Result<(), Box<dyn std::error::Error>> {
-    let data_file = index_dir.join(format!("{}_data.json", index));
-    let gz_data_file = index_dir.join(format!("{}_data.json.gz", index));
-
-    let data_path = if data_file.exists() {
-        data_file
-    } else if gz_data_file.exists() {
-        log(log_file, &format!("Uncompressing data file for index: {}", index))?;
-        let status = Command::new("gunzip").arg("-k").arg(&gz_data_file).status()?;
+    dir: &Path,
+    log_file: &LogFile
+) -> Result<PathBuf, EsDumperError> {
+    if config.source_cluster.is_none() && !config.namespace_by_cluster {
+        return Ok(dir.to_path_buf());
+    }
 
-        if !status.success() {
-            pb_index.abandon_with_message("Failed to uncompress data file");
-            return Err(format!("Failed to uncompress data file for index '{}'", index).into());
+    // Cluster directories hold index directories, which only hold files
+    let cluster_dirs: Vec<PathBuf> = fs
+        ::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            fs::read_dir(path).is_ok_and(|entries| {
+                entries.filter_map(Result::ok).any(|entry| entry.path().is_dir())
+            })
+        })
+        .collect();
+
+    if let Some(cluster) = &config.source_cluster {
+        let named = dir.join(cluster_dir_name(cluster));
+        let cluster_dir = if cluster_dirs.contains(&named) {
+            Some(named)
+        } else {
+            cluster_dirs
+                .iter()
+                .find(|cluster_dir| backed_up_from(cluster_dir, cluster))
+                .cloned()
+        };
+        if let Some(cluster_dir) = cluster_dir {
+            log(
+                log_file,
+                &format!("Restoring backups of cluster {} from {}", cluster, cluster_dir.display())
+            )?;
+            return Ok(cluster_dir);
         }
+        return Ok(dir.to_path_buf());
+    }
 
-        data_file
-    } else {
-        pb_index.abandon_with_message("Data file not found");
-        return Err(format!("Data file for index '{}' not found", index).into());
+    match cluster_dirs.as_slice() {
+        [] => Ok(dir.to_path_buf()),
+        [only] => Ok(only.clone()),
+        _ => {
+            let names: Vec<String> = cluster_dirs
+                .iter()
+                .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+                .collect();
+            Err(
+                EsDumperError::Config(
+                    format!(
+                        "{} holds backups of several clusters ({}), choose one with --source-cluster",
+                        dir.display(),
+                        names.join(", ")
+                    )
+                )
+            )
+        }
+    }
+}
+
+/// Whether the manifests in `cluster_dir` record `cluster` (a name or UUID) as their source.
+fn backed_up_from(cluster_dir: &Path, cluster: &str) -> bool {
+    let Ok(entries) = fs::read_dir(cluster_dir) else {
+        return false;
     };
+    entries.filter_map(Result::ok).any(|entry| {
+        let index = entry.file_name().to_string_lossy().into_owned();
+        IndexManifest::load(&entry.path(), &index)
+            .ok()
+            .flatten()
+            .is_some_and(|manifest| {
+                manifest.cluster_name.as_deref() == Some(cluster) ||
+                    manifest.cluster_uuid.as_deref() == Some(cluster)
+            })
+    })
+}
 
-    log(log_file, &format!("Reading data file for index: {}", index))?;
+/// Refuses backups taken from a cluster other than `--source-cluster`, or from several clusters
+/// when none was given. Backups without a manifest are not checked.
+fn verify_source_cluster(
+    config: &BackupConfig,
+    source_dir: &Path,
+    indices: &[String]
+) -> Result<(), EsDumperError> {
+    let mut clusters: Vec<(String, String)> = Vec::new();
+    for index in indices {
+        let Some(manifest) = IndexManifest::load(&source_dir.join(index), index)? else {
+            continue;
+        };
+        let (Some(name), Some(uuid)) = (manifest.cluster_name, manifest.cluster_uuid) else {
+            continue;
+        };
+        if let Some(expected) = &config.source_cluster {
+            if expected != &name && expected != &uuid {
+                return Err(
+                    EsDumperError::Config(
+                        format!(
+                            "The backup of index '{}' was taken from cluster {} ({}), not {}",
+                            index,
+                            name,
+                            uuid,
+                            expected
+                        )
+                    )
+                );
+            }
+        }
+        if !clusters.contains(&(name.clone(), uuid.clone())) {
+            clusters.push((name, uuid));
+        }
+    }
 
-    let file = File::open(&data_path)?;
-    let reader = BufReader::with_capacity(config.buffer_size, file);
-    let documents: Vec<Value> = serde_json::from_reader(reader)?;
+    if clusters.len() > 1 {
+        let clusters: Vec<String> = clusters
+            .iter()
+            .map(|(name, uuid)| format!("{} ({})", name, uuid))
+            .collect();
+        return Err(
+            EsDumperError::Config(
+                format!(
+                    "The backups in {} were taken from several clusters: {}; choose one with --source-cluster",
+                    source_dir.display(),
+                    clusters.join(", ")
+                )
+            )
+        );
+    }
+    Ok(())
+}
 
-    let doc_count = documents.len() as u64;
-    if doc_count == 0 {
-        log(log_file, &format!("Index {} has no documents, skipping restore", index))?;
-        pb_index.set_message(format!("{} (empty)", index));
-        pb_index.finish_and_clear();
-        return Ok(());
+impl Drop for RestoreSource {
+    fn drop(&mut self) {
+        if let Some(unpacked) = &self.unpacked {
+            let _ = fs::remove_dir_all(unpacked);
+        }
     }
+}
 
-    log(log_file, &format!("Found {} documents to restore for index: {}", doc_count, index))?;
+struct RestoreOutcome {
+    docs: u64,
+    bytes: u64,
+    warnings: Vec<String>,
+}
 
-    let batch_count = ((doc_count as f64) / (config.bulk_batch_size as f64)).ceil() as u64;
-    pb_index.set_length(batch_count);
-    pb_index.set_message(index.to_string());
+/// The indices a backup is restored into, created with their mappings before any documents
+/// were loaded (`create_indices_first`).
+struct Precreated {
+    /// The indices created, also when creating a later one failed.
+    created: Vec<String>,
+    /// The warnings of their mappings, or why creating them failed.
+    result: Result<Vec<String>, EsDumperError>,
+}
 
-    let client = build_http_client(config)?;
-    let bulk_url = format!("{}/_bulk", config.host);
+/// Creates the indices of every backup in `indices`, `create_concurrency` at a time, so
+/// restores of many small indices don't wait on index creation one index after another.
+/// Returns what was created for each backup.
+#[allow(clippy::too_many_arguments)]
+fn create_indices_first(
+    config: &BackupConfig,
+    run_client: &HttpClient,
+    source_dir: &Path,
+    indices: &[String],
+    api: &dyn EsApi,
+    templates: &TargetTemplates,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<HashMap<String, Precreated>, EsDumperError> {
+    log(
+        log_file,
+        &format!(
+            "Creating {} indices before loading documents ({} at a time)",
+            indices.len(),
+            config.create_concurrency
+        )
+    )?;
+    let precreated = Mutex::new(HashMap::new());
+    run_queue(indices, config.create_concurrency, |index| {
+        // Missing backups are reported when their restore starts
+        let index_dir = source_dir.join(index);
+        if job.is_cancelled() || !index_dir.is_dir() {
+            return;
+        }
+        let log_file = &log_file.for_index(index);
+        let mut created = Vec::new();
+        let result = catch_panic(&format!("Creating the indices of {}", index), || {
+            let client = run_client.with_opaque_id(&opaque_id(config, job, Some(index)))?;
+            let doc_types = if config.split_types {
+                backed_up_types(&index_dir, index)?
+            } else {
+                Vec::new()
+            };
+            create_indices(
+                config,
+                &client,
+                index,
+                &index_dir,
+                &doc_types,
+                api,
+                templates,
+                &mut created,
+                log_file,
+                job
+            )
+        });
+        if let Err(e) = &result {
+            let _ = log(log_file, &format!("Failed to create the indices of {}: {}", index, e));
+        }
+        precreated.lock().unwrap().insert(index.clone(), Precreated { created, result });
+    });
+    Ok(precreated.into_inner().unwrap())
+}
 
-    for (batch_num, chunk) in documents.chunks(config.bulk_batch_size).enumerate() {
-        let mut bulk_body = String::with_capacity(config.buffer_size);
+/// Creates the indices a backup is restored into with their mappings, settings and aliases:
+/// the index itself, or with several `doc_types` an index per document type. Adds them to
+/// `created` and returns the warnings of their mappings.
+#[allow(clippy::too_many_arguments)]
+fn create_indices(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    index_dir: &Path,
+    doc_types: &[String],
+    api: &dyn EsApi,
+    templates: &TargetTemplates,
+    created: &mut Vec<String>,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<Vec<String>, EsDumperError> {
+    if doc_types.len() <= 1 {
+        return restore_mapping(
+            config,
+            client,
+            index,
+            index,
+            index_dir,
+            None,
+            api,
+            templates,
+            created,
+            log_file,
+            job
+        );
+    }
 
-        for doc in chunk {
-            let doc_id = doc["_id"].as_str().unwrap_or("");
-            let action = format!(
-                "{{ \"index\": {{ \"_index\": \"{}\", \"_id\": \"{}\" }} }}\n",
+    let mut warnings = Vec::new();
+    for doc_type in doc_types {
+        warnings.extend(
+            restore_mapping(
+                config,
+                client,
                 index,
-                doc_id
-            );
-            bulk_body.push_str(&action);
+                index,
+                index_dir,
+                Some(doc_type),
+                api,
+                templates,
+                created,
+                log_file,
+                job
+            )?
+        );
+    }
+    Ok(warnings)
+}
 
-            if let Some(source) = doc["_source"].as_object() {
-                let source_line = serde_json::to_string(source)?;
-                bulk_body.push_str(&source_line);
-                bulk_body.push('\n');
-            }
+/// Restores an index, adding the indices it creates to `created`. Indices `precreated` before
+/// the run loaded any documents are not created again.
+#[allow(clippy::too_many_arguments)]
+fn restore_index(
+    config: &BackupConfig,
+    run_client: &HttpClient,
+    source_dir: &Path,
+    index: &str,
+    api: &dyn EsApi,
+    templates: &TargetTemplates,
+    precreated: Option<Precreated>,
+    created: &mut Vec<String>,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<RestoreOutcome, EsDumperError> {
+    log(log_file, &format!("Starting restore for index: {}", index))?;
+    let client = run_client.with_opaque_id(&opaque_id(config, job, Some(index)))?;
+    if config.wait_between_indices {
+        wait_for_health(config, &client, log_file)?;
+    }
+
+    // Imported bulk files carry no mapping: the index must exist or is created dynamically
+    if let Some(bulk_file) = &config.bulk_file {
+        let bulk_file = Path::new(bulk_file);
+        let mut restored = stream_bulk_file(
+            config,
+            &client,
+            index,
+            bulk_file,
+            None,
+            log_file,
+            job
+        )?;
+        let mismatch = verify_doc_count(config, &client, api, index, restored.docs, log_file)?;
+        if let Some(warning) = mismatch {
+            restored.warnings.push(warning);
         }
+        if let Some(warning) = forcemerge(config, &client, index, log_file)? {
+            restored.warnings.push(warning);
+        }
+        log(log_file, &format!("Import completed for index: {}", index))?;
+        return Ok(restored);
+    }
+
+    let index_dir = source_dir.join(index);
+    if !index_dir.exists() || !index_dir.is_dir() {
+        return Err(
+            EsDumperError::Config(format!("Backup directory for index '{}' not found", index))
+        );
+    }
 
+    let manifest = IndexManifest::load(&index_dir, index)?;
+    let doc_types = if config.split_types {
+        backed_up_types(&index_dir, index)?
+    } else {
+        Vec::new()
+    };
+    let split = doc_types.len() > 1;
+    // Documents are counted and merged across the indices a multi-type backup is split into
+    let target = if split {
+        let targets: Vec<String> = doc_types
+            .iter()
+            .map(|doc_type| split_index_name(index, doc_type))
+            .collect();
         log(
             log_file,
             &format!(
-                "Uploading batch {} for index: {} ({} documents)",
-                batch_num + 1,
+                "Index {} holds {} document types, restoring each into an index of its own: {}",
                 index,
-                chunk.len()
+                doc_types.len(),
+                targets.join(", ")
             )
         )?;
+        targets.join(",")
+    } else {
+        index.to_string()
+    };
+
+    let mapping_warnings = match precreated {
+        Some(precreated) => {
+            created.extend(precreated.created);
+            precreated.result?
+        }
+        None if config.data_only => Vec::new(),
+        None =>
+            create_indices(
+                config,
+                &client,
+                index,
+                &index_dir,
+                &doc_types,
+                api,
+                templates,
+                created,
+                log_file,
+                job
+            )?,
+    };
+    if config.mapping_only {
+        log(log_file, &format!("Index created without data: {}", index))?;
+        return Ok(RestoreOutcome {
+            docs: 0,
+            bytes: 0,
+            warnings: mapping_warnings,
+        });
+    }
 
-        let response = client
-            .post(&bulk_url)
-            .header(header::CONTENT_TYPE, "application/x-ndjson")
-            .body(bulk_body)
-            .send()?;
+    if let Some(reason) = manifest.as_ref().and_then(|manifest| manifest.data_skipped.as_ref()) {
+        log(log_file, &format!("No documents to restore for index {}: {}", index, reason))?;
+        return Ok(RestoreOutcome {
+            docs: 0,
+            bytes: 0,
+            warnings: mapping_warnings,
+        });
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text()?;
-            pb_index.abandon_with_message(format!("Bulk upload failed: {}", status));
-            return Err(
-                format!(
-                    "Bulk upload failed for index '{}': {} - {}",
-                    index,
-                    status,
-                    error_text
-                ).into()
-            );
+    let mut restored = restore_data(
+        config,
+        &client,
+        index,
+        index,
+        &index_dir,
+        manifest.as_ref(),
+        split,
+        log_file,
+        job
+    )?;
+    restored.warnings.splice(0..0, mapping_warnings);
+    for limitation in manifest.into_iter().flat_map(|manifest| manifest.limitations) {
+        let warning = format!("Backup limitation: {}", limitation);
+        log(log_file, &format!("Index {}: {}", index, warning))?;
+        restored.warnings.push(warning);
+    }
+
+    let mismatch = verify_doc_count(config, &client, api, &target, restored.docs, log_file)?;
+    if let Some(warning) = mismatch {
+        if config.strict {
+            return Err(EsDumperError::PartialFailure(warning));
         }
+        restored.warnings.push(warning);
+    }
+    if let Some(warning) = forcemerge(config, &client, &target, log_file)? {
+        restored.warnings.push(warning);
+    }
 
-        let response_text = response.text()?;
-        let response_json: Value = serde_json::from_str(&response_text)?;
-        if response_json["errors"].as_bool().unwrap_or(false) {
-            log(
+    log(log_file, &format!("Restore completed for index: {}", index))?;
+    Ok(restored)
+}
+
+/// Restores the backup of `index` in `index_dir` into a temporary index on the same cluster,
+/// checks that it holds `expected_docs` documents (or every document read from the backup when
+/// unknown) along with the sample of `verify_sample_size`, and deletes it again, whatever the
+/// outcome. Returns the warnings of the restore; a count mismatch fails the self-test.
+#[allow(clippy::too_many_arguments)]
+pub fn self_test(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index_dir: &Path,
+    index: &str,
+    expected_docs: Option<u64>,
+    api: &dyn EsApi,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<Vec<String>, EsDumperError> {
+    let temp_index = format!("{}{}-{:08x}", SELF_TEST_PREFIX, index, rand::random::<u32>());
+    log(log_file, &format!("Self-test: restoring index {} into {}", index, temp_index))?;
+    // Compressed data files are uncompressed next to themselves, which the backup must not keep
+    let files_before: Vec<PathBuf> = fs
+        ::read_dir(index_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+
+    let result = (|| {
+        let manifest = IndexManifest::load(index_dir, index)?;
+        // The temporary index is named so that no template meant for the original applies
+        let mut warnings = restore_mapping(
+            config,
+            client,
+            index,
+            &temp_index,
+            index_dir,
+            None,
+            api,
+            &TargetTemplates::default(),
+            &mut Vec::new(),
+            log_file,
+            job
+        )?;
+        let restored_docs = if
+            manifest.as_ref().is_some_and(|manifest| manifest.data_skipped.is_some())
+        {
+            0
+        } else {
+            let restored = restore_data(
+                config,
+                client,
+                index,
+                &temp_index,
+                index_dir,
+                manifest.as_ref(),
+                false,
                 log_file,
-                &format!("Warning: Some errors occurred during bulk upload for index: {}", index)
+                job
             )?;
+            warnings.extend(restored.warnings);
+            restored.docs
+        };
 
-            if let Some(items) = response_json["items"].as_array() {
-                let errors: Vec<_> = items
-                    .iter()
-                    .filter_map(|item| {
-                        if let Some(error) = item["index"]["error"].as_object() {
-                            Some(
-                                format!(
-                                    "{}: {}",
-                                    error["type"].as_str().unwrap_or("unknown"),
-                                    error["reason"].as_str().unwrap_or("unknown reason")
-                                )
-                            )
-                        } else {
-                            None
-                        }
-                    })
-                    .take(5)
-                    .collect();
-
-                if !errors.is_empty() {
-                    log(log_file, &format!("First few errors: {}", errors.join(", ")))?;
-                }
-            }
+        let expected = expected_docs.unwrap_or(restored_docs);
+        let mismatch = verify_doc_count(config, client, api, &temp_index, expected, log_file)?;
+        if let Some(mismatch) = mismatch {
+            return Err(EsDumperError::PartialFailure(mismatch));
         }
+        Ok(warnings)
+    })();
 
-        pb_index.inc(1);
+    let deleted = delete_indices(
+        config,
+        client,
+        std::slice::from_ref(&temp_index),
+        "the self-test",
+        log_file
+    );
+    for entry in fs::read_dir(index_dir)?.flatten() {
+        if !files_before.contains(&entry.path()) {
+            fs::remove_file(entry.path())?;
+        }
     }
+    let warnings = result?;
+    deleted?;
+    log(log_file, &format!("Self-test passed for index: {}", index))?;
+    Ok(
+        warnings
+            .into_iter()
+            .map(|warning| format!("Self-test: {}", warning))
+            .collect()
+    )
+}
 
-    log(
-        log_file,
-        &format!("Data restoration completed for index: {}. Total documents: {}", index, doc_count)
-    )?;
+/// Deletes the indices a failed attempt created, so the next attempt can create them again,
+/// or the temporary index of a self-test; `created_by` names which in the log.
+fn delete_indices(
+    config: &BackupConfig,
+    client: &HttpClient,
+    indices: &[String],
+    created_by: &str,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    for index in indices {
+        log(log_file, &format!("Deleting index {} created by {}", index, created_by))?;
+        let response = config.hosts.send(|host| {
+            client
+                .delete(format!("{}/{}", host, index))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            let context = format!("Failed to delete index '{}'", index);
+            return Err(EsDumperError::from_response(context, response));
+        }
+    }
     Ok(())
 }
 
-fn restore_mapping(
+/// Blocks until the cluster reaches `wait_for_status`, failing after
+/// `wait_for_status_timeout_secs`.
+fn wait_for_health(
     config: &BackupConfig,
-    index: &str,
-    index_dir: &Path,
-    log_file: &Arc<Mutex<File>>
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = build_http_client(config)?;
-    let mapping_file = index_dir.join(format!("{}_mapping.json", index));
-
-    let file = File::open(&mapping_file)?;
-    let reader = BufReader::new(file);
-    let mapping_json: Value = serde_json::from_reader(reader)?;
+    client: &HttpClient,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let Some(status) = config.wait_for_status else {
+        return Ok(());
+    };
 
-    let create_index_url = format!("{}/{}", config.host, index);
-    let response = client.put(&create_index_url).json(&mapping_json).send()?;
+    log(log_file, &format!("Waiting for cluster health {}", status.as_str()))?;
+    let response = config.hosts.send(|host| {
+        client
+            .get(
+                format!(
+                    "{}/_cluster/health?wait_for_status={}&timeout={}s",
+                    host,
+                    status.as_str(),
+                    config.wait_for_status_timeout_secs
+                )
+            )
+            .timeout(
+                Duration::from_secs(
+                    config.wait_for_status_timeout_secs + config.metadata_timeout_secs
+                )
+            )
+    })?;
 
-    if !response.status().is_success() {
-        let error_text = response.text()?;
-        return Err(format!("Failed to create index '{}': {}", index, error_text).into());
+    // A timed-out wait is answered with 408 and the current health
+    let http_status = response.status();
+    let body = response.text()?;
+    let health: Value = serde_json::from_str(&body).unwrap_or_default();
+    if !http_status.is_success() || health["timed_out"].as_bool().unwrap_or(false) {
+        return Err(
+            EsDumperError::from_status(
+                format!(
+                    "Cluster did not reach {} health within {} seconds",
+                    status.as_str(),
+                    config.wait_for_status_timeout_secs
+                ),
+                http_status,
+                body
+            )
+        );
     }
 
-    log(log_file, &format!("Mapping restored for index: {}", index))?;
+    let current = health["status"].as_str().unwrap_or("unknown");
+    log(log_file, &format!("Cluster health is {}", current))?;
     Ok(())
 }
+
+/// Force-merges a restored index when `forcemerge_max_segments` is set. A failed merge leaves a
+/// working index behind, so it is returned as a warning.
+fn forcemerge(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    let Some(max_segments) = config.forcemerge_max_segments else {
+        return Ok(None);
+    };
+
+    log(log_file, &format!("Force-merging index {} to {} segments", index, max_segments))?;
+    let response = config.hosts.send(|host| {
+        client
+            .post(format!("{}/{}/_forcemerge?max_num_segments={}", host, index, max_segments))
+            .timeout(Duration::from_secs(config.bulk_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        let warning = format!("Force merge of index {} failed: {}", index, response.text()?);
+        log(log_file, &warning)?;
+        return Ok(Some(warning));
+    }
+    Ok(None)
+}
+
+/// Refreshes the restored index and compares its document count with the number of documents
+/// uploaded. Returns a warning describing the discrepancy, if any.
+fn verify_doc_count(
+    config: &BackupConfig,
+    client: &HttpClient,
+    api: &dyn EsApi,
+    index: &str,
+    expected: u64,
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    // Clusters that refresh on their own schedule count the documents visible so far
+    if config.final_refresh && api.supports_refresh() {
+        let response = config.hosts.send(|host| {
+            client
+                .post(format!("{}/{}/_refresh", host, index))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if !response.status().is_success() {
+            return Err(
+                EsDumperError::from_response(
+                    format!("Failed to refresh index '{}'", index),
+                    response
+                )
+            );
+        }
+    }
+
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/{}/_count", host, index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Failed to count documents in index '{}'", index),
+                response
+            )
+        );
+    }
+    let response_json: Value = response.json()?;
+    let actual = response_json["count"]
+        .as_u64()
+        .ok_or_else(|| EsDumperError::UnexpectedResponse("Invalid _count response".to_string()))?;
+
+    if actual == expected {
+        log(
+            log_file,
+            &format!("Document count verified for index: {} ({} documents)", index, actual)
+        )?;
+        return Ok(None);
+    }
+
+    let warning = format!(
+        "Document count mismatch for index '{}': uploaded {}, found {}",
+        index,
+        expected,
+        actual
+    );
+    log(log_file, &warning)?;
+    Ok(Some(warning))
+}
+
+/// Restores the documents of a backup of `index` into `target_index`, from its data file or,
+/// for a partitioned backup, from the data file of every partition in turn. With `split`, each
+/// document goes to the index of its document type.
+#[allow(clippy::too_many_arguments)]
+fn restore_data(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    target_index: &str,
+    index_dir: &Path,
+    manifest: Option<&IndexManifest>,
+    split: bool,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<RestoreOutcome, EsDumperError> {
+    if let Some(reason) = manifest.and_then(|manifest| manifest.interrupted.as_ref()) {
+        return Err(
+            EsDumperError::Config(
+                format!(
+                    "Backup of index '{}' is incomplete: {} (finish the backup with --resume)",
+                    index,
+                    reason
+                )
+            )
+        );
+    }
+    let partitions = manifest.map_or(&[][..], |manifest| &manifest.partitions[..]);
+    if partitions.is_empty() {
+        let candidates = data_file_candidates(index_dir, index);
+        let sha256 = manifest.and_then(|manifest| manifest.data_sha256.as_deref());
+        return restore_data_file(
+            config,
+            client,
+            target_index,
+            &candidates,
+            sha256,
+            split,
+            log_file,
+            job
+        );
+    }
+    if let Some(number) = partitions.iter().position(|partition| partition.docs.is_none()) {
+        return Err(
+            EsDumperError::Config(
+                format!(
+                    "Backup of index '{}' is incomplete: partition {} of {} was not exported (finish the backup with --resume)",
+                    index,
+                    number + 1,
+                    partitions.len()
+                )
+            )
+        );
+    }
+
+    let mut restored = RestoreOutcome {
+        docs: 0,
+        bytes: 0,
+        warnings: Vec::new(),
+    };
+    for (number, partition) in partitions.iter().enumerate() {
+        log(
+            log_file,
+            &format!(
+                "Restoring partition {} of {} of index {}",
+                number + 1,
+                partitions.len(),
+                index
+            )
+        )?;
+        let candidates = partition_files(index_dir, partition);
+        let outcome = restore_data_file(
+            config,
+            client,
+            target_index,
+            &candidates,
+            partition.sha256.as_deref(),
+            split,
+            log_file,
+            job
+        )?;
+        restored.docs += outcome.docs;
+        restored.bytes += outcome.bytes;
+        restored.warnings.extend(outcome.warnings);
+    }
+    Ok(restored)
+}
+
+/// Restores the documents of the first of `candidates` that exists, checking the uncompressed
+/// file against the `sha256` recorded at backup.
+#[allow(clippy::too_many_arguments)]
+fn restore_data_file(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    candidates: &[PathBuf],
+    sha256: Option<&str>,
+    split: bool,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<RestoreOutcome, EsDumperError> {
+    let data_path = match candidates.iter().find(|path| path.exists()) {
+        Some(gz_data_file) if gz_data_file.extension().is_some_and(|ext| ext == "gz") => {
+            log(log_file, &format!("Uncompressing data file for index: {}", index))?;
+            let status = Command::new("gunzip").arg("-k").arg(gz_data_file).status()?;
+
+            if !status.success() {
+                return Err(
+                    EsDumperError::Io(
+                        io::Error::other(
+                            format!("Failed to uncompress data file for index '{}'", index)
+                        )
+                    )
+                );
+            }
+
+            gz_data_file.with_extension("")
+        }
+        Some(data_file) => data_file.clone(),
+        None => {
+            return Err(
+                EsDumperError::Config(format!("Data file for index '{}' not found", index))
+            );
+        }
+    };
+
+    if data_path.extension().is_some_and(|extension| extension == "ndjson") {
+        if split {
+            return Err(
+                EsDumperError::Config(
+                    format!(
+                        "Data file of index '{}' is in bulk format, which does not record document types to split by",
+                        index
+                    )
+                )
+            );
+        }
+        return stream_bulk_file(config, client, index, &data_path, sha256, log_file, job);
+    }
+
+    log(log_file, &format!("Reading data file for index: {}", index))?;
+
+    let file = File::open(&data_path)?;
+    let data_bytes = file.metadata()?.len();
+    let mut reader = ChecksumReader::new(file);
+    let documents = read_documents(
+        BufReader::with_capacity(config.buffer_size, &mut reader),
+        &data_path
+    )?;
+    let mut warnings = Vec::new();
+    if let Some(warning) = check_checksum(config, &data_path, sha256, &reader, log_file)? {
+        warnings.push(warning);
+    }
+
+    let doc_count = documents.len() as u64;
+    if doc_count == 0 {
+        log(log_file, &format!("Index {} has no documents, skipping restore", index))?;
+        return Ok(RestoreOutcome {
+            docs: 0,
+            bytes: data_bytes,
+            warnings,
+        });
+    }
+
+    log(log_file, &format!("Found {} documents to restore for index: {}", doc_count, index))?;
+
+    for (batch_num, chunk) in documents.chunks(config.bulk_batch_size).enumerate() {
+        if job.is_cancelled() {
+            return Err(EsDumperError::Cancelled);
+        }
+
+        let mut bulk_body = String::with_capacity(config.buffer_size);
+
+        for doc in chunk {
+            let doc_id = doc["_id"].as_str().unwrap_or("");
+            let doc_type = if split { Some(document_type(index, doc)?) } else { None };
+            let target = match doc_type {
+                Some(doc_type) => split_index_name(index, doc_type),
+                None => index.to_string(),
+            };
+            // Without an `_id`, the cluster assigns a new one. Routed documents go back to the
+            // shard their routing value picks, as the bulk format's actions keep it too
+            let mut action = serde_json::json!({ "_index": target });
+            if config.copy_id_to.is_none() {
+                action["_id"] = Value::from(doc_id);
+            }
+            if !doc["_routing"].is_null() {
+                action["routing"] = doc["_routing"].clone();
+            }
+            bulk_body.push_str(&serde_json::to_string(&serde_json::json!({ "index": action }))?);
+            bulk_body.push('\n');
+
+            if let Some(source) = doc["_source"].as_object() {
+                let source_line = if doc_type.is_none() && config.copy_id_to.is_none() {
+                    serde_json::to_string(source)?
+                } else {
+                    let mut source = source.clone();
+                    if let Some(doc_type) = doc_type {
+                        source.insert(TYPE_NAME_FIELD.to_string(), Value::from(doc_type));
+                    }
+                    if let Some(field) = &config.copy_id_to {
+                        source.insert(field.clone(), Value::from(doc_id));
+                    }
+                    serde_json::to_string(&source)?
+                };
+                bulk_body.push_str(&source_line);
+                bulk_body.push('\n');
+            }
+        }
+
+        log(
+            log_file,
+            &format!(
+                "Uploading batch {} for index: {} ({} documents)",
+                batch_num + 1,
+                index,
+                chunk.len()
+            )
+        )?;
+        let bytes = bulk_body.len() as u64;
+        send_bulk(config, client, index, bulk_body, chunk.len(), log_file, job)?;
+
+        job.observer().on_batch_done(
+            &(BatchDone {
+                index,
+                docs: chunk.len() as u64,
+                bytes,
+                expected_docs: Some(doc_count),
+                projected_bytes: None,
+                written_bytes: None,
+            })
+        );
+    }
+
+    log(
+        log_file,
+        &format!("Data restoration completed for index: {}. Total documents: {}", index, doc_count)
+    )?;
+
+    // Sampled documents are looked up by `_id` in the unsplit index, so split restores and
+    // restores under new IDs skip the check
+    if config.verify_sample_size > 0 && !split && config.copy_id_to.is_none() {
+        if let Some(warning) = verify_sample(config, client, index, &documents, log_file)? {
+            warnings.push(warning);
+        }
+    }
+
+    Ok(RestoreOutcome {
+        docs: doc_count,
+        bytes: data_bytes,
+        warnings,
+    })
+}
+
+/// Streams a file of `_bulk` action and source lines to `_bulk`, in requests of at most
+/// `bulk_max_bytes` and `bulk_batch_size` actions. Every action is retargeted at `index`. The
+/// file is checked against `sha256` once it is read to the end, before the last request.
+fn stream_bulk_file(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    path: &Path,
+    sha256: Option<&str>,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<RestoreOutcome, EsDumperError> {
+    log(log_file, &format!("Streaming bulk file {} into index: {}", path.display(), index))?;
+
+    let file = File::open(path)?;
+    let data_bytes = file.metadata()?.len();
+
+    let mut reader = ChecksumReader::new(file);
+    let mut lines = BufReader::with_capacity(config.buffer_size, &mut reader).lines();
+    let mut bulk_body = String::with_capacity(config.buffer_size);
+    let mut batch_actions = 0;
+    let mut batch_num = 0;
+    let mut doc_count: u64 = 0;
+
+    // Reservoir sample of (id, source) pairs for post-restore verification
+    let mut rng = rand::thread_rng();
+    let mut sample: Vec<(Value, String)> = Vec::new();
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut action: Value = serde_json::from_str(&line)?;
+        let (kind, meta) = action
+            .as_object_mut()
+            .and_then(|action| action.iter_mut().next())
+            .ok_or_else(|| {
+                invalid_data(format!("Invalid bulk action line in {}: {}", path.display(), line))
+            })?;
+        let kind = kind.clone();
+        let id = meta["_id"].clone();
+        // Updates and deletes apply to the backed-up `_id`, so only new documents get new IDs
+        let new_id = config.copy_id_to.is_some() && (kind == "index" || kind == "create");
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert("_index".to_string(), Value::from(index));
+            meta.remove("_type");
+            if new_id {
+                meta.remove("_id");
+            }
+        }
+
+        // Every action but delete is followed by a source line
+        let source = if kind == "delete" {
+            None
+        } else {
+            let source = lines
+                .next()
+                .ok_or_else(|| {
+                    invalid_data(format!("Bulk action without source line in {}", path.display()))
+                })??;
+            Some(source)
+        };
+        let source = match (source, &config.copy_id_to) {
+            (Some(source), Some(field)) if new_id => {
+                let mut document: Value = serde_json::from_str(&source)?;
+                if let Some(document) = document.as_object_mut() {
+                    document.insert(field.clone(), id.clone());
+                }
+                Some(serde_json::to_string(&document)?)
+            }
+            (source, _) => source,
+        };
+
+        let entry_len = line.len() + source.as_ref().map_or(0, |source| source.len()) + 2;
+        if
+            batch_actions > 0 &&
+            (bulk_body.len() + entry_len > config.bulk_max_bytes ||
+                batch_actions >= config.bulk_batch_size)
+        {
+            if job.is_cancelled() {
+                return Err(EsDumperError::Cancelled);
+            }
+            batch_num += 1;
+            log(
+                log_file,
+                &format!(
+                    "Uploading batch {} for index: {} ({} actions, {} bytes)",
+                    batch_num,
+                    index,
+                    batch_actions,
+                    bulk_body.len()
+                )
+            )?;
+            let bytes = bulk_body.len() as u64;
+            send_bulk(
+                config,
+                client,
+                index,
+                std::mem::take(&mut bulk_body),
+                batch_actions,
+                log_file,
+                job
+            )?;
+            job.observer().on_batch_done(
+                &(BatchDone {
+                    index,
+                    docs: batch_actions as u64,
+                    bytes,
+                    expected_docs: None,
+                    projected_bytes: None,
+                    written_bytes: None,
+                })
+            );
+            batch_actions = 0;
+        }
+
+        bulk_body.push_str(&serde_json::to_string(&action)?);
+        bulk_body.push('\n');
+        if let Some(source) = source {
+            bulk_body.push_str(&source);
+            bulk_body.push('\n');
+
+            if kind == "index" || kind == "create" {
+                doc_count += 1;
+                if config.verify_sample_size > 0 && id.is_string() && !new_id {
+                    if sample.len() < config.verify_sample_size {
+                        sample.push((id, source));
+                    } else {
+                        let slot = rng.gen_range(0..doc_count) as usize;
+                        if slot < sample.len() {
+                            sample[slot] = (id, source);
+                        }
+                    }
+                }
+            }
+        }
+        batch_actions += 1;
+    }
+
+    let mut warnings = Vec::new();
+    if let Some(warning) = check_checksum(config, path, sha256, &reader, log_file)? {
+        warnings.push(warning);
+    }
+
+    if batch_actions > 0 {
+        batch_num += 1;
+        log(
+            log_file,
+            &format!(
+                "Uploading batch {} for index: {} ({} actions, {} bytes)",
+                batch_num,
+                index,
+                batch_actions,
+                bulk_body.len()
+            )
+        )?;
+        let bytes = bulk_body.len() as u64;
+        send_bulk(config, client, index, bulk_body, batch_actions, log_file, job)?;
+        job.observer().on_batch_done(
+            &(BatchDone {
+                index,
+                docs: batch_actions as u64,
+                bytes,
+                expected_docs: None,
+                projected_bytes: None,
+                written_bytes: None,
+            })
+        );
+    }
+
+    log(
+        log_file,
+        &format!("Data restoration completed for index: {}. Total documents: {}", index, doc_count)
+    )?;
+
+    if !sample.is_empty() {
+        let documents = sample
+            .into_iter()
+            .map(|(id, source)| {
+                let source: Value = serde_json::from_str(&source)?;
+                Ok(serde_json::json!({ "_id": id, "_source": source }))
+            })
+            .collect::<Result<Vec<Value>, serde_json::Error>>()?;
+        if let Some(warning) = verify_sample(config, client, index, &documents, log_file)? {
+            warnings.push(warning);
+        }
+    }
+
+    Ok(RestoreOutcome {
+        docs: doc_count,
+        bytes: data_bytes,
+        warnings,
+    })
+}
+
+/// Compares the checksum of a data file read to its end with the one recorded at backup, failing
+/// on a mismatch or, with `checksum_mismatch = "warn"`, returning a warning.
+fn check_checksum<R>(
+    config: &BackupConfig,
+    path: &Path,
+    expected: Option<&str>,
+    reader: &ChecksumReader<R>,
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    let Some(expected) = expected else {
+        return Ok(None);
+    };
+    let actual = reader.sha256();
+    if actual == expected {
+        return Ok(None);
+    }
+
+    let message = format!(
+        "Data file {} changed since it was backed up (SHA-256 {}, expected {})",
+        path.display(),
+        actual,
+        expected
+    );
+    match config.checksum_mismatch {
+        ChecksumMismatch::Abort => Err(invalid_data(message)),
+        ChecksumMismatch::Warn => {
+            log(log_file, &format!("Warning: {}", message))?;
+            Ok(Some(message))
+        }
+    }
+}
+
+/// Sends one `_bulk` request of `actions` actions and logs the first few item errors it
+/// reports.
+fn send_bulk(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    bulk_body: String,
+    actions: usize,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<(), EsDumperError> {
+    let mut params = Vec::new();
+    if let Some(refresh) = config.bulk_refresh.as_param() {
+        params.push(format!("refresh={}", refresh));
+    }
+    if let Some(active_shards) = &config.wait_for_active_shards {
+        params.push(format!("wait_for_active_shards={}", active_shards.as_param()));
+    }
+    let url = |host: &str| {
+        if params.is_empty() {
+            format!("{}/_bulk", host)
+        } else {
+            format!("{}/_bulk?{}", host, params.join("&"))
+        }
+    };
+    config.shard_watch.wait(&config.hosts, client, log_file)?;
+    config.restore_throttle.wait(actions as u64, bulk_body.len() as u64);
+    let _permit = config.write_pacer.acquire(&config.hosts, client, log_file);
+
+    let response = loop {
+        let compress = config.compress_bulk.is_enabled();
+        let body = if compress {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bulk_body.as_bytes())?;
+            encoder.finish()?
+        } else {
+            bulk_body.as_bytes().to_vec()
+        };
+
+        let response = job.latencies().time_request(LatencyKind::Bulk, || {
+            config.hosts.send(|host| {
+                let request = client
+                    .post(url(host))
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(body.clone())
+                    .timeout(Duration::from_secs(config.bulk_timeout_secs));
+                if compress {
+                    request.header(header::CONTENT_ENCODING, "gzip")
+                } else {
+                    request
+                }
+            })
+        })?;
+
+        let status = response.status();
+        if status.is_success() {
+            if compress {
+                config.compress_bulk.accepted();
+            }
+            break response;
+        }
+
+        let error_text = response.text()?;
+        if
+            compress &&
+            compression_rejected(status, &error_text) &&
+            config.compress_bulk.rejected()
+        {
+            log(
+                log_file,
+                &format!(
+                    "The cluster rejected a compressed bulk request ({}), sending bulk requests uncompressed",
+                    status
+                )
+            )?;
+            continue;
+        }
+        return Err(
+            EsDumperError::from_status(
+                format!("Bulk upload failed for index '{}'", index),
+                status,
+                error_text
+            )
+        );
+    };
+
+    let response_text = response.text()?;
+    let response_json: Value = serde_json::from_str(&response_text)?;
+    audit_bulk(config, job, index, &response_json)?;
+    if response_json["errors"].as_bool().unwrap_or(false) {
+        log(
+            log_file,
+            &format!("Warning: Some errors occurred during bulk upload for index: {}", index)
+        )?;
+
+        if let Some(items) = response_json["items"].as_array() {
+            let errors: Vec<_> = items
+                .iter()
+                .filter_map(|item| {
+                    // Each item is keyed by its action type (index, create, update, delete)
+                    let result = item.as_object()?.values().next()?;
+                    result["error"].as_object().map(|error| {
+                        format!(
+                            "{}: {}",
+                            error["type"].as_str().unwrap_or("unknown"),
+                            error["reason"].as_str().unwrap_or("unknown reason")
+                        )
+                    })
+                })
+                .take(5)
+                .collect();
+
+            if !errors.is_empty() {
+                log(log_file, &format!("First few errors: {}", errors.join(", ")))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records the documents a `_bulk` request wrote and deleted in the audit log.
+fn audit_bulk(
+    config: &BackupConfig,
+    job: &Job,
+    index: &str,
+    response: &Value
+) -> Result<(), EsDumperError> {
+    let (mut written, mut deleted) = (0, 0);
+    for item in response["items"].as_array().into_iter().flatten() {
+        let Some((action, result)) = item.as_object().and_then(|item| item.iter().next()) else {
+            continue;
+        };
+        if !result["status"].as_u64().is_some_and(|status| (200..300).contains(&status)) {
+            continue;
+        }
+        if action == "delete" {
+            deleted += 1;
+        } else {
+            written += 1;
+        }
+    }
+
+    if written > 0 {
+        config.audit.record(&job.run_id, "documents_written", Some(index), Some(written), None)?;
+    }
+    if deleted > 0 {
+        config.audit.record(&job.run_id, "documents_deleted", Some(index), Some(deleted), None)?;
+    }
+    Ok(())
+}
+
+/// Whether a failed `_bulk` response says the cluster (or a proxy in front of it) cannot read
+/// gzip request bodies.
+fn compression_rejected(status: StatusCode, body: &str) -> bool {
+    let body = body.to_lowercase();
+    status == StatusCode::UNSUPPORTED_MEDIA_TYPE ||
+        ((status == StatusCode::BAD_REQUEST || status == StatusCode::NOT_ACCEPTABLE) &&
+            (body.contains("content-encoding") ||
+                body.contains("gzip") ||
+                body.contains("compress")))
+}
+
+/// Fetches a random sample of the restored documents by `_id` and compares their `_source` with
+/// the backup. Returns a warning describing the mismatches, if any.
+fn verify_sample(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    documents: &[Value],
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    let candidates: Vec<&Value> = documents
+        .iter()
+        .filter(|doc| doc["_id"].as_str().is_some_and(|id| !id.is_empty()))
+        .collect();
+    let sample_size = config.verify_sample_size.min(candidates.len());
+    if sample_size == 0 {
+        return Ok(None);
+    }
+
+    let mut rng = rand::thread_rng();
+    let sample: Vec<&Value> = rand::seq::index
+        ::sample(&mut rng, candidates.len(), sample_size)
+        .into_iter()
+        .map(|i| candidates[i])
+        .collect();
+    // Routed documents are only found on the shard their routing value picks
+    let docs: Vec<Value> = sample
+        .iter()
+        .map(|doc| {
+            let mut lookup = serde_json::json!({ "_id": doc["_id"] });
+            if !doc["_routing"].is_null() {
+                lookup["routing"] = doc["_routing"].clone();
+            }
+            lookup
+        })
+        .collect();
+
+    let response = config.hosts.send(|host| {
+        client
+            .post(format!("{}/{}/_mget", host, index))
+            .json(&serde_json::json!({ "docs": docs }))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(
+                format!("Sample verification for index '{}' failed", index),
+                response
+            )
+        );
+    }
+
+    let response_json: Value = response.json()?;
+    let restored = response_json["docs"]
+        .as_array()
+        .ok_or_else(|| EsDumperError::UnexpectedResponse("Invalid _mget response".to_string()))?;
+
+    let mut mismatches = Vec::new();
+    for (expected, actual) in sample.iter().zip(restored) {
+        let id = expected["_id"].as_str().unwrap_or("");
+        if !actual["found"].as_bool().unwrap_or(false) {
+            mismatches.push(format!("{} (missing)", id));
+        } else if actual["_source"] != expected["_source"] {
+            mismatches.push(format!("{} (source differs)", id));
+        }
+    }
+
+    if mismatches.is_empty() {
+        log(
+            log_file,
+            &format!("Sample verification passed for index: {} ({} documents)", index, sample_size)
+        )?;
+        return Ok(None);
+    }
+
+    let warning = format!(
+        "Sample verification found {} of {} documents differing in index '{}': {}",
+        mismatches.len(),
+        sample_size,
+        index,
+        mismatches.iter().take(10).cloned().collect::<Vec<_>>().join(", ")
+    );
+    log(log_file, &warning)?;
+    Ok(Some(warning))
+}
+
+/// Creates `target_index` with the mapping, settings and aliases backed up for `index`, adding
+/// it to `created`. A copy restored under another name is created without the aliases, which
+/// stay with the original. With `update_existing`, an index that already exists is updated
+/// instead and not added.
+#[allow(clippy::too_many_arguments)]
+fn restore_mapping(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    target_index: &str,
+    index_dir: &Path,
+    doc_type: Option<&str>,
+    api: &dyn EsApi,
+    templates: &TargetTemplates,
+    created: &mut Vec<String>,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<Vec<String>, EsDumperError> {
+    // A document type of a multi-type backup is restored into an index of its own
+    let target = match doc_type {
+        Some(doc_type) => split_index_name(target_index, doc_type),
+        None => target_index.to_string(),
+    };
+    let mapping_file = index_dir.join(format!("{}_mapping.json", index));
+
+    let file = File::open(&mapping_file)?;
+    let reader = BufReader::new(file);
+    let mapping_json: Value = serde_json::from_reader(reader)?;
+
+    // Backed-up metadata files hold the raw API responses, keyed by the source index name
+    let mut mappings = backed_up_section(&mapping_json, "mappings");
+    if let Some(doc_type) = doc_type {
+        mappings = mappings[doc_type].clone();
+        mappings["properties"][TYPE_NAME_FIELD] = serde_json::json!({ "type": "keyword" });
+    } else if !api.typed_mappings() && is_typed(&mappings) {
+        // Clusters without mapping types reject a mapping nested under its document type
+        log(log_file, &format!("Restoring the typed mapping of index {} without its type", index))?;
+        mappings = typeless(&mappings).clone();
+    }
+    let mut warnings = Vec::new();
+    // Parameters removed by newer versions would fail the index creation with a 400
+    if let Some(adapted) = adapt_mappings(&mut mappings, api) {
+        let warning = format!("Index {}: {}", target, adapted);
+        log(log_file, &warning)?;
+        warnings.push(warning);
+    }
+    if doc_type.is_none() {
+        if let Some(warning) = restore_runtime_fields(&mut mappings, index, index_dir, api, log_file)? {
+            warnings.push(format!("Index {}: {}", target, warning));
+        }
+    }
+    let mut body = serde_json::json!({ "mappings": mappings });
+
+    let settings_file = index_dir.join(format!("{}_settings.json", index));
+    if settings_file.exists() {
+        let settings_json: Value = serde_json::from_reader(
+            BufReader::new(File::open(settings_file)?)
+        )?;
+        let mut settings = backed_up_section(&settings_json, "settings");
+        if let Some(index_settings) = settings["index"].as_object_mut() {
+            // Assigned by the cluster when the index is created
+            for key in ["creation_date", "uuid", "version", "provided_name"] {
+                index_settings.remove(key);
+            }
+        }
+        // Frozen indices and searchable snapshots come back as regular, writable indices
+        let frozen = settings["index"]["frozen"] == "true";
+        let mounted = settings["index"]["store"]["type"] == "snapshot";
+        if frozen || mounted {
+            for key in READ_ONLY_INDEX_SETTINGS {
+                remove_setting(&mut settings["index"], key);
+            }
+            log(
+                log_file,
+                &format!(
+                    "Index {} was backed up from a {}, restoring it as a regular index",
+                    target,
+                    if mounted { "searchable snapshot" } else { "frozen index" }
+                )
+            )?;
+        }
+        body["settings"] = settings;
+    }
+
+    if !config.index_settings.is_empty() {
+        if !body["settings"].is_object() {
+            body["settings"] = serde_json::json!({});
+        }
+        for (key, value) in &config.index_settings {
+            override_setting(&mut body["settings"], key, value.clone());
+        }
+        let mut overridden: Vec<&str> = config.index_settings.keys().map(String::as_str).collect();
+        overridden.sort();
+        log(
+            log_file,
+            &format!("Overriding settings of index {}: {}", target, overridden.join(", "))
+        )?;
+    }
+
+    if config.update_existing && index_exists(config, client, &target)? {
+        warnings.extend(update_existing_index(config, client, &target, &body, log_file, job)?);
+        return Ok(warnings);
+    }
+
+    if let Some(applied) = templates.applied(&target) {
+        let names = applied.names.join(", ");
+        if applied.data_stream {
+            return Err(
+                EsDumperError::Config(
+                    format!(
+                        "Index '{}' matches index template {}, which only creates data streams; change or remove the template to restore it as an index",
+                        target,
+                        names
+                    )
+                )
+            );
+        }
+
+        let conflicts = mapping_conflicts(&applied.mappings, &body["mappings"]);
+        let listed = conflicts
+            .iter()
+            .map(|conflict| conflict.describe())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match config.template_conflicts {
+            _ if conflicts.is_empty() => {
+                log(
+                    log_file,
+                    &format!(
+                        "Index {} matches index templates {}, which agree with the backed-up mapping",
+                        target,
+                        names
+                    )
+                )?;
+            }
+            TemplateConflicts::Fail => {
+                return Err(
+                    EsDumperError::Config(
+                        format!(
+                            "The mapping of index '{}' conflicts with index templates {}: {} (restore with --prefer-backup or --prefer-template)",
+                            target,
+                            names,
+                            listed
+                        )
+                    )
+                );
+            }
+            TemplateConflicts::PreferBackup => {
+                warnings.push(
+                    format!(
+                        "Index templates {} define {} differently, kept the backed-up definitions",
+                        names,
+                        listed
+                    )
+                );
+            }
+            TemplateConflicts::PreferTemplate => {
+                remove_conflicts(&mut body["mappings"], &conflicts);
+                warnings.push(
+                    format!(
+                        "Index templates {} define {} differently, used the template definitions",
+                        names,
+                        listed
+                    )
+                );
+            }
+        }
+        if let Some(warning) = warnings.last() {
+            log(log_file, &format!("Index {}: {}", target, warning))?;
+        }
+    }
+
+    let aliases_file = index_dir.join(format!("{}_aliases.json", index));
+    if aliases_file.exists() && target_index == index {
+        let aliases_json: Value = serde_json::from_reader(
+            BufReader::new(File::open(aliases_file)?)
+        )?;
+        body["aliases"] = backed_up_section(&aliases_json, "aliases");
+    }
+
+    let url = |host: &str| match &config.wait_for_active_shards {
+        Some(active_shards) =>
+            format!("{}/{}?wait_for_active_shards={}", host, target, active_shards.as_param()),
+        None => format!("{}/{}", host, target),
+    };
+    config.shard_watch.wait(&config.hosts, client, log_file)?;
+    let response = config.hosts.send(|host| {
+        client
+            .put(url(host))
+            .json(&body)
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+
+    if !response.status().is_success() {
+        return Err(
+            EsDumperError::from_response(format!("Failed to create index '{}'", target), response)
+        );
+    }
+    created.push(target.clone());
+    let overridden = if config.index_settings.is_empty() {
+        None
+    } else {
+        Some(format!("settings overridden: {}", serde_json::to_string(&config.index_settings)?))
+    };
+    config.audit.record(&job.run_id, "index_created", Some(&target), None, overridden)?;
+
+    log(log_file, &format!("Mapping restored for index: {}", target))?;
+    warnings.extend(check_mapping(config, client, &target, &body["mappings"], log_file)?);
+    Ok(warnings)
+}
+
+fn index_exists(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str
+) -> Result<bool, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .head(format!("{}/{}", host, index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(false),
+        status if status.is_success() => Ok(true),
+        _ => {
+            Err(
+                EsDumperError::from_response(
+                    format!("Failed to check whether index '{}' exists", index),
+                    response
+                )
+            )
+        }
+    }
+}
+
+/// Brings an index that exists already closer to the backed-up index `body` would create,
+/// with only the changes an open index accepts: the fields its mapping lacks are added and the
+/// dynamic settings that differ are updated. Fields defined differently and static settings
+/// are left as they are, and returned as warnings.
+fn update_existing_index(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    body: &Value,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<Vec<String>, EsDumperError> {
+    log(log_file, &format!("Index {} exists, updating its mapping and settings", index))?;
+    let get = |endpoint: &str| -> Result<Value, EsDumperError> {
+        let response = config.hosts.send(|host| {
+            client
+                .get(format!("{}/{}/{}", host, index, endpoint))
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if !response.status().is_success() {
+            return Err(
+                EsDumperError::from_response(
+                    format!("Failed to read {} of index '{}'", endpoint, index),
+                    response
+                )
+            );
+        }
+        Ok(response.json()?)
+    };
+    let put = |endpoint: &str, body: &Value| -> Result<(), EsDumperError> {
+        config.shard_watch.wait(&config.hosts, client, log_file)?;
+        let response = config.hosts.send(|host| {
+            client
+                .put(format!("{}/{}/{}", host, index, endpoint))
+                .json(body)
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if !response.status().is_success() {
+            return Err(
+                EsDumperError::from_response(
+                    format!("Failed to update {} of index '{}'", endpoint, index),
+                    response
+                )
+            );
+        }
+        Ok(())
+    };
+
+    let mut warnings = Vec::new();
+    let live_mappings = backed_up_section(&get("_mapping")?, "mappings");
+    let additions = mapping_additions(&live_mappings, &body["mappings"]);
+    if !additions.mapping.is_empty() {
+        // Clusters with mapping types take the fields under the type of the backed-up mapping
+        let endpoint = match body["mappings"].as_object() {
+            Some(mappings) if is_typed(&body["mappings"]) =>
+                format!("_mapping/{}", mappings.keys().next().unwrap()),
+            _ => "_mapping".to_string(),
+        };
+        put(&endpoint, &Value::Object(additions.mapping))?;
+        let detail = format!("fields added: {}", additions.added.join(", "));
+        config.audit.record(&job.run_id, "mapping_updated", Some(index), None, Some(detail))?;
+        log(log_file, &format!("Added fields to index {}: {}", index, additions.added.join(", ")))?;
+    }
+    if !additions.conflicts.is_empty() {
+        let warning = format!(
+            "Index {} defines fields differently from the backup, kept them: {}",
+            index,
+            additions.conflicts.join(", ")
+        );
+        log(log_file, &warning)?;
+        warnings.push(warning);
+    }
+
+    let live_settings = get("_settings?flat_settings=true")?;
+    let live_settings = backed_up_section(&live_settings, "settings");
+    let changes = settings_changes(&live_settings, &body["settings"]);
+    if !changes.settings.is_empty() {
+        let mut keys: Vec<&str> = changes.settings.keys().map(String::as_str).collect();
+        keys.sort();
+        let keys = keys.join(", ");
+        put("_settings", &Value::Object(changes.settings))?;
+        let detail = format!("settings updated: {}", keys);
+        config.audit.record(&job.run_id, "settings_updated", Some(index), None, Some(detail))?;
+        log(log_file, &format!("Updated settings of index {}: {}", index, keys))?;
+    }
+    if !changes.static_changes.is_empty() {
+        let warning = format!(
+            "Index {} has static settings that differ from the backup, kept them: {}",
+            index,
+            changes.static_changes.join(", ")
+        );
+        log(log_file, &warning)?;
+        warnings.push(warning);
+    }
+    Ok(warnings)
+}
+
+/// Adds the runtime fields backed up to `<index>_runtime.json` to the mapping an index is
+/// created with, keeping those the mapping defines itself. Drops the runtime fields of mappings
+/// restored into clusters without them, returning a warning naming them.
+fn restore_runtime_fields(
+    mappings: &mut Value,
+    index: &str,
+    index_dir: &Path,
+    api: &dyn EsApi,
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    let runtime_file = index_dir.join(format!("{}_runtime.json", index));
+    if runtime_file.exists() {
+        let runtime_json: Value = serde_json::from_reader(BufReader::new(File::open(runtime_file)?))?;
+        let backed_up = backed_up_section(&runtime_json, "runtime");
+        if let Some(backed_up) = backed_up.as_object().filter(|fields| !fields.is_empty()) {
+            if !mappings["runtime"].is_object() {
+                mappings["runtime"] = serde_json::json!({});
+            }
+            let runtime = mappings["runtime"].as_object_mut().unwrap();
+            let mut added = 0;
+            for (name, field) in backed_up {
+                if !runtime.contains_key(name) {
+                    runtime.insert(name.clone(), field.clone());
+                    added += 1;
+                }
+            }
+            log(
+                log_file,
+                &format!(
+                    "Re-applying {} runtime fields of index {} ({} were not in the mapping)",
+                    backed_up.len(),
+                    index,
+                    added
+                )
+            )?;
+        }
+    }
+
+    if api.supports_runtime_fields() {
+        return Ok(None);
+    }
+    let Some(runtime) = mappings.as_object_mut().and_then(|mappings| mappings.remove("runtime")) else {
+        return Ok(None);
+    };
+    let names: Vec<&str> = runtime
+        .as_object()
+        .map(|fields| fields.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        return Ok(None);
+    }
+    let warning = format!(
+        "dropped runtime fields {}, which {} {} does not support",
+        names.join(", "),
+        api.distribution().as_str(),
+        api.version()
+    );
+    log(log_file, &format!("WARNING: {}: {}", index, warning))?;
+    Ok(Some(warning))
+}
+
+/// Reads the mapping of a newly created index back and compares it with the backed-up one, so
+/// dynamic templates, `_meta` or parameters the cluster silently dropped or altered don't go
+/// unnoticed. Returns a warning listing the differences, if any.
+fn check_mapping(
+    config: &BackupConfig,
+    client: &HttpClient,
+    index: &str,
+    expected: &Value,
+    log_file: &LogFile
+) -> Result<Option<String>, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/{}/_mapping", host, index))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(
+            log_file,
+            &format!(
+                "Could not read back the mapping of index {} ({}), not comparing it with the backup",
+                index,
+                response.status()
+            )
+        )?;
+        return Ok(None);
+    }
+    let created = backed_up_section(&response.json()?, "mappings");
+
+    let mut differences = Vec::new();
+    mapping_differences(expected, &created, "", &mut differences);
+    if differences.is_empty() {
+        return Ok(None);
+    }
+
+    let more = match differences.len().saturating_sub(MAPPING_DIFFERENCES_SHOWN) {
+        0 => String::new(),
+        more => format!(" and {} more", more),
+    };
+    differences.truncate(MAPPING_DIFFERENCES_SHOWN);
+    let warning = format!(
+        "Mapping of index '{}' differs from the backup: {}{}",
+        index,
+        differences.join(", "),
+        more
+    );
+    log(log_file, &warning)?;
+    Ok(Some(warning))
+}
+
+/// Collects the parts of the backed-up mapping `expected` that `actual` lacks or holds with
+/// another value, by dotted path. What the cluster added, such as defaults, is not a difference,
+/// nor is a value given as a string on one side and a number or boolean on the other.
+fn mapping_differences(
+    expected: &Value,
+    actual: &Value,
+    path: &str,
+    differences: &mut Vec<String>
+) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match actual.get(key) {
+                    Some(actual) => mapping_differences(value, actual, &path, differences),
+                    None => differences.push(format!("{} was dropped", path)),
+                }
+            }
+        }
+        // Dynamic templates are matched in order, so their positions have to agree as well
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (position, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                let path = format!("{}[{}]", path, position);
+                mapping_differences(expected, actual, &path, differences);
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            differences.push(
+                format!("{} has {} entries instead of {}", path, actual.len(), expected.len())
+            );
+        }
+        _ if scalar_text(expected) == scalar_text(actual) => {}
+        _ => {
+            differences.push(format!("{} changed from {} to {}", path, expected, actual));
+        }
+    }
+}
+
+fn scalar_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Removes a dotted setting such as `blocks.write` from nested settings.
+fn remove_setting(settings: &mut Value, path: &str) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            if let Some(inner) = settings.get_mut(head) {
+                remove_setting(inner, rest);
+            }
+        }
+        None => {
+            if let Some(settings) = settings.as_object_mut() {
+                settings.remove(path);
+            }
+        }
+    }
+}
+
+/// Sets `key` (e.g. `number_of_replicas` or `index.codec`) in backed-up settings, which nest
+/// every dotted segment under the top-level `index` object.
+fn override_setting(settings: &mut Value, key: &str, value: Value) {
+    let key = key.strip_prefix("index.").unwrap_or(key);
+    set_path(settings, &format!("index.{}", key), value);
+}
+
+/// Document types of a backed-up pre-6.x mapping that nests more than one type under
+/// `mappings`; empty for typeless mappings.
+fn backed_up_types(index_dir: &Path, index: &str) -> Result<Vec<String>, EsDumperError> {
+    let mapping_file = index_dir.join(format!("{}_mapping.json", index));
+    let mapping_json: Value = serde_json::from_reader(BufReader::new(File::open(mapping_file)?))?;
+    let mappings = backed_up_section(&mapping_json, "mappings");
+    if mappings.get("properties").is_some() {
+        return Ok(Vec::new());
+    }
+    Ok(
+        mappings
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(doc_type, mapping)| {
+                *doc_type != "_default_" && mapping.get("properties").is_some()
+            })
+            .map(|(doc_type, _)| doc_type.clone())
+            .collect()
+    )
+}
+
+/// Index a document type of a multi-type backup is restored into, e.g. `logs-event`.
+fn split_index_name(index: &str, doc_type: &str) -> String {
+    format!("{}-{}", index, doc_type.to_lowercase())
+}
+
+/// The `_type` a document of a multi-type backup was stored with.
+fn document_type<'a>(index: &str, doc: &'a Value) -> Result<&'a str, EsDumperError> {
+    doc["_type"].as_str().ok_or_else(|| {
+        EsDumperError::Config(
+            format!(
+                "Document '{}' of index '{}' has no _type to split by; back the index up again to record it",
+                doc["_id"].as_str().unwrap_or(""),
+                index
+            )
+        )
+    })
+}
+
+/// Extracts `section` (e.g. `mappings`) from a backed-up `{ "<index>": { "<section>": ... } }`
+/// response, or an empty object if it is missing.
+fn backed_up_section(response: &Value, section: &str) -> Value {
+    response
+        .as_object()
+        .and_then(|indices| indices.values().next())
+        .map(|index| index[section].clone())
+        .filter(|value| !value.is_null())
+        .unwrap_or_else(|| serde_json::json!({}))
+}
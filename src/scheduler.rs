@@ -0,0 +1,91 @@
+use std::fmt;
+use std::sync::{ Condvar, Mutex };
+
+/// Shares a run-wide budget of scroll slices between the indices backed up at a time, so one
+/// huge index cannot take every worker while small indices wait behind it. An index gets at
+/// most `max_share` of the budget and never more than is free; when nothing is free it waits
+/// for another index to finish its export.
+pub struct SliceScheduler {
+    settings: Option<SchedulerSettings>,
+    in_use: Mutex<usize>,
+    released: Condvar,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerSettings {
+    /// Slices running at a time across all indices.
+    pub max_total_slices: usize,
+    /// Fraction of `max_total_slices` a single index may run, e.g. 0.5.
+    pub max_share: f64,
+}
+
+/// Slices granted to the export of an index; dropping it returns them to the budget.
+pub struct SliceGrant<'a> {
+    scheduler: &'a SliceScheduler,
+    slices: usize,
+}
+
+impl SliceScheduler {
+    pub fn new(settings: SchedulerSettings) -> Self {
+        SliceScheduler {
+            settings: Some(settings),
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// A scheduler that grants every index the slices it asks for.
+    pub fn disabled() -> Self {
+        SliceScheduler {
+            settings: None,
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Most slices a single index is granted; unlimited when disabled.
+    pub fn max_per_index(&self) -> Option<usize> {
+        let settings = self.settings.as_ref()?;
+        let share = ((settings.max_total_slices as f64) * settings.max_share).ceil() as usize;
+        Some(share.clamp(1, settings.max_total_slices.max(1)))
+    }
+
+    /// Blocks until at least one slice is free, then grants up to `requested` of them.
+    pub fn acquire(&self, requested: usize) -> SliceGrant<'_> {
+        let requested = requested.max(1);
+        let (Some(settings), Some(max_per_index)) = (&self.settings, self.max_per_index()) else {
+            return SliceGrant { scheduler: self, slices: requested };
+        };
+
+        let total = settings.max_total_slices.max(1);
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use >= total {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        let slices = requested.min(max_per_index).min(total - *in_use);
+        *in_use += slices;
+        SliceGrant { scheduler: self, slices }
+    }
+}
+
+impl SliceGrant<'_> {
+    pub fn slices(&self) -> usize {
+        self.slices
+    }
+}
+
+impl Drop for SliceGrant<'_> {
+    fn drop(&mut self) {
+        if self.scheduler.settings.is_none() {
+            return;
+        }
+        *self.scheduler.in_use.lock().unwrap() -= self.slices;
+        self.scheduler.released.notify_all();
+    }
+}
+
+impl fmt::Debug for SliceScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SliceScheduler").field("settings", &self.settings).finish()
+    }
+}
@@ -0,0 +1,210 @@
+use crate::backup;
+use crate::config::BackupConfig;
+use crate::date_math::resolve_index_name;
+use crate::history;
+use crate::job::{ Job, JobStatus };
+use crate::plugins::compiled_plugins;
+use crate::progress::ProgressObserver;
+use crate::restore;
+use crate::utils::{ dir_size, index_backup_dirs, log, open_log, LogFile };
+use chrono::Utc;
+use reqwest::Url;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use tiny_http::{ Header, Method, Request, Response, Server };
+
+type Jobs = Arc<Mutex<Vec<Arc<Job>>>>;
+
+/// Runs the HTTP control API until the process is stopped.
+///
+/// - `POST /backup[?index=name]` and `POST /restore[?index=name]` start a job
+/// - `GET /jobs` and `GET /jobs/<id>` report status and progress
+/// - `DELETE /jobs/<id>` cancels a running job
+/// - `GET /backups` lists the index backups in backup_dir
+pub fn serve(
+    config: Arc<BackupConfig>,
+    log_file: LogFile,
+    listen: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::http(listen).map_err(|e| format!("Failed to listen on {}: {}", listen, e))?;
+    log(&log_file, &format!("Control API listening on {}", listen))?;
+    println!("Control API listening on http://{}", listen);
+
+    let jobs: Jobs = Arc::new(Mutex::new(Vec::new()));
+    let next_id = AtomicU64::new(1);
+
+    for request in server.incoming_requests() {
+        let (status, body) = handle(&config, &log_file, &jobs, &next_id, &request);
+        let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(e) = request.respond(response) {
+            let _ = log(&log_file, &format!("Failed to send API response: {}", e));
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(
+    config: &Arc<BackupConfig>,
+    log_file: &LogFile,
+    jobs: &Jobs,
+    next_id: &AtomicU64,
+    request: &Request
+) -> (u16, Value) {
+    let url = match Url::parse(&format!("http://localhost{}", request.url())) {
+        Ok(url) => url,
+        Err(e) => {
+            return (400, serde_json::json!({ "error": format!("Invalid URL: {}", e) }));
+        }
+    };
+    let index = url
+        .query_pairs()
+        .find(|(key, _)| key == "index")
+        .map(|(_, value)| value.to_string());
+    let segments: Vec<&str> = url
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["backup"]) => start_job(config, log_file, jobs, next_id, "backup", index),
+        (Method::Post, ["restore"]) => start_job(config, log_file, jobs, next_id, "restore", index),
+        (Method::Get, ["jobs"]) => {
+            let jobs = jobs.lock().unwrap();
+            (200, Value::Array(jobs.iter().map(|job| job.to_json()).collect()))
+        }
+        (Method::Get, ["jobs", id]) =>
+            match find_job(jobs, id) {
+                Some(job) => (200, job.to_json()),
+                None => (404, serde_json::json!({ "error": format!("Job '{}' not found", id) })),
+            }
+        (Method::Delete, ["jobs", id]) =>
+            match find_job(jobs, id) {
+                Some(job) => {
+                    job.cancel();
+                    let _ = log(log_file, &format!("Cancellation requested for job {}", job.id));
+                    (202, job.to_json())
+                }
+                None => (404, serde_json::json!({ "error": format!("Job '{}' not found", id) })),
+            }
+        (Method::Get, ["backups"]) =>
+            match list_backups(&config.backup_dir) {
+                Ok(backups) => (200, backups),
+                Err(e) => (500, serde_json::json!({ "error": e.to_string() })),
+            }
+        _ => (404, serde_json::json!({ "error": "Not found" })),
+    }
+}
+
+fn start_job(
+    config: &Arc<BackupConfig>,
+    log_file: &LogFile,
+    jobs: &Jobs,
+    next_id: &AtomicU64,
+    operation: &'static str,
+    index: Option<String>
+) -> (u16, Value) {
+    let mut jobs = jobs.lock().unwrap();
+
+    // Jobs share backup_dir, so only one runs at a time
+    if let Some(running) = jobs.iter().find(|job| job.status() == JobStatus::Running) {
+        return (
+            409,
+            serde_json::json!({ "error": format!("Job {} is still running", running.id) }),
+        );
+    }
+
+    // Date math in the index name is resolved when the job starts, so a scheduler can request
+    // e.g. `logs-{now-1d}` every night
+    let index = match index {
+        Some(name) =>
+            match resolve_index_name(&name, Utc::now()) {
+                Ok(index) => {
+                    if index != name {
+                        let _ = log(log_file, &format!("Resolved index name {} to {}", name, index));
+                    }
+                    Some(index)
+                }
+                Err(e) => {
+                    return (400, serde_json::json!({ "error": e.to_string() }));
+                }
+            }
+        None => None,
+    };
+
+    let mut progress = ProgressObserver::new(config.no_local_files);
+    if operation == "restore" {
+        progress = progress.with_rate_limits(config.restore_throttle.limits());
+    }
+    let job = Job::new(next_id.fetch_add(1, Ordering::SeqCst), operation, index).with_observer(
+        Box::new(progress)
+    );
+    let job = match config.plugins.attach(&compiled_plugins(), job) {
+        Ok(job) => Arc::new(job),
+        Err(e) => {
+            return (500, serde_json::json!({ "error": format!("Failed to load plugins: {}", e) }));
+        }
+    };
+    // Every job logs to a file of its own
+    let job_log = match open_log(&config.backup_dir, &job.run_id, config.no_local_files) {
+        Ok(job_log) => job_log,
+        Err(e) => {
+            return (500, serde_json::json!({ "error": format!("Failed to open the job's log: {}", e) }));
+        }
+    };
+    jobs.push(Arc::clone(&job));
+
+    let _ = log(
+        log_file,
+        &format!("Starting {} job {} via control API, logging as run {}", operation, job.id, job.run_id)
+    );
+
+    let config = Arc::clone(config);
+    let log_file = job_log;
+    let worker_job = Arc::clone(&job);
+    thread::spawn(move || {
+        let job = worker_job;
+        let result = match operation {
+            "restore" => restore::run_restore(&config, &log_file, job.index.as_deref(), &job),
+            _ => backup::run_backup(&config, &log_file, job.index.as_deref(), &job),
+        };
+        job.finish(&result);
+        history::record_result(
+            &config.backup_dir,
+            operation,
+            job.started_at.clone(),
+            &result,
+            &log_file
+        );
+        history::publish_result(&config, operation, job.started_at.clone(), &result, &log_file);
+    });
+
+    (202, job.to_json())
+}
+
+fn find_job(jobs: &Jobs, id: &str) -> Option<Arc<Job>> {
+    let id: u64 = id.parse().ok()?;
+    jobs.lock()
+        .unwrap()
+        .iter()
+        .find(|job| job.id == id)
+        .cloned()
+}
+
+fn list_backups(backup_dir: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let backups: Vec<Value> = index_backup_dirs(Path::new(backup_dir))?
+        .into_iter()
+        .map(|index| {
+            let bytes = dir_size(&Path::new(backup_dir).join(&index));
+            serde_json::json!({ "index": index, "bytes": bytes })
+        })
+        .collect();
+    Ok(Value::Array(backups))
+}
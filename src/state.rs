@@ -0,0 +1,203 @@
+use crate::config::DEFAULT_STATE_DB;
+use crate::report::IndexReport;
+use crate::utils::timestamp;
+use rusqlite::{ params, Connection, OptionalExtension };
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Checkpoint of an index whose run was deferred by `--max-duration`, picked up by `--resume`.
+pub const CHECKPOINT_DEFERRED: &str = "deferred";
+/// Checkpoint of an index completed by a run that was cut short, so the `--resume` run can
+/// count it towards its progress.
+pub const CHECKPOINT_DONE: &str = "done";
+/// Checkpoint of a partitioned index whose export failed before every partition was written,
+/// continued from its first unfinished partition by `--resume`.
+pub const CHECKPOINT_PARTIAL: &str = "partial";
+/// Checkpoint of an index whose export `--max-index-duration` stopped, exported again by
+/// `--resume` (from its first unfinished partition when it is partitioned).
+pub const CHECKPOINT_INTERRUPTED: &str = "interrupted";
+
+/// Persisted per-index state, keyed by cluster UUID, index name and operation.
+#[derive(Debug, Clone, Default)]
+pub struct IndexState {
+    pub last_run_at: Option<String>,
+    pub last_success: bool,
+    pub last_docs: u64,
+    pub last_bytes: u64,
+    pub last_duration_secs: f64,
+}
+
+impl IndexState {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} at {} ({} documents, {} bytes, {:.2} seconds)",
+            if self.last_success { "succeeded" } else { "failed" },
+            self.last_run_at.as_deref().unwrap_or("unknown time"),
+            self.last_docs,
+            self.last_bytes,
+            self.last_duration_secs
+        )
+    }
+}
+
+/// Embedded state store shared by every operation that needs to remember something about an
+/// index between runs (watermarks, checkpoints, last-run statistics).
+pub struct StateStore {
+    conn: Mutex<Connection>,
+}
+
+impl StateStore {
+    pub fn open(backup_dir: &str) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(Path::new(backup_dir).join(DEFAULT_STATE_DB))?)
+    }
+
+    /// A store that lives only for the current run, used when no local files may be written.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS index_state (
+                cluster TEXT NOT NULL,
+                index_name TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                watermark TEXT,
+                checkpoint TEXT,
+                last_run_at TEXT,
+                last_success INTEGER NOT NULL DEFAULT 0,
+                last_docs INTEGER NOT NULL DEFAULT 0,
+                last_bytes INTEGER NOT NULL DEFAULT 0,
+                last_duration_secs REAL NOT NULL DEFAULT 0,
+                PRIMARY KEY (cluster, index_name, operation)
+            );
+            CREATE TABLE IF NOT EXISTS open_scrolls (
+                scroll_id TEXT PRIMARY KEY,
+                cluster TEXT NOT NULL,
+                index_name TEXT NOT NULL,
+                opened_at TEXT NOT NULL
+            );"
+        )?;
+
+        Ok(StateStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn get(
+        &self,
+        cluster: &str,
+        operation: &str,
+        index: &str
+    ) -> rusqlite::Result<Option<IndexState>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT last_run_at, last_success, last_docs, last_bytes, last_duration_secs
+             FROM index_state WHERE cluster = ?1 AND index_name = ?2 AND operation = ?3",
+            params![cluster, index, operation],
+            |row| {
+                Ok(IndexState {
+                    last_run_at: row.get(0)?,
+                    last_success: row.get(1)?,
+                    last_docs: row.get::<_, i64>(2)? as u64,
+                    last_bytes: row.get::<_, i64>(3)? as u64,
+                    last_duration_secs: row.get(4)?,
+                })
+            }
+        ).optional()
+    }
+
+    pub fn record_index(
+        &self,
+        cluster: &str,
+        operation: &str,
+        report: &IndexReport
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO index_state (cluster, index_name, operation, last_run_at, last_success,
+                                      last_docs, last_bytes, last_duration_secs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT (cluster, index_name, operation) DO UPDATE SET
+                last_run_at = excluded.last_run_at,
+                last_success = excluded.last_success,
+                last_docs = excluded.last_docs,
+                last_bytes = excluded.last_bytes,
+                last_duration_secs = excluded.last_duration_secs",
+            params![
+                cluster,
+                report.index,
+                operation,
+                timestamp(),
+                report.success,
+                report.docs as i64,
+                report.bytes as i64,
+                report.duration_secs
+            ]
+        )?;
+        Ok(())
+    }
+
+    /// Sets or clears the resume checkpoint of an index.
+    pub fn set_checkpoint(
+        &self,
+        cluster: &str,
+        operation: &str,
+        index: &str,
+        checkpoint: Option<&str>
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO index_state (cluster, index_name, operation, checkpoint)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (cluster, index_name, operation) DO UPDATE SET
+                checkpoint = excluded.checkpoint",
+            params![cluster, index, operation, checkpoint]
+        )?;
+        Ok(())
+    }
+
+    /// Indices whose resume checkpoint for the given operation is `checkpoint`.
+    pub fn checkpointed(
+        &self,
+        cluster: &str,
+        operation: &str,
+        checkpoint: &str
+    ) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT index_name FROM index_state
+             WHERE cluster = ?1 AND operation = ?2 AND checkpoint = ?3
+             ORDER BY index_name"
+        )?;
+        let rows = stmt.query_map(params![cluster, operation, checkpoint], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Remembers a scroll context opened on `cluster`, until [`StateStore::remove_scroll`].
+    pub fn add_scroll(&self, cluster: &str, index: &str, scroll_id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO open_scrolls (scroll_id, cluster, index_name, opened_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![scroll_id, cluster, index, timestamp()]
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_scroll(&self, scroll_id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM open_scrolls WHERE scroll_id = ?1", params![scroll_id])?;
+        Ok(())
+    }
+
+    /// Scroll contexts on `cluster` that were opened and not cleared, as `(index, scroll ID)`.
+    pub fn open_scrolls(&self, cluster: &str) -> rusqlite::Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT index_name, scroll_id FROM open_scrolls WHERE cluster = ?1 ORDER BY opened_at"
+        )?;
+        let rows = stmt.query_map(params![cluster], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+}
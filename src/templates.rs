@@ -0,0 +1,303 @@
+use crate::config::BackupConfig;
+use crate::error::EsDumperError;
+use crate::utils::matches_pattern;
+use crate::http_client::HttpClient;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Index templates of a restore's target cluster, read once per run. The cluster applies them
+/// to every index the restore creates, merged with the backed-up mapping.
+#[derive(Debug, Default)]
+pub struct TargetTemplates {
+    /// Composable templates (`_index_template`) by name.
+    composable: Vec<(String, Value)>,
+    components: HashMap<String, Value>,
+    /// Legacy templates (`_template`) by name.
+    legacy: Vec<(String, Value)>,
+}
+
+/// The templates the cluster applies when creating an index, and their combined mappings.
+#[derive(Debug)]
+pub struct AppliedTemplates {
+    pub names: Vec<String>,
+    pub mappings: Value,
+    /// Whether the template creates data streams, which cannot be created as plain indices.
+    pub data_stream: bool,
+}
+
+/// A part of the mapping that a template and the backup both define, differently.
+#[derive(Debug, Clone)]
+pub struct MappingConflict {
+    /// Keys leading to the part, e.g. `["properties", "user", "properties", "id"]`.
+    pub keys: Vec<String>,
+    pub template: Value,
+    pub backup: Value,
+}
+
+impl MappingConflict {
+    /// The conflict as e.g. `properties.user.properties.id (template: long, backup: keyword)`.
+    pub fn describe(&self) -> String {
+        let types = (self.template["type"].as_str(), self.backup["type"].as_str());
+        match types {
+            (Some(template), Some(backup)) if template != backup => {
+                format!("{} (template: {}, backup: {})", self.keys.join("."), template, backup)
+            }
+            _ => format!("{} (defined differently)", self.keys.join(".")),
+        }
+    }
+}
+
+impl TargetTemplates {
+    /// Reads the composable, component and legacy templates of the cluster; kinds of template
+    /// the cluster does not support are left empty.
+    pub fn fetch(config: &BackupConfig, client: &HttpClient) -> Result<Self, EsDumperError> {
+        let get = |endpoint: &str| -> Result<Value, EsDumperError> {
+            let response = config.hosts.send(|host| {
+                client
+                    .get(format!("{}/{}", host, endpoint))
+                    .timeout(Duration::from_secs(config.metadata_timeout_secs))
+            })?;
+            Ok(if response.status().is_success() { response.json()? } else { Value::Null })
+        };
+
+        let named = |entries: &Value, key: &str| -> Vec<(String, Value)> {
+            entries
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| Some((entry["name"].as_str()?.to_string(), entry[key].clone())))
+                .collect()
+        };
+
+        let composable = named(&get("_index_template")?["index_templates"], "index_template");
+        let components = named(
+            &get("_component_template")?["component_templates"],
+            "component_template"
+        );
+        let legacy = get("_template")?
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, template)| (name.clone(), template.clone()))
+            .collect();
+
+        Ok(TargetTemplates {
+            composable,
+            components: components.into_iter().collect(),
+            legacy,
+        })
+    }
+
+    /// The templates the cluster would apply to a new index named `index`: the composable
+    /// template of highest priority with its component templates or, when no composable
+    /// template matches, every matching legacy template by `order`.
+    pub fn applied(&self, index: &str) -> Option<AppliedTemplates> {
+        let matches = |template: &Value| {
+            let patterns = match &template["index_patterns"] {
+                Value::String(pattern) => vec![pattern.as_str()],
+                patterns =>
+                    patterns
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(Value::as_str)
+                        .collect(),
+            };
+            patterns.iter().any(|pattern| matches_pattern(pattern, index))
+        };
+
+        let composable = self.composable
+            .iter()
+            .filter(|(_, template)| matches(template))
+            .max_by_key(|(_, template)| template["priority"].as_i64().unwrap_or(0));
+        if let Some((name, template)) = composable {
+            let mut mappings = serde_json::json!({});
+            let mut names = Vec::new();
+            for component in template["composed_of"].as_array().into_iter().flatten() {
+                let Some(component) = component.as_str() else {
+                    continue;
+                };
+                if let Some(definition) = self.components.get(component) {
+                    merge(&mut mappings, &definition["template"]["mappings"]);
+                    names.push(component.to_string());
+                }
+            }
+            merge(&mut mappings, &template["template"]["mappings"]);
+            names.insert(0, name.clone());
+            return Some(AppliedTemplates {
+                names,
+                mappings,
+                data_stream: !template["data_stream"].is_null(),
+            });
+        }
+
+        let mut legacy: Vec<&(String, Value)> = self.legacy
+            .iter()
+            .filter(|(_, template)| matches(template))
+            .collect();
+        if legacy.is_empty() {
+            return None;
+        }
+        legacy.sort_by_key(|(_, template)| template["order"].as_i64().unwrap_or(0));
+        let mut mappings = serde_json::json!({});
+        for (_, template) in &legacy {
+            merge(&mut mappings, typeless(&template["mappings"]));
+        }
+        Some(AppliedTemplates {
+            names: legacy
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            mappings,
+            data_stream: false,
+        })
+    }
+}
+
+/// The parts of the backed-up mapping `backup` that the template mapping `template` also
+/// defines, differently. Fields are compared as a whole, since a definition given when the
+/// index is created replaces the template's; object fields are compared field by field.
+pub fn mapping_conflicts(template: &Value, backup: &Value) -> Vec<MappingConflict> {
+    let mut conflicts = Vec::new();
+    collect_conflicts(typeless(template), typeless(backup), &mut Vec::new(), &mut conflicts);
+    conflicts
+}
+
+/// Leaves the conflicting parts out of the backed-up mapping, so the template's definitions
+/// apply instead.
+pub fn remove_conflicts(backup: &mut Value, conflicts: &[MappingConflict]) {
+    let backup = typeless_mut(backup);
+    for conflict in conflicts {
+        let Some((last, parents)) = conflict.keys.split_last() else {
+            continue;
+        };
+        let mut parent = &mut *backup;
+        for key in parents {
+            parent = &mut parent[key.as_str()];
+        }
+        if let Some(parent) = parent.as_object_mut() {
+            parent.remove(last);
+        }
+    }
+}
+
+fn collect_conflicts(
+    template: &Value,
+    backup: &Value,
+    keys: &mut Vec<String>,
+    conflicts: &mut Vec<MappingConflict>
+) {
+    for (key, backup_value) in backup.as_object().into_iter().flatten() {
+        let Some(template_value) = template.get(key) else {
+            continue;
+        };
+        keys.push(key.clone());
+        if key == "properties" {
+            for (field, backup_field) in backup_value.as_object().into_iter().flatten() {
+                let Some(template_field) = template_value.get(field) else {
+                    continue;
+                };
+                keys.push(field.clone());
+                let objects =
+                    template_field.get("properties").is_some() &&
+                    backup_field.get("properties").is_some();
+                if objects {
+                    collect_conflicts(template_field, backup_field, keys, conflicts);
+                } else if !same(template_field, backup_field) {
+                    conflicts.push(MappingConflict {
+                        keys: keys.clone(),
+                        template: template_field.clone(),
+                        backup: backup_field.clone(),
+                    });
+                }
+                keys.pop();
+            }
+        } else if !same(template_value, backup_value) {
+            conflicts.push(MappingConflict {
+                keys: keys.clone(),
+                template: template_value.clone(),
+                backup: backup_value.clone(),
+            });
+        }
+        keys.pop();
+    }
+}
+
+/// Whether two mapping values are equal, taking a string and the number or boolean it spells
+/// as the same value.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| same(a, b)))
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (Value::String(a), b) | (b, Value::String(a)) if !b.is_string() => {
+            serde_json::from_str::<Value>(a).is_ok_and(|parsed| parsed == *b)
+        }
+        _ => a == b,
+    }
+}
+
+/// Merges `overlay` into `base`, objects key by key and everything else replaced.
+fn merge(base: &mut Value, overlay: &Value) {
+    match (base.as_object_mut(), overlay.as_object()) {
+        (Some(base), Some(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(existing) if existing.is_object() && value.is_object() => {
+                        merge(existing, value);
+                    }
+                    _ => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        _ if !overlay.is_null() => {
+            *base = overlay.clone();
+        }
+        _ => {}
+    }
+}
+
+/// Mapping parameters found at the top of a typeless mapping.
+const MAPPING_ROOT_KEYS: &[&str] = &[
+    "properties",
+    "dynamic",
+    "dynamic_templates",
+    "date_detection",
+    "numeric_detection",
+    "runtime",
+    "enabled",
+];
+
+/// Whether a mapping is pre-7.x style, nested under its single document type.
+pub fn is_typed(mapping: &Value) -> bool {
+    mapping
+        .as_object()
+        .filter(|mapping| mapping.len() == 1)
+        .and_then(|mapping| mapping.iter().next())
+        .is_some_and(|(key, value)| {
+            !key.starts_with('_') && !MAPPING_ROOT_KEYS.contains(&key.as_str()) && value.is_object()
+        })
+}
+
+/// The mapping of the single document type of a pre-7.x mapping, or the mapping itself.
+pub fn typeless(mapping: &Value) -> &Value {
+    if is_typed(mapping) {
+        mapping.as_object().and_then(|mapping| mapping.values().next()).unwrap_or(mapping)
+    } else {
+        mapping
+    }
+}
+
+fn typeless_mut(mapping: &mut Value) -> &mut Value {
+    if is_typed(mapping) {
+        mapping.as_object_mut().unwrap().values_mut().next().unwrap()
+    } else {
+        mapping
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::{ BackupConfig, Durability };
+use crate::error::EsDumperError;
+use crate::job::Job;
+use crate::output::{ DataFormat, DataWriter };
+use crate::utils::{ log, matches_pattern, LogFile };
+use crate::http_client::HttpClient;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{ self, BufReader };
+use std::path::Path;
+use std::time::Duration;
+
+const TRANSFORMS_FILE: &str = "transforms.json";
+
+/// Transform settings accepted when a transform is created; the rest is assigned by the cluster.
+const TRANSFORM_FIELDS: &[&str] = &[
+    "source",
+    "dest",
+    "pivot",
+    "latest",
+    "frequency",
+    "sync",
+    "description",
+    "settings",
+    "retention_policy",
+    "_meta",
+];
+
+/// Backs up the transforms and rollup jobs that read from or write to any of `indices`.
+pub fn backup_transforms(
+    config: &BackupConfig,
+    client: &HttpClient,
+    target_dir: &Path,
+    indices: &[String],
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let transforms: Vec<Value> = fetch_list(config, client, "_transform?size=1000", log_file)?
+        ["transforms"].as_array()
+        .into_iter()
+        .flatten()
+        .filter(|transform| {
+            let sources = index_list(&transform["source"]["index"]);
+            let dest = transform["dest"]["index"].as_str().unwrap_or_default();
+            references(indices, &sources) || indices.iter().any(|index| index == dest)
+        })
+        .cloned()
+        .collect();
+
+    let rollup_jobs: Vec<Value> = fetch_list(config, client, "_rollup/job/_all", log_file)?
+        ["jobs"].as_array()
+        .into_iter()
+        .flatten()
+        .map(|job| job["config"].clone())
+        .filter(|job| {
+            let pattern = job["index_pattern"].as_str().unwrap_or_default().to_string();
+            let rollup_index = job["rollup_index"].as_str().unwrap_or_default();
+            references(indices, &[pattern]) || indices.iter().any(|index| index == rollup_index)
+        })
+        .collect();
+
+    if transforms.is_empty() && rollup_jobs.is_empty() {
+        return Ok(());
+    }
+
+    let definitions = serde_json::json!({ "transforms": transforms, "rollup_jobs": rollup_jobs });
+    if config.no_local_files {
+        let record = serde_json::json!({ "_transforms": definitions });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        let file = File::create(target_dir.join(TRANSFORMS_FILE))?;
+        serde_json::to_writer_pretty(&file, &definitions)?;
+        if config.durability == Durability::Fsync {
+            file.sync_all()?;
+        }
+    }
+
+    log(
+        log_file,
+        &format!(
+            "Backed up {} transforms and {} rollup jobs",
+            transforms.len(),
+            rollup_jobs.len()
+        )
+    )?;
+    Ok(())
+}
+
+/// Recreates the backed-up transforms and rollup jobs that reference any of the restored
+/// `indices`, starting them when `start_transforms` is set. Jobs the target cluster rejects are
+/// logged and skipped.
+pub fn restore_transforms(
+    config: &BackupConfig,
+    client: &HttpClient,
+    source_dir: &Path,
+    indices: &[String],
+    log_file: &LogFile,
+    job: &Job
+) -> Result<(), EsDumperError> {
+    let path = source_dir.join(TRANSFORMS_FILE);
+    if !path.exists() {
+        log(log_file, "No transforms or rollup jobs in the backup")?;
+        return Ok(());
+    }
+    let definitions: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+    let mut requests = Vec::new();
+    for transform in definitions["transforms"].as_array().into_iter().flatten() {
+        let sources = index_list(&transform["source"]["index"]);
+        let dest = transform["dest"]["index"].as_str().unwrap_or_default();
+        if !references(indices, &sources) && !indices.iter().any(|index| index == dest) {
+            continue;
+        }
+
+        let id = transform["id"].as_str().unwrap_or_default();
+        let mut body = serde_json::Map::new();
+        for field in TRANSFORM_FIELDS {
+            if let Some(value) = transform.get(*field) {
+                body.insert(field.to_string(), value.clone());
+            }
+        }
+        requests.push((format!("_transform/{}", id), Value::Object(body)));
+    }
+
+    for job in definitions["rollup_jobs"].as_array().into_iter().flatten() {
+        let pattern = job["index_pattern"].as_str().unwrap_or_default().to_string();
+        let rollup_index = job["rollup_index"].as_str().unwrap_or_default();
+        if !references(indices, &[pattern]) && !indices.iter().any(|index| index == rollup_index) {
+            continue;
+        }
+
+        let id = job["id"].as_str().unwrap_or_default();
+        let mut body = job.clone();
+        if let Some(body) = body.as_object_mut() {
+            body.remove("id");
+        }
+        requests.push((format!("_rollup/job/{}", id), body));
+    }
+
+    for (path, body) in requests {
+        let response = config.hosts.send(|host| {
+            client
+                .put(format!("{}/{}", host, path))
+                .json(&body)
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if !response.status().is_success() {
+            log(log_file, &format!("Failed to recreate {}: {}", path, response.text()?))?;
+            continue;
+        }
+        let detail = Some(path.clone());
+        config.audit.record(&job.run_id, "definition_created", None, None, detail)?;
+        log(log_file, &format!("Recreated {}", path))?;
+
+        if config.start_transforms {
+            let response = config.hosts.send(|host| {
+                client
+                    .post(format!("{}/{}/_start", host, path))
+                    .timeout(Duration::from_secs(config.metadata_timeout_secs))
+            })?;
+            if !response.status().is_success() {
+                log(log_file, &format!("Failed to start {}: {}", path, response.text()?))?;
+            } else {
+                let detail = Some(path.clone());
+                config.audit.record(&job.run_id, "definition_started", None, None, detail)?;
+                log(log_file, &format!("Started {}", path))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// GETs a listing API, treating an unavailable API (missing feature or license) as empty.
+fn fetch_list(
+    config: &BackupConfig,
+    client: &HttpClient,
+    endpoint: &str,
+    log_file: &LogFile
+) -> Result<Value, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .get(format!("{}/{}", host, endpoint))
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(log_file, &format!("Skipping {}: {}", endpoint, response.status()))?;
+        return Ok(Value::Null);
+    }
+    Ok(response.json()?)
+}
+
+/// Source indices of a transform, given as a string or an array of (possibly wildcard) names.
+fn index_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(index) => index.split(',').map(str::to_string).collect(),
+        Value::Array(indices) => indices
+            .iter()
+            .filter_map(|index| index.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn references(indices: &[String], patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| indices.iter().any(|index| matches_pattern(pattern, index)))
+}
@@ -1,67 +1,538 @@
-use crate::config::DEFAULT_LOG_FILE;
-use chrono::Local;
+use chrono::{ Local, SecondsFormat, Utc };
 use serde_json::Value;
+use std::any::Any;
 use std::fs::{ self, File };
-use std::io::Write;
-use std::path::Path;
+use std::io::{ self, Write };
+use std::panic::{ self, AssertUnwindSafe };
+use std::path::{ Path, PathBuf };
 use std::process::Command;
-use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, PoisonError };
+use std::thread;
+use std::time::{ Duration, Instant };
+use crate::api::{ select_api, serverless_api, Distribution, EsApi, EsVersion };
+use crate::config::{
+    BackupConfig,
+    Compatibility,
+    AUTO_PARALLEL_HIGH_LATENCY_MS,
+    AUTO_PARALLEL_MEMORY_PER_INDEX_MB,
+    DEFAULT_MAX_PARALLEL_INDICES,
+    LATEST_LOG,
+    LOG_DIR,
+    MAX_AUTO_PARALLEL_INDICES,
+};
+use crate::error::EsDumperError;
+use crate::job::Job;
+use crate::manifest::IndexManifest;
+use crate::output::partial_file;
+use crate::http_client::HttpClient;
 use reqwest::blocking::Client;
 
-pub fn setup_backup_dir(backup_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Destination of the log records of a run. Every record names the run, by its run ID and a
+/// UUID unique across hosts and invocations, so it can be matched with the run's report,
+/// manifests and audit records; records about a single index name the index as well, so the
+/// interleaved records of indices processed in parallel can be told apart.
+pub struct Log {
+    sink: Arc<Mutex<Box<dyn Write + Send>>>,
+    run_id: String,
+    run_uuid: String,
+    index: Option<String>,
+}
+
+pub type LogFile = Arc<Log>;
+
+impl Log {
+    /// Log records of run `run_id` written to `sink`, under a new run UUID.
+    pub fn new(sink: Box<dyn Write + Send>, run_id: &str) -> LogFile {
+        Arc::new(Log {
+            sink: Arc::new(Mutex::new(sink)),
+            run_id: run_id.to_string(),
+            run_uuid: new_uuid(),
+            index: None,
+        })
+    }
+
+    /// The same destination, with every record naming `index`.
+    pub fn for_index(&self, index: &str) -> LogFile {
+        Arc::new(Log {
+            sink: Arc::clone(&self.sink),
+            run_id: self.run_id.clone(),
+            run_uuid: self.run_uuid.clone(),
+            index: Some(index.to_string()),
+        })
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    pub fn run_uuid(&self) -> &str {
+        &self.run_uuid
+    }
+}
+
+/// A random (version 4) UUID.
+fn new_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Opens the log of run `run_id`: `backup_dir/logs/<run ID>.log`, which `backup_dir/latest.log`
+/// is pointed at, or stderr when running without local files.
+pub fn open_log(backup_dir: &str, run_id: &str, to_stderr: bool) -> Result<LogFile, EsDumperError> {
+    if to_stderr {
+        return Ok(Log::new(Box::new(io::stderr()), run_id));
+    }
+    let log_dir = Path::new(backup_dir).join(LOG_DIR);
+    fs::create_dir_all(&log_dir)?;
+    let name = format!("{}.log", run_id);
+    let file = File::options().append(true).create(true).open(log_dir.join(&name))?;
+    let log_file = Log::new(Box::new(file), run_id);
+    if let Err(e) = link_latest_log(Path::new(backup_dir), &name) {
+        log(&log_file, &format!("Could not point {} at this run's log: {}", LATEST_LOG, e))?;
+    }
+    Ok(log_file)
+}
+
+/// Points `latest.log` at the log file `name`, replacing the link in a single rename so it
+/// never goes missing.
+#[cfg(unix)]
+fn link_latest_log(backup_dir: &Path, name: &str) -> io::Result<()> {
+    let latest = backup_dir.join(LATEST_LOG);
+    let temporary = partial_file(&latest);
+    let _ = fs::remove_file(&temporary);
+    std::os::unix::fs::symlink(Path::new(LOG_DIR).join(name), &temporary)?;
+    fs::rename(temporary, latest)
+}
+
+/// Symbolic links need privileges elsewhere, so there is no `latest.log` there.
+#[cfg(not(unix))]
+fn link_latest_log(_backup_dir: &Path, _name: &str) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn setup_backup_dir(backup_dir: &str) -> Result<(), EsDumperError> {
     fs::create_dir_all(backup_dir)?;
     Ok(())
 }
 
-pub fn log(log_file: &Arc<Mutex<File>>, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    let log_message = format!("[{}] {}\n", timestamp, message);
-    let mut file = log_file.lock().unwrap();
+/// Total size in bytes of the files directly inside `path`.
+pub fn dir_size(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Names of the index backups in `dir`, sorted: the directories holding the mapping or the
+/// manifest of an index named like them. The run logs in `LOG_DIR`, hidden directories and
+/// anything else that happens to be there are left out.
+pub fn index_backup_dirs(dir: &Path) -> io::Result<Vec<String>> {
+    let mut indices: Vec<String> = fs
+        ::read_dir(dir)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.to_string();
+            if !path.is_dir() || name.starts_with('.') || name == LOG_DIR {
+                return None;
+            }
+            let backed_up =
+                path.join(format!("{}_mapping.json", name)).is_file() ||
+                IndexManifest::path(&path, &name).is_file();
+            backed_up.then_some(name)
+        })
+        .collect();
+    indices.sort();
+    Ok(indices)
+}
+
+/// Space available to the current user on the filesystem holding `path`, in megabytes.
+pub fn free_space_mb(path: &str) -> io::Result<u64> {
+    Ok(fs2::available_space(path)? / (1024 * 1024))
+}
+
+/// Memory available for new allocations in megabytes, from `/proc/meminfo`; None elsewhere.
+pub fn available_memory_mb() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes / 1024)
+}
+
+/// Median round trip of a few `GET /` requests to `host`, or None when it cannot be reached.
+pub fn measure_latency(host: &str, auth: Option<&(String, String)>) -> Option<Duration> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .ok()?;
+    let mut round_trips = Vec::new();
+    for _ in 0..3 {
+        let mut request = client.get(host);
+        if let Some((username, password)) = auth {
+            request = request.basic_auth(username, Some(password));
+        }
+        let started = Instant::now();
+        request.send().ok()?;
+        round_trips.push(started.elapsed());
+    }
+    round_trips.sort();
+    Some(round_trips[1])
+}
+
+/// Indices processed at a time when `max_parallel_indices` is unset: one per CPU, or two when
+/// the cluster is far enough away that workers mostly wait on the network, capped by the
+/// available memory and `MAX_AUTO_PARALLEL_INDICES`. Returns the value with a description of
+/// what it was derived from.
+pub fn auto_parallelism(latency: Option<Duration>) -> (usize, String) {
+    let cpus = thread::available_parallelism().map_or(DEFAULT_MAX_PARALLEL_INDICES, |n| n.get());
+    let mut parallelism = cpus;
+    let mut factors = vec![format!("CPUs: {}", cpus)];
+
+    if let Some(latency) = latency {
+        if latency.as_millis() > (AUTO_PARALLEL_HIGH_LATENCY_MS as u128) {
+            parallelism *= 2;
+        }
+        factors.push(format!("cluster latency: {} ms", latency.as_millis()));
+    }
+    if let Some(memory_mb) = available_memory_mb() {
+        let by_memory = (memory_mb / AUTO_PARALLEL_MEMORY_PER_INDEX_MB).max(1) as usize;
+        parallelism = parallelism.min(by_memory);
+        factors.push(format!("available memory: {} MB", memory_mb));
+    }
+
+    let parallelism = parallelism.clamp(1, MAX_AUTO_PARALLEL_INDICES);
+    (parallelism, format!("max_parallel_indices = {} ({})", parallelism, factors.join(", ")))
+}
+
+/// Runs `work`, turning a panic into an error naming `what`, so a bug hit while processing one
+/// index fails that index instead of taking down the run with every other index.
+pub fn catch_panic<T>(
+    what: &str,
+    work: impl FnOnce() -> Result<T, EsDumperError>
+) -> Result<T, EsDumperError> {
+    panic::catch_unwind(AssertUnwindSafe(work)).unwrap_or_else(|payload| {
+        Err(EsDumperError::Panic(format!("{} panicked: {}", what, panic_message(payload.as_ref()))))
+    })
+}
+
+/// The message a panic was raised with, as given to `panic!` or by an `unwrap`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string())
+}
+
+/// How often a wait between retries checks for cancellation.
+const RETRY_POLL: Duration = Duration::from_millis(250);
+
+/// Runs `attempt` until it succeeds, fails with an error that is not transient, or has failed
+/// `1 + retries` times, waiting `backoff` before the first retry and twice as long before each
+/// further one. `attempt` is given the number of the attempt, starting at 1. Returns the
+/// outcome of the last attempt and the number of attempts made.
+pub fn retry_transient<T>(
+    what: &str,
+    retries: u32,
+    backoff: Duration,
+    log_file: &LogFile,
+    job: &Job,
+    mut attempt: impl FnMut(u32) -> Result<T, EsDumperError>
+) -> (Result<T, EsDumperError>, u32) {
+    let mut number = 1;
+    loop {
+        let result = attempt(number);
+        let error = match &result {
+            Err(e) if e.is_transient() && number <= retries && !job.is_cancelled() => e,
+            _ => {
+                return (result, number);
+            }
+        };
+
+        let wait = backoff.saturating_mul(1 << (number - 1).min(16));
+        let _ = log(
+            log_file,
+            &format!(
+                "Transient error in {} (attempt {} of {}): {}; retrying in {} seconds",
+                what,
+                number,
+                retries + 1,
+                error,
+                wait.as_secs()
+            )
+        );
+        // Cancelling the job ends the wait early
+        let until = Instant::now() + wait;
+        while Instant::now() < until {
+            if job.is_cancelled() {
+                return (Err(EsDumperError::Cancelled), number);
+            }
+            thread::sleep(until.saturating_duration_since(Instant::now()).min(RETRY_POLL));
+        }
+        number += 1;
+    }
+}
+
+/// Flushes a completed file to stable storage.
+pub fn sync_file(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+/// Creates a symbolic link at `link` to the directory `target`.
+pub fn link_dir(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::fs::symlink_dir(target, link)
+    }
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name[first.len()..].ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(position) => {
+                rest = &rest[position + part.len()..];
+            }
+            None => {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Calls `process` for every item from `workers` threads. Each worker takes the next unprocessed
+/// item as soon as it is free, so one slow item never holds up the items behind it.
+pub fn run_queue<T: Sync>(items: &[T], workers: usize, process: impl Fn(&T) + Sync) {
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, items.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    process(item);
+                }
+            });
+        }
+    });
+}
+
+pub fn timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Writes a record to the log, stamped with the time in UTC to the millisecond (RFC 3339) and
+/// the fields correlating it with its run and index, e.g.
+/// `2025-01-01T09:00:01.250Z run=20250101T090000-0 uuid=... index=logs Starting backup`.
+pub fn log(log_file: &LogFile, message: &str) -> Result<(), EsDumperError> {
+    let index = match &log_file.index {
+        Some(index) => format!(" index={}", index),
+        None => String::new(),
+    };
+    let log_message = format!(
+        "{} run={} uuid={}{} {}\n",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        log_file.run_id,
+        log_file.run_uuid,
+        index,
+        message
+    );
+    // Still usable after an index panicked while writing to it
+    let mut file = log_file.sink.lock().unwrap_or_else(PoisonError::into_inner);
     file.write_all(log_message.as_bytes())?;
     file.flush()?;
     Ok(())
 }
 
-pub fn reduce_document_size(doc: &Value) -> Result<Value, Box<dyn std::error::Error>> {
-    let mut reduced = doc.clone();
-    if let Some(obj) = reduced.as_object_mut() {
-        obj.remove("_index");
-        obj.remove("_type");
-        obj.remove("_score");
+/// Parses an Elasticsearch time value such as `30s`, `10m` or `2h`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+
+    let secs = match unit {
+        "ms" => {
+            return Some(Duration::from_millis(amount));
+        }
+        "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        "d" => amount * 60 * 60 * 24,
+        _ => {
+            return None;
+        }
+    };
+
+    Some(Duration::from_secs(secs))
+}
+
+/// Sets the value at a dotted `path` (e.g. `user.name`), creating or replacing the objects on
+/// the way.
+pub fn set_path(target: &mut Value, path: &str, value: Value) {
+    let mut segments: Vec<&str> = path.split('.').collect();
+    let leaf = segments.pop().unwrap_or_default();
+
+    let mut object = target;
+    for segment in segments {
+        if !object[segment].is_object() {
+            object[segment] = serde_json::json!({});
+        }
+        object = &mut object[segment];
     }
-    Ok(reduced)
+    object[leaf] = value;
 }
 
-#[cfg(feature = "compression")]
-pub fn compress_file(file_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let status = Command::new("gzip").arg("-k").arg(file_path).status()?;
+/// The parts of a search hit written to a backup: its `_id` and `_source`, the doc values a
+/// document is rebuilt from, and the metadata fields named in `preserve_metadata`.
+pub fn reduce_document_size(
+    hit: &Value,
+    preserve_metadata: &[String]
+) -> Result<Value, EsDumperError> {
+    let Some(fields) = hit.as_object() else {
+        return Ok(hit.clone());
+    };
+    let reduced = fields
+        .iter()
+        .filter(|(key, value)| {
+            match key.as_str() {
+                "_id" | "_source" | "fields" => true,
+                // Types other than the 7.x+ placeholder are kept for splitting multi-type indices
+                "_type" => *value != "_doc",
+                _ => preserve_metadata.iter().any(|field| field == *key),
+            }
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Ok(Value::Object(reduced))
+}
+
+/// Gzips a file next to itself, keeping the original and replacing an earlier `.gz`.
+pub fn compress_file(file_path: &Path) -> Result<(), EsDumperError> {
+    // Written under a temporary name and renamed once complete, so `<file>.gz` is never a
+    // truncated gzip stream
+    let compressed = PathBuf::from(format!("{}.gz", file_path.display()));
+    let partial = partial_file(&compressed);
+    let status = Command::new("gzip")
+        .arg("-c")
+        .arg(file_path)
+        .stdout(File::create(&partial)?)
+        .status()?;
     if !status.success() {
-        return Err("Failed to compress file".into());
+        let _ = fs::remove_file(&partial);
+        return Err(EsDumperError::Io(io::Error::other("Failed to compress file")));
     }
+    fs::rename(&partial, &compressed)?;
     Ok(())
 }
 
-pub fn get_elasticsearch_version(
-    client: &Client,
-    host: &str,
-    log_file: &Arc<Mutex<File>>
-) -> Result<String, Box<dyn std::error::Error>> {
-    let url = format!("{}/", host);
-    let response = client.get(&url).send()?;
+#[derive(Debug, Clone)]
+pub struct ClusterInfo {
+    pub name: String,
+    pub uuid: String,
+    pub version: String,
+    /// Version-specific requests and responses of the cluster.
+    pub api: Arc<dyn EsApi>,
+}
+
+/// Directory of a cluster's backups when `namespace_by_cluster` is set: the cluster name with
+/// anything but letters, digits, `-`, `_` and `.` replaced by `_`.
+pub fn cluster_dir_name(cluster_name: &str) -> String {
+    cluster_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+/// Reads the name, UUID and version of the cluster, refusing versions older than the supported
+/// floor of its distribution unless `force` is set.
+pub fn get_cluster_info(
+    config: &BackupConfig,
+    client: &HttpClient,
+    log_file: &LogFile
+) -> Result<ClusterInfo, EsDumperError> {
+    // Serverless targets have no root endpoint; the host stands in for the cluster
+    if config.compatibility == Compatibility::Serverless {
+        let host = config.hosts.hosts().first().cloned().unwrap_or_default();
+        log(log_file, &format!("Using the serverless API profile for {}", host))?;
+        return Ok(ClusterInfo {
+            name: host.clone(),
+            uuid: host,
+            version: "serverless".to_string(),
+            api: serverless_api(),
+        });
+    }
+
+    let response = config.hosts.send(|host| client.get(format!("{}/", host)))?;
     let status = response.status();
     let response_text = response.text()?;
 
     log(log_file, &format!("Response from version check (status: {}): {}", status, response_text))?;
 
     if !status.is_success() {
-        return Err(format!("Failed to fetch version: {}", status).into());
+        return Err(EsDumperError::from_status("Failed to fetch version", status, response_text));
     }
 
     let json: Value = serde_json::from_str(&response_text)?;
     let version = json["version"]["number"]
         .as_str()
-        .ok_or("No version number found in response")?
+        .ok_or_else(|| {
+            EsDumperError::UnexpectedResponse("No version number found in response".to_string())
+        })?
         .to_string();
+    let distribution = Distribution::parse(json["version"]["distribution"].as_str());
+    let api = select_api(EsVersion::parse(&version)?, distribution);
+    if !api.supported() {
+        let message = format!(
+            "{} {} is older than the oldest supported version, {}",
+            distribution.as_str(),
+            api.version(),
+            distribution.minimum_version()
+        );
+        if !config.force {
+            return Err(
+                EsDumperError::Config(format!("{} (run with --force to try anyway)", message))
+            );
+        }
+        log(log_file, &format!("{}, continuing because of --force", message))?;
+    }
+    for deprecation in api.deprecations() {
+        log(
+            log_file,
+            &format!("Deprecated in {} {}: {}", distribution.as_str(), api.version(), deprecation)
+        )?;
+    }
 
-    Ok(version)
+    Ok(ClusterInfo {
+        name: json["cluster_name"].as_str().unwrap_or("unknown").to_string(),
+        uuid: json["cluster_uuid"].as_str().unwrap_or("unknown").to_string(),
+        version,
+        api,
+    })
 }
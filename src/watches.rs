@@ -0,0 +1,152 @@
+use crate::config::{ BackupConfig, Durability };
+use crate::error::EsDumperError;
+use crate::job::Job;
+use crate::output::{ DataFormat, DataWriter };
+use crate::utils::{ log, LogFile };
+use crate::http_client::HttpClient;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{ self, BufReader };
+use std::path::Path;
+use std::time::Duration;
+
+const WATCHES_FILE: &str = "watches.json";
+const MAX_WATCHES: u64 = 10000;
+
+/// Backs up Watcher watches (Elasticsearch) and alerting monitors (OpenSearch) to
+/// `watches.json`. Whichever API the cluster lacks is recorded as an empty list.
+pub fn backup_watches(
+    config: &BackupConfig,
+    client: &HttpClient,
+    target_dir: &Path,
+    log_file: &LogFile
+) -> Result<(), EsDumperError> {
+    let watches: Vec<Value> = search(
+        config,
+        client,
+        "_watcher/_query/watches",
+        serde_json::json!({ "size": MAX_WATCHES }),
+        log_file
+    )?
+        ["watches"].as_array()
+        .into_iter()
+        .flatten()
+        .map(|watch| {
+            serde_json::json!({
+            "id": watch["_id"],
+            "active": watch["status"]["state"]["active"],
+            "watch": watch["watch"],
+        })
+        })
+        .collect();
+
+    let monitors: Vec<Value> = search(
+        config,
+        client,
+        "_plugins/_alerting/monitors/_search",
+        serde_json::json!({ "query": { "match_all": {} }, "size": MAX_WATCHES }),
+        log_file
+    )?
+        ["hits"]["hits"].as_array()
+        .into_iter()
+        .flatten()
+        .map(|hit| serde_json::json!({ "id": hit["_id"], "monitor": hit["_source"] }))
+        .collect();
+
+    let definitions = serde_json::json!({ "watches": watches, "monitors": monitors });
+    if config.no_local_files {
+        let record = serde_json::json!({ "_watches": definitions });
+        DataWriter::new(Box::new(io::stdout()), DataFormat::Ndjson).write_docs(&[record])?;
+    } else {
+        let file = File::create(target_dir.join(WATCHES_FILE))?;
+        serde_json::to_writer_pretty(&file, &definitions)?;
+        if config.durability == Durability::Fsync {
+            file.sync_all()?;
+        }
+    }
+
+    log(
+        log_file,
+        &format!("Backed up {} watches and {} monitors", watches.len(), monitors.len())
+    )?;
+    Ok(())
+}
+
+/// Recreates the backed-up watches under their original IDs and activation state. Monitors get
+/// new IDs, as the alerting API only creates monitors under generated ones. Definitions the
+/// target cluster rejects are logged and skipped.
+pub fn restore_watches(
+    config: &BackupConfig,
+    client: &HttpClient,
+    source_dir: &Path,
+    log_file: &LogFile,
+    job: &Job
+) -> Result<(), EsDumperError> {
+    let path = source_dir.join(WATCHES_FILE);
+    if !path.exists() {
+        log(log_file, "No watches or monitors in the backup")?;
+        return Ok(());
+    }
+    let definitions: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+    let mut restored = 0;
+    for watch in definitions["watches"].as_array().into_iter().flatten() {
+        let id = watch["id"].as_str().unwrap_or_default();
+        let active = watch["active"].as_bool().unwrap_or(true);
+        let response = config.hosts.send(|host| {
+            client
+                .put(format!("{}/_watcher/watch/{}?active={}", host, id, active))
+                .json(&watch["watch"])
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if response.status().is_success() {
+            restored += 1;
+            let detail = Some(format!("watch {}", id));
+            config.audit.record(&job.run_id, "definition_created", None, None, detail)?;
+        } else {
+            log(log_file, &format!("Failed to restore watch {}: {}", id, response.text()?))?;
+        }
+    }
+
+    for monitor in definitions["monitors"].as_array().into_iter().flatten() {
+        let id = monitor["id"].as_str().unwrap_or_default();
+        let response = config.hosts.send(|host| {
+            client
+                .post(format!("{}/_plugins/_alerting/monitors", host))
+                .json(&monitor["monitor"])
+                .timeout(Duration::from_secs(config.metadata_timeout_secs))
+        })?;
+        if response.status().is_success() {
+            restored += 1;
+            let detail = Some(format!("monitor {}", id));
+            config.audit.record(&job.run_id, "definition_created", None, None, detail)?;
+        } else {
+            log(log_file, &format!("Failed to restore monitor {}: {}", id, response.text()?))?;
+        }
+    }
+
+    log(log_file, &format!("Restored {} watches and monitors", restored))?;
+    Ok(())
+}
+
+/// POSTs a search-style listing API, treating an unavailable API (missing plugin or license)
+/// as empty.
+fn search(
+    config: &BackupConfig,
+    client: &HttpClient,
+    endpoint: &str,
+    body: Value,
+    log_file: &LogFile
+) -> Result<Value, EsDumperError> {
+    let response = config.hosts.send(|host| {
+        client
+            .post(format!("{}/{}", host, endpoint))
+            .json(&body)
+            .timeout(Duration::from_secs(config.metadata_timeout_secs))
+    })?;
+    if !response.status().is_success() {
+        log(log_file, &format!("Skipping {}: {}", endpoint, response.status()))?;
+        return Ok(Value::Null);
+    }
+    Ok(response.json()?)
+}
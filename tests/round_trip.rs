@@ -0,0 +1,109 @@
+use serde_json::{ json, Value };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+
+/// Recorded traffic of an 8.15 cluster holding the three-document index `books`.
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+const CONFIG: &str = r#"
+[elastic]
+host = "http://127.0.0.1:9200"
+
+[restore]
+
+[backup]
+backup_dir = "backups"
+max_parallel_indices = 1
+"#;
+
+/// A working directory of its own for the binary, which reads `config.toml` from it.
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    /// A directory whose config holds `backup_options` in its `[backup]` section.
+    fn new(name: &str, backup_options: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("es-dumper-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("config.toml"), format!("{}{}", CONFIG, backup_options)).unwrap();
+        WorkDir(path)
+    }
+
+    fn run(&self, args: &[&str]) {
+        let output = Command::new(env!("CARGO_BIN_EXE_dump-dump"))
+            .args(args)
+            .current_dir(&self.0)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "dump-dump {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.0.join(path)).unwrap()
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn fixtures(recording: &str) -> String {
+    Path::new(FIXTURES).join(recording).to_string_lossy().into_owned()
+}
+
+/// Backs `books` up, with its second document routed, and restores it into an empty cluster.
+#[test]
+fn replayed_backup_restores_every_document() {
+    let dir = WorkDir::new("round-trip", "");
+
+    dir.run(&["backup", "books", "--strict", "--replay", &fixtures("round_trip/backup")]);
+    let data: Value = serde_json::from_str(&dir.read("backups/books/books_data.json")).unwrap();
+    assert_eq!(
+        data,
+        json!([
+            { "_id": "1", "_source": { "title": "Dune", "year": 1965 } },
+            { "_id": "2", "_routing": "lem", "_source": { "title": "Solaris", "year": 1961 } },
+            { "_id": "3", "_source": { "title": "Hyperion", "year": 1989 } },
+        ])
+    );
+    let mapping = dir.read("backups/books/books_mapping.json");
+    let mapping: Value = serde_json::from_str(&mapping).unwrap();
+    assert_eq!(mapping["books"]["mappings"]["properties"]["year"]["type"], "integer");
+
+    // The recorded restore only answers the index creation and bulk request it was recorded
+    // with, routing included, so a change to either on the way through the backup fails the
+    // index
+    dir.run(&["restore", "books", "--strict", "--replay", &fixtures("round_trip/restore")]);
+    let log = dir.read("backups/latest.log");
+    assert!(!log.contains("Error"), "{}", log);
+    assert!(log.contains("Document count verified for index: books (3 documents)"), "{}", log);
+}
+
+/// Backs `books` up a document per page through a point in time that expires after the first
+/// page, so the export resumes after the first document in a new one.
+#[test]
+fn replayed_backup_resumes_an_expired_point_in_time() {
+    let options = "scroll_size = 1\nmax_slices = 1\nmax_scroll_restarts = 1\n";
+    let dir = WorkDir::new("pit-resume", options);
+
+    dir.run(&["backup", "books", "--strict", "--replay", &fixtures("pit_resume")]);
+    let data: Value = serde_json::from_str(&dir.read("backups/books/books_data.json")).unwrap();
+    assert_eq!(
+        data,
+        json!([
+            { "_id": "1", "_source": { "title": "Dune", "year": 1965 } },
+            { "_id": "2", "_source": { "title": "Solaris", "year": 1961 } },
+            { "_id": "3", "_source": { "title": "Hyperion", "year": 1989 } },
+        ])
+    );
+    let log = dir.read("backups/latest.log");
+    assert!(log.contains("expired after 1 documents, resuming after the last one"), "{}", log);
+}